@@ -10,3 +10,146 @@ CREATE TABLE project_shares (
   FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
   FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
+
+-- Migration: Add owner-scoped git credentials
+-- An owner token authorizes push/pull to every repo under that owner
+
+ALTER TABLE api_token ALTER COLUMN project_id DROP NOT NULL;
+ALTER TABLE api_token ADD COLUMN owner_id UUID;
+ALTER TABLE api_token ADD FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE;
+ALTER TABLE api_token ADD CONSTRAINT api_token_scope CHECK ((project_id IS NULL) <> (owner_id IS NULL));
+
+-- Migration: Record built image size and layer count per build
+
+ALTER TABLE builds ADD COLUMN image_size_bytes BIGINT;
+ALTER TABLE builds ADD COLUMN layer_count INTEGER;
+
+-- Migration: Record built commit and environs per build
+
+-- Used to skip rebuilding a commit that is already deployed with the same environs
+
+ALTER TABLE builds ADD COLUMN commit_sha TEXT;
+ALTER TABLE builds ADD COLUMN environs JSONB;
+
+-- Migration: Add terminal websocket tokens
+
+CREATE TABLE terminal_token (
+  token       TEXT          NOT NULL,
+  project_id  UUID          NOT NULL,
+  user_id     UUID          NOT NULL,
+  expires_at  TIMESTAMPTZ   NOT NULL,
+  created_at  TIMESTAMPTZ   NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (token),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Migration: Record build priority
+
+ALTER TABLE builds ADD COLUMN priority TEXT NOT NULL DEFAULT 'push';
+
+-- Migration: Static build type
+
+ALTER TABLE projects ADD COLUMN build_type TEXT NOT NULL DEFAULT 'docker';
+ALTER TABLE projects ADD COLUMN build_command TEXT;
+ALTER TABLE projects ADD COLUMN output_dir TEXT;
+ALTER TABLE projects ADD CONSTRAINT project_build_type CHECK (build_type IN ('docker', 'static'));
+
+-- Migration: Idempotency keys
+
+CREATE TABLE idempotency_keys (
+  user_id     UUID          NOT NULL,
+  key         TEXT          NOT NULL,
+  request     TEXT          NOT NULL,
+  status_code INTEGER,
+  response    TEXT,
+  expires_at  TIMESTAMPTZ   NOT NULL,
+  created_at  TIMESTAMPTZ   NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (user_id, key),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Migration: Project health
+
+ALTER TABLE projects ADD COLUMN health_status TEXT NOT NULL DEFAULT 'unknown';
+ALTER TABLE projects ADD COLUMN last_checked_at TIMESTAMPTZ;
+
+-- Migration: Placeholder page
+
+ALTER TABLE projects ADD COLUMN placeholder_enabled BOOLEAN NOT NULL DEFAULT true;
+
+-- Migration: Dependency cache
+
+ALTER TABLE projects ADD COLUMN cache_generation INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE projects ADD COLUMN dependency_cache_key TEXT;
+
+-- Migration: Inbound webhook
+
+ALTER TABLE projects ADD COLUMN webhook_secret TEXT;
+
+-- Migration: Build config snapshot
+
+ALTER TABLE builds ADD COLUMN config_snapshot JSONB;
+
+-- Migration: Clone strategy
+
+ALTER TABLE projects ADD COLUMN clone_strategy TEXT NOT NULL DEFAULT 'clone';
+ALTER TABLE projects ADD CONSTRAINT project_clone_strategy CHECK (clone_strategy IN ('clone', 'export'));
+
+-- Migration: Submodules
+
+ALTER TABLE projects ADD COLUMN submodules_enabled BOOLEAN NOT NULL DEFAULT false;
+
+-- Migration: Freeze deploys
+
+ALTER TABLE projects ADD COLUMN pinned_commit TEXT;
+
+-- Migration: Repository size
+
+ALTER TABLE projects ADD COLUMN repo_size_bytes BIGINT;
+ALTER TABLE projects ADD COLUMN repo_size_updated_at TIMESTAMPTZ;
+
+-- Migration: Idle sleeping
+
+ALTER TABLE projects ADD COLUMN idle_timeout INTEGER;
+ALTER TABLE projects ADD COLUMN sleeping BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE projects ADD COLUMN slept_at TIMESTAMPTZ;
+
+-- Migration: Build branch
+
+ALTER TABLE projects ADD COLUMN build_branch TEXT;
+
+-- Migration: Deploy webhook
+
+ALTER TABLE projects ADD COLUMN deploy_webhook_url TEXT;
+ALTER TABLE projects ADD COLUMN deploy_webhook_secret TEXT;
+
+-- Migration: Build branch of builds
+
+ALTER TABLE builds ADD COLUMN branch TEXT;
+
+-- Migration: Secret environment variables
+
+ALTER TABLE projects ADD COLUMN secret_environs TEXT[] NOT NULL DEFAULT '{}';
+
+-- Migration: Project share roles
+
+ALTER TABLE project_shares ADD COLUMN role TEXT NOT NULL DEFAULT 'editor' CHECK (role IN ('viewer', 'editor'));
+
+-- Migration: Environment variable names only in build config snapshots
+
+UPDATE builds
+SET config_snapshot = jsonb_set(
+  config_snapshot,
+  '{environs}',
+  (SELECT COALESCE(jsonb_agg(key ORDER BY key), '[]'::jsonb) FROM jsonb_object_keys(config_snapshot->'environs') AS key)
+)
+WHERE jsonb_typeof(config_snapshot->'environs') = 'object';
+
+-- Migration: Idempotent project creation without stored git passwords
+
+ALTER TABLE idempotency_keys ADD COLUMN project_id UUID REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE;
+-- responses of created projects carried the plaintext git password
+DELETE FROM idempotency_keys WHERE status_code BETWEEN 200 AND 299;
@@ -42,12 +42,51 @@ CREATE TABLE projects (
   owner_id    UUID          NOT NULL,
   name        TEXT          NOT NULL,
   environs    JSONB         NOT NULL default '{"PRODUCTION": "True"}'::jsonb,
+  -- keys of environs whose values are masked and redacted from build logs
+  secret_environs TEXT[]    NOT NULL default '{}',
+  -- docker, or static to run build_command and serve output_dir
+  build_type  TEXT          NOT NULL default 'docker',
+  build_command TEXT,
+  output_dir  TEXT,
+  -- healthy, unhealthy or unknown, set by the health prober
+  health_status TEXT        NOT NULL default 'unknown',
+  last_checked_at TIMESTAMPTZ,
+  -- show a "not deployed yet" page on the subdomain until the first successful build
+  placeholder_enabled BOOLEAN NOT NULL default true,
+  -- bumped when the dependency cache is cleared so the next build starts from an empty one
+  cache_generation INTEGER NOT NULL default 0,
+  -- dependency cache key of the last successful build
+  dependency_cache_key TEXT,
+  -- signs requests to the inbound trigger webhook, the webhook is off while it's unset
+  webhook_secret TEXT,
+  -- clone, or export to build from the files of the commit without .git
+  clone_strategy TEXT       NOT NULL default 'clone',
+  -- fetch submodules into the build source, off since they may point anywhere
+  submodules_enabled BOOLEAN NOT NULL default false,
+  -- frozen while set, pushes are kept but the deployment stays on this commit
+  pinned_commit TEXT,
+  -- branch whose pushes are deployed, the default branch when unset
+  build_branch TEXT,
+  -- posted to after every finished build, signed with the secret when there is one
+  deploy_webhook_url TEXT,
+  deploy_webhook_secret TEXT,
+  -- size of the bare repository, measured after every push
+  repo_size_bytes BIGINT,
+  repo_size_updated_at TIMESTAMPTZ,
+  -- seconds without traffic before the container is put to sleep, the global default when
+  -- unset and never when 0
+  idle_timeout INTEGER,
+  -- stopped for being idle, started again by the next request to the subdomain
+  sleeping    BOOLEAN       NOT NULL default false,
+  slept_at    TIMESTAMPTZ,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
 
   PRIMARY KEY (id),
-  FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE
+  FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT project_build_type CHECK (build_type IN ('docker', 'static')),
+  CONSTRAINT project_clone_strategy CHECK (clone_strategy IN ('clone', 'export'))
 );
 
 CREATE TABLE domains (
@@ -66,14 +105,18 @@ CREATE TABLE domains (
 
 CREATE TABLE api_token (
   id          UUID          NOT NULL,
-  project_id  UUID          NOT NULL,
+  -- a token is scoped to either a single project or every project of an owner
+  project_id  UUID,
+  owner_id    UUID,
   token       TEXT          NOT NULL,
   created_at  TIMESTAMPTZ   NOT NULL default now(),
   updated_at  TIMESTAMPTZ   NOT NULL default now(),
   deleted_at  TIMESTAMPTZ,
 
   PRIMARY KEY (id),
-  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (owner_id) REFERENCES project_owners(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CONSTRAINT api_token_scope CHECK ((project_id IS NULL) <> (owner_id IS NULL))
 );
 
 CREATE TABLE builds (
@@ -82,6 +125,16 @@ CREATE TABLE builds (
   
   status build_state NOT NULL DEFAULT 'pending',
   log TEXT NOT NULL DEFAULT '',
+  image_size_bytes BIGINT,
+  layer_count INTEGER,
+  commit_sha TEXT,
+  -- branch the commit was taken from, null for a tag or a commit
+  branch TEXT,
+  environs JSONB,
+  -- preview, push or rebuild, higher ones are dispatched first
+  priority TEXT NOT NULL DEFAULT 'push',
+  -- settings the build ran with, secret looking environ values masked
+  config_snapshot JSONB,
 
   created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
   updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
@@ -107,9 +160,44 @@ CREATE TABLE sessions (
 CREATE TABLE project_shares (
   project_id  UUID          NOT NULL,
   user_id     UUID          NOT NULL,
+  -- editor can deploy and change the environment, viewer can only look
+  role        TEXT          NOT NULL DEFAULT 'editor' CHECK (role IN ('viewer', 'editor')),
   created_at  TIMESTAMPTZ   NOT NULL DEFAULT now(),
 
   PRIMARY KEY (project_id, user_id),
   FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
   FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
-);
\ No newline at end of file
+);
+
+-- short lived, single use tokens for opening a terminal websocket without the session cookie
+CREATE TABLE terminal_token (
+  token       TEXT          NOT NULL,
+  project_id  UUID          NOT NULL,
+  user_id     UUID          NOT NULL,
+  expires_at  TIMESTAMPTZ   NOT NULL,
+  created_at  TIMESTAMPTZ   NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (token),
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- responses of create requests sent with an Idempotency-Key, replayed on retries
+CREATE TABLE idempotency_keys (
+  user_id     UUID          NOT NULL,
+  key         TEXT          NOT NULL,
+  -- what the key was first used for, a different request with the same key is rejected
+  request     TEXT          NOT NULL,
+  -- NULL while the first request is still running
+  status_code INTEGER,
+  -- body of a rejected request, a created project is replayed from project_id instead so
+  -- its git password is never stored
+  response    TEXT,
+  project_id  UUID,
+  expires_at  TIMESTAMPTZ   NOT NULL,
+  created_at  TIMESTAMPTZ   NOT NULL DEFAULT now(),
+
+  PRIMARY KEY (user_id, key),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,19 @@
+use crate::{auth::{auth, Auth}, startup::AppState};
+use crate::configuration::Settings;
+use axum::routing::get;
+use axum::{Router, middleware};
+use axum_extra::routing::RouterExt;
+use hyper::Body;
+
+mod view_config;
+
+pub async fn router(_state: AppState, config: &Settings) -> Router<AppState, Body> {
+    let sanitized_config = config.sanitized();
+
+    Router::new()
+        .route_with_tsr(
+            "/api/admin/config",
+            get(move |auth: Auth| view_config::get(auth, sanitized_config.clone())),
+        )
+        .route_layer(middleware::from_fn(auth))
+}
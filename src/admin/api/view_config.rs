@@ -0,0 +1,32 @@
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+
+use crate::auth::Auth;
+
+/// Permission token (from `user_permissions`) allowed to read instance internals
+pub const ADMIN_PERMISSION: &str = "admin";
+
+#[tracing::instrument(skip(auth, config))]
+pub async fn get(auth: Auth, config: serde_json::Value) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"message": "Unauthorized"}"#))
+            .unwrap();
+    };
+
+    if !user.permissions.contains(ADMIN_PERMISSION) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"message": "Forbidden"}"#))
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(config.to_string()))
+        .unwrap()
+}
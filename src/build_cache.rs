@@ -0,0 +1,138 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+use bollard::{volume::CreateVolumeOptions, Docker};
+use git2::{ObjectType, Oid};
+
+/// Lockfiles the dependency cache is keyed on, a repo without any of them isn't cached
+pub const LOCKFILES: &[&str] = &[
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "requirements.txt",
+    "poetry.lock",
+    "Pipfile.lock",
+    "Cargo.lock",
+];
+
+/// Where the cache volume is mounted in the build container of static projects
+pub const CACHE_DIR: &str = "/cache";
+
+/// Package manager settings pointing their caches into [`CACHE_DIR`]
+pub const CACHE_ENV: &[(&str, &str)] = &[
+    ("npm_config_cache", "/cache/npm"),
+    ("npm_config_store_dir", "/cache/pnpm"),
+    ("YARN_CACHE_FOLDER", "/cache/yarn"),
+    ("PIP_CACHE_DIR", "/cache/pip"),
+    ("CARGO_HOME", "/cache/cargo"),
+];
+
+/// Build arg telling a Dockerfile which BuildKit cache mount belongs to the project, e.g.
+/// `RUN --mount=type=cache,id=${PWS_CACHE_ID},target=/root/.npm npm ci`
+pub const CACHE_ID_BUILD_ARG: &str = "PWS_CACHE_ID";
+
+#[derive(Debug, Clone)]
+pub struct DependencyCache {
+    pub key: String,
+    /// volume of static builds and BuildKit cache mount id of docker builds
+    pub id: String,
+    pub hit: bool,
+}
+
+impl DependencyCache {
+    pub fn log_line(&self) -> String {
+        match self.hit {
+            true => format!("Dependency cache hit ({})\n", self.key),
+            false => format!("Dependency cache miss ({}), dependencies are installed from scratch\n", self.key),
+        }
+    }
+}
+
+/// Hash of the lockfiles in the repo root and how often the cache was cleared, so clearing
+/// moves every later build to a fresh cache. `None` when there's no lockfile.
+pub fn cache_key(container_src: &str, generation: i32) -> Result<Option<String>> {
+    let mut content = Vec::new();
+
+    for lockfile in LOCKFILES {
+        let path = Path::new(container_src).join(lockfile);
+        if !path.is_file() {
+            continue;
+        }
+
+        content.extend(lockfile.as_bytes());
+        content.push(0);
+        content.extend(std::fs::read(path)?);
+        content.push(0);
+    }
+
+    if content.is_empty() {
+        return Ok(None);
+    }
+
+    content.extend(generation.to_string().as_bytes());
+    let oid = Oid::hash_object(ObjectType::Blob, &content)?;
+
+    Ok(Some(oid.to_string()[..16].to_string()))
+}
+
+pub fn volume_name(container_name: &str) -> String {
+    format!("{container_name}-deps")
+}
+
+/// Gets the cache for `key` ready. Docker builds don't see the BuildKit cache from here,
+/// `stored_key` is the key of their last successful build.
+pub async fn prepare(
+    container_name: &str,
+    key: &str,
+    stored_key: Option<&str>,
+    static_build: bool,
+) -> Result<DependencyCache> {
+    if !static_build {
+        // BuildKit keeps cache mounts by id, a new key starts from an empty one
+        return Ok(DependencyCache {
+            key: key.to_string(),
+            id: format!("{container_name}-deps-{key}"),
+            hit: stored_key == Some(key),
+        });
+    }
+
+    let docker = Docker::connect_with_local_defaults()?;
+    let name = volume_name(container_name);
+
+    let volume_key = match docker.inspect_volume(&name).await {
+        Ok(volume) => volume.labels.get("pws.cache.key").cloned(),
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    let hit = volume_key.as_deref() == Some(key);
+
+    // the volume only ever holds the cache of one key, anything else is stale
+    if !hit {
+        clear(&docker, container_name).await?;
+        docker
+            .create_volume(CreateVolumeOptions {
+                name: name.clone(),
+                labels: HashMap::from([
+                    ("pws.cache.project".to_string(), container_name.to_string()),
+                    ("pws.cache.key".to_string(), key.to_string()),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    Ok(DependencyCache {
+        key: key.to_string(),
+        id: name,
+        hit,
+    })
+}
+
+/// Removes the cache volume of a project, BuildKit drops unused cache mounts by itself
+pub async fn clear(docker: &Docker, container_name: &str) -> Result<()> {
+    match docker.remove_volume(&volume_name(container_name), None).await {
+        Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
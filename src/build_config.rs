@@ -0,0 +1,278 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path};
+
+use byte_unit::Byte;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{configuration::Settings, projects::environ};
+
+/// Files looked up in the repository root, only one of them may exist
+pub const BUILD_CONFIG_FILES: &[&str] = &[".pws.yaml", ".pws.yml", ".pws.toml"];
+
+const DEFAULT_DOCKERFILE: &str = "Dockerfile";
+const DEFAULT_PORT: u16 = 80;
+
+#[derive(Error, Debug)]
+pub enum BuildConfigError {
+    #[error("Found more than one build config file: {0}, keep only one")]
+    Ambiguous(String),
+    #[error("Failed to read {file}: {source}")]
+    Read {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {file}: {message}")]
+    Parse { file: String, message: String },
+    #[error("Invalid {file}: {message}")]
+    Invalid { file: String, message: String },
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceRequests {
+    /// fraction of cpu cores, e.g. 0.25
+    pub cpu: Option<f64>,
+    /// human readable size, e.g. 128M
+    pub memory: Option<String>,
+}
+
+/// Build options committed in the repository as `.pws.yaml` or `.pws.toml`.
+///
+/// Precedence: project environs set through the API win over `build_args` with the same key,
+/// resource requests can only lower the server container limits and never raise them.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BuildConfig {
+    /// path of the Dockerfile relative to the repository root
+    pub dockerfile: Option<String>,
+    #[serde(default)]
+    pub build_args: HashMap<String, String>,
+    /// port the application listens on inside the container
+    pub port: Option<u16>,
+    /// http path the proxy polls to check the application is healthy
+    pub healthcheck: Option<String>,
+    #[serde(default)]
+    pub resources: ResourceRequests,
+    /// file the config was read from, empty when the repo has none
+    #[serde(skip)]
+    pub source: String,
+}
+
+impl BuildConfig {
+    /// Reads and validates the build config in `container_src`, a repo without one gets the defaults
+    pub fn load(container_src: &str, config: &Settings) -> Result<Self, BuildConfigError> {
+        let found = BUILD_CONFIG_FILES
+            .iter()
+            .filter(|file| Path::new(container_src).join(file).is_file())
+            .collect::<Vec<_>>();
+
+        let file = match found.as_slice() {
+            [] => return Ok(Self::default()),
+            [file] => file.to_string(),
+            files => {
+                return Err(BuildConfigError::Ambiguous(
+                    files.iter().map(|file| file.to_string()).collect::<Vec<_>>().join(", "),
+                ))
+            }
+        };
+
+        let content = std::fs::read_to_string(Path::new(container_src).join(&file))
+            .map_err(|source| BuildConfigError::Read { file: file.clone(), source })?;
+
+        let parsed = match file.ends_with(".toml") {
+            true => toml::from_str::<Self>(&content).map_err(|err| err.to_string()),
+            false => serde_yaml::from_str::<Self>(&content).map_err(|err| err.to_string()),
+        };
+
+        let mut build_config = parsed.map_err(|message| BuildConfigError::Parse {
+            file: file.clone(),
+            message,
+        })?;
+        build_config.source = file;
+        build_config.validate(container_src, config)?;
+
+        Ok(build_config)
+    }
+
+    fn validate(&self, container_src: &str, config: &Settings) -> Result<(), BuildConfigError> {
+        let invalid = |message: String| BuildConfigError::Invalid {
+            file: self.source.clone(),
+            message,
+        };
+
+        if let Some(dockerfile) = &self.dockerfile {
+            let escapes_repo = Path::new(dockerfile)
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+            if escapes_repo {
+                return Err(invalid(format!(
+                    "dockerfile must be a path inside the repository, got {dockerfile}"
+                )));
+            }
+            if !Path::new(container_src).join(dockerfile).is_file() {
+                return Err(invalid(format!("dockerfile {dockerfile} does not exist")));
+            }
+        }
+
+        if self.port == Some(0) {
+            return Err(invalid("port must be between 1 and 65535".to_string()));
+        }
+
+        if let Some(healthcheck) = &self.healthcheck {
+            if !healthcheck.starts_with('/') || healthcheck.contains(char::is_whitespace) {
+                return Err(invalid(format!(
+                    "healthcheck must be an http path starting with /, got {healthcheck}"
+                )));
+            }
+        }
+
+        if let Some(key) = self.build_args.keys().find(|key| key.is_empty() || key.contains('=')) {
+            return Err(invalid(format!("build_args key {key:?} is not a valid name")));
+        }
+
+        if let Some(cpu) = self.resources.cpu {
+            if !(cpu > 0.0 && cpu <= config.container.cpu) {
+                return Err(invalid(format!(
+                    "resources.cpu must be greater than 0 and at most {}, got {cpu}",
+                    config.container.cpu
+                )));
+            }
+        }
+
+        if let Some(memory) = &self.resources.memory {
+            let bytes = Byte::from_str(memory)
+                .map_err(|err| invalid(format!("resources.memory {memory} is not a valid size: {err}")))?
+                .get_bytes() as i64;
+            let limit = config.container_memory_bytes().unwrap_or(256 * 1024 * 1024);
+            if bytes == 0 || bytes > limit {
+                return Err(invalid(format!(
+                    "resources.memory must be greater than 0 and at most {}, got {memory}",
+                    config.container.memory
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn dockerfile(&self) -> &str {
+        self.dockerfile.as_deref().unwrap_or(DEFAULT_DOCKERFILE)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    pub fn memory_bytes(&self, config: &Settings) -> i64 {
+        self.resources
+            .memory
+            .as_ref()
+            .and_then(|memory| Byte::from_str(memory).ok())
+            .map(|memory| memory.get_bytes() as i64)
+            .unwrap_or_else(|| config.container_memory_bytes().unwrap_or(256 * 1024 * 1024))
+    }
+
+    pub fn cpu_quota(&self, config: &Settings) -> i64 {
+        match self.resources.cpu {
+            Some(cpu) => (cpu * config.container_cpu_period() as f64) as i64,
+            None => config.container_cpu_quota(),
+        }
+    }
+}
+
+/// Settings a build ran with, stored on the build so two builds of the same commit can be
+/// compared. Environ values that look like credentials are masked.
+#[derive(Serialize, Debug)]
+pub struct ConfigSnapshot {
+    /// `dockerfile`, `generated` when the repo has no Dockerfile, or `static`
+    pub builder: &'static str,
+    /// build config file the settings were read from
+    pub config_file: Option<String>,
+    pub dockerfile: Option<String>,
+    pub build_command: Option<String>,
+    pub output_dir: Option<String>,
+    /// names only, values may come from the environs
+    pub build_args: BTreeSet<String>,
+    /// names only, the snapshot is kept with the build and never holds a value
+    pub environs: BTreeSet<String>,
+    pub port: Option<u16>,
+    pub healthcheck: Option<String>,
+    pub memory_bytes: i64,
+    pub cpu_period: i64,
+    pub cpu_quota: i64,
+    pub timeout_seconds: u64,
+    /// the build config couldn't be loaded, the build fails on the same error
+    pub config_error: Option<String>,
+}
+
+impl ConfigSnapshot {
+    /// Reads the settings the same way the build is about to, `container_src` has to be checked
+    /// out already
+    pub fn capture(
+        build_type: &str,
+        build_command: Option<&str>,
+        output_dir: Option<&str>,
+        container_src: &str,
+        environs: &serde_json::Value,
+        config: &Settings,
+    ) -> Self {
+        let environs = environ::pairs(environs);
+
+        let mut snapshot = Self {
+            builder: "static",
+            config_file: None,
+            dockerfile: None,
+            build_command: None,
+            output_dir: None,
+            build_args: BTreeSet::new(),
+            environs: environs.iter().map(|(key, _)| key.clone()).collect(),
+            port: None,
+            healthcheck: None,
+            memory_bytes: config.container_memory_bytes().unwrap_or(256 * 1024 * 1024),
+            cpu_period: config.container_cpu_period(),
+            cpu_quota: config.container_cpu_quota(),
+            timeout_seconds: config.build.timeout as u64 / 1000,
+            config_error: None,
+        };
+
+        // static builds only run the build command, the config file doesn't apply to them
+        if build_type == "static" {
+            snapshot.build_command = build_command.map(str::to_string);
+            snapshot.output_dir = output_dir.map(str::to_string);
+            return snapshot;
+        }
+
+        let build_config = match BuildConfig::load(container_src, config) {
+            Ok(build_config) => build_config,
+            Err(err) => {
+                snapshot.builder = "dockerfile";
+                snapshot.config_error = Some(err.to_string());
+                return snapshot;
+            }
+        };
+
+        let dockerfile = build_config.dockerfile().to_string();
+        match Path::new(container_src).join(&dockerfile).exists() {
+            true => {
+                snapshot.builder = "dockerfile";
+                snapshot.dockerfile = Some(dockerfile);
+                snapshot.build_args = build_config
+                    .build_args
+                    .keys()
+                    .cloned()
+                    .chain(environs.into_iter().map(|(key, _)| key))
+                    .collect();
+            }
+            false => snapshot.builder = "generated",
+        }
+
+        snapshot.config_file = Some(build_config.source.clone()).filter(|source| !source.is_empty());
+        snapshot.port = Some(build_config.port());
+        snapshot.healthcheck = build_config.healthcheck.clone();
+        snapshot.memory_bytes = build_config.memory_bytes(config);
+        snapshot.cpu_quota = build_config.cpu_quota(config);
+
+        snapshot
+    }
+}
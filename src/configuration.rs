@@ -8,11 +8,11 @@ use std::{
 use axum_session::SessionConfig;
 use byte_unit::Byte;
 use chrono::Duration;
-use config::{Config, ConfigError};
-use serde::Deserialize;
+use config::{builder::DefaultState, Config, ConfigBuilder, ConfigError};
+use serde::{Deserialize, Serialize, Serializer};
 use sqlx::postgres::PgConnectOptions;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
@@ -20,15 +20,41 @@ pub struct Settings {
     pub auth: AuthSettings,
     pub build: BuilderSettings,
     pub container: ContainerSettings,
+    pub ratelimit: RateLimitSettings,
+    pub health: HealthSettings,
+    pub sleep: SleepSettings,
+    pub environ: EnvironSettings,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct BuilderSettings {
     pub max: usize,
+    /// waiting builds past which pushes are turned away, 0 for no limit
+    pub maxqueue: usize,
+    /// builds of one owner running at once, 0 for no limit
+    pub maxperowner: usize,
     pub timeout: usize,
+    /// in days, finished builds older than this are pruned
+    pub retention: i32,
+    /// builds per project kept regardless of age
+    pub keep: i64,
+    /// in minutes
+    pub pruneinterval: u64,
+    /// in seconds, how long running builds may finish when the server shuts down
+    pub shutdowngrace: u64,
+    /// image the build command of static projects runs in
+    pub staticimage: String,
+    /// where built static sites are served from
+    pub staticroot: String,
+    /// in seconds, for the build command of static projects
+    pub commandtimeout: u64,
+    /// globs of base images a Dockerfile may use, empty allows any
+    pub baseimages: Vec<String>,
+    /// levels of nested submodules fetched for projects that use them, 0 turns them off
+    pub submoduledepth: usize,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
@@ -38,9 +64,10 @@ pub struct ApplicationSettings {
     pub secure: bool,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DatabaseSettings {
     pub user: String,
+    #[serde(serialize_with = "redact")]
     pub password: String,
     pub host: String,
     pub port: u16,
@@ -48,14 +75,26 @@ pub struct DatabaseSettings {
     pub timeout: u64,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GitSettings {
     pub base: String,
     pub auth: bool,
+    /// globs left out of tree listings when asked for with `ignore=default`
+    pub treeignore: Vec<String>,
+    /// path to the git executable, `git` looks it up on PATH
+    pub binary: String,
+    /// most entries a page of a tree listing can have
+    pub treemaxentries: usize,
+    /// most entries a recursive tree listing returns before it's cut off
+    pub treemaxrecursive: usize,
+    /// accept tokens stored before they were hashed, each one is hashed on its first use
+    pub plaintexttokens: bool,
+    /// raw downloads of files larger than this many bytes are streamed instead of buffered
+    pub rawstreamthreshold: usize,
 }
 
 // TODO: _ doesn't work for env vars
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct AuthSettings {
     pub sso: bool,
     /// in hours
@@ -69,14 +108,86 @@ pub struct AuthSettings {
     pub maxlifespan: i64,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ContainerSettings {
     pub cpu: f64,
     pub memory: String,
     pub swap: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// tree, blob, commits and refs
+    pub read: RouteLimitSettings,
+    /// archive and diff
+    pub heavy: RouteLimitSettings,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RouteLimitSettings {
+    /// requests allowed at once before the refill rate kicks in
+    pub burst: u32,
+    pub perminute: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HealthSettings {
+    pub enabled: bool,
+    /// in seconds, between probes of every deployed project
+    pub interval: u64,
+    /// requested on each project unless its build config sets a healthcheck
+    pub path: String,
+    /// in milliseconds, a probe taking longer counts as unhealthy
+    pub timeout: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SleepSettings {
+    pub enabled: bool,
+    /// in seconds without traffic before a container is stopped, projects can override it
+    pub idletimeout: u64,
+    /// in seconds, between checks of every running project
+    pub interval: u64,
+    /// received bytes in one interval that count as traffic, health checks stay below it
+    pub minbytes: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EnvironSettings {
+    /// base64 of the 32 byte key environment variable values are encrypted with, stored in
+    /// plain text while empty
+    #[serde(serialize_with = "redact")]
+    pub key: String,
+    /// stored with each value, bump it when the key changes
+    pub keyversion: u32,
+    /// `<version>:<key>` of earlier keys, to read values stored before a rotation
+    #[serde(serialize_with = "redact_all")]
+    pub previouskeys: Vec<String>,
+}
+
+/// Secrets are written out as a placeholder so a serialized `Settings` is safe to log
+fn redact<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match value.is_empty() {
+        true => serializer.serialize_str(""),
+        false => serializer.serialize_str("[REDACTED]"),
+    }
+}
+
+fn redact_all<S: Serializer>(values: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|_| "[REDACTED]"))
+}
+
 pub fn get_configuration() -> Result<Settings, ConfigError> {
+    defaults()?
+        .add_source(config::File::with_name("configuration"))
+        .add_source(config::Environment::default().separator("_"))
+        .build()?
+        .try_deserialize::<Settings>()
+}
+
+/// Every setting that has a default, before the configuration file and the environment
+pub(crate) fn defaults() -> Result<ConfigBuilder<DefaultState>, ConfigError> {
     Config::builder()
         .set_default("application.port", 8080)?
         .set_default("application.host", "0.0.0.0")?
@@ -92,6 +203,15 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .set_default("database.timeout", 20)?
         .set_default("git.base", "./git-repo")?
         .set_default("git.auth", true)?
+        .set_default("git.binary", "git")?
+        .set_default(
+            "git.treeignore",
+            vec!["**/node_modules", "**/vendor", "**/.venv", "**/venv", "**/__pycache__", "**/staticfiles"],
+        )?
+        .set_default("git.treemaxentries", 5000)?
+        .set_default("git.treemaxrecursive", 10_000)?
+        .set_default("git.plaintexttokens", false)?
+        .set_default("git.rawstreamthreshold", 1024 * 1024)?
         .set_default("auth.sso", true)?
         .set_default("auth.lifespan", 24 * 7)?
         .set_default("auth.cookiename", "session")?
@@ -100,9 +220,36 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         .set_default("auth.secure", true)?
         .set_default("auth.maxlifespan", 365)?
         .set_default("build.timeout", 120000)?
+        .set_default("build.maxqueue", 100)?
+        .set_default("build.maxperowner", 2)?
+        .set_default("build.retention", 30)?
+        .set_default("build.keep", 20)?
+        .set_default("build.pruneinterval", 60)?
+        .set_default("build.shutdowngrace", 30)?
+        .set_default("build.staticimage", "node:20-alpine")?
+        .set_default("build.staticroot", "./static-sites")?
+        .set_default("build.commandtimeout", 100)?
+        .set_default("build.baseimages", Vec::<String>::new())?
+        .set_default("build.submoduledepth", 2)?
         .set_default("container.cpu", 0.5)?
         .set_default("container.memory", "256M")?
         .set_default("container.swap", "320M")?
+        .set_default("ratelimit.enabled", true)?
+        .set_default("ratelimit.read.burst", 30)?
+        .set_default("ratelimit.read.perminute", 120)?
+        .set_default("ratelimit.heavy.burst", 5)?
+        .set_default("ratelimit.heavy.perminute", 10)?
+        .set_default("health.enabled", true)?
+        .set_default("health.interval", 60)?
+        .set_default("health.path", "/")?
+        .set_default("health.timeout", 5000)?
+        .set_default("sleep.enabled", false)?
+        .set_default("sleep.idletimeout", 1800)?
+        .set_default("sleep.interval", 60)?
+        .set_default("sleep.minbytes", 8192)?
+        .set_default("environ.key", "")?
+        .set_default("environ.keyversion", 1)?
+        .set_default("environ.previouskeys", Vec::<String>::new())?
         .set_default(
             "builder.max",
             available_parallelism()
@@ -110,14 +257,15 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
                 .get() as i32
                 - 1,
         )?
-        .set_default("builder.cpums", 100000)?
-        .add_source(config::File::with_name("configuration"))
-        .add_source(config::Environment::default().separator("_"))
-        .build()?
-        .try_deserialize::<Settings>()
+        .set_default("builder.cpums", 100000)
 }
 
 impl Settings {
+    /// Effective configuration with secrets redacted, for logs and the admin api
+    pub fn sanitized(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
     pub fn connection_options(&self) -> PgConnectOptions {
         PgConnectOptions::new()
             .host(&self.database.host)
@@ -189,3 +337,34 @@ impl Settings {
         100000
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_environ_settings_hide_the_keys() {
+        let settings = EnvironSettings {
+            key: "c2VjcmV0LWtleQ==".to_string(),
+            keyversion: 2,
+            previouskeys: vec!["1:b2xkLWtleQ==".to_string()],
+        };
+
+        let json = serde_json::to_value(&settings).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({ "key": "[REDACTED]", "keyversion": 2, "previouskeys": ["[REDACTED]"] }),
+        );
+    }
+
+    #[test]
+    fn an_empty_secret_stays_visibly_empty() {
+        let settings = EnvironSettings { key: String::new(), keyversion: 1, previouskeys: Vec::new() };
+
+        let json = serde_json::to_value(&settings).unwrap();
+
+        assert_eq!(json["key"], "");
+        assert_eq!(json["previouskeys"], serde_json::json!([]));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, pagination::{Paginated, PaginationParams}, startup::AppState};
 use axum::extract::State;
+use chrono::{DateTime, Utc};
 use axum::response::Response;
 use hyper::{Body, StatusCode};
 use serde::Serialize;
@@ -11,15 +12,22 @@ struct Project {
     id: Uuid,
     name: String,
     owner_name: String,
+    health_status: String,
+    last_checked_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Debug)]
 struct DashboardProjectResponse {
-    data: Vec<Project>,
+    #[serde(flatten)]
+    projects: Paginated<Project>,
     owned_count: i32,
     shared_count: i32,
 }
-pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> Response<Body> {
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    pagination: PaginationParams,
+) -> Response<Body> {
     let Some(user) = auth.current_user else {
         return Response::builder()
             .status(StatusCode::UNAUTHORIZED)
@@ -30,16 +38,20 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
 
     // Get projects user owns OR is shared with
     let projects_result = sqlx::query(
-        r#"SELECT DISTINCT projects.id, projects.name AS project, project_owners.name AS owner
+        r#"SELECT DISTINCT projects.id, projects.name AS project, project_owners.name AS owner,
+             projects.health_status, projects.last_checked_at
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            LEFT JOIN users_owners ON project_owners.id = users_owners.owner_id
            LEFT JOIN project_shares ON projects.id = project_shares.project_id
            WHERE users_owners.user_id = $1 OR project_shares.user_id = $1
            ORDER BY projects.name ASC
+           LIMIT $2 OFFSET $3
         "#,
     )
     .bind(user.id)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
     .fetch_all(&pool)
     .await;
 
@@ -60,9 +72,37 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
             id: record.get::<Uuid, _>("id"),
             name: record.get::<String, _>("project"),
             owner_name: record.get::<String, _>("owner"),
+            health_status: record.get::<String, _>("health_status"),
+            last_checked_at: record.get::<Option<DateTime<Utc>>, _>("last_checked_at"),
         }
     }).collect();
 
+    // Get total of projects user owns OR is shared with, across all pages
+    let total_result = sqlx::query_as::<_, (i64,)>(
+        r#"SELECT COUNT(DISTINCT projects.id)
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN users_owners ON project_owners.id = users_owners.owner_id
+           LEFT JOIN project_shares ON projects.id = project_shares.project_id
+           WHERE users_owners.user_id = $1 OR project_shares.user_id = $1
+        "#,
+    )
+    .bind(user.id)
+    .fetch_one(&pool)
+    .await;
+
+    let total = match total_result {
+        Ok(record) => record.0,
+        Err(err) => {
+            tracing::error!(?err, "Can't count projects: Failed to query database");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"message": "Failed to query database"}"#))
+                .unwrap();
+        }
+    };
+
     // Get owned projects count
     let owned_count_result = sqlx::query_as::<_, (i32,)>(
         r#"SELECT COUNT(*)::int as count
@@ -81,10 +121,10 @@ pub async fn get(auth: Auth, State(AppState { pool, .. }): State<AppState>) -> R
         Err(_) => 0,
     };
 
-    let shared_count = projects.len() as i32 - owned_count;
+    let shared_count = total as i32 - owned_count;
 
     let json = serde_json::to_string(&DashboardProjectResponse {
-        data: projects,
+        projects: Paginated::new(projects, &pagination, total),
         owned_count,
         shared_count,
     }).unwrap();
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How long the receiving end gets to answer, a slow one only delays the notification
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body posted to a project's deploy webhook once a build finished
+#[derive(Serialize, Debug)]
+pub struct DeployEvent {
+    pub owner: String,
+    pub repo: String,
+    pub build_id: Uuid,
+    /// `successful` or `failed`
+    pub status: &'static str,
+    /// set when the build was deployed
+    pub subdomain: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// `sha256=<hex>` of the body, the same format the inbound trigger webhook checks
+fn sign(secret: &str, body: &[u8]) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(body);
+    Some(format!("sha256={}", HEXLOWER.encode(&mac.finalize().into_bytes())))
+}
+
+/// Tells the project's deploy webhook, if it has one, how a build ended. Runs on its own task
+/// so the build slot is released without waiting for it, failures are only logged.
+pub fn notify(pool: PgPool, event: DeployEvent) {
+    tokio::spawn(async move {
+        let webhook = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            r#"SELECT projects.deploy_webhook_url, projects.deploy_webhook_secret
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE project_owners.name = $1
+                 AND projects.name = $2
+            "#,
+        )
+        .bind(&event.owner)
+        .bind(&event.repo)
+        .fetch_optional(&pool)
+        .await;
+
+        let (url, secret) = match webhook {
+            Ok(Some((Some(url), secret))) => (url, secret),
+            Ok(_) => return,
+            Err(err) => {
+                tracing::warn!(?err, build_id = %event.build_id, "Can't send deploy webhook: Failed to query database");
+                return;
+            }
+        };
+
+        let body = serde_json::to_vec(&event).unwrap();
+        let mut request = reqwest::Client::new()
+            .post(&url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .header("Content-Type", "application/json");
+        if let Some(signature) = secret.as_deref().and_then(|secret| sign(secret, &body)) {
+            request = request.header("X-PWS-Signature", signature);
+        }
+
+        match request.body(body).send().await {
+            Ok(response) if response.status().is_success() => {
+                tracing::info!(build_id = %event.build_id, url, "Deploy webhook sent");
+            }
+            Ok(response) => {
+                tracing::warn!(build_id = %event.build_id, url, status = %response.status(), "Deploy webhook was rejected");
+            }
+            Err(err) => {
+                tracing::warn!(?err, build_id = %event.build_id, url, "Failed to send deploy webhook");
+            }
+        }
+    });
+}
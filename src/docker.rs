@@ -11,14 +11,51 @@ use bollard::{
     service::{HostConfig, NetworkContainer, RestartPolicy, RestartPolicyNameEnum},
     Docker,
 };
-use crate::{dockerfile_templates::DjangoDockerfile, get_env, configuration::Settings};
+use crate::{build_cache::{DependencyCache, CACHE_ID_BUILD_ARG}, build_config::BuildConfig, live_log, dockerfile::{self, BaseImageAllowlist}, dockerfile_templates::DjangoDockerfile, get_env, configuration::Settings, projects::environ};
 use sqlx::PgPool;
-use tokio::process::Command;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::{Child, Command},
+};
 
 pub struct DockerContainer {
     pub ip: String,
     pub port: i32,
     pub build_log: String,
+    pub image_size_bytes: Option<i64>,
+    pub layer_count: Option<i32>,
+}
+
+/// Waits for `docker build`, sending its progress to the live log of the build a line at a
+/// time. Returns the exit status and the progress output, which buildkit writes to stderr.
+/// The secret values are redacted from both, since build args show up in the output.
+async fn wait_with_live_log(mut child: Child, secrets: &[String]) -> std::io::Result<(std::process::ExitStatus, String)> {
+    // drained so a chatty stdout can't fill the pipe and stall the build
+    let stdout = child.stdout.take().map(|mut stdout| {
+        tokio::spawn(async move {
+            let mut sink = Vec::new();
+            let _ = stdout.read_to_end(&mut sink).await;
+        })
+    });
+
+    let mut output = String::new();
+    if let Some(stderr) = child.stderr.take() {
+        let mut stderr = BufReader::new(stderr);
+        let mut line = Vec::new();
+        while stderr.read_until(b'\n', &mut line).await? > 0 {
+            let text = environ::redact(&String::from_utf8_lossy(&line), secrets);
+            live_log::push(text.trim_end_matches('\n'));
+            output.push_str(&text);
+            line.clear();
+        }
+    }
+
+    let status = child.wait().await?;
+    if let Some(stdout) = stdout {
+        let _ = stdout.await;
+    }
+
+    Ok((status, output))
 }
 
 #[tracing::instrument(skip(pool))]
@@ -27,6 +64,7 @@ pub async fn build_docker(
     project_name: &str,
     container_name: &str,
     container_src: &str,
+    cache: Option<&DependencyCache>,
     pool: PgPool,
     config: &Settings,
 ) -> Result<DockerContainer> {
@@ -76,29 +114,59 @@ pub async fn build_docker(
             })?;
     };
 
-    // Get user environment variables for Django
-    let envs = sqlx::query!(
-        r#"SELECT environs 
-        FROM projects
-        JOIN project_owners ON projects.owner_id = project_owners.id
-        WHERE projects.name = $1 AND project_owners.name = $2"#,
-        project_name, owner,
+    // Get user environment variables for Django, and which of them are kept out of the log
+    let (environs, secret_keys) = sqlx::query_as::<_, (serde_json::Value, Vec<String>)>(
+        r#"SELECT projects.environs, projects.secret_environs
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1 AND project_owners.name = $2"#,
     )
+    .bind(project_name)
+    .bind(owner)
     .fetch_one(&pool)
     .await
     .map_err(|err| {
         tracing::error!("Failed to query database: {}", err);
         err
     })?;
+    let secrets = environ::secret_values(&environs, &secret_keys);
+
+    // errors end up in the build log so users can fix their config file
+    let build_config = BuildConfig::load(container_src, config).map_err(|err| {
+        tracing::error!(container_name, "Invalid build config: {}", err);
+        anyhow::anyhow!(err.to_string())
+    })?;
+
+    // build args from the config file, project environs override the same keys
+    let mut build_args = build_config.build_args.clone();
+    build_args.extend(environ::pairs(&environs));
 
     tracing::info!("BUILDING START");
 
     let build_log = match std::path::Path::new(container_src)
-        .join("Dockerfile")
+        .join(build_config.dockerfile())
         .exists()
     {
         true => {
             tracing::debug!(container_name, "Build using existing dockerfile");
+
+            // only the allowlist fails a build, other findings are for the validate endpoint
+            let dockerfile_path = std::path::Path::new(container_src).join(build_config.dockerfile());
+            let content = std::fs::read_to_string(&dockerfile_path)?;
+            let allowlist = BaseImageAllowlist::new(&config.build.baseimages)?;
+            let rejected = dockerfile::analyze(&content, &allowlist)
+                .base_images
+                .into_iter()
+                .filter(|base_image| !base_image.allowed)
+                .map(|base_image| base_image.image)
+                .collect::<Vec<_>>();
+            if !rejected.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Base image {} is not allowed on this server",
+                    rejected.join(", ")
+                ));
+            }
+
             // build from existing Dockerfile with user env vars as build args
             let mut cmd = Command::new("docker");
             let mut args = vec![
@@ -109,23 +177,28 @@ pub async fn build_docker(
                 image_name.clone(),
                 "-f".to_string(),
                 std::path::Path::new(container_src)
-                    .join("Dockerfile")
+                    .join(build_config.dockerfile())
                     .to_str()
                     .unwrap()
                     .to_string(),
             ];
             
             // Add environment variables as build args
-            if let Some(env_map) = envs.environs.as_object() {
-                for (key, value) in env_map {
-                    args.push("--build-arg".to_string());
-                    args.push(format!("{}={}", key, value.as_str().unwrap_or("")));
-                }
-                tracing::debug!(container_name, "Added {} build args", env_map.len());
+            for (key, value) in &build_args {
+                args.push("--build-arg".to_string());
+                args.push(format!("{}={}", key, value));
+            }
+            tracing::debug!(container_name, "Added {} build args", build_args.len());
+
+            // for `RUN --mount=type=cache,id=${PWS_CACHE_ID},...` in the project Dockerfile
+            if let Some(cache) = cache {
+                args.push("--build-arg".to_string());
+                args.push(format!("{CACHE_ID_BUILD_ARG}={}", cache.id));
             }
             
             args.push(container_src.to_string());
             cmd.args(&args)
+            .env("DOCKER_BUILDKIT", "1")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
@@ -135,30 +208,23 @@ pub async fn build_docker(
                 err
             })?;
 
-            let output = child.wait_with_output().await.map_err(|err| {
+            let (status, output) = wait_with_live_log(child, &secrets).await.map_err(|err| {
                 tracing::error!("Failed to wait for docker build: {}", err);
                 err
             })?;
 
-            if !output.status.success() {
-                return Err(anyhow::anyhow!(String::from_utf8(output.stderr).unwrap()));
+            if !status.success() {
+                return Err(anyhow::anyhow!(output));
             }
-            String::from_utf8(output.stderr).unwrap()
+            output
         }
         false => {
             tracing::debug!(container_name, "Generating efficient Django Dockerfile");
             
             // Generate our efficient multi-stage Dockerfile with environment variables
-            let environment_strings = match envs.environs.as_object() {
-                Some(map) => {
-                    map.into_iter().map(|(key, value)| {
-                        format!("{}={}", key, value.as_str().unwrap_or(""))
-                    }).collect::<Vec<_>>()
-                },
-                None => Vec::new(),
-            };
-            
-            let django_dockerfile = DjangoDockerfile::new().with_environment(environment_strings);
+            let django_dockerfile = DjangoDockerfile::new()
+                .with_environment(environ::pairs(&environs))
+                .with_cache_id(cache.map(|cache| cache.id.clone()));
             let dockerfile_content = django_dockerfile.generate();
             
             // Write Dockerfile to temporary file (don't pollute project directory)
@@ -185,6 +251,7 @@ pub async fn build_docker(
                 dockerfile_path.to_str().unwrap(),
                 container_src,
             ])
+            .env("DOCKER_BUILDKIT", "1")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
@@ -194,7 +261,7 @@ pub async fn build_docker(
                 err
             })?;
 
-            let output = child.wait_with_output().await.map_err(|err| {
+            let (status, output) = wait_with_live_log(child, &secrets).await.map_err(|err| {
                 tracing::error!("Failed to wait for docker build: {}", err);
                 err
             })?;
@@ -206,11 +273,11 @@ pub async fn build_docker(
                 tracing::debug!("Cleaned up temporary Dockerfile: {:?}", dockerfile_path);
             }
 
-            if !output.status.success() {
-                return Err(anyhow::anyhow!(String::from_utf8(output.stderr).unwrap()));
+            if !status.success() {
+                return Err(anyhow::anyhow!(output));
             }
             
-            String::from_utf8(output.stderr).unwrap()
+            output
         }
     };
 
@@ -229,6 +296,21 @@ pub async fn build_docker(
 
     let _image = images.first().ok_or(anyhow::anyhow!("No image found"))?;
 
+    // image size and layer count are informational, a failed inspect shouldn't fail the build
+    let (image_size_bytes, layer_count) = match docker.inspect_image(&image_name).await {
+        Ok(image) => (
+            image.size,
+            image
+                .root_fs
+                .and_then(|root_fs| root_fs.layers)
+                .map(|layers| layers.len() as i32),
+        ),
+        Err(err) => {
+            tracing::warn!("Failed to inspect image {}: {}", image_name, err);
+            (None, None)
+        }
+    };
+
     // check if container exists
     let containers = docker
         .list_containers(Some(ListContainersOptions::<String> {
@@ -312,8 +394,7 @@ pub async fn build_docker(
         }
     };
 
-    // TODO: figure out if we need make this configurable
-    let port = 80;
+    let port = build_config.port() as i32;
 
     let envs = sqlx::query!(
         r#"SELECT environs 
@@ -329,41 +410,55 @@ pub async fn build_docker(
         err
     })?;
 
-    let environment_strings = match envs.environs.as_object() {
-        Some(map) => {
-            let environment_strings = map.into_iter().map(|(key, value)| {
-                format!("{}={}", key, value.as_str().unwrap())
-            }).collect::<Vec<_>>();
+    // docker takes one `KEY=value` entry per variable and splits on the first `=`, keys are
+    // validated so a value can't introduce another variable
+    let environment_strings = match envs.environs.is_object() {
+        true => {
+            let environment_strings = environ::pairs(&envs.environs)
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>();
 
             Ok(environment_strings)
         },
-        None => {
+        false => {
             tracing::error!("Non object value passed as environment variable {}", container_name);
             Err(anyhow::anyhow!("Non object value passed as environment variable {}", container_name))
         }
     }?;
 
 
+    // Auto-add Traefik labels for PWS deployed containers with HTTPS
+    let mut labels = HashMap::from([
+        ("traefik.enable".to_string(), "true".to_string()),
+        (format!("traefik.http.routers.{}.rule", container_name), format!("Host(`{}.{}`)", container_name, get_env::domain())),
+        (format!("traefik.http.routers.{}.entrypoints", container_name), "websecure".to_string()),
+        (format!("traefik.http.routers.{}.tls", container_name), "true".to_string()),
+        (format!("traefik.http.services.{}.loadbalancer.server.port", container_name), port.to_string()),
+    ]);
+    if let Some(healthcheck) = &build_config.healthcheck {
+        labels.insert(format!("traefik.http.services.{}.loadbalancer.healthcheck.path", container_name), healthcheck.clone());
+        labels.insert(format!("traefik.http.services.{}.loadbalancer.healthcheck.interval", container_name), "10s".to_string());
+    }
+
+    let memory = build_config.memory_bytes(config);
+    let memory_swap = config.container_swap_bytes().unwrap_or(320 * 1024 * 1024).max(memory);
+    let cpu_quota = build_config.cpu_quota(config);
+
     let config: Config<String> = Config {
         image: Some(image_name.clone()),
         env: Some(environment_strings),
-        // Auto-add Traefik labels for PWS deployed containers with HTTPS
-        labels: Some(HashMap::from([
-            ("traefik.enable".to_string(), "true".to_string()),
-            (format!("traefik.http.routers.{}.rule", container_name), format!("Host(`{}.{}`)", container_name, get_env::domain())),
-            (format!("traefik.http.routers.{}.entrypoints", container_name), "websecure".to_string()),
-            (format!("traefik.http.routers.{}.tls", container_name), "true".to_string()),
-            (format!("traefik.http.services.{}.loadbalancer.server.port", container_name), "80".to_string()),
-        ])),
+        labels: Some(labels),
         host_config: Some(HostConfig {
             restart_policy: Some(RestartPolicy {
                 name: Some(RestartPolicyNameEnum::ON_FAILURE),
                 ..Default::default()
             }),
             // Resource limits from configuration - prevent resource abuse
-            memory: Some(config.container_memory_bytes().unwrap_or(256 * 1024 * 1024)),
-            memory_swap: Some(config.container_swap_bytes().unwrap_or(320 * 1024 * 1024)),
-            cpu_quota: Some(config.container_cpu_quota()),
+            // the build config may lower these, never raise them
+            memory: Some(memory),
+            memory_swap: Some(memory_swap),
+            cpu_quota: Some(cpu_quota),
             cpu_period: Some(config.container_cpu_period()),
             ..Default::default()
         }),
@@ -467,9 +562,40 @@ pub async fn build_docker(
             err
         });
 
+    let build_log = match build_config.source.is_empty() {
+        true => build_log,
+        false => format!("Using build config from {}\n{}", build_config.source, build_log),
+    };
+
     Ok(DockerContainer {
         ip,
         port,
         build_log,
+        image_size_bytes,
+        layer_count,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn secret_build_args_are_redacted_from_the_build_log() {
+        // what buildkit echoes back, the arg on the command line and a step printing it
+        let child = Command::new("sh")
+            .args(["-c", "echo '#5 [2/3] RUN echo $API_TOKEN' >&2; echo '#5 0.2 tok-1234-secret' >&2; exit 1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let (status, output) = wait_with_live_log(child, &["tok-1234-secret".to_string()]).await.unwrap();
+
+        assert!(!status.success());
+        assert!(!output.contains("tok-1234-secret"));
+        assert!(output.contains(&format!("#5 0.2 {}", environ::MASKED_VALUE)));
+        assert!(output.contains("RUN echo $API_TOKEN"));
+    }
+}
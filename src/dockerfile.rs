@@ -0,0 +1,246 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+
+/// Port the proxy sends traffic to unless the build config says otherwise
+pub const DEFAULT_PORT: u16 = 80;
+
+const INSTRUCTIONS: &[&str] = &[
+    "ADD", "ARG", "CMD", "COPY", "ENTRYPOINT", "ENV", "EXPOSE", "FROM", "HEALTHCHECK", "LABEL",
+    "MAINTAINER", "ONBUILD", "RUN", "SHELL", "STOPSIGNAL", "USER", "VOLUME", "WORKDIR",
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// 1-based line the instruction starts on
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BaseImage {
+    pub image: String,
+    pub line: usize,
+    pub allowed: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DockerfileReport {
+    pub valid: bool,
+    pub base_images: Vec<BaseImage>,
+    pub exposed_ports: Vec<u16>,
+    pub findings: Vec<Finding>,
+}
+
+/// Base images builds may start from, as globs matched against the full image reference.
+/// No globs allows any image.
+pub struct BaseImageAllowlist {
+    set: Option<GlobSet>,
+}
+
+impl BaseImageAllowlist {
+    pub fn new(globs: &[String]) -> Result<Self, globset::Error> {
+        if globs.is_empty() {
+            return Ok(Self { set: None });
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(Glob::new(glob)?);
+        }
+
+        Ok(Self {
+            set: Some(builder.build()?),
+        })
+    }
+
+    pub fn allows(&self, image: &str) -> bool {
+        match &self.set {
+            None => true,
+            // an image without a tag is the same as asking for latest
+            Some(set) => {
+                set.is_match(image) || (!has_tag(image) && set.is_match(format!("{image}:latest")))
+            }
+        }
+    }
+}
+
+fn has_tag(image: &str) -> bool {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    name.contains(':') || name.contains('@')
+}
+
+/// Instructions with their continuation lines joined, comments and blank lines dropped
+fn instructions(content: &str) -> Vec<(usize, String)> {
+    let mut instructions = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+
+        // docker skips these even in the middle of a continued instruction
+        if trimmed.starts_with('#') || trimmed.is_empty() {
+            continue;
+        }
+
+        let (start, mut text) = current.take().unwrap_or((index + 1, String::new()));
+        match trimmed.strip_suffix('\\') {
+            Some(continued) => {
+                text.push_str(continued);
+                text.push(' ');
+                current = Some((start, text));
+            }
+            None => {
+                text.push_str(trimmed);
+                instructions.push((start, text));
+            }
+        }
+    }
+
+    if let Some(instruction) = current {
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+/// Checks a Dockerfile the way a build would see it, without building it
+pub fn analyze(content: &str, allowlist: &BaseImageAllowlist) -> DockerfileReport {
+    let mut findings = Vec::new();
+    let mut base_images = Vec::new();
+    let mut exposed_ports = Vec::new();
+    let mut stages: Vec<String> = Vec::new();
+    let mut seen_from = false;
+    let mut has_command = false;
+
+    let mut finding = |severity, line, message: String| findings.push(Finding { severity, line, message });
+
+    for (line, instruction) in instructions(content) {
+        let mut parts = instruction.split_whitespace();
+        let keyword = parts.next().unwrap_or_default().to_uppercase();
+        let args = parts.collect::<Vec<_>>();
+
+        if !INSTRUCTIONS.contains(&keyword.as_str()) {
+            finding(Severity::Error, Some(line), format!("Unknown instruction {keyword}"));
+            continue;
+        }
+
+        if !seen_from && keyword != "FROM" && keyword != "ARG" {
+            finding(Severity::Error, Some(line), format!("{keyword} comes before the first FROM"));
+        }
+
+        match keyword.as_str() {
+            "FROM" => {
+                seen_from = true;
+
+                let mut args = args.iter().filter(|arg| !arg.starts_with("--"));
+                let Some(image) = args.next() else {
+                    finding(Severity::Error, Some(line), "FROM is missing an image".to_string());
+                    continue;
+                };
+
+                // building on an earlier stage isn't a new base image
+                let is_stage = stages.contains(&image.to_lowercase());
+
+                if let (Some(as_keyword), Some(alias)) = (args.next(), args.next()) {
+                    if as_keyword.eq_ignore_ascii_case("as") {
+                        stages.push(alias.to_lowercase());
+                    }
+                }
+
+                if is_stage {
+                    continue;
+                }
+
+                if image.contains('$') {
+                    finding(
+                        Severity::Warning,
+                        Some(line),
+                        format!("Base image {image} uses a build argument, it can't be checked before the build"),
+                    );
+                } else if (*image != "scratch" && !has_tag(image)) || image.ends_with(":latest") {
+                    finding(
+                        Severity::Warning,
+                        Some(line),
+                        format!("Base image {image} isn't pinned to a version, builds may break when it is updated"),
+                    );
+                }
+
+                let allowed = *image == "scratch" || image.contains('$') || allowlist.allows(image);
+                if !allowed {
+                    finding(Severity::Error, Some(line), format!("Base image {image} is not allowed on this server"));
+                }
+
+                base_images.push(BaseImage {
+                    image: image.to_string(),
+                    line,
+                    allowed,
+                });
+            }
+            "EXPOSE" => {
+                for arg in args {
+                    let port = arg.split('/').next().unwrap_or(arg);
+                    match port.parse::<u16>() {
+                        Ok(port) if port > 0 => exposed_ports.push(port),
+                        _ if port.contains('$') => finding(
+                            Severity::Warning,
+                            Some(line),
+                            format!("Exposed port {arg} uses a variable, it can't be checked before the build"),
+                        ),
+                        _ => finding(Severity::Error, Some(line), format!("Invalid port {arg}")),
+                    }
+                }
+            }
+            "CMD" | "ENTRYPOINT" => has_command = true,
+            _ => {}
+        }
+    }
+
+    if !seen_from {
+        finding(Severity::Error, None, "Dockerfile has no FROM instruction".to_string());
+    }
+
+    if !has_command {
+        finding(
+            Severity::Warning,
+            None,
+            "No CMD or ENTRYPOINT, the container only runs what the base image starts".to_string(),
+        );
+    }
+
+    match exposed_ports.as_slice() {
+        [] => finding(
+            Severity::Info,
+            None,
+            format!("No EXPOSE, requests are sent to port {DEFAULT_PORT} unless `port` is set in .pws.yaml"),
+        ),
+        [port] if *port != DEFAULT_PORT => finding(
+            Severity::Warning,
+            None,
+            format!("Port {port} is exposed but requests are sent to port {DEFAULT_PORT}, set `port: {port}` in .pws.yaml"),
+        ),
+        [_] => {}
+        _ => finding(
+            Severity::Warning,
+            None,
+            "More than one port is exposed, requests only go to the one set as `port` in .pws.yaml".to_string(),
+        ),
+    }
+
+    let valid = !findings.iter().any(|finding| finding.severity == Severity::Error);
+
+    DockerfileReport {
+        valid,
+        base_images,
+        exposed_ports,
+        findings,
+    }
+}
@@ -1,20 +1,35 @@
 pub struct DjangoDockerfile {
-    pub environment_vars: Vec<String>,
+    pub environment_vars: Vec<(String, String)>,
+    /// BuildKit cache mount the pip downloads are kept in between builds
+    pub cache_id: Option<String>,
 }
 
 impl DjangoDockerfile {
     pub fn new() -> Self {
         Self {
             environment_vars: Vec::new(),
+            cache_id: None,
         }
     }
     
-    pub fn with_environment(mut self, env_vars: Vec<String>) -> Self {
+    pub fn with_environment(mut self, env_vars: Vec<(String, String)>) -> Self {
         self.environment_vars = env_vars;
         self
     }
 
+    pub fn with_cache_id(mut self, cache_id: Option<String>) -> Self {
+        self.cache_id = cache_id;
+        self
+    }
+
     pub fn generate(&self) -> String {
+        let pip_install = match &self.cache_id {
+            Some(cache_id) => format!(
+                "RUN --mount=type=cache,id={cache_id},target=/root/.cache/pip pip install -r requirements.txt"
+            ),
+            None => "RUN pip install --no-cache-dir -r requirements.txt".to_string(),
+        };
+
         let mut dockerfile = String::from(r#"
 # Multi-stage build for smaller image
 FROM python:3.11-alpine AS builder
@@ -26,7 +41,7 @@ RUN apk add --no-cache gcc musl-dev
 
 # Install Python packages
 COPY requirements.txt .
-RUN pip install --no-cache-dir -r requirements.txt
+{pip_install}
 
 # Runtime stage
 FROM python:3.11-alpine AS runtime
@@ -39,13 +54,13 @@ COPY --from=builder /usr/local/bin /usr/local/bin
 
 # Copy app
 COPY . .
-"#);
+"#).replace("{pip_install}", &pip_install);
 
         // Add environment variables
         if !self.environment_vars.is_empty() {
             dockerfile.push_str("\n# Environment variables\n");
-            for env_var in &self.environment_vars {
-                dockerfile.push_str(&format!("ENV {}\n", env_var));
+            for (key, value) in &self.environment_vars {
+                dockerfile.push_str(&format!("ENV {}=\"{}\"\n", key, escape_env_value(value)));
             }
         }
 
@@ -64,3 +79,12 @@ CMD ["sh", "-c", "\
     }
 
 }
+
+/// Quoted `ENV` values only need backslashes, quotes and variable expansion escaped, control
+/// characters are rejected before a value is stored
+fn escape_env_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+}
@@ -1,13 +1,16 @@
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fs::File,
-    io::Read,
-    path::Path as StdPath,
+    io::{Read, Write},
+    path::{Path as StdPath, PathBuf},
     process::{Output, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
 use argon2::{
-    password_hash::{PasswordHash, PasswordVerifier},
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use axum::{
@@ -18,24 +21,124 @@ use axum::{
     Router,
 };
 use axum_extra::routing::RouterExt;
+use lazy_static::lazy_static;
 use git2::Repository;
+use ulid::Ulid;
+use uuid::Uuid;
 use http_body::combinators::UnsyncBoxBody;
 use hyper::{
-    body::Bytes, http::response::Builder as ResponseBuilder, Body, HeaderMap, Request, StatusCode,
+    body::{Bytes, HttpBody}, http::response::Builder as ResponseBuilder, Body, HeaderMap, Request,
+    StatusCode,
 };
 
 use anyhow::Result;
-use serde::Deserialize;
-use tokio::{io::AsyncWriteExt, process::Command};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use futures_util::StreamExt;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, process::{Child, Command}};
+use tokio_util::io::ReaderStream;
 use tower_http::limit::RequestBodyLimitLayer;
 
-use crate::{configuration::Settings, queue::BuildQueueItem, startup::AppState};
+use crate::{configuration::Settings, projects::content_type::attachment, queue::{BuildPriority, BuildQueueItem}, startup::AppState, usage::refresh_repo_size};
+
+/// Config passed to every upload-pack run, the ref advertisement included so clients see the
+/// same capabilities they negotiate with. `allowFilter` enables partial clones
+/// (`--filter=blob:none`) and `allowAnySHA1InWant` lets those clients fetch the missing
+/// blobs by id afterwards.
+///
+/// Shallow clones need no config, upload-pack always offers them. Protocol v0/v1 advertise
+/// `shallow deepen-since deepen-not deepen-relative filter allow-reachable-sha1-in-want
+/// allow-tip-sha1-in-want`, v2 advertises `fetch=shallow wait-for-done filter`.
+const UPLOAD_PACK_CONFIG: &[&str] = &[
+    "-c",
+    "uploadpack.allowFilter=true",
+    "-c",
+    "uploadpack.allowAnySHA1InWant=true",
+];
+
+/// How long git gets to clean up after the client dropped mid-request before it is killed
+const RPC_ABORT_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+/// Bytes of rpc output read at once when it's streamed to the client
+const RPC_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a push waits for the queue to acknowledge its build before answering
+const ENQUEUE_REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 use data_encoding::BASE64;
 
+/// Realm of every git challenge, browsers show it on their login prompt
+const GIT_REALM: &str = "PWS Git";
+
+/// Basic challenge git prompts for credentials on. The body is for browsers opening the clone
+/// URL, git itself doesn't print it.
+fn unauthorized(reason: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("WWW-Authenticate", format!("Basic realm=\"{GIT_REALM}\", charset=\"UTF-8\""))
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(format!(
+            "{reason}\n\nThis is a git repository, log in with the project's git credentials, not your \
+             dashboard account. The username is the project owner and the password is shown on the \
+             project page, or can be regenerated there.\n"
+        )))
+        .unwrap()
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenMatch {
+    Hashed,
+    /// stored before tokens were hashed
+    Plaintext,
+    None,
+}
+
+/// Checks `token` against a stored argon2 hash. A stored value that isn't a PHC string is a
+/// token from before hashing, it only matches while those are still accepted.
+fn verify_token(token: &str, stored: &str, allow_plaintext: bool) -> TokenMatch {
+    match PasswordHash::new(stored) {
+        Ok(hash) => match Argon2::default().verify_password(token.as_bytes(), &hash) {
+            Ok(_) => TokenMatch::Hashed,
+            Err(_) => TokenMatch::None,
+        },
+        // compared in constant time so the response time doesn't tell how much of it was right
+        Err(_) if allow_plaintext && bool::from(stored.as_bytes().ct_eq(token.as_bytes())) => TokenMatch::Plaintext,
+        Err(_) => TokenMatch::None,
+    }
+}
+
+/// Hashes a token for storing in `api_token`
+pub fn hash_token(token: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(token.as_bytes(), &salt)?.to_string())
+}
+
+/// Replaces a plain text token that was just used with its hash, a failure only means it
+/// gets another try on its next use
+async fn upgrade_plaintext_token(pool: &PgPool, id: Uuid, token: &str) {
+    let hash = match hash_token(token) {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!(?err, %id, "Can't hash plain text token");
+            return;
+        }
+    };
+
+    match sqlx::query("UPDATE api_token SET token = $1, updated_at = now() WHERE id = $2")
+        .bind(hash)
+        .bind(id)
+        .execute(pool)
+        .await
+    {
+        Ok(_) => tracing::info!(%id, "Plain text token hashed"),
+        Err(err) => tracing::error!(?err, %id, "Can't hash plain text token: Failed to query database"),
+    }
+}
+
 async fn basic_auth<B>(
-    State(AppState { pool, git_auth, .. }): State<AppState>,
-    Path((_owner, repo)): Path<(String, String)>,
+    State(AppState { pool, git_auth, git_plaintext_tokens, .. }): State<AppState>,
+    // by name, some routes have more params than the owner and repo
+    Path(params): Path<HashMap<String, String>>,
     headers: HeaderMap,
     request: Request<B>,
     next: Next<B>,
@@ -44,24 +147,13 @@ async fn basic_auth<B>(
         return Ok(next.run(request).await);
     }
 
-    let auth_err = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", "Basic realm=\"git\"")
-        .body(Body::empty())
-        .unwrap();
-
-    let auth_failed = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", "Basic realm=\"failed to login\"")
-        .body(Body::empty())
-        .unwrap();
+    let auth_err = unauthorized("Authentication required.");
+    let auth_failed = unauthorized("Invalid username or password.");
 
-    let repo = match repo.ends_with(".git") {
-        true => {
-            repo.split(".git").next().unwrap_or("")
-        }.to_owned(),
-        false => format!("{repo}"),
+    let (Some(owner), Some(repo)) = (params.get("owner").cloned(), params.get("repo")) else {
+        return Err(auth_err);
     };
+    let repo = canonical_repo_name(repo);
 
     match headers.get("Authorization").and_then(|v| v.to_str().ok()) {
         None => Err(auth_err),
@@ -70,25 +162,43 @@ async fn basic_auth<B>(
             let scheme = parts.next().unwrap_or("");
             let token = parts.next().unwrap_or("");
 
-            if scheme != "Basic" {
-                return Err(auth_err);
-            }
-
-            let decoded = BASE64.decode(token.as_bytes()).unwrap();
-            let decoded = String::from_utf8(decoded).unwrap();
-            let mut parts = decoded.split(':');
-            let owner_name = parts.next().unwrap_or("");
-            let token = parts.next().unwrap_or("");
+            let (owner_name, token) = match scheme.to_ascii_lowercase().as_str() {
+                "basic" => {
+                    // the header comes straight from the client, anything malformed is just
+                    // unauthenticated
+                    let Some(decoded) = BASE64
+                        .decode(token.as_bytes())
+                        .ok()
+                        .and_then(|decoded| String::from_utf8(decoded).ok())
+                    else {
+                        return Err(auth_err);
+                    };
+                    let (owner_name, token) = decoded.split_once(':').unwrap_or((&decoded, ""));
+                    (owner_name.to_string(), token.to_string())
+                }
+                // a bare token for CI, e.g. `git -c http.extraHeader="Authorization: Bearer ..."`,
+                // the owner is the one in the url
+                "bearer" if !token.is_empty() => (owner.clone(), token.to_string()),
+                _ => return Err(auth_err),
+            };
+            let (owner_name, token) = (owner_name.as_str(), token.as_str());
 
-            let tokens = match sqlx::query!(
-                r#"SELECT projects.name AS project_name, api_token.token AS token, project_owners.name AS project_owner
+            // project tokens only authorize their own repo, owner tokens authorize every repo
+            // under that owner
+            let tokens = match sqlx::query_as::<_, (Uuid, Option<String>, String, String)>(
+                r#"SELECT api_token.id, projects.name AS project_name, api_token.token AS token, project_owners.name AS project_owner
                     FROM project_owners
                     JOIN projects ON project_owners.id = projects.owner_id
                     JOIN api_token ON projects.id = api_token.project_id
                     WHERE project_owners.name = $1
+                   UNION ALL
+                   SELECT api_token.id, NULL AS project_name, api_token.token AS token, project_owners.name AS project_owner
+                    FROM project_owners
+                    JOIN api_token ON project_owners.id = api_token.owner_id
+                    WHERE project_owners.name = $1
                 "#,
-                owner_name
             )
+            .bind(owner_name)
             .fetch_all(&pool)
             .await
             {
@@ -97,21 +207,34 @@ async fn basic_auth<B>(
                 Err(_) => return Err(auth_err),
             };
 
-            tracing::debug!("AUTH_DEBUG: Auth attempt - owner: {}, repo: {}, token: {}", owner_name, repo, token);
-            tracing::debug!("AUTH_DEBUG: Found {} tokens in database", tokens.len());
-            
-            let authenticated = tokens.iter().any(|rec| {
-                tracing::info!("Checking token - project: {}, owner: {}, stored_token: {}", rec.project_name, rec.project_owner, rec.token);
-                
-                // Use plain text comparison instead of argon2 hashing
-                let token_match = rec.token == token;
-                let authorization_match = rec.project_name == repo && rec.project_owner == owner_name;
-                
-                tracing::info!("Token match: {}, Authorization match: {}", token_match, authorization_match);
-
-                token_match && authorization_match
-            });
-            
+            tracing::debug!(owner_name, repo, tokens = tokens.len(), "Git auth attempt");
+
+            // only tokens that could authorize this repo are checked, each check is a full
+            // argon2 verification. All of them are checked without stopping at a match so the
+            // time taken doesn't tell which one it was.
+            let matched = tokens
+                .iter()
+                .filter(|(_, project_name, _, project_owner)| {
+                    project_owner == owner_name
+                        && owner == owner_name
+                        && project_name.as_ref().map_or(true, |name| *name == repo)
+                })
+                .fold(None, |matched, (id, _, stored_token, _)| {
+                    match verify_token(token, stored_token, git_plaintext_tokens) {
+                        TokenMatch::None => matched,
+                        token_match => matched.or(Some((*id, token_match))),
+                    }
+                });
+
+            let authenticated = match matched {
+                Some((id, TokenMatch::Plaintext)) => {
+                    upgrade_plaintext_token(&pool, id, token).await;
+                    true
+                }
+                Some(_) => true,
+                None => false,
+            };
+
             if !authenticated {
                 return Err(auth_failed);
             }
@@ -130,8 +253,9 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
             "/:owner/:repo/HEAD",
             get(
                 |Path((owner, repo)): Path<(String, String)>,
-                 State(AppState { base, .. }): State<AppState>| async move {
-                    get_file_text(&base, &owner, &repo, "HEAD").await
+                 State(AppState { base, .. }): State<AppState>,
+                 headers: HeaderMap| async move {
+                    get_file_text(&base, &owner, &repo, "HEAD", &headers).await
                 },
             ),
         )
@@ -139,8 +263,9 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
             "/:owner/:repo/objects/info/alternates",
             get(
                 |Path((owner, repo)): Path<(String, String)>,
-                 State(AppState { base, .. }): State<AppState>| async move {
-                    get_file_text(&base, &owner, &repo, "objects/info/alternates").await
+                 State(AppState { base, .. }): State<AppState>,
+                 headers: HeaderMap| async move {
+                    get_file_text(&base, &owner, &repo, "objects/info/alternates", &headers).await
                 },
             ),
         )
@@ -148,8 +273,9 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
             "/:owner/:repo/objects/info/http-alternates",
             get(
                 |Path((owner, repo)): Path<(String, String)>,
-                 State(AppState { base, .. }): State<AppState>| async move {
-                    get_file_text(&base, &owner, &repo, "objects/info/http-alternates").await
+                 State(AppState { base, .. }): State<AppState>,
+                 headers: HeaderMap| async move {
+                    get_file_text(&base, &owner, &repo, "objects/info/http-alternates", &headers).await
                 },
             ),
         )
@@ -157,15 +283,18 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
         .route_with_tsr(
             "/:owner/:repo/objects/info/:file",
             get(
-                |Path((owner, repo, head, file)): Path<(String, String, String, String)>,
-                 State(AppState { base, .. }): State<AppState>| async move {
-                    get_file_text(&base, &owner, &repo, format!("{}/{}", head, file).as_ref()).await
+                |Path((owner, repo, file)): Path<(String, String, String)>,
+                 State(AppState { base, .. }): State<AppState>,
+                 headers: HeaderMap| async move {
+                    get_file_text(&base, &owner, &repo, format!("objects/info/{}", file).as_ref(), &headers).await
                 },
             ),
         )
         .route_with_tsr("/:owner/:repo/objects/:head/:hash", get(get_loose_object))
+        // `<ref>.tar.gz` or `<ref>.zip`, refs can have slashes
+        .route("/:owner/:repo/archive/*file", get(download_archive))
         .route_with_tsr(
-            "/:owner/:repo/objects/packs/:file",
+            "/:owner/:repo/objects/pack/:file",
             get(get_pack_or_idx_file),
         )
         .route_layer(middleware::from_fn_with_state(state, basic_auth))
@@ -175,25 +304,59 @@ pub fn router(state: AppState, config: &Settings) -> Router<AppState, Body> {
     // .with_state(state)
 }
 
-async fn git_command<P, IA, S, IE, K, V>(dir: P, args: IA, envs: IE) -> Result<Output>
+/// Server env vars git and its hooks still get. Everything else, database credentials
+/// included, is left out of the child environment.
+const GIT_ENV_PASSTHROUGH: &[&str] = &["PATH", "HOME", "LANG", "LC_ALL", "TZ", "TMPDIR"];
+
+/// Environment for a git subprocess, the passthrough vars plus `GIT_PROTOCOL` when the
+/// client asked for one
+pub fn git_env(protocol: Option<&str>) -> Vec<(String, String)> {
+    GIT_ENV_PASSTHROUGH
+        .iter()
+        .filter_map(|key| std::env::var(key).ok().map(|value| (key.to_string(), value)))
+        .chain(protocol.map(|protocol| ("GIT_PROTOCOL".to_string(), protocol.to_string())))
+        .collect()
+}
+
+/// Runs git to completion, the process is killed if the returned future is dropped
+pub async fn git_command<P, IA, S>(git_binary: &str, dir: P, args: IA, protocol: Option<&str>) -> Result<Output>
 where
     P: AsRef<StdPath>,
     IA: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
-    IE: IntoIterator<Item = (K, V)>,
-    K: AsRef<OsStr>,
-    V: AsRef<OsStr>,
 {
-    let output = Command::new("git")
+    let output = Command::new(git_binary)
         .current_dir(dir)
         .args(args)
-        .envs(envs)
+        .env_clear()
+        .envs(git_env(protocol))
+        .kill_on_drop(true)
         .output()
         .await?;
 
     Ok(output)
 }
 
+/// Starts git with its stdout piped, for output streamed into a response instead of
+/// collected like [`git_command`] does
+pub fn git_spawn<P, IA, S>(git_binary: &str, dir: P, args: IA) -> std::io::Result<Child>
+where
+    P: AsRef<StdPath>,
+    IA: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new(git_binary)
+        .current_dir(dir)
+        .args(args)
+        .env_clear()
+        .envs(git_env(None))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
 fn get_git_service(service: &str) -> &str {
     match service.starts_with("git-") {
         true => &service[4..],
@@ -201,6 +364,527 @@ fn get_git_service(service: &str) -> &str {
     }
 }
 
+/// `Git-Protocol` header forwarded to git as `GIT_PROTOCOL`. Only colon separated
+/// `key[=value]` parameters are passed through.
+fn git_protocol(headers: &HeaderMap) -> Option<String> {
+    let protocol = headers.get("Git-Protocol").and_then(|v| v.to_str().ok())?;
+
+    let valid = protocol.split(':').all(|param| {
+        !param.is_empty()
+            && param
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '=' | '.' | '-' | '_'))
+    });
+
+    valid.then(|| protocol.to_string())
+}
+
+fn is_protocol_v2(protocol: Option<&str>) -> bool {
+    protocol.map_or(false, |protocol| protocol.split(':').any(|param| param == "version=2"))
+}
+
+/// HEAD is a symbolic ref to a branch that doesn't exist, either a fresh repo or one whose
+/// default branch was deleted. Both are treated like an empty repo.
+pub fn head_is_unborn(repo: &Repository) -> bool {
+    match repo.head() {
+        Ok(_) => false,
+        Err(err) => matches!(err.code(), git2::ErrorCode::UnbornBranch | git2::ErrorCode::NotFound),
+    }
+}
+
+/// Local branch a build spec like `HEAD`, `refs/heads/main` or `main` names, `None` for a
+/// tag, a commit or a detached HEAD
+pub fn spec_branch(repo: &Repository, spec: &str) -> Option<String> {
+    let full = match spec {
+        "HEAD" => repo.find_reference("HEAD").ok()?.symbolic_target()?.to_string(),
+        spec if spec.starts_with("refs/") => spec.to_string(),
+        spec => repo.find_branch(spec, git2::BranchType::Local).ok()?.get().name()?.to_string(),
+    };
+    full.strip_prefix("refs/heads/").map(str::to_string)
+}
+
+/// Why a build that wasn't pushed can't deploy what it asked for
+#[derive(Debug, PartialEq)]
+pub enum DeployRefError {
+    /// the ref or commit doesn't exist, or the deploy branch itself doesn't
+    Unknown(String),
+    /// only the deploy branch goes to the project's domain, there are no preview deployments
+    NotDeployBranch { requested: String, deploy_branch: String },
+}
+
+impl std::fmt::Display for DeployRefError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeployRefError::Unknown(spec) => write!(f, "{spec} doesn't point to a commit"),
+            DeployRefError::NotDeployBranch { requested, deploy_branch } => {
+                write!(f, "Only {deploy_branch} is deployed, {requested} isn't on it")
+            }
+        }
+    }
+}
+
+/// What a build that isn't pushed deploys, so rebuilds follow the same rule as pushes: only the
+/// deploy branch, the project's build branch or else the default branch HEAD points to, gets to
+/// the project's domain. A requested branch has to be that branch, a requested tag or commit has
+/// to be in its history. Returns the commit together with the deploy branch.
+pub fn resolve_deploy_commit(
+    repo: &Repository,
+    build_branch: Option<&str>,
+    requested: Option<&str>,
+) -> Result<(git2::Oid, Option<String>), DeployRefError> {
+    let deploy_spec = build_branch.map_or_else(|| "HEAD".to_string(), |branch| format!("refs/heads/{branch}"));
+    let deploy_branch = spec_branch(repo, &deploy_spec);
+    let deploy_name = deploy_branch.clone().unwrap_or_else(|| "HEAD".to_string());
+
+    let tip = repo
+        .revparse_single(&deploy_spec)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| DeployRefError::Unknown(deploy_name.clone()))?
+        .id();
+
+    let Some(requested) = requested else {
+        return Ok((tip, deploy_branch));
+    };
+
+    if let Some(branch) = spec_branch(repo, requested) {
+        return match Some(&branch) == deploy_branch.as_ref() {
+            true => Ok((tip, deploy_branch)),
+            false => Err(DeployRefError::NotDeployBranch { requested: branch, deploy_branch: deploy_name }),
+        };
+    }
+
+    let commit = repo
+        .revparse_single(requested)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| DeployRefError::Unknown(requested.to_string()))?
+        .id();
+
+    let in_history = commit == tip || repo.graph_descendant_of(tip, commit).unwrap_or(false);
+    if !in_history {
+        return Err(DeployRefError::NotDeployBranch {
+            requested: requested.to_string(),
+            deploy_branch: deploy_name,
+        });
+    }
+
+    Ok((commit, deploy_branch))
+}
+
+#[derive(Debug)]
+pub enum OpenRepoError {
+    /// the directory isn't there even though the project is, the database and the disk
+    /// are out of sync
+    Missing,
+    /// the directory is there but git doesn't recognize it as a repository
+    NotARepository(git2::Error),
+    /// permissions, corruption, anything else
+    Other(git2::Error),
+}
+
+/// Project name a repo URL segment refers to, clients may address `booker` as `booker.git`
+pub fn canonical_repo_name(repo: &str) -> String {
+    repo.strip_suffix(".git").unwrap_or(repo).to_string()
+}
+
+/// Where the bare repository of a project lives, `{base}/{owner}/{name}.git` whether or not
+/// `repo` came with the suffix
+pub fn resolve_repo_path(base: &str, owner: &str, repo: &str) -> PathBuf {
+    StdPath::new(base).join(owner).join(format!("{}.git", canonical_repo_name(repo)))
+}
+
+/// Opens a project's bare repository, telling apart why it can't be opened so callers can
+/// answer with a 404 instead of an opaque 500
+pub fn open_bare_repo(path: impl AsRef<StdPath>) -> Result<Repository, OpenRepoError> {
+    let path = path.as_ref();
+    match Repository::open_bare(path) {
+        Ok(repo) => Ok(repo),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => match path.try_exists() {
+            Ok(false) => {
+                tracing::warn!(path = %path.display(), "REPO_MISSING: Project exists but its repository is not on disk");
+                Err(OpenRepoError::Missing)
+            }
+            Ok(true) => {
+                tracing::error!(path = %path.display(), ?err, "REPO_INVALID: Project directory is not a git repository");
+                Err(OpenRepoError::NotARepository(err))
+            }
+            Err(io_err) => {
+                tracing::error!(path = %path.display(), ?io_err, "Failed to check repository directory");
+                Err(OpenRepoError::Other(err))
+            }
+        },
+        Err(err) => {
+            tracing::error!(path = %path.display(), ?err, "Failed to open repository");
+            Err(OpenRepoError::Other(err))
+        }
+    }
+}
+
+lazy_static! {
+    /// How long the last fresh clone of each repository took, what updating an existing
+    /// clone instead is compared against
+    static ref LAST_CLONE_DURATION: Mutex<HashMap<String, Duration>> = Mutex::new(HashMap::new());
+}
+
+/// Inside the bare repo, the working trees of builds that are queued or running, one each
+const BUILD_SOURCES_DIR: &str = "builds";
+/// Inside the bare repo, the clone of the last finished build, taken over by the next checkout
+/// so it only has to fetch new objects
+const REUSABLE_CLONE: &str = "clone";
+
+/// How the working directory builds run from is made from the bare repo
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CloneStrategy {
+    /// full clone, the history in `.git` is part of the build context
+    #[default]
+    Clone,
+    /// only the files of the commit, without `.git`. Submodules are left as empty directories.
+    Export,
+}
+
+impl CloneStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Clone => "clone",
+            Self::Export => "export",
+        }
+    }
+
+    /// Unknown values are read as a clone
+    pub fn from_column(value: &str) -> Self {
+        match value {
+            "export" => Self::Export,
+            _ => Self::Clone,
+        }
+    }
+}
+
+/// Strategy a project is set to, a failed lookup falls back to a clone since that always works
+pub async fn clone_strategy(pool: &PgPool, owner: &str, project: &str) -> CloneStrategy {
+    match sqlx::query_as::<_, (String,)>(
+        r#"SELECT projects.clone_strategy
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+             AND projects.name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(project)
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some((strategy,))) => CloneStrategy::from_column(&strategy),
+        Ok(None) => CloneStrategy::default(),
+        Err(err) => {
+            tracing::warn!(?err, owner, project, "Can't get clone strategy: Failed to query database");
+            CloneStrategy::default()
+        }
+    }
+}
+
+/// Branch whose pushes are deployed, `None` for the default branch HEAD points to
+pub async fn build_branch(pool: &PgPool, owner: &str, project: &str) -> Option<String> {
+    match sqlx::query_as::<_, (Option<String>,)>(
+        r#"SELECT projects.build_branch
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+             AND projects.name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(project)
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some((branch,))) => branch,
+        Ok(None) => None,
+        Err(err) => {
+            tracing::warn!(?err, owner, project, "Can't get build branch: Failed to query database");
+            None
+        }
+    }
+}
+
+/// Commit a frozen project stays deployed at, `None` when pushes deploy as usual
+pub async fn pinned_commit(pool: &PgPool, owner: &str, project: &str) -> Option<String> {
+    match sqlx::query_as::<_, (Option<String>,)>(
+        r#"SELECT projects.pinned_commit
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+             AND projects.name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(project)
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some((pinned,))) => pinned,
+        Ok(None) => None,
+        Err(err) => {
+            tracing::warn!(?err, owner, project, "Can't get pinned commit: Failed to query database");
+            None
+        }
+    }
+}
+
+/// Working tree a build runs from, a directory of its own so checking out the next commit
+/// never changes the files under a build that's still running. Once dropped, after the build
+/// or when it's turned away, a clone is kept for the next checkout and anything else deleted.
+#[derive(Debug)]
+pub struct BuildSource {
+    path: String,
+}
+
+impl BuildSource {
+    /// Takes over the working tree at `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self { path: path.to_string_lossy().into_owned() }
+    }
+}
+
+impl std::ops::Deref for BuildSource {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for BuildSource {
+    fn drop(&mut self) {
+        let path = PathBuf::from(std::mem::take(&mut self.path));
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(move || release_build_source(&path));
+            }
+            Err(_) => release_build_source(&path),
+        }
+    }
+}
+
+/// Keeps the clone of a finished build as the reusable one, deletes it when there already is
+/// one or it's an export
+fn release_build_source(source: &StdPath) {
+    if !source.exists() {
+        return;
+    }
+
+    // <repo>/builds/<id>
+    let reusable = source.parent().and_then(StdPath::parent).map(|repo| repo.join(REUSABLE_CLONE));
+    if let Some(reusable) = reusable {
+        if source.join(".git").is_dir() && !reusable.exists() && std::fs::rename(source, &reusable).is_ok() {
+            return;
+        }
+    }
+
+    if let Err(err) = std::fs::remove_dir_all(source) {
+        tracing::error!(?err, source = %source.display(), "Failed to remove build source");
+    }
+}
+
+/// Checks the files of `commit` from the bare repo at `path` out into a new working tree for
+/// a build
+pub fn checkout_build_source(path: &StdPath, commit: git2::Oid, strategy: CloneStrategy) -> Result<BuildSource, git2::Error> {
+    let builds = path.join(BUILD_SOURCES_DIR);
+    std::fs::create_dir_all(&builds).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    // dropped on an error, which removes whatever was checked out so far
+    let source = BuildSource::new(builds.join(Ulid::new().to_string()));
+    let container_src: &str = &source;
+
+    if strategy == CloneStrategy::Export {
+        tracing::info!("Exporting commit {} from bare repo to: {}", commit, container_src);
+        std::fs::create_dir_all(container_src).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+
+        // checks the tree out straight from the bare repo, nothing of the repo itself is copied
+        let bare_repo = Repository::open_bare(path)?;
+        let commit = bare_repo.find_commit(commit)?;
+        bare_repo.checkout_tree(
+            commit.as_object(),
+            Some(
+                git2::build::CheckoutBuilder::default()
+                    .target_dir(StdPath::new(container_src))
+                    .update_index(false)
+                    .force(),
+            ),
+        )?;
+
+        tracing::info!("Successfully exported commit: {}", commit.id());
+        return Ok(source);
+    }
+
+    // A clone left by a previous build only needs the new objects. It's taken by renaming
+    // it, two checkouts at once never get the same one.
+    let started = Instant::now();
+    let repo_key = path.to_string_lossy().into_owned();
+    if std::fs::rename(path.join(REUSABLE_CLONE), container_src).is_ok() {
+        match update_working_clone(path, container_src, commit) {
+            Ok(()) => {
+                let elapsed = started.elapsed();
+                let last_clone = LAST_CLONE_DURATION.lock().unwrap().get(&repo_key).copied();
+                tracing::info!(
+                    elapsed_ms = elapsed.as_millis() as u64,
+                    saved_ms = last_clone.map(|clone| clone.saturating_sub(elapsed).as_millis() as u64),
+                    "Updated existing clone to commit: {}",
+                    commit
+                );
+                return Ok(source);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to update existing clone, making a fresh one: {}", e);
+                remove_working_dir(container_src);
+            }
+        }
+    }
+
+    // Fresh clone from bare repo - always up-to-date
+    tracing::info!("Creating fresh clone from bare repo to: {}", container_src);
+    let cloned_repo = git2::Repository::clone(&path.to_string_lossy(), container_src)?;
+    tracing::info!("Fresh clone completed, now setting to exact commit");
+
+    // Set to the exact commit that was resolved in the bare repo (matching tree view)
+    if let Err(e) = cloned_repo.set_head_detached(commit) {
+        tracing::error!("Failed to set cloned repo HEAD: {}", e);
+    } else if let Err(e) = cloned_repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force())) {
+        // Force checkout to make working directory match
+        tracing::error!("Failed to checkout cloned repo HEAD: {}", e);
+    } else {
+        tracing::info!("Successfully set working directory to commit: {}", commit);
+    }
+
+    let elapsed = started.elapsed();
+    tracing::info!(elapsed_ms = elapsed.as_millis() as u64, "Fresh clone took");
+    LAST_CLONE_DURATION.lock().unwrap().insert(repo_key, elapsed);
+
+    Ok(source)
+}
+
+fn remove_working_dir(container_src: &str) {
+    if std::path::Path::new(container_src).exists() {
+        tracing::info!("Removing existing working directory: {}", container_src);
+        if let Err(e) = std::fs::remove_dir_all(container_src) {
+            tracing::error!("Failed to remove existing directory: {}", e);
+        }
+    }
+}
+
+/// Brings the clone of a previous build to `commit`, ending up where a fresh clone would:
+/// the same refs, HEAD detached at `commit`, and no files the commit doesn't have.
+fn update_working_clone(path: &StdPath, container_src: &str, commit: git2::Oid) -> Result<(), git2::Error> {
+    let repo = Repository::open(container_src)?;
+
+    // nested submodule repos survive a reset, only a fresh clone is sure to drop them
+    if !repo.submodules()?.is_empty() {
+        return Err(git2::Error::from_str("clone has submodules"));
+    }
+
+    // the bare repo may have moved since the clone was made
+    repo.remote_set_url("origin", &path.to_string_lossy())?;
+    let mut origin = repo.find_remote("origin")?;
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.prune(git2::FetchPrune::On).download_tags(git2::AutotagOption::All);
+    origin.fetch(&["+refs/heads/*:refs/remotes/origin/*"], Some(&mut fetch_options), None)?;
+
+    let target = repo.find_commit(commit)?;
+    repo.set_head_detached(commit)?;
+    repo.reset(
+        target.as_object(),
+        git2::ResetType::Hard,
+        Some(
+            git2::build::CheckoutBuilder::default()
+                .force()
+                .remove_untracked(true)
+                .remove_ignored(true),
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Fetches the submodules of a cloned build source at the commits it pins, nested ones up to
+/// `max_depth` levels. Returns what was done for the build log, or why it failed.
+pub fn update_submodules(container_src: &str, max_depth: usize) -> Result<String, String> {
+    let repo = Repository::open(container_src).map_err(|err| format!("Failed to open build source: {}", err.message()))?;
+
+    let mut log = String::new();
+    update_submodules_in(&repo, "", 1, max_depth, &mut log)?;
+
+    Ok(log)
+}
+
+fn update_submodules_in(repo: &Repository, prefix: &str, depth: usize, max_depth: usize, log: &mut String) -> Result<(), String> {
+    let submodules = repo.submodules().map_err(|err| format!("Failed to read .gitmodules: {}", err.message()))?;
+
+    for mut submodule in submodules {
+        let path = format!("{prefix}{}", submodule.path().display());
+        let url = submodule.url().unwrap_or_default().to_string();
+
+        // relative, file and ssh urls would reach into this server, e.g. another project's repo
+        if !url.starts_with("https://") {
+            return Err(format!("Submodule {path} points to {url:?}, only https:// submodules can be fetched"));
+        }
+
+        submodule
+            .update(true, None)
+            .map_err(|err| format!("Failed to fetch submodule {path} from {url}: {}", err.message()))?;
+        log.push_str(&format!("Fetched submodule {path} from {url}\n"));
+
+        let Ok(nested) = submodule.open() else {
+            continue;
+        };
+        if nested.submodules().map_or(true, |nested| nested.is_empty()) {
+            continue;
+        }
+
+        match depth < max_depth {
+            true => update_submodules_in(&nested, &format!("{path}/"), depth + 1, max_depth, log)?,
+            false => log.push_str(&format!("Skipped submodules of {path}, they are nested deeper than {max_depth} levels\n")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Urls of the submodules in a `.gitmodules` file by their path. Only what the tree view
+/// needs, sections without both a path and an url are left out.
+pub fn gitmodule_urls(content: &str) -> HashMap<String, String> {
+    let mut sections: Vec<(Option<String>, Option<String>)> = Vec::new();
+    let mut in_submodule = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_submodule = line.starts_with("[submodule");
+            if in_submodule {
+                sections.push((None, None));
+            }
+            continue;
+        }
+
+        let (Some(section), Some((key, value))) = (sections.last_mut().filter(|_| in_submodule), line.split_once('=')) else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim() {
+            "path" => section.0 = Some(value.trim_end_matches('/').to_string()),
+            "url" => section.1 = Some(value),
+            _ => {}
+        }
+    }
+
+    sections
+        .into_iter()
+        .filter_map(|(path, url)| Some((path?, url?)))
+        .collect()
+}
+
 fn packet_write(s: &str) -> Vec<u8> {
     let length = s.len() + 4;
     let mut length_hex = format!("{:x}", length);
@@ -218,6 +902,51 @@ fn packet_flush() -> Vec<u8> {
     "0000".into()
 }
 
+/// Logs an internal error under a correlation id and builds the 500 for it. The git CLI gets an
+/// empty body as before, clients sending `Accept: application/json` get the id and a message.
+fn internal_error<E: std::fmt::Debug>(headers: &HeaderMap, message: &str, err: E) -> Response<Body> {
+    let correlation_id = Ulid::new().to_string();
+    tracing::error!(correlation_id, ?err, "{}", message);
+
+    let wants_json = headers
+        .get("Accept")
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("application/json"));
+
+    let response = Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .header("X-Correlation-Id", correlation_id.as_str());
+
+    match wants_json {
+        true => response
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::json!({
+                    "message": message,
+                    "correlation_id": correlation_id,
+                })
+                .to_string(),
+            ))
+            .unwrap(),
+        false => response.body(Body::empty()).unwrap(),
+    }
+}
+
+/// Contents of a file the dumb protocol serves. One that can't be opened is a 404, one that
+/// can't be read a 500.
+fn read_repo_file(headers: &HeaderMap, path: &StdPath) -> Result<Vec<u8>, Response<Body>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Err(Response::builder().status(404).body(Body::empty()).unwrap()),
+    };
+
+    let mut contents = Vec::new();
+    match file.read_to_end(&mut contents) {
+        Ok(_) => Ok(contents),
+        Err(err) => Err(internal_error(headers, "Failed to read repository file", (path, err))),
+    }
+}
+
 trait GitServer {
     fn no_cache(self) -> Self;
     fn cache_forever(self) -> Self;
@@ -243,21 +972,17 @@ impl GitServer for ResponseBuilder {
 }
 
 pub async fn get_info_packs(
-    Path(repo): Path<String>,
+    Path((owner, repo)): Path<(String, String)>,
     State(AppState { base, .. }): State<AppState>,
+    headers: HeaderMap,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/info/packs"),
-        false => format!("{base}/{repo}.git/objects/info/packs"),
-    };
+    let path = resolve_repo_path(&base, &owner, &repo).join("objects/info/packs");
 
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
+    let contents = match read_repo_file(&headers, &path) {
+        Ok(contents) => contents,
+        Err(response) => return response,
     };
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
     Response::builder()
         .no_cache()
         .header("Content-Type", "text/plain; charset=utf-8")
@@ -266,20 +991,21 @@ pub async fn get_info_packs(
 }
 
 pub async fn get_loose_object(
-    Path((repo, head, hash)): Path<(String, String, String)>,
+    Path((owner, repo, head, hash)): Path<(String, String, String, String)>,
     State(AppState { base, .. }): State<AppState>,
+    headers: HeaderMap,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/{head}/{hash}"),
-        false => format!("{base}/{repo}.git/objects/{head}{hash}"),
-    };
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
-    };
+    // `..` would reach out of the objects directory
+    let is_hex = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_hexdigit());
+    if head.len() != 2 || !is_hex(&head) || !is_hex(&hash) {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
+    }
 
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).unwrap();
+    let path = resolve_repo_path(&base, &owner, &repo).join("objects").join(head).join(hash);
+    let contents = match read_repo_file(&headers, &path) {
+        Ok(contents) => contents,
+        Err(response) => return response,
+    };
 
     Response::builder()
         .cache_forever()
@@ -289,68 +1015,128 @@ pub async fn get_loose_object(
 }
 
 pub async fn get_pack_or_idx_file(
-    Path((repo, file)): Path<(String, String)>,
+    Path((owner, repo, file)): Path<(String, String, String)>,
     State(AppState { base, .. }): State<AppState>,
+    headers: HeaderMap,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{repo}/objects/pack/{file}"),
-        false => format!("{base}/{repo}.git/objects/pack{file}"),
-    };
-    let mut file = match File::open(&path) {
-        Ok(file) => file,
-        Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
-    };
+    let path = resolve_repo_path(&base, &owner, &repo).join("objects/pack").join(file);
 
     let res = Response::builder().cache_forever();
 
-    let res = match StdPath::new(&path).extension().and_then(|ext| ext.to_str()) {
+    let res = match path.extension().and_then(|ext| ext.to_str()) {
         Some("pack") => res.header("Content-Type", "application/x-git-packed-objects"),
         Some("idx") => res.header("Content-Type", "application/x-git-packed-objects-toc"),
         _ => return Response::builder().status(404).body(Body::empty()).unwrap(),
     };
 
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).unwrap();
+    let contents = match read_repo_file(&headers, &path) {
+        Ok(contents) => contents,
+        Err(response) => return response,
+    };
 
     res.body(Body::from(contents)).unwrap()
 }
 
-pub async fn get_file_text(base: &str, owner: &str, repo: &str, file: &str) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{owner}/{repo}/{file}"),
-        false => format!("{base}/{owner}/{repo}.git/{file}"),
-    };
+/// Snapshot of a ref without cloning, `/:owner/:repo/archive/<ref>.tar.gz` or `.zip`
+pub async fn download_archive(
+    Path((owner, repo, file)): Path<(String, String, String)>,
+    State(AppState { base, git_binary, .. }): State<AppState>,
+) -> Response<Body> {
+    let not_found = || Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
 
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
+    let (r#ref, format, content_type) = match (file.strip_suffix(".tar.gz"), file.strip_suffix(".zip")) {
+        (Some(r#ref), _) => (r#ref, "tar.gz", "application/gzip"),
+        (_, Some(r#ref)) => (r#ref, "zip", "application/zip"),
+        _ => return not_found(),
     };
+    // a ref starting with a dash would be read as an option
+    if r#ref.is_empty() || r#ref.starts_with('-') {
+        return not_found();
+    }
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    Response::builder()
-        .header("Content-Type", "text/plain")
-        .body(Body::from(contents))
-        .unwrap()
-}
+    let repo_path = resolve_repo_path(&base, &owner, &repo);
+    if !repo_path.is_dir() {
+        return not_found();
+    }
 
-pub async fn receive_pack_rpc(
-    Path((owner, repo)): Path<(String, String)>,
-    State(AppState {
+    // once the archive streams the status is sent, unknown refs are caught before that
+    let rev = format!("{}^{{commit}}", r#ref);
+    let commit = match git_command(&git_binary, &repo_path, ["rev-parse", "--verify", "--quiet", &rev], None).await {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Ok(_) => return not_found(),
+        Err(err) => {
+            tracing::error!(?err, owner, repo, "Can't create archive: Failed to run git rev-parse");
+            return Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap();
+        }
+    };
+
+    let name = format!("{}-{}", canonical_repo_name(&repo), r#ref.replace('/', "-"));
+    let mut child = match git_spawn(
+        &git_binary,
+        &repo_path,
+        ["archive".to_string(), format!("--format={format}"), format!("--prefix={name}/"), commit],
+    ) {
+        Ok(child) => child,
+        Err(err) => {
+            tracing::error!(?err, owner, repo, "Can't create archive: Failed to spawn git archive");
+            return Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap();
+        }
+    };
+
+    let stdout = child.stdout.take().expect("failed to get stdout");
+    tokio::spawn(async move {
+        match child.wait_with_output().await {
+            Ok(output) if !output.status.success() => {
+                tracing::error!(status = ?output.status, stderr = %String::from_utf8_lossy(&output.stderr), "git archive failed");
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!(?err, "Failed to wait for git archive"),
+        }
+    });
+
+    Response::builder()
+        .no_cache()
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", attachment(&format!("{name}.{format}")))
+        .body(Body::wrap_stream(ReaderStream::new(stdout)))
+        .unwrap()
+}
+
+pub async fn get_file_text(base: &str, owner: &str, repo: &str, file: &str, headers: &HeaderMap) -> Response<Body> {
+    let path = resolve_repo_path(base, owner, repo).join(file);
+
+    let contents = match read_repo_file(headers, &path) {
+        Ok(contents) => contents,
+        Err(response) => return response,
+    };
+
+    Response::builder()
+        .header("Content-Type", "text/plain")
+        .body(Body::from(contents))
+        .unwrap()
+}
+
+pub async fn receive_pack_rpc(
+    Path((owner, repo)): Path<(String, String)>,
+    State(AppState {
         base,
+        pool,
         build_channel,
+        git_binary,
         ..
     }): State<AppState>,
     headers: HeaderMap,
-    body: Bytes,
+    body: Body,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{owner}/{repo}"),
-        false => format!("{base}/{owner}/{repo}.git"),
-    };
-    let head_dir = format!("{path}/refs/heads");
+    let path = resolve_repo_path(&base, &owner, &repo);
 
-    let res = service_rpc("receive-pack", &path, headers, body).await;
+    // compared with the refs after the push to know which ones it updated
+    let refs_before = ref_snapshot(&path);
+
+    let request_headers = headers.clone();
+    let (res, request_head) = run_rpc(&git_binary, "receive-pack", &path.to_string_lossy(), headers, body).await;
+    // progress messages can only be added when the client asked for side-band
+    let sideband = client_wants_sideband(&request_head);
     if res.status() != StatusCode::OK {
         return res;
     }
@@ -364,220 +1150,540 @@ pub async fn receive_pack_rpc(
         return res;
     }
 
-    let container_src = format!("{path}/clone");
-    let container_name = format!("{owner}-{}", repo.trim_end_matches(".git")).replace('.', "-");
+    {
+        let (pool, owner, repo, path) = (pool.clone(), owner.clone(), repo.clone(), path.clone());
+        tokio::spawn(async move { refresh_repo_size(&pool, &owner, &repo, path).await });
+    }
+
+    // e.g. `git push origin :branch`, there's nothing new to build. The client gets the
+    // report of receive-pack as it is.
+    let (updated, deleted) = ref_changes(&refs_before, &ref_snapshot(&path));
+    if updated.is_empty() && !deleted.is_empty() {
+        tracing::info!(owner, repo, ?deleted, "Push only deleted refs, skipping build");
+        return res;
+    }
+
+    // the push is kept, the deployment stays where it was pinned until the project is unfrozen
+    if let Some(pinned) = pinned_commit(&pool, &owner, &repo).await {
+        tracing::info!(owner, repo, pinned, "Project is frozen, skipping build");
+        return append_sideband_message(
+            res,
+            sideband,
+            &format!("Project is frozen at {}, the push was saved but not deployed", &pinned[..pinned.len().min(7)]),
+        )
+        .await;
+    }
+
+    let container_name = format!("{owner}-{}", canonical_repo_name(&repo)).replace('.', "-");
+
+    let build_branch = build_branch(&pool, &owner, &repo).await;
 
     // FIXED: Get HEAD commit directly from bare repo to ensure consistency 
     // This resolves the issue where copy directory was out of sync with tree view
-    let bare_repo_path = if repo.ends_with(".git") {
-        format!("{base}/{owner}/{repo}")
-    } else {
-        format!("{base}/{owner}/{repo}.git")
-    };
-    
-    let head_commit_id = match git2::Repository::open_bare(&bare_repo_path) {
+    let head_commit_id = match open_bare_repo(&path) {
         Ok(bare_repo) => {
-            match bare_repo.revparse_single("HEAD") {
+            let default_branch = bare_repo
+                .find_reference("HEAD")
+                .ok()
+                .and_then(|head| head.symbolic_target().map(|target| target.to_string()));
+            // the project's build branch when it has one, what HEAD points to otherwise
+            let deploy_branch = match &build_branch {
+                Some(branch) => Some(format!("refs/heads/{branch}")),
+                None => default_branch.clone(),
+            };
+
+            match bare_repo.revparse_single(deploy_branch.as_deref().unwrap_or("HEAD")) {
+                // only the deployed branch builds, pushing another branch doesn't touch what runs
+                Ok(_) if deploy_branch.as_ref().map_or(false, |branch| !updated.contains(branch)) => {
+                    let branch = deploy_branch.unwrap_or_default();
+                    tracing::info!(owner, repo, branch, "Deployed branch wasn't pushed to, skipping build");
+                    Err(format!(
+                        "Only pushes to {} are deployed, nothing to build",
+                        branch.trim_start_matches("refs/heads/")
+                    ))
+                }
+                Err(_) if build_branch.is_some() => {
+                    let branch = build_branch.unwrap_or_default();
+                    tracing::info!(owner, repo, branch, "Build branch doesn't exist, skipping build");
+                    Err(format!("The build branch {branch} doesn't exist, nothing to build"))
+                }
                 Ok(obj) => {
                     let commit_id = obj.id();
                     tracing::info!("Got HEAD commit from bare repo: {}", commit_id);
-                    commit_id
+                    let branch = deploy_branch.and_then(|branch| branch.strip_prefix("refs/heads/").map(str::to_string));
+                    Ok((commit_id, branch))
                 },
+                // e.g. HEAD still points to a deleted master while only main was pushed
+                Err(_) if head_is_unborn(&bare_repo) => {
+                    let head = default_branch.unwrap_or_else(|| "HEAD".to_string());
+                    tracing::info!(owner, repo, head, "HEAD points to a missing branch, skipping build");
+                    Err(format!("HEAD points to {head} which doesn't exist, nothing to build"))
+                }
                 Err(e) => {
-                    tracing::error!("Failed to resolve HEAD in bare repo: {}", e);
-                    return Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::empty())
-                        .unwrap();
+                    return internal_error(&request_headers, "Failed to resolve HEAD in bare repo", e);
                 }
             }
         },
+        // the push itself just went through, whatever went wrong here is on the server
         Err(e) => {
-            tracing::error!("Failed to open bare repo: {}", e);
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::empty())
-                .unwrap();
+            return internal_error(&request_headers, "Failed to open bare repo", e);
         }
     };
 
-    // Always fresh clone to guarantee up-to-date state
-    // Delete existing working directory if it exists
-    if std::path::Path::new(&container_src).exists() {
-        tracing::info!("Removing existing working directory: {}", container_src);
-        if let Err(e) = std::fs::remove_dir_all(&container_src) {
-            tracing::error!("Failed to remove existing directory: {}", e);
-        }
-    }
-    
-    // Fresh clone from bare repo - always up-to-date
-    tracing::info!("Creating fresh clone from bare repo to: {}", container_src);
-    match git2::Repository::clone(&path, &container_src) {
-        Ok(cloned_repo) => {
-            tracing::info!("Fresh clone completed, now setting to exact HEAD commit");
-            
-            // Set to exact same commit as HEAD in bare repo (matching tree view)
-            if let Err(e) = cloned_repo.set_head_detached(head_commit_id) {
-                tracing::error!("Failed to set cloned repo HEAD: {}", e);
-            } else {
-                // Force checkout to make working directory match
-                if let Err(e) = cloned_repo.checkout_head(Some(
-                    git2::build::CheckoutBuilder::default().force()
-                )) {
-                    tracing::error!("Failed to checkout cloned repo HEAD: {}", e);
-                } else {
-                    tracing::info!("Successfully set working directory to commit: {}", head_commit_id);
-                }
-            }
+    let (head_commit_id, branch) = match head_commit_id {
+        Ok(head) => head,
+        Err(message) => return append_sideband_message(res, sideband, &message).await,
+    };
+
+    let strategy = clone_strategy(&pool, &owner, &repo).await;
+    let checkout = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || checkout_build_source(&path, head_commit_id, strategy)).await
+    };
+    let container_src = match checkout {
+        Ok(Ok(source)) => source,
+        Ok(Err(e)) => return internal_error(&request_headers, "Fresh clone failed", e),
+        Err(e) => return internal_error(&request_headers, "Fresh clone failed", e),
+    };
+
+    let (reply, outcome) = tokio::sync::oneshot::channel();
+    let sent = build_channel
+        .send(BuildQueueItem {
+            container_name,
+            container_src,
+            owner,
+            repo,
+            commit_sha: head_commit_id.to_string(),
+            branch,
+            force: false,
+            reply: Some(reply),
+            priority: BuildPriority::Push,
+        })
+        .await;
+
+    let message = match sent {
+        Ok(_) => match tokio::time::timeout(ENQUEUE_REPLY_TIMEOUT, outcome).await {
+            Ok(Ok(outcome)) => outcome.to_string(),
+            _ => "Build requested, check the dashboard for its status".to_string(),
         },
-        Err(e) => {
-            tracing::error!("Fresh clone failed: {}", e);
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::empty())
-                .unwrap();
+        Err(err) => {
+            tracing::error!(?err, "Failed to send build request to queue");
+            "Build queue is unavailable, please try again later".to_string()
         }
+    };
+
+    append_sideband_message(res, sideband, &message).await
+}
+
+/// Branches and tags of a repository with what they point to, empty when it can't be read
+fn ref_snapshot(path: &StdPath) -> HashMap<String, git2::Oid> {
+    let Ok(repo) = open_bare_repo(path) else {
+        return HashMap::new();
+    };
+    let Ok(references) = repo.references() else {
+        return HashMap::new();
+    };
+
+    references
+        .flatten()
+        .filter_map(|reference| Some((reference.name()?.to_string(), reference.target()?)))
+        .filter(|(name, _)| name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+        .collect()
+}
+
+/// Refs a push created or moved, and the ones it deleted
+fn ref_changes(before: &HashMap<String, git2::Oid>, after: &HashMap<String, git2::Oid>) -> (Vec<String>, Vec<String>) {
+    let updated = after
+        .iter()
+        .filter(|(name, oid)| before.get(*name) != Some(*oid))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let deleted = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .cloned()
+        .collect();
+
+    (updated, deleted)
+}
+
+/// Length of the first pkt-line of `head`, 0 when it doesn't start with one
+fn first_pkt_line_len(head: &[u8]) -> usize {
+    head.get(..4)
+        .and_then(|length| std::str::from_utf8(length).ok())
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .unwrap_or(0)
+}
+
+/// Capabilities the client asked for, sent after a NUL on the first command of a push or the
+/// first want of a fetch
+fn requested_capabilities(head: &[u8]) -> Vec<String> {
+    let length = first_pkt_line_len(head);
+    let Some(line) = head.get(4..length) else {
+        return Vec::new();
+    };
+
+    match line.iter().position(|byte| *byte == 0) {
+        Some(nul) => String::from_utf8_lossy(&line[nul + 1..])
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
     }
+}
 
+/// Whether the client asked for its response multiplexed in bands
+fn client_wants_sideband(head: &[u8]) -> bool {
+    requested_capabilities(head)
+        .iter()
+        .any(|capability| capability == "side-band-64k" || capability == "side-band")
+}
 
-    tokio::spawn(async move {
-        build_channel
-            .send(BuildQueueItem {
-                container_name,
-                container_src,
-                owner,
-                repo,
-            })
-            .await
-    });
+/// Appends a band 2 (progress) message to a receive-pack response so the client prints it as
+/// `remote: ...`. Without `sideband` the response is left alone, the client didn't ask for bands
+/// and would take the message for part of the report.
+async fn append_sideband_message(res: Response<Body>, sideband: bool, message: &str) -> Response<Body> {
+    if !sideband {
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let output = match hyper::body::to_bytes(body).await {
+        Ok(output) => output,
+        Err(err) => {
+            tracing::error!(?err, "Failed to read receive-pack output");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
 
-    res
+    // the message goes before the closing flush
+    if !output.ends_with(b"0000") {
+        return Response::from_parts(parts, Body::from(output));
+    }
+
+    let mut body = output[..output.len() - 4].to_vec();
+    body.extend(packet_write(&format!("\x02{message}\n")));
+    body.extend(packet_flush());
+
+    Response::from_parts(parts, Body::from(body))
 }
 
 pub async fn upload_pack_rpc(
     Path((owner, repo)): Path<(String, String)>,
-    State(AppState { base, .. }): State<AppState>,
+    State(AppState { base, git_binary, .. }): State<AppState>,
     headers: HeaderMap,
-    body: Bytes,
+    body: Body,
 ) -> Response<Body> {
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{owner}/{repo}"),
-        false => format!("{base}/{owner}/{repo}.git"),
-    };
+    let path = resolve_repo_path(&base, &owner, &repo);
+
+    service_rpc(&git_binary, "upload-pack", &path.to_string_lossy(), headers, body).await
+}
+
+/// Why the request body of an rpc couldn't be read to the end
+#[derive(Debug)]
+enum RpcBodyError {
+    /// the client went away, e.g. a push whose connection dropped halfway through the pack
+    Disconnected(hyper::Error),
+    Decode(std::io::Error),
+}
 
-    service_rpc("upload-pack", &path, headers, body).await
+/// Next piece of the request body, gunzipped when the client compressed it. `Ok(None)` is
+/// the end of the body.
+async fn read_rpc_body(
+    body: &mut Body,
+    decoder: &mut Option<flate2::write::GzDecoder<Vec<u8>>>,
+) -> Result<Option<Vec<u8>>, RpcBodyError> {
+    match body.data().await {
+        Some(Ok(chunk)) => match decoder {
+            Some(decoder) => {
+                decoder.write_all(&chunk).map_err(RpcBodyError::Decode)?;
+                Ok(Some(std::mem::take(decoder.get_mut())))
+            }
+            None => Ok(Some(chunk.to_vec())),
+        },
+        Some(Err(err)) => Err(RpcBodyError::Disconnected(err)),
+        // whatever the decoder still holds comes out once, the next call ends the body
+        None => match decoder.take() {
+            Some(decoder) => decoder.finish().map(Some).map_err(RpcBodyError::Decode),
+            None => Ok(None),
+        },
+    }
 }
 
-pub async fn service_rpc(rpc: &str, path: &str, headers: HeaderMap, body: Bytes) -> Response<Body> {
+/// Response for a request body that couldn't be read. Nothing was handed to git yet or git
+/// was stopped before updating any ref, so the caller must not act on the push.
+fn rpc_body_error(headers: &HeaderMap, rpc: &str, path: &str, err: RpcBodyError) -> Response<Body> {
+    match err {
+        RpcBodyError::Disconnected(err) => {
+            let event = match rpc {
+                "receive-pack" => "PUSH_ABORTED",
+                _ => "RPC_ABORTED",
+            };
+            tracing::warn!(rpc, path, ?err, "{}: client disconnected before sending the whole request", event);
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .unwrap()
+        }
+        RpcBodyError::Decode(err) => internal_error(headers, "Failed to decode gzip request body", err),
+    }
+}
+
+pub async fn service_rpc(
+    git_binary: &str,
+    rpc: &str,
+    path: &str,
+    headers: HeaderMap,
+    body: Body,
+) -> Response<Body> {
+    run_rpc(git_binary, rpc, path, headers, body).await.0
+}
+
+/// Runs the rpc like [`service_rpc`] and also returns the start of the request, at least its
+/// first pkt-line, which carries the capabilities the client asked for
+async fn run_rpc(
+    git_binary: &str,
+    rpc: &str,
+    path: &str,
+    headers: HeaderMap,
+    mut body: Body,
+) -> (Response<Body>, Vec<u8>) {
     let mut response = Response::builder()
         .header("Content-Type", format!("application/x-git-{rpc}-result"))
         .body(Body::empty())
         .unwrap();
 
-    let body = match headers
+    let mut decoder = headers
         .get("Content-Encoding")
         .and_then(|enc| enc.to_str().ok())
-    {
-        Some("gzip") => {
-            let mut reader = flate2::read::GzDecoder::new(body.as_ref());
-            let mut new_bytes = Vec::new();
-            match reader.read_to_end(&mut new_bytes) {
-                Ok(_) => Bytes::from(new_bytes),
-                Err(_) => {
-                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-                    return response;
-                }
+        .filter(|enc| *enc == "gzip")
+        .map(|_| flate2::write::GzDecoder::new(Vec::new()));
+
+    // enough of the body to tell a lone flush packet apart and to read the first pkt-line,
+    // the rest is streamed to git
+    let mut head = Vec::new();
+    let mut ended = false;
+    while head.len() <= 4 || head.len() < first_pkt_line_len(&head) {
+        match read_rpc_body(&mut body, &mut decoder).await {
+            Ok(Some(data)) => head.extend(data),
+            Ok(None) => {
+                ended = true;
+                break;
             }
+            Err(err) => return (rpc_body_error(&headers, rpc, path, err), head),
         }
-        _ => body,
-    };
+    }
 
-    if body == b"0000".as_slice() {
+    if ended && head == b"0000".as_slice() {
         response
             .headers_mut()
             .insert("Vary", "Accept-Encoding".parse().unwrap());
         response
             .headers_mut()
             .insert("Content-Length", "0".parse().unwrap());
-        return response;
+        return (response, head);
     }
 
-    let env = match headers.get("Git-Protocol").and_then(|v| v.to_str().ok()) {
-        Some("version=2") => ("GIT_PROTOCOL".to_string(), "version=2".to_string()),
-        _ => ("".to_string(), "".to_string()),
-    };
+    // a block of its own so every early return below still hands the head back
+    let response = async {
+        let mut cmd = Command::new(git_binary);
+        if rpc == "upload-pack" {
+            cmd.args(UPLOAD_PACK_CONFIG);
+        }
+        cmd.args([rpc, "--stateless-rpc", path])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .env_clear()
+            .envs(git_env(git_protocol(&headers).as_deref()))
+            // a streamed response can be dropped halfway when the client goes away
+            .kill_on_drop(true);
+
+        // e.g. a wrong `git.binary`, the client gets a 500 instead of the connection dropping
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => return internal_error(&headers, &format!("Failed to run git {rpc}"), err),
+        };
+        let mut stdin = child.stdin.take().expect("failed to get stdin");
 
-    let envs = std::env::vars().chain([env]).collect::<Vec<_>>();
+        if let Err(e) = stdin.write_all(&head).await {
+            return internal_error(&headers, "Failed to write to stdin", e);
+        }
 
-    let mut cmd = Command::new("git");
-    cmd.args([rpc, "--stateless-rpc", path])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .envs(envs);
+        while !ended {
+            let data = match read_rpc_body(&mut body, &mut decoder).await {
+                Ok(Some(data)) => data,
+                Ok(None) => break,
+                Err(err) => {
+                    // closing stdin on a truncated pack makes git fail the push and drop its
+                    // quarantined objects itself, refs are only updated after the whole pack is in
+                    drop(stdin);
+                    match tokio::time::timeout(RPC_ABORT_GRACE, child.wait()).await {
+                        Ok(_) => {}
+                        Err(_) => {
+                            if let Err(err) = child.kill().await {
+                                tracing::error!(?err, rpc, path, "Failed to kill aborted git process");
+                            }
+                        }
+                    }
+
+                    return rpc_body_error(&headers, rpc, path, err);
+                }
+            };
 
-    let mut child = cmd.spawn().expect("failed to spawn command");
-    let mut stdin = child.stdin.take().expect("failed to get stdin");
+            if let Err(e) = stdin.write_all(&data).await {
+                return internal_error(&headers, "Failed to write to stdin", e);
+            }
+        }
+        drop(stdin);
 
-    if let Err(e) = stdin.write_all(&body).await {
-        tracing::error!("Failed to write to stdin: {}", e);
-        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-        return response;
+        // a clone or fetch sends the whole pack, it goes out as git writes it. Pushes stay
+        // buffered, receive_pack_rpc acts on them once git is done and appends to the output.
+        if rpc == "upload-pack" {
+            return stream_rpc_output(child, rpc, path, &headers, response).await;
+        }
+
+        let output = match child.wait_with_output().await {
+            Ok(output) => output,
+            Err(err) => return internal_error(&headers, &format!("Failed to wait for git {rpc}"), err),
+        };
+
+        if !output.status.success() {
+            return internal_error(
+                &headers,
+                &format!("git {rpc} failed"),
+                (output.status, String::from_utf8_lossy(&output.stderr)),
+            );
+        } else {
+            tracing::debug!(rpc, path, stderr = %String::from_utf8_lossy(&output.stderr), "git rpc finished");
+            *response.body_mut() = Body::from(output.stdout);
+        }
+
+        response
     }
-    drop(stdin);
+    .await;
 
-    let output = child
-        .wait_with_output()
-        .await
-        .expect("Failed to read stdout/stderr");
+    (response, head)
+}
 
-    if !output.status.success() {
-        tracing::error!("Command failed: {:?}", output.status);
-        tracing::error!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
-        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-    } else {
-        tracing::info!("Command succeeded!");
-        tracing::info!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
-        tracing::info!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
-        *response.body_mut() = Body::from(output.stdout);
+/// Sends the output of a git rpc as it's written. Once the first chunk is out the status
+/// can't become a 500 anymore, a failing git aborts the body instead so the client doesn't
+/// take a truncated pack for a whole one.
+async fn stream_rpc_output(
+    mut child: Child,
+    rpc: &str,
+    path: &str,
+    headers: &HeaderMap,
+    mut response: Response<Body>,
+) -> Response<Body> {
+    let mut stdout = child.stdout.take().expect("failed to get stdout");
+    let mut stderr = child.stderr.take().expect("failed to get stderr");
+
+    // read alongside stdout, git blocks once a pipe nobody reads is full
+    let stderr = tokio::spawn(async move {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output).await;
+        output
+    });
+
+    let mut first = vec![0; RPC_CHUNK_SIZE];
+    let read = match stdout.read(&mut first).await {
+        Ok(read) => read,
+        Err(err) => return internal_error(headers, "Failed to read stdout", err),
+    };
+    first.truncate(read);
+
+    // nothing written, whether it worked is still known before answering
+    if read == 0 {
+        let status = child.wait().await;
+        let stderr = stderr.await.unwrap_or_default();
+        return match status {
+            Ok(status) if status.success() => response,
+            Ok(status) => internal_error(headers, &format!("git {rpc} failed"), (status, String::from_utf8_lossy(&stderr))),
+            Err(err) => internal_error(headers, &format!("Failed to wait for git {rpc}"), err),
+        };
     }
 
+    let (rpc, path) = (rpc.to_string(), path.to_string());
+    let exit = async move {
+        let status = child.wait().await;
+        let stderr = stderr.await.unwrap_or_default();
+
+        match status {
+            Ok(status) if status.success() => None,
+            Ok(status) => {
+                tracing::error!(rpc, path, ?status, stderr = %String::from_utf8_lossy(&stderr), "git rpc failed while streaming");
+                Some(Err(std::io::Error::new(std::io::ErrorKind::Other, format!("git {rpc} exited with {status}"))))
+            }
+            Err(err) => {
+                tracing::error!(rpc, path, ?err, "Failed to wait for git rpc");
+                Some(Err(err))
+            }
+        }
+    };
+
+    let stream = futures_util::stream::once(async move { Ok(Bytes::from(first)) })
+        .chain(ReaderStream::with_capacity(stdout, RPC_CHUNK_SIZE))
+        .chain(futures_util::stream::once(exit).filter_map(|exit| async move { exit }));
+    *response.body_mut() = Body::wrap_stream(stream);
+
     response
 }
 
 #[derive(Deserialize, Debug)]
 pub struct GitQuery {
+    /// dumb clients ask for `info/refs` without one
+    #[serde(default)]
     service: String,
 }
 
 pub async fn get_info_refs(
     Path((owner, repo)): Path<(String, String)>,
-    State(AppState { base, .. }): State<AppState>,
+    State(AppState { base, git_binary, build_queue_load, .. }): State<AppState>,
     Query(GitQuery { service }): Query<GitQuery>,
     headers: HeaderMap,
 ) -> Response<Body> {
     let service = get_git_service(&service);
 
-    let path = match repo.ends_with(".git") {
-        true => format!("{base}/{owner}/{repo}"),
-        false => format!("{base}/{owner}/{repo}.git"),
-    };
+    // refused at the ref advertisement, before the client sends its pack. git prints the
+    // text/plain body of a failed advertisement as `remote: ...`, the body of a failed
+    // receive-pack POST would never be shown.
+    if service == "receive-pack" && build_queue_load.is_saturated() {
+        tracing::warn!(owner, repo, queue_length = build_queue_load.depth(), "PUSH_REJECTED: build queue saturated");
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "text/plain")
+            .header("Retry-After", "60")
+            .body(Body::from(format!(
+                "The build queue is full ({} builds waiting), please push again in a few minutes\n",
+                build_queue_load.depth()
+            )))
+            .unwrap();
+    }
+
+    let path = resolve_repo_path(&base, &owner, &repo);
     if service != "receive-pack" && service != "upload-pack" {
-        git_command(
-            &path,
-            &["update-server-info"],
-            std::iter::empty::<(String, String)>(),
-        )
-        .await
-        .unwrap();
+        // git can't even start in a directory that isn't there
+        if !path.is_dir() {
+            return Response::builder().status(404).body(Body::empty()).unwrap();
+        }
 
-        let mut file = match File::open(path) {
-            Ok(file) => file,
-            Err(_) => return Response::builder().status(404).body(Body::empty()).unwrap(),
-        };
+        match git_command(&git_binary, &path, &["update-server-info"], None).await {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => {
+                return internal_error(
+                    &headers,
+                    "git update-server-info failed",
+                    (output.status, String::from_utf8_lossy(&output.stderr)),
+                );
+            }
+            Err(err) => return internal_error(&headers, "Failed to run git update-server-info", err),
+        }
 
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
+        let contents = match read_repo_file(&headers, &path.join("info/refs")) {
+            Ok(contents) => contents,
+            Err(response) => return response,
+        };
 
         return Response::builder()
             .no_cache()
@@ -586,32 +1692,35 @@ pub async fn get_info_refs(
             .unwrap();
     }
 
-    let env = match headers.get("Git-Protocol").and_then(|v| v.to_str().ok()) {
-        Some("version=2") => ("GIT_PROTOCOL".to_string(), "version=2".to_string()),
-        _ => ("".to_string(), "".to_string()),
-    };
-
-    let envs = std::env::vars().chain([env]).collect::<Vec<_>>();
+    let protocol = git_protocol(&headers);
 
+    let config: &[&str] = match service {
+        "upload-pack" => UPLOAD_PACK_CONFIG,
+        _ => &[],
+    };
     let out = match git_command(
+        &git_binary,
         &path,
-        &[service, "--stateless-rpc", "--advertise-refs", "."],
-        envs,
+        [config, &[service, "--stateless-rpc", "--advertise-refs", "."]].concat(),
+        protocol.as_deref(),
     )
     .await
     {
         Ok(out) => out,
         Err(err) => {
-            tracing::error!(path, service, ?err, "Failed to run git command: {}", err);
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::empty())
-                .unwrap();
+            return internal_error(&headers, &format!("Failed to run git {service}"), err);
         }
     };
 
-    let body = packet_write(&format!("# service=git-{}\n", service));
-    let body = [body, packet_flush(), out.stdout].concat();
+    // v2 clients expect the capability advertisement right away, without the
+    // `# service=` preamble, same as git http-backend
+    let body = match service == "upload-pack" && is_protocol_v2(protocol.as_deref()) {
+        true => out.stdout,
+        false => {
+            let body = packet_write(&format!("# service=git-{}\n", service));
+            [body, packet_flush(), out.stdout].concat()
+        }
+    };
 
     Response::builder()
         .no_cache()
@@ -624,3 +1733,604 @@ pub async fn get_info_refs(
         .body(Body::from(body))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    use super::*;
+
+    /// A bare repo with `main` and `feature` branched off its first commit, removed on drop
+    struct TestRepo {
+        path: std::path::PathBuf,
+        repo: Repository,
+        first: git2::Oid,
+        main: git2::Oid,
+        feature: git2::Oid,
+    }
+
+    impl TestRepo {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("pws-git-test-{}", uuid::Uuid::new_v4()));
+            let repo = Repository::init_bare(&path).unwrap();
+            repo.set_head("refs/heads/main").unwrap();
+
+            let commit = |parent: Option<git2::Oid>, message: &str| {
+                let signature = git2::Signature::now("test", "test@example.com").unwrap();
+                let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+                let parents = parent.map(|parent| repo.find_commit(parent).unwrap());
+                repo.commit(None, &signature, &signature, message, &tree, parents.as_slice()).unwrap()
+            };
+
+            let first = commit(None, "first");
+            let main = commit(Some(first), "main");
+            let feature = commit(Some(first), "feature");
+            repo.reference("refs/heads/main", main, true, "").unwrap();
+            repo.reference("refs/heads/feature", feature, true, "").unwrap();
+
+            Self { path, repo, first, main, feature }
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// The git routes served on a free local port, the repositories under `base` and the
+    /// work trees of the client are removed on drop
+    struct TestServer {
+        base: PathBuf,
+        address: std::net::SocketAddr,
+    }
+
+    impl TestServer {
+        fn base() -> String {
+            std::env::temp_dir()
+                .join(format!("pws-git-test-{}", uuid::Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string()
+        }
+
+        fn start(state: AppState) -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let base = PathBuf::from(&state.base);
+
+            let app = router(state.clone(), &test_support::settings()).with_state(state);
+            tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+            Self { base, address }
+        }
+
+        /// Remote of a project for the git client, logged in as `username`
+        fn url(&self, username: &str, token: &str, owner: &str, repo: &str) -> String {
+            format!("http://{username}:{token}@{}/{owner}/{repo}", self.address)
+        }
+
+        async fn get(&self, path: &str, authorization: Option<&str>) -> Response<Body> {
+            let request = Request::get(format!("http://{}{path}", self.address));
+            let request = match authorization {
+                Some(authorization) => request.header("Authorization", authorization),
+                None => request,
+            };
+
+            hyper::Client::new().request(request.body(Body::empty()).unwrap()).await.unwrap()
+        }
+
+        /// Empty directory for the client to work in
+        fn work_tree(&self, name: &str) -> PathBuf {
+            let path = self.base.join("client").join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    /// The git client in `dir`, without the config or credentials of whoever runs the tests
+    fn git_client(dir: &StdPath) -> Command {
+        let mut command = Command::new("git");
+        command
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com");
+        command
+    }
+
+    async fn git(dir: &StdPath, args: &[&str]) -> Output {
+        git_client(dir).args(args).output().await.unwrap()
+    }
+
+    /// `Authorization` header of a git client
+    fn basic(username: &str, token: &str) -> String {
+        format!("Basic {}", BASE64.encode(format!("{username}:{token}").as_bytes()))
+    }
+
+    /// Commits `file` to the repository in `dir`, creating the repository first if needed
+    async fn commit(dir: &StdPath, file: &str) -> String {
+        if !dir.join(".git").exists() {
+            git(dir, &["init", "-q"]).await;
+        }
+        std::fs::write(dir.join(file), file).unwrap();
+        git(dir, &["add", file]).await;
+        git(dir, &["commit", "-q", "-m", file]).await;
+
+        String::from_utf8(git(dir, &["rev-parse", "HEAD"]).await.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn rebuilds_default_to_the_deploy_branch() {
+        let test = TestRepo::new();
+
+        assert_eq!(resolve_deploy_commit(&test.repo, None, None), Ok((test.main, Some("main".to_string()))));
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, Some("feature"), None),
+            Ok((test.feature, Some("feature".to_string()))),
+        );
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, None, Some("refs/heads/main")),
+            Ok((test.main, Some("main".to_string()))),
+        );
+    }
+
+    #[test]
+    fn rebuilds_commits_in_the_history_of_the_deploy_branch() {
+        let test = TestRepo::new();
+
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, None, Some(&test.first.to_string())),
+            Ok((test.first, Some("main".to_string()))),
+        );
+    }
+
+    #[test]
+    fn refuses_other_branches_without_preview_environments() {
+        let test = TestRepo::new();
+
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, None, Some("refs/heads/feature")),
+            Err(DeployRefError::NotDeployBranch { requested: "feature".to_string(), deploy_branch: "main".to_string() }),
+        );
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, None, Some(&test.feature.to_string())),
+            Err(DeployRefError::NotDeployBranch {
+                requested: test.feature.to_string(),
+                deploy_branch: "main".to_string(),
+            }),
+        );
+    }
+
+    #[test]
+    fn refuses_refs_that_dont_exist() {
+        let test = TestRepo::new();
+
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, Some("missing"), None),
+            Err(DeployRefError::Unknown("missing".to_string())),
+        );
+        assert_eq!(
+            resolve_deploy_commit(&test.repo, None, Some("0123456")),
+            Err(DeployRefError::Unknown("0123456".to_string())),
+        );
+    }
+
+    /// First command of a push, as git sends it
+    fn push_head(capabilities: &str) -> Vec<u8> {
+        let command = format!("{} {} refs/heads/main\0{capabilities}\n", "0".repeat(40), "1".repeat(40));
+        [packet_write(&command), b"0000PACK".to_vec()].concat()
+    }
+
+    #[test]
+    fn requested_capabilities_come_from_the_first_command() {
+        let head = push_head("report-status side-band-64k agent=git/2.43.0");
+
+        assert_eq!(requested_capabilities(&head), ["report-status", "side-band-64k", "agent=git/2.43.0"]);
+        assert!(client_wants_sideband(&head));
+        assert!(client_wants_sideband(&push_head("report-status side-band")));
+    }
+
+    #[test]
+    fn no_sideband_unless_the_client_asked_for_it() {
+        assert!(!client_wants_sideband(&push_head("report-status")));
+        assert!(!client_wants_sideband(b"0000"));
+        assert!(!client_wants_sideband(b""));
+        // cut off before the end of the first pkt-line
+        assert!(!client_wants_sideband(&push_head("side-band-64k")[..20]));
+    }
+
+    #[tokio::test]
+    async fn git_doesnt_get_the_server_environment() {
+        std::env::set_var("PWS_TEST_DATABASE_PASSWORD", "hunter2");
+
+        // `env` in place of git prints the environment the child gets
+        let output = git_command("env", std::env::temp_dir(), Vec::<&str>::new(), Some("version=2")).await.unwrap();
+        let environment = String::from_utf8_lossy(&output.stdout);
+
+        assert!(!environment.contains("PWS_TEST_DATABASE_PASSWORD"));
+        assert!(environment.lines().any(|line| line == "GIT_PROTOCOL=version=2"));
+    }
+
+    #[test]
+    fn every_build_gets_its_own_checkout() {
+        let test = TestRepo::new();
+        let head = |source: &BuildSource| Repository::open(&**source).unwrap().head().unwrap().target();
+
+        let running = checkout_build_source(&test.path, test.first, CloneStrategy::Clone).unwrap();
+        let next = checkout_build_source(&test.path, test.main, CloneStrategy::Clone).unwrap();
+
+        // checking out the next push leaves the running build's files alone
+        assert_ne!(&*running, &*next);
+        assert_eq!(head(&running), Some(test.first));
+        assert_eq!(head(&next), Some(test.main));
+
+        // a finished build's clone is kept for the next checkout
+        drop(running);
+        assert!(test.path.join(REUSABLE_CLONE).join(".git").is_dir());
+        let reused = checkout_build_source(&test.path, test.feature, CloneStrategy::Clone).unwrap();
+        assert!(!test.path.join(REUSABLE_CLONE).exists());
+        assert_eq!(head(&reused), Some(test.feature));
+
+        drop(next);
+        drop(reused);
+        assert_eq!(std::fs::read_dir(test.path.join(BUILD_SOURCES_DIR)).unwrap().count(), 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn owner_tokens_push_to_every_repo_of_their_owner(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        let alice = test_support::user(&pool, "alice").await;
+        let alice_id = test_support::owner(&pool, "alice", &alice).await;
+        let bob = test_support::user(&pool, "bob").await;
+        let bob_id = test_support::owner(&pool, "bob", &bob).await;
+        for (owner_id, owner, name) in [(alice_id, "alice", "one"), (alice_id, "alice", "two"), (bob_id, "bob", "three")] {
+            test_support::project(&pool, owner_id, name).await;
+            test_support::repository(&base, owner, name);
+        }
+        let token = test_support::token(&pool, None, Some(alice_id)).await;
+        let server = TestServer::start(state);
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        let push = |username: &str, owner: &str, repo: &str| {
+            let url = server.url(username, &token, owner, repo);
+            let work_tree = work_tree.clone();
+            async move { git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success() }
+        };
+
+        assert!(push("alice", "alice", "one").await);
+        assert!(push("alice", "alice", "two").await);
+        // neither as alice nor as bob on bob's repo
+        assert!(!push("alice", "bob", "three").await);
+        assert!(!push("bob", "bob", "three").await);
+        assert!(ref_snapshot(&resolve_repo_path(&base, "bob", "three")).is_empty());
+    }
+
+    /// alice's project `site` with an empty repository, and a token for pushing to it
+    async fn site(pool: &PgPool, base: &str) -> (Uuid, String) {
+        let alice = test_support::user(pool, "alice").await;
+        let owner_id = test_support::owner(pool, "alice", &alice).await;
+        let project_id = test_support::project(pool, owner_id, "site").await;
+        test_support::repository(base, "alice", "site");
+
+        (project_id, test_support::token(pool, Some(project_id), None).await)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn partial_clones_fetch_missing_blobs_by_id(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        let (_, token) = site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let url = server.url("alice", &token, "alice", "site");
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        assert!(git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success());
+        let blob = String::from_utf8(git(&work_tree, &["rev-parse", "HEAD:index.html"]).await.stdout).unwrap();
+        let blob = blob.trim();
+
+        let partial = server.work_tree("partial");
+        let clone = git(&partial, &["clone", "-q", "--filter=blob:none", "--no-checkout", &url, "."]).await;
+        assert!(clone.status.success(), "{}", String::from_utf8_lossy(&clone.stderr));
+        let missing = |partial: PathBuf| async move {
+            let objects = git(&partial, &["rev-list", "--objects", "--missing=print", "HEAD"]).await.stdout;
+            String::from_utf8(objects)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix('?'))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(missing(partial.clone()).await, [blob]);
+
+        // reading the blob fetches just that one from the promisor remote
+        let content = git(&partial, &["cat-file", "-p", blob]).await;
+        assert_eq!(String::from_utf8_lossy(&content.stdout), "index.html");
+        assert!(missing(partial).await.is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn dumb_clients_clone_from_packs(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        let (_, token) = site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let url = server.url("alice", &token, "alice", "site");
+
+        let work_tree = server.work_tree("site");
+        let head = commit(&work_tree, "index.html").await;
+        assert!(git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success());
+        git(&resolve_repo_path(&base, "alice", "site"), &["repack", "-a", "-d", "-q"]).await;
+
+        let packs = server.get("/alice/site/objects/info/packs", Some(&basic("alice", &token))).await;
+        assert_eq!(packs.status(), StatusCode::OK);
+        let packs = hyper::body::to_bytes(packs.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&packs).lines().any(|line| line.starts_with("P pack-")));
+
+        let dumb = server.work_tree("dumb");
+        let clone = git_client(&dumb)
+            .env("GIT_SMART_HTTP", "0")
+            .args(["clone", "-q", &url, "."])
+            .output()
+            .await
+            .unwrap();
+        assert!(clone.status.success(), "{}", String::from_utf8_lossy(&clone.stderr));
+        let cloned = String::from_utf8(git(&dumb, &["rev-parse", "HEAD"]).await.stdout).unwrap();
+        assert_eq!(cloned.trim(), head);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn truncated_pushes_leave_the_repo_alone(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), &base, test_support::settings()).await;
+        test_support::accept_builds(build_queue);
+        let (project_id, token) = site(&pool, &base).await;
+        let server = TestServer::start(state.clone());
+        let path = resolve_repo_path(&base, "alice", "site");
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        let url = server.url("alice", &token, "alice", "site");
+        assert!(git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success());
+        let refs = ref_snapshot(&path);
+
+        // the connection drops in the middle of the pack
+        let sent = [push_head("report-status"), b"\0\0\0\x02\0\0\0\x03".to_vec()].concat();
+        let body = Body::wrap_stream(futures_util::stream::iter([
+            Ok(Bytes::from(sent)),
+            Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "client went away")),
+        ]));
+        let request = Request::post("/alice/site/git-receive-pack")
+            .header("Authorization", basic("alice", &token))
+            .header("Content-Type", "application/x-git-receive-pack-request")
+            .body(body)
+            .unwrap();
+        let app = router(state.clone(), &test_support::settings()).with_state(state);
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ref_snapshot(&path), refs);
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+        // git dropped the quarantine the partial pack went to
+        let objects = std::fs::read_dir(path.join("objects")).unwrap();
+        assert!(!objects.flatten().any(|entry| entry.file_name().to_string_lossy().starts_with("incoming-")));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn pushes_past_the_queue_limit_are_turned_away(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let mut settings = test_support::settings();
+        settings.build.maxqueue = 1;
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), &base, settings).await;
+        test_support::accept_builds(build_queue);
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        let first = test_support::project(&pool, owner_id, "first").await;
+        let second = test_support::project(&pool, owner_id, "second").await;
+        test_support::repository(&base, "alice", "first");
+        test_support::repository(&base, "alice", "second");
+        let token = test_support::token(&pool, None, Some(owner_id)).await;
+        let server = TestServer::start(state);
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        let push = |repo: &str| {
+            let url = server.url("alice", &token, "alice", repo);
+            let work_tree = work_tree.clone();
+            async move { git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await }
+        };
+
+        // nothing runs builds, the first one stays in the queue
+        assert!(push("first").await.status.success());
+        assert_eq!(test_support::builds(&pool, first).await, 1);
+
+        let rejected = push("second").await;
+        assert!(!rejected.status.success());
+        assert!(String::from_utf8_lossy(&rejected.stderr).contains("The build queue is full (1 builds waiting)"));
+        assert_eq!(test_support::builds(&pool, second).await, 0);
+        assert!(ref_snapshot(&resolve_repo_path(&base, "alice", "second")).is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn anonymous_clones_are_challenged(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        site(&pool, &base).await;
+        let server = TestServer::start(state);
+
+        let response = server.get("/alice/site/info/refs?service=git-upload-pack", None).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Basic realm=\"PWS Git\", charset=\"UTF-8\"");
+    }
+
+    #[test]
+    fn repo_names_come_with_or_without_the_suffix() {
+        assert_eq!(canonical_repo_name("booker"), "booker");
+        assert_eq!(canonical_repo_name("booker.git"), "booker");
+        assert_eq!(canonical_repo_name("my.site"), "my.site");
+        assert_eq!(canonical_repo_name("my.site.git"), "my.site");
+        // only one suffix is the clone url's, the rest is part of the name
+        assert_eq!(canonical_repo_name("booker.git.git"), "booker.git");
+        assert_eq!(canonical_repo_name("booker.GIT"), "booker.GIT");
+        assert_eq!(canonical_repo_name(".git"), "");
+    }
+
+    #[test]
+    fn repo_paths_always_have_the_suffix() {
+        let path = PathBuf::from("/srv/git/alice/booker.git");
+
+        assert_eq!(resolve_repo_path("/srv/git", "alice", "booker"), path);
+        assert_eq!(resolve_repo_path("/srv/git", "alice", "booker.git"), path);
+        assert_eq!(resolve_repo_path("/srv/git/", "alice", "booker"), path);
+        assert_eq!(resolve_repo_path("/srv/git", "alice", "my.site"), PathBuf::from("/srv/git/alice/my.site.git"));
+        assert_eq!(resolve_repo_path("/srv/git", "alice", "booker.git.git"), PathBuf::from("/srv/git/alice/booker.git.git"));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn pushes_to_frozen_projects_are_kept_but_not_built(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), &base, test_support::settings()).await;
+        test_support::accept_builds(build_queue);
+        let (project_id, token) = site(&pool, &base).await;
+        sqlx::query("UPDATE projects SET pinned_commit = $1 WHERE id = $2")
+            .bind("1".repeat(40))
+            .bind(project_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let server = TestServer::start(state);
+
+        let work_tree = server.work_tree("site");
+        let head = commit(&work_tree, "index.html").await;
+        let url = server.url("alice", &token, "alice", "site");
+        let push = git(&work_tree, &["push", &url, "HEAD:refs/heads/main"]).await;
+
+        assert!(push.status.success());
+        assert!(String::from_utf8_lossy(&push.stderr).contains("Project is frozen at 1111111"));
+        let refs = ref_snapshot(&resolve_repo_path(&base, "alice", "site"));
+        assert_eq!(refs["refs/heads/main"].to_string(), head);
+        assert_eq!(test_support::builds(&pool, project_id).await, 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn malformed_credentials_are_challenged_again(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let not_utf8 = format!("Basic {}", BASE64.encode(b"alice:\xff\xfe"));
+
+        for authorization in ["Basic !!not base64!!", "Basic", "Bearer", "Digest abc", not_utf8.as_str()] {
+            let response = server.get("/alice/site/info/refs?service=git-upload-pack", Some(authorization)).await;
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{authorization}");
+            assert!(response.headers().contains_key("WWW-Authenticate"), "{authorization}");
+        }
+    }
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        let token = "k3TqZr8vLm2Xw9Pa";
+        let hash = hash_token(token).unwrap();
+
+        assert_eq!(verify_token(token, &hash, false), TokenMatch::Hashed);
+        for near_miss in ["k3TqZr8vLm2Xw9PA", "k3TqZr8vLm2Xw9P", "k3TqZr8vLm2Xw9Pa ", ""] {
+            assert_eq!(verify_token(near_miss, &hash, false), TokenMatch::None, "{near_miss:?}");
+            assert_eq!(verify_token(near_miss, token, true), TokenMatch::None, "{near_miss:?}");
+        }
+
+        // tokens stored before hashing only while they are still accepted
+        assert_eq!(verify_token(token, token, true), TokenMatch::Plaintext);
+        assert_eq!(verify_token(token, token, false), TokenMatch::None);
+        // the hash itself isn't a password
+        assert_eq!(verify_token(&hash, &hash, true), TokenMatch::None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn pushes_to_other_branches_are_not_built(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), &base, test_support::settings()).await;
+        test_support::accept_builds(build_queue);
+        let (project_id, token) = site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let url = server.url("alice", &token, "alice", "site");
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        assert!(git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success());
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+
+        commit(&work_tree, "draft.html").await;
+        let push = git(&work_tree, &["push", &url, "HEAD:refs/heads/feature"]).await;
+
+        assert!(push.status.success());
+        assert!(String::from_utf8_lossy(&push.stderr).contains("Only pushes to main are deployed"));
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn deleting_a_branch_builds_nothing(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), &base, test_support::settings()).await;
+        test_support::accept_builds(build_queue);
+        let (project_id, token) = site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let url = server.url("alice", &token, "alice", "site");
+
+        let work_tree = server.work_tree("site");
+        commit(&work_tree, "index.html").await;
+        let push = git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main", "HEAD:refs/heads/feature"]).await;
+        assert!(push.status.success());
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+
+        let delete = git(&work_tree, &["push", "-q", &url, ":refs/heads/feature"]).await;
+
+        assert!(delete.status.success(), "{}", String::from_utf8_lossy(&delete.stderr));
+        let refs = ref_snapshot(&resolve_repo_path(&base, "alice", "site"));
+        assert!(refs.contains_key("refs/heads/main") && !refs.contains_key("refs/heads/feature"));
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn objects_are_served_without_the_suffix(pool: sqlx::PgPool) {
+        let base = TestServer::base();
+        let state = test_support::app_state(pool.clone(), &base).await;
+        let (_, token) = site(&pool, &base).await;
+        let server = TestServer::start(state);
+        let authorization = basic("alice", &token);
+
+        let work_tree = server.work_tree("site");
+        let head = commit(&work_tree, "index.html").await;
+        let url = server.url("alice", &token, "alice", "site");
+        assert!(git(&work_tree, &["push", "-q", &url, "HEAD:refs/heads/main"]).await.status.success());
+
+        // small pushes are unpacked into loose objects
+        let loose = server.get(&format!("/alice/site/objects/{}/{}", &head[..2], &head[2..]), Some(&authorization)).await;
+        assert_eq!(loose.status(), StatusCode::OK);
+        assert_eq!(loose.headers()["Content-Type"], "application/x-git-loose-object");
+
+        git(&resolve_repo_path(&base, "alice", "site"), &["repack", "-a", "-d", "-q"]).await;
+        let packs = server.get("/alice/site/objects/info/packs", Some(&authorization)).await;
+        let packs = String::from_utf8(hyper::body::to_bytes(packs.into_body()).await.unwrap().to_vec()).unwrap();
+        let pack = packs.lines().find_map(|line| line.strip_prefix("P ")).unwrap();
+
+        let response = server.get(&format!("/alice/site/objects/pack/{pack}"), Some(&authorization)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().starts_with(b"PACK"));
+        let index = format!("/alice/site/objects/pack/{}", pack.replace(".pack", ".idx"));
+        assert_eq!(server.get(&index, Some(&authorization)).await.status(), StatusCode::OK);
+    }
+}
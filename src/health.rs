@@ -0,0 +1,185 @@
+use std::path::Path;
+use std::time::Duration;
+
+use bollard::Docker;
+use hyper::{header::HOST, Body, Client, Request, StatusCode};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{configuration::Settings, get_env, git::canonical_repo_name};
+
+/// Network the project containers join so traefik, and this prober, can reach them
+const PROJECT_NETWORK: &str = "pemasak";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthStatus {
+    Healthy,
+    Unhealthy,
+    /// never deployed, or not probed yet
+    Unknown,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContainerState {
+    Missing,
+    Stopped,
+    Running,
+}
+
+/// Status of a project that has been deployed at least once. `response` is `None` when the
+/// app didn't answer in time. Anything below 500 counts as up, a 404 on the probe path still
+/// means the app is serving requests.
+pub fn classify(container: ContainerState, response: Option<StatusCode>) -> HealthStatus {
+    match (container, response) {
+        (ContainerState::Missing | ContainerState::Stopped, _) => HealthStatus::Unhealthy,
+        (ContainerState::Running, None) => HealthStatus::Unhealthy,
+        (ContainerState::Running, Some(status)) if status.is_server_error() => HealthStatus::Unhealthy,
+        (ContainerState::Running, Some(_)) => HealthStatus::Healthy,
+    }
+}
+
+/// Probes every deployed project on an interval and records the result on the project
+pub async fn run_prober(pool: PgPool, config: Settings) {
+    if !config.health.enabled {
+        tracing::info!("Health prober is disabled");
+        return;
+    }
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't start health prober: Failed to connect to docker");
+            return;
+        }
+    };
+
+    let client = Client::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.health.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        // only projects with a successful build have something to probe
+        let projects = match sqlx::query_as::<_, (Uuid, String, String, String)>(
+            r#"SELECT projects.id, project_owners.name, projects.name, projects.build_type
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.deleted_at IS NULL
+                 AND NOT projects.sleeping
+                 AND EXISTS (
+                   SELECT 1 FROM builds
+                   WHERE builds.project_id = projects.id AND builds.status = 'successful'
+                 )
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        {
+            Ok(projects) => projects,
+            Err(err) => {
+                tracing::error!(?err, "Can't probe projects: Failed to query database");
+                continue;
+            }
+        };
+
+        for (id, owner, project, build_type) in projects {
+            let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+
+            let status = match build_type.as_str() {
+                // served by the app itself, up as long as the site is published
+                "static" => match Path::new(&config.build.staticroot).join(&container_name).is_dir() {
+                    true => HealthStatus::Healthy,
+                    false => HealthStatus::Unhealthy,
+                },
+                _ => probe_container(&docker, &client, &container_name, &config).await,
+            };
+
+            if let Err(err) = sqlx::query(
+                "UPDATE projects SET health_status = $1, last_checked_at = now() WHERE id = $2",
+            )
+            .bind(status.as_str())
+            .bind(id)
+            .execute(&pool)
+            .await
+            {
+                tracing::error!(?err, "Can't update project health: Failed to query database");
+            }
+
+            tracing::debug!(container_name, status = status.as_str(), "Project probed");
+        }
+    }
+}
+
+async fn probe_container(
+    docker: &Docker,
+    client: &Client<hyper::client::HttpConnector, Body>,
+    container_name: &str,
+    config: &Settings,
+) -> HealthStatus {
+    let container = match docker.inspect_container(container_name, None).await {
+        Ok(container) => container,
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+            return classify(ContainerState::Missing, None);
+        }
+        Err(err) => {
+            tracing::warn!(?err, container_name, "Can't probe project: Failed to inspect container");
+            return HealthStatus::Unknown;
+        }
+    };
+
+    if !container.state.as_ref().and_then(|state| state.running).unwrap_or(false) {
+        return classify(ContainerState::Stopped, None);
+    }
+
+    let ip = container
+        .network_settings
+        .and_then(|settings| settings.networks)
+        .and_then(|networks| networks.get(PROJECT_NETWORK).and_then(|network| network.ip_address.clone()))
+        .filter(|ip| !ip.is_empty());
+
+    let Some(ip) = ip else {
+        tracing::warn!(container_name, "Can't probe project: Container has no address");
+        return HealthStatus::Unknown;
+    };
+
+    // the labels traefik routes with know the port and the healthcheck path from the build config
+    let labels = container.config.and_then(|config| config.labels).unwrap_or_default();
+    let port = labels
+        .get(&format!("traefik.http.services.{container_name}.loadbalancer.server.port"))
+        .cloned()
+        .unwrap_or_else(|| "80".to_string());
+    let path = labels
+        .get(&format!("traefik.http.services.{container_name}.loadbalancer.healthcheck.path"))
+        .cloned()
+        .unwrap_or_else(|| config.health.path.clone());
+
+    let request = match Request::get(format!("http://{ip}:{port}{path}"))
+        .header(HOST, format!("{container_name}.{}", get_env::domain()))
+        .body(Body::empty())
+    {
+        Ok(request) => request,
+        Err(err) => {
+            tracing::warn!(?err, container_name, "Can't probe project: Invalid probe request");
+            return HealthStatus::Unknown;
+        }
+    };
+
+    let response = tokio::time::timeout(Duration::from_millis(config.health.timeout), client.request(request))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .map(|response| response.status());
+
+    classify(ContainerState::Running, response)
+}
@@ -1,12 +1,28 @@
+pub mod admin;
 pub mod auth;
+pub mod build_cache;
+pub mod build_config;
 pub mod configuration;
+pub mod deploy_webhook;
 pub mod docker;
+pub mod dockerfile;
 pub mod dockerfile_templates;
 pub mod get_env;
 pub mod git;
+pub mod health;
+pub mod live_log;
+pub mod metrics;
 pub mod owner;
+pub mod pagination;
+pub mod placeholder;
 pub mod projects;
 pub mod queue;
+pub mod rate_limit;
+pub mod sleep;
+pub mod static_site;
 pub mod startup;
 pub mod telemetry;
+#[cfg(test)]
+pub mod test_support;
+pub mod usage;
 pub mod dashboard;
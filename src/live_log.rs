@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Lines a watcher may fall behind by before it misses some
+const LINE_CAPACITY: usize = 1024;
+
+/// Output of a running build, for watchers joining at any point
+pub struct LiveLog {
+    sender: broadcast::Sender<String>,
+    /// everything sent so far, replayed to new watchers
+    backlog: Mutex<String>,
+}
+
+impl LiveLog {
+    fn push(&self, line: &str) {
+        // under the backlog lock, so a watcher gets every line exactly once
+        let mut backlog = self.backlog.lock().unwrap();
+        backlog.push_str(line);
+        backlog.push('\n');
+        let _ = self.sender.send(line.to_string());
+    }
+}
+
+lazy_static! {
+    static ref LIVE_LOGS: Mutex<HashMap<Uuid, Arc<LiveLog>>> = Mutex::new(HashMap::new());
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<LiveLog>;
+}
+
+/// Unregisters the log when the build ends, also when its future is dropped by a timeout or
+/// a cancel
+struct Registration(Uuid);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        LIVE_LOGS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Runs a build with its output going to a live log watchers can subscribe to by build id.
+/// Watchers see the log close once the build is done.
+pub async fn scope<F: Future>(build_id: Uuid, build: F) -> F::Output {
+    let (sender, _) = broadcast::channel(LINE_CAPACITY);
+    let log = Arc::new(LiveLog {
+        sender,
+        backlog: Mutex::new(String::new()),
+    });
+
+    LIVE_LOGS.lock().unwrap().insert(build_id, Arc::clone(&log));
+    let _registration = Registration(build_id);
+
+    CURRENT.scope(log, build).await
+}
+
+/// Adds a line to the log of the build running on this task, outside of a build it's a no-op
+pub fn push(line: &str) {
+    let _ = CURRENT.try_with(|log| log.push(line));
+}
+
+/// Adds every line of `text`, for output that was collected before it could be streamed
+pub fn push_lines(text: &str) {
+    for line in text.lines() {
+        push(line);
+    }
+}
+
+/// The output of a running build so far and the lines that follow, `None` when the build
+/// isn't running
+pub fn subscribe(build_id: Uuid) -> Option<(String, broadcast::Receiver<String>)> {
+    let log = LIVE_LOGS.lock().unwrap().get(&build_id).cloned()?;
+
+    let backlog = log.backlog.lock().unwrap();
+    Some((backlog.clone(), log.sender.subscribe()))
+}
@@ -1,12 +1,14 @@
 use hyper::{client::HttpConnector, Body};
 use pemasak_infra::{
-    configuration,
+    configuration, health, sleep,
+    projects::environ_cipher,
     queue::{build_queue_handler, BuildQueue},
     startup, telemetry,
 };
 use sqlx::postgres::PgPoolOptions;
 use std::{net::TcpListener, path::Path, process};
 use tokio::fs::OpenOptions;
+use tokio_util::sync::CancellationToken;
 
 type Client = hyper::client::Client<HttpConnector, Body>;
 
@@ -20,6 +22,12 @@ async fn main() {
             process::exit(1);
         }
     };
+    tracing::info!(config = %config.sanitized(), "Loaded configuration");
+
+    if let Err(err) = environ_cipher::init(&config.environ) {
+        tracing::error!(err, "Invalid environ encryption key");
+        process::exit(1);
+    }
 
     let pool = match PgPoolOptions::new()
         .max_connections(300) 
@@ -43,6 +51,16 @@ async fn main() {
         process::exit(1);
     }
 
+    // values stored before the key was set, or with an older one
+    match environ_cipher::encrypt_existing(&pool).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "Encrypted stored environment variables"),
+        Err(err) => {
+            tracing::error!(?err, "Failed to encrypt stored environment variables");
+            process::exit(1);
+        }
+    }
+
     // Atlas migration check removed - using schema.sql initialization instead
 
     // check docker permissions
@@ -90,20 +108,55 @@ async fn main() {
     }
 
     let (build_queue, build_channel) = BuildQueue::new(config.build.max, pool.clone(), config.clone());
+    let build_queue_load = build_queue.load.clone();
+    let build_control = build_queue.control();
+
+    // cancelled by SIGTERM or ctrl-c, the server stops and the build queue drains
+    let shutdown = CancellationToken::new();
+    tokio::spawn(startup::shutdown_signal(shutdown.clone()));
+
+    let build_queue_task = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            build_queue_handler(build_queue, shutdown).await;
+        })
+    };
+
+    {
+        let pool = pool.clone();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            health::run_prober(pool, config).await;
+        });
+    }
 
-    tokio::spawn(async move {
-        build_queue_handler(build_queue).await;
-    });
+    {
+        let pool = pool.clone();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            sleep::run_sleeper(pool, config).await;
+        });
+    }
 
     let state = startup::AppState {
         base: config.git.base.clone(),
         git_auth: config.git.auth,
+        git_plaintext_tokens: config.git.plaintexttokens,
         sso: config.auth.sso.clone(),
         client: Client::new(),
         domain: config.domain(),
         build_channel,
         pool,
         secure: config.application.secure,
+        tree_ignore: config.git.treeignore.clone(),
+        tree_max_entries: config.git.treemaxentries,
+        tree_max_recursive: config.git.treemaxrecursive,
+        raw_stream_threshold: config.git.rawstreamthreshold,
+        git_binary: config.git.binary.clone(),
+        build_queue_load,
+        build_control,
     };
 
     let addr_string = config.address_string();
@@ -124,8 +177,15 @@ async fn main() {
         }
     };
 
-    if let Err(err) = startup::run(listener, state, config).await {
+    if let Err(err) = startup::run(listener, state, config, shutdown.clone()).await {
         tracing::error!(?err, "Failed to start server on address {}", addr_string);
         process::exit(1);
     };
+
+    // the server only returns once it's shutting down, running builds get to finish
+    shutdown.cancel();
+    if let Err(err) = build_queue_task.await {
+        tracing::error!(?err, "Build queue stopped unexpectedly");
+    }
+    tracing::info!("Shut down");
 }
@@ -0,0 +1,80 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds in seconds of the build duration histogram buckets
+const DURATION_BUCKETS: [u64; 7] = [10, 30, 60, 120, 300, 600, 1200];
+
+/// Counters of the build queue since the server started, scraped from `/metrics`
+#[derive(Default, Debug)]
+pub struct BuildMetrics {
+    pub enqueued: AtomicU64,
+    pub succeeded: AtomicU64,
+    pub failed: AtomicU64,
+    pub timed_out: AtomicU64,
+    pub cancelled: AtomicU64,
+    /// turned away by a full queue
+    pub rejected: AtomicU64,
+    /// cumulative, the builds that took at most the bucket's bound
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_count: AtomicU64,
+    duration_sum_ms: AtomicU64,
+}
+
+impl BuildMetrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long a build ran, cancelled builds aren't recorded
+    pub fn observe_duration(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            if seconds <= bound as f64 {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.duration_count.fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_ms.fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// The counters plus the current queue gauges in the Prometheus text format
+    pub fn render(&self, queue_depth: usize, available_slots: usize) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let counters = [
+            ("pws_builds_enqueued_total", "Builds added to the queue", &self.enqueued),
+            ("pws_builds_succeeded_total", "Builds that deployed", &self.succeeded),
+            ("pws_builds_failed_total", "Builds that failed", &self.failed),
+            ("pws_builds_timed_out_total", "Builds stopped for taking too long", &self.timed_out),
+            ("pws_builds_cancelled_total", "Builds cancelled by a user", &self.cancelled),
+            ("pws_builds_rejected_total", "Builds turned away by a full queue", &self.rejected),
+        ];
+        for (name, help, counter) in counters {
+            let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n", load(counter));
+        }
+
+        let gauges = [
+            ("pws_build_queue_depth", "Builds waiting for a slot", queue_depth),
+            ("pws_build_slots_available", "Builds that can start right now", available_slots),
+        ];
+        for (name, help, value) in gauges {
+            let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+        }
+
+        let name = "pws_build_duration_seconds";
+        let _ = write!(out, "# HELP {name} How long finished builds ran\n# TYPE {name} histogram\n");
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {}", load(bucket));
+        }
+        let count = load(&self.duration_count);
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {}", load(&self.duration_sum_ms) as f64 / 1000.0);
+        let _ = writeln!(out, "{name}_count {count}");
+
+        out
+    }
+}
@@ -0,0 +1,106 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct AddOwnerMemberRequest {
+    pub username: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Adds a user to an owner namespace. Unlike a project share this gives access to every
+/// project of the owner, so only existing members can do it.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(owner): Path<String>,
+    Json(req): Json<AddOwnerMemberRequest>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return json_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let owner_id = match sqlx::query_as::<_, (Uuid,)>(
+        r#"SELECT project_owners.id
+           FROM project_owners
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE project_owners.name = $1
+             AND project_owners.deleted_at IS NULL
+             AND users_owners.user_id = $2
+        "#,
+    )
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some((owner_id,))) => owner_id,
+        Ok(None) => {
+            return json_response(StatusCode::NOT_FOUND, "Owner does not exist or you are not a member");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project owner: Failed to query database");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let target_user_id = match sqlx::query_as::<_, (Uuid,)>(
+        "SELECT id FROM users WHERE username = $1 AND deleted_at IS NULL",
+    )
+    .bind(&req.username)
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some((user_id,))) => user_id,
+        Ok(None) => return json_response(StatusCode::BAD_REQUEST, "User not found"),
+        Err(err) => {
+            tracing::error!(?err, "Can't get user: Failed to query database");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let inserted = sqlx::query(
+        r#"INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)
+           ON CONFLICT DO NOTHING"#,
+    )
+    .bind(target_user_id)
+    .bind(owner_id)
+    .execute(&pool)
+    .await;
+
+    match inserted {
+        Ok(result) if result.rows_affected() == 0 => {
+            json_response(StatusCode::CONFLICT, "User is already a member of this owner")
+        }
+        Ok(_) => {
+            tracing::info!(owner, username = req.username, added_by = %user.id, "Owner member added");
+            json_response(StatusCode::OK, "Member added successfully")
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't insert users_owners: Failed to insert into database");
+            json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to insert into database")
+        }
+    }
+}
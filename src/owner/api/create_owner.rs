@@ -0,0 +1,127 @@
+use axum::{extract::State, response::Response, Json};
+use garde::{Unvalidated, Validate};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+/// Owner names end up in repository paths and container names, same rules as project names
+#[derive(Deserialize, Validate, Debug)]
+pub struct CreateOwnerRequest {
+    #[garde(length(min = 1, max = 128), pattern(r"^[a-z0-9_-]+$"))]
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CreateOwnerResponse {
+    id: Uuid,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Creates an owner namespace with the current user as its first member
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<Unvalidated<CreateOwnerRequest>>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized".to_string());
+    };
+
+    let CreateOwnerRequest { name } = match req.validate(&()) {
+        Ok(valid) => valid.into_inner(),
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => {
+            tracing::error!(?err, "Can't insert project owner: Failed to begin transaction");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to begin transaction".to_string());
+        }
+    };
+
+    // the existence check and the insert share the transaction, a concurrent create of the
+    // same name is caught by the lock
+    match sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(format!("project_owners:{name}"))
+        .execute(&mut *tx)
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!(?err, "Can't insert project owner: Failed to acquire lock");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database".to_string());
+        }
+    }
+
+    match sqlx::query("SELECT 1 FROM project_owners WHERE name = $1 AND deleted_at IS NULL")
+        .bind(&name)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return error_response(StatusCode::CONFLICT, format!("Owner {name} already exists"));
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get existing project owner: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database".to_string());
+        }
+    }
+
+    let owner_id = Uuid::from(Ulid::new());
+
+    if let Err(err) = sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2)")
+        .bind(owner_id)
+        .bind(&name)
+        .execute(&mut *tx)
+        .await
+    {
+        tracing::error!(?err, "Can't insert project owner: Failed to insert into database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to insert into database".to_string());
+    }
+
+    if let Err(err) = sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+        .bind(user.id)
+        .bind(owner_id)
+        .execute(&mut *tx)
+        .await
+    {
+        tracing::error!(?err, "Can't insert users_owners: Failed to insert into database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to insert into database".to_string());
+    }
+
+    if let Err(err) = tx.commit().await {
+        tracing::error!(?err, "Can't insert project owner: Failed to commit transaction");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to commit transaction".to_string());
+    }
+
+    tracing::info!(owner = name, user_id = %user.id, "Owner created");
+
+    let json = serde_json::to_string(&CreateOwnerResponse { id: owner_id, name }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
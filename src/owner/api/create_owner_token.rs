@@ -0,0 +1,150 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{auth::Auth, git::hash_token, startup::AppState};
+use sqlx::Row;
+
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Serialize, Debug)]
+struct CreateOwnerTokenResponse {
+    id: Uuid,
+    owner_name: String,
+    git_username: String,
+    git_password: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(owner): Path<String>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Unauthorized".to_string(),
+        }).unwrap();
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    };
+
+    // only members of the owner may mint credentials for it
+    let owner_id: Uuid = match sqlx::query(
+        r#"SELECT project_owners.id
+           FROM project_owners
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE project_owners.name = $1
+             AND users_owners.user_id = $2
+             AND project_owners.deleted_at IS NULL
+        "#,
+    )
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(row)) => row.get::<Uuid, _>("id"),
+        Ok(None) => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Owner does not exist or you don't have access".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project_owners: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Internal server error".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let token = (0..TOKEN_LENGTH)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect::<String>();
+
+    // only the hash is stored, the token is shown once in the response
+    let token_hash = match hash_token(&token) {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!(?err, "Can't create owner token: Failed to hash token");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to create token".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let token_id = Uuid::from(Ulid::new());
+    if let Err(err) = sqlx::query(
+        "INSERT INTO api_token (id, owner_id, token) VALUES ($1, $2, $3)",
+    )
+    .bind(token_id)
+    .bind(owner_id)
+    .bind(&token_hash)
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't insert api_token: Failed to insert into database");
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to create token".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    let json = serde_json::to_string(&CreateOwnerTokenResponse {
+        id: token_id,
+        owner_name: owner.clone(),
+        git_username: owner,
+        git_password: token,
+        message: "Token created successfully. It can push to every project of this owner and won't be shown again.".to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
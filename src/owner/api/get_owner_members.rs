@@ -0,0 +1,99 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct OwnerMember {
+    user_id: Uuid,
+    username: String,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct OwnerMembersResponse {
+    members: Vec<OwnerMember>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Members of an owner namespace, they have access to every project of the owner
+#[tracing::instrument(skip(auth, pool))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path(owner): Path<String>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    // only members get to see who else is a member
+    let members = sqlx::query_as::<_, (Uuid, String, String, DateTime<Utc>)>(
+        r#"SELECT users.id, users.username, users.name, users_owners.created_at
+           FROM users_owners
+           JOIN users ON users_owners.user_id = users.id
+           JOIN project_owners ON users_owners.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+             AND project_owners.deleted_at IS NULL
+             AND EXISTS (
+               SELECT 1 FROM users_owners AS membership
+               WHERE membership.owner_id = project_owners.id AND membership.user_id = $2
+             )
+           ORDER BY users_owners.created_at ASC
+        "#,
+    )
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await;
+
+    let members = match members {
+        Ok(members) if members.is_empty() => {
+            return error_response(StatusCode::NOT_FOUND, "Owner does not exist or you are not a member");
+        }
+        Ok(members) => members,
+        Err(err) => {
+            tracing::error!(?err, "Can't get owner members: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let json = serde_json::to_string(&OwnerMembersResponse {
+        members: members
+            .into_iter()
+            .map(|(user_id, username, name, created_at)| OwnerMember {
+                user_id,
+                username,
+                name,
+                created_at,
+            })
+            .collect(),
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
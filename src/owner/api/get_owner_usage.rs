@@ -0,0 +1,147 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    auth::Auth,
+    git::{canonical_repo_name, resolve_repo_path},
+    startup::AppState,
+    usage::{docker_usage, refresh_repo_size, DockerUsage},
+};
+
+#[derive(Serialize, Debug)]
+struct ProjectUsage {
+    project: String,
+    repo_bytes: u64,
+    #[serde(flatten)]
+    docker: DockerUsage,
+    total_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct OwnerUsageResponse {
+    owner: String,
+    projects: Vec<ProjectUsage>,
+    total_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Disk used by every project of an owner, largest first
+#[tracing::instrument(skip(auth, pool, base))]
+pub async fn get(
+    auth: Auth,
+    State(AppState { pool, base, .. }): State<AppState>,
+    Path(owner): Path<String>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    // only members of the owner get to see its usage
+    let is_member = sqlx::query_scalar::<_, bool>(
+        r#"SELECT EXISTS (
+             SELECT 1 FROM users_owners
+             JOIN project_owners ON users_owners.owner_id = project_owners.id
+             WHERE project_owners.name = $1
+               AND project_owners.deleted_at IS NULL
+               AND users_owners.user_id = $2
+           )
+        "#,
+    )
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_one(&pool)
+    .await;
+
+    match is_member {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(StatusCode::NOT_FOUND, "Owner does not exist or you are not a member");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get owner usage: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    }
+
+    let projects = match sqlx::query_as::<_, (String, Option<i64>)>(
+        r#"SELECT projects.name, projects.repo_size_bytes
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE project_owners.name = $1
+             AND projects.deleted_at IS NULL
+        "#,
+    )
+    .bind(&owner)
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(projects) => projects,
+        Err(err) => {
+            tracing::error!(?err, "Can't get owner usage: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let container_names = projects
+        .iter()
+        .map(|(project, _)| format!("{owner}-{}", canonical_repo_name(project)).replace('.', "-"))
+        .collect::<Vec<_>>();
+
+    let mut docker = match docker_usage(&container_names).await {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't get owner usage: Failed to get docker disk usage");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get docker disk usage");
+        }
+    };
+
+    let mut usages = Vec::with_capacity(projects.len());
+    for ((project, repo_bytes), container_name) in projects.into_iter().zip(container_names) {
+        let repo_bytes = match repo_bytes {
+            Some(size) => size as u64,
+            // not pushed to since sizes are tracked
+            None => refresh_repo_size(&pool, &owner, &project, resolve_repo_path(&base, &owner, &project))
+                .await
+                .unwrap_or(0),
+        };
+        let docker = docker.remove(&container_name).unwrap_or_default();
+
+        usages.push(ProjectUsage {
+            project,
+            repo_bytes,
+            docker,
+            total_bytes: repo_bytes + docker.total_bytes(),
+        });
+    }
+    usages.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
+
+    let json = serde_json::to_string(&OwnerUsageResponse {
+        total_bytes: usages.iter().map(|usage| usage.total_bytes).sum(),
+        owner,
+        projects: usages,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
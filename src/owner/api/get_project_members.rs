@@ -12,6 +12,8 @@ struct ProjectShare {
     user_id: Uuid,
     username: String,
     name: String,
+    /// `viewer` or `editor`
+    role: String,
     created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -69,7 +71,7 @@ pub async fn get(
 
     // Get project shares
     let shares_result = sqlx::query(
-        r#"SELECT u.id, u.username, u.name, ps.created_at
+        r#"SELECT u.id, u.username, u.name, ps.role, ps.created_at
            FROM users u
            JOIN project_shares ps ON u.id = ps.user_id
            WHERE ps.project_id = $1
@@ -87,6 +89,7 @@ pub async fn get(
             user_id: row.get::<Uuid, _>("id"),
             username: row.get::<String, _>("username"),
             name: row.get::<String, _>("name"),
+            role: row.get::<String, _>("role"),
             created_at: row.get::<chrono::DateTime<chrono::Utc>, _>("created_at"),
         })
         .collect();
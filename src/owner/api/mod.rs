@@ -6,9 +6,14 @@ use crate::{auth::auth, configuration::Settings, startup::AppState};
 
 mod create_project_owner;
 mod update_project_owner;
-mod invite_project_member;
-mod remove_project_member;
 mod get_project_members;
+mod create_owner_token;
+mod revoke_owner_token;
+mod create_owner;
+mod get_owner_members;
+mod add_owner_member;
+mod remove_owner_member;
+mod get_owner_usage;
 
 pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
@@ -16,21 +21,37 @@ pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Bo
             "/api/owner",
             post(create_project_owner::post),
         )
+        .route_with_tsr(
+            "/api/owner/new",
+            post(create_owner::post),
+        )
         .route_with_tsr(
             "/api/owner/:owner_id",
             post(update_project_owner::post),
         )
         .route_with_tsr(
-            "/api/owner/:owner/:project/invite",
-            post(invite_project_member::post),
+            "/api/owner/:owner/members",
+            get(get_owner_members::get).post(add_owner_member::post),
+        )
+        .route_with_tsr(
+            "/api/owner/:owner/members/:user_id/remove",
+            post(remove_owner_member::post),
         )
         .route_with_tsr(
             "/api/owner/:owner/:project/members", 
             get(get_project_members::get)
         )
         .route_with_tsr(
-            "/api/owner/:owner/:project/remove/:user_id",
-            post(remove_project_member::post),
+            "/api/owner/:owner/usage",
+            get(get_owner_usage::get),
+        )
+        .route_with_tsr(
+            "/api/owner/:owner/tokens",
+            post(create_owner_token::post),
+        )
+        .route_with_tsr(
+            "/api/owner/:owner/tokens/:token_id/revoke",
+            post(revoke_owner_token::post),
         )
         .route_layer(middleware::from_fn(auth))
 }
\ No newline at end of file
@@ -0,0 +1,93 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Removes a user from an owner namespace, members can also remove themselves. The last
+/// member stays so the owner's projects are never left without anyone able to manage them.
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, user_id)): Path<(String, Uuid)>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return json_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let membership = sqlx::query_as::<_, (Uuid, i64)>(
+        r#"SELECT project_owners.id,
+                  (SELECT COUNT(*) FROM users_owners AS members WHERE members.owner_id = project_owners.id)
+           FROM project_owners
+           JOIN users_owners ON project_owners.id = users_owners.owner_id
+           WHERE project_owners.name = $1
+             AND project_owners.deleted_at IS NULL
+             AND users_owners.user_id = $2
+        "#,
+    )
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_optional(&pool)
+    .await;
+
+    let (owner_id, member_count) = match membership {
+        Ok(Some(membership)) => membership,
+        Ok(None) => {
+            return json_response(StatusCode::NOT_FOUND, "Owner does not exist or you are not a member");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project owner: Failed to query database");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    if member_count <= 1 {
+        return json_response(StatusCode::CONFLICT, "Can't remove the last member of an owner");
+    }
+
+    // the count is checked again so two members leaving at once can't empty the owner
+    let deleted = sqlx::query(
+        r#"DELETE FROM users_owners
+           WHERE owner_id = $1 AND user_id = $2
+             AND (SELECT COUNT(*) FROM users_owners AS members WHERE members.owner_id = $1) > 1
+        "#,
+    )
+    .bind(owner_id)
+    .bind(user_id)
+    .execute(&pool)
+    .await;
+
+    match deleted {
+        Ok(result) if result.rows_affected() == 0 => {
+            json_response(StatusCode::NOT_FOUND, "User is not a member of this owner")
+        }
+        Ok(_) => {
+            tracing::info!(owner, %user_id, removed_by = %user.id, "Owner member removed");
+            json_response(StatusCode::OK, "Member removed successfully")
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't delete users_owners: Failed to query database");
+            json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database")
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{auth::Auth, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(auth, pool))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, token_id)): Path<(String, Uuid)>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Unauthorized".to_string(),
+        }).unwrap();
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    };
+
+    // only owner-scoped tokens can be revoked here, project tokens go through
+    // regenerate-git-password
+    let result = sqlx::query(
+        r#"DELETE FROM api_token
+           USING project_owners, users_owners
+           WHERE api_token.id = $1
+             AND api_token.owner_id = project_owners.id
+             AND project_owners.name = $2
+             AND users_owners.owner_id = project_owners.id
+             AND users_owners.user_id = $3
+        "#,
+    )
+    .bind(token_id)
+    .bind(&owner)
+    .bind(user.id)
+    .execute(&pool)
+    .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Token does not exist or you don't have access".to_string(),
+            }).unwrap();
+
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap()
+        }
+        Ok(_) => Response::builder()
+            .status(StatusCode::OK)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"message": "Token revoked successfully"}"#))
+            .unwrap(),
+        Err(err) => {
+            tracing::error!(?err, "Can't delete api_token: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Internal server error".to_string(),
+            }).unwrap();
+
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Query};
+use hyper::http::request::Parts;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE: i64 = 1;
+pub const DEFAULT_PER_PAGE: i64 = 20;
+pub const MAX_PER_PAGE: i64 = 100;
+
+/// Response envelope shared by every paginated endpoint
+#[derive(Serialize, Debug)]
+pub struct Paginated<T> {
+    pub data: Vec<T>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub total_pages: i64,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(data: Vec<T>, params: &PaginationParams, total: i64) -> Self {
+        Self {
+            data,
+            page: params.page,
+            per_page: params.per_page,
+            total,
+            total_pages: (total + params.per_page - 1) / params.per_page,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RawPaginationParams {
+    page: Option<String>,
+    per_page: Option<String>,
+}
+
+/// `page` and `per_page` query params, values that are missing or can't be parsed fall back
+/// to the defaults and `per_page` is clamped to [`MAX_PER_PAGE`]
+#[derive(Debug, Clone, Copy)]
+pub struct PaginationParams {
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl PaginationParams {
+    pub fn limit(&self) -> i64 {
+        self.per_page
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+}
+
+impl Default for PaginationParams {
+    fn default() -> Self {
+        Self {
+            page: DEFAULT_PAGE,
+            per_page: DEFAULT_PER_PAGE,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PaginationParams
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Ok(Query(raw)) = Query::<RawPaginationParams>::from_request_parts(parts, state).await else {
+            return Ok(Self::default());
+        };
+
+        let page = raw
+            .page
+            .and_then(|page| page.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_PAGE)
+            .max(1);
+
+        let per_page = raw
+            .per_page
+            .and_then(|per_page| per_page.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+
+        Ok(Self { page, per_page })
+    }
+}
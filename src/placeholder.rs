@@ -0,0 +1,141 @@
+use axum::{extract::State, middleware::Next, response::Response};
+use hyper::{header::HOST, Body, Request, StatusCode};
+use uuid::Uuid;
+
+use crate::{git::canonical_repo_name, startup::AppState, static_site::project_subdomain};
+
+/// Latest build of a project that never deployed successfully, `None` when nothing was
+/// pushed yet
+struct Undeployed {
+    owner: String,
+    project: String,
+    /// id and status of the latest build
+    latest_build: Option<(Uuid, String)>,
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render(undeployed: &Undeployed, dashboard_url: &str) -> String {
+    let project = escape_html(&canonical_repo_name(&undeployed.project));
+    let dashboard_url = escape_html(dashboard_url);
+
+    let (status, hint) = match &undeployed.latest_build {
+        None => (
+            "No builds yet".to_string(),
+            "Push to the project repository to deploy it.".to_string(),
+        ),
+        Some((_, status)) if status == "pending" || status == "building" => (
+            "Building".to_string(),
+            "The first deployment is on its way, refresh this page in a moment.".to_string(),
+        ),
+        Some((build_id, _)) => (
+            "Failed".to_string(),
+            format!(r#"See the <a href="{dashboard_url}/build/{build_id}">build log</a> for what went wrong."#),
+        ),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{project} is not deployed yet</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 36rem; margin: 15vh auto; padding: 0 1rem; color: #1f2937; }}
+h1 {{ font-size: 1.5rem; }}
+.status {{ display: inline-block; padding: .125rem .5rem; border-radius: .25rem; background: #e5e7eb; }}
+a {{ color: #2563eb; }}
+</style>
+</head>
+<body>
+<h1>{project} is not deployed yet</h1>
+<p>Latest build: <span class="status">{status}</span></p>
+<p>{hint}</p>
+<p><a href="{dashboard_url}">Open the project dashboard</a></p>
+</body>
+</html>
+"#
+    )
+}
+
+/// Answers requests to the subdomain of a project that has never been deployed with a page
+/// saying so, instead of the proxy error the missing container would give. Projects can turn
+/// it off with `placeholder_enabled`.
+pub async fn serve_placeholder(
+    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let subdomain = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| project_subdomain(host, &domain))
+        .map(|subdomain| subdomain.to_string());
+
+    let Some(subdomain) = subdomain else {
+        return next.run(request).await;
+    };
+
+    // subdomains are container names, see docker::build_docker
+    let project = sqlx::query_as::<_, (String, String, Option<Uuid>, Option<String>)>(
+        r#"SELECT project_owners.name, projects.name, latest.id, latest.status::TEXT
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN LATERAL (
+             SELECT builds.id, builds.status FROM builds
+             WHERE builds.project_id = projects.id
+             ORDER BY builds.created_at DESC
+             LIMIT 1
+           ) AS latest ON true
+           WHERE replace(project_owners.name || '-' || regexp_replace(projects.name, '\.git$', ''), '.', '-') = $1
+             AND projects.deleted_at IS NULL
+             AND projects.placeholder_enabled
+             AND NOT EXISTS (
+               SELECT 1 FROM builds
+               WHERE builds.project_id = projects.id AND builds.status = 'successful'
+             )
+           LIMIT 1
+        "#,
+    )
+    .bind(&subdomain)
+    .fetch_optional(&pool)
+    .await;
+
+    let undeployed = match project {
+        Ok(Some((owner, project, build_id, status))) => Undeployed {
+            owner,
+            project,
+            latest_build: build_id.zip(status),
+        },
+        Ok(None) => return next.run(request).await,
+        Err(err) => {
+            tracing::error!(?err, subdomain, "Can't check for placeholder: Failed to query database");
+            return next.run(request).await;
+        }
+    };
+
+    let scheme = match secure {
+        true => "https",
+        false => "http",
+    };
+    let dashboard_url = format!(
+        "{scheme}://{domain}/web/project/{}/{}",
+        undeployed.owner, undeployed.project
+    );
+
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(axum::http::header::CACHE_CONTROL, "no-store")
+        .body(axum::body::boxed(Body::from(render(&undeployed, &dashboard_url))))
+        .unwrap()
+}
@@ -0,0 +1,158 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    projects::context::{ProjectContext, SHARE_ROLES},
+    startup::AppState,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct AddMemberRequest {
+    pub username: String,
+    /// `viewer` or `editor`, editor when omitted
+    pub role: Option<String>,
+}
+
+/// Same shape as the shares of the members list
+#[derive(Serialize, Debug)]
+struct ProjectShare {
+    user_id: Uuid,
+    username: String,
+    name: String,
+    role: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Shares the project with a user by username. Sharing with someone who already is a member
+/// is a conflict rather than a role change.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(AddMemberRequest { username, role }): Json<AddMemberRequest>,
+) -> Response<Body> {
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let role = role.unwrap_or_else(|| "editor".to_string());
+    if !SHARE_ROLES.contains(&role.as_str()) {
+        return error_response(StatusCode::BAD_REQUEST, "role must be viewer or editor");
+    }
+
+    let user = sqlx::query_as::<_, (Uuid, String, bool)>(
+        r#"SELECT users.id, users.name,
+             EXISTS (
+               SELECT 1 FROM users_owners
+               WHERE users_owners.owner_id = $2 AND users_owners.user_id = users.id
+             )
+           FROM users
+           WHERE users.username = $1
+             AND users.deleted_at IS NULL
+        "#,
+    )
+    .bind(&username)
+    .bind(project.owner_id)
+    .fetch_optional(&pool)
+    .await;
+
+    let (user_id, name) = match user {
+        Ok(Some((_, _, true))) => {
+            return error_response(StatusCode::BAD_REQUEST, "User already has access through the project owner");
+        }
+        Ok(Some((user_id, name, false))) => (user_id, name),
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "User not found"),
+        Err(err) => {
+            tracing::error!(?err, "Can't add project member: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let created_at = sqlx::query_as::<_, (DateTime<Utc>,)>(
+        r#"INSERT INTO project_shares (project_id, user_id, role) VALUES ($1, $2, $3)
+           ON CONFLICT (project_id, user_id) DO NOTHING
+           RETURNING created_at
+        "#,
+    )
+    .bind(project.id)
+    .bind(user_id)
+    .bind(&role)
+    .fetch_optional(&pool)
+    .await;
+
+    let created_at = match created_at {
+        Ok(Some((created_at,))) => created_at,
+        Ok(None) => return error_response(StatusCode::CONFLICT, "User is already a member of this project"),
+        Err(err) => {
+            tracing::error!(?err, "Can't add project member: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    tracing::info!(owner = project.owner, project = project.project, username, role, "Project member added");
+
+    let json = serde_json::to_string(&ProjectShare {
+        user_id,
+        username,
+        name,
+        role,
+        created_at,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    use super::*;
+
+    async fn add(state: &AppState, user: crate::auth::User, username: &str) -> StatusCode {
+        let project = ProjectContext::resolve(state, user, "alice", "site").await.unwrap();
+        let request = AddMemberRequest { username: username.to_string(), role: None };
+        post(project, State(state.clone()), Json(request)).await.status()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn only_the_owner_adds_members_once(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        test_support::project(&pool, owner_id, "site").await;
+        let bob = test_support::user(&pool, "bob").await;
+        test_support::user(&pool, "carol").await;
+
+        assert_eq!(add(&state, alice.clone(), "bob").await, StatusCode::CREATED);
+        assert_eq!(add(&state, alice.clone(), "bob").await, StatusCode::CONFLICT);
+        assert_eq!(add(&state, alice, "nobody").await, StatusCode::NOT_FOUND);
+        // an editor can't hand out access
+        assert_eq!(add(&state, bob, "carol").await, StatusCode::FORBIDDEN);
+    }
+}
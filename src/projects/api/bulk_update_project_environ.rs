@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::{context::ProjectContext, environ, environ_cipher}, startup::AppState};
 
 #[derive(Deserialize, Debug)]
 pub struct BulkUpdateProjectEnvironRequest {
@@ -18,8 +18,9 @@ struct ErrorResponse {
     message: String
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(context, auth, pool))]
 pub async fn post(
+    context: ProjectContext,
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
@@ -27,11 +28,27 @@ pub async fn post(
 ) -> Response<Body> {
     let _user = auth.current_user.unwrap();
 
-    let BulkUpdateProjectEnvironRequest { envs } = req;
+    if let Err(response) = context.require_editor() {
+        return response;
+    }
+
+    let BulkUpdateProjectEnvironRequest { mut envs } = req;
+
+    if let Some(message) = envs
+        .iter()
+        .find_map(|(key, value)| environ::validate(key, value).err())
+    {
+        let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
 
     // check if project exist
-    let project = match sqlx::query_as::<_, (uuid::Uuid, String, serde_json::Value)>(
-        r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env
+    let project = match sqlx::query_as::<_, (uuid::Uuid, String, serde_json::Value, Vec<String>)>(
+        r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env, projects.secret_environs AS secrets
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
@@ -71,6 +88,15 @@ pub async fn post(
 
     let project_id = project.0;
 
+    // secrets are listed masked, sending the mask back keeps the stored value
+    for (key, value) in envs.iter_mut() {
+        let stored = project.2.get(key).and_then(|value| value.as_str());
+        *value = match stored {
+            Some(stored) if value == environ::MASKED_VALUE && environ::is_masked(key, &project.3) => stored.to_string(),
+            _ => environ_cipher::encrypt(value),
+        };
+    }
+
     // Convert HashMap to JSON value for bulk update
     let envs_json = match serde_json::to_value(&envs) {
         Ok(json) => json,
@@ -86,10 +112,13 @@ pub async fn post(
         }
     };
 
-    // Bulk replace all environment variables
+    // Bulk replace all environment variables, the secret flags of the ones that stay are kept
     match sqlx::query(
         r#"UPDATE projects
-            SET environs = $1
+            SET environs = $1,
+                secret_environs = ARRAY(
+                    SELECT key FROM unnest(secret_environs) AS key WHERE $1 ? key
+                )
             WHERE id = $2
         "#
     )
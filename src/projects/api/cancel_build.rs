@@ -0,0 +1,100 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    projects::context::ProjectContext,
+    queue::{CancelOutcome, CANCELLED_LOG},
+    startup::AppState,
+};
+
+use super::view_build_log::BuildState;
+
+#[derive(Serialize, Debug)]
+struct CancelBuildResponse {
+    id: Uuid,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Stops a build that is waiting in the queue or running. Either way it ends up failed with
+/// a cancelled log, the deployment stays on the previous build.
+#[tracing::instrument(skip(project, pool, build_control))]
+pub async fn post(
+    project: ProjectContext,
+    Path((_, _, build_id)): Path<(String, String, Uuid)>,
+    State(AppState { pool, build_control, .. }): State<AppState>,
+) -> Response<Body> {
+    // only users of the owner can stop a deploy
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let status = sqlx::query_scalar::<_, BuildState>("SELECT status FROM builds WHERE id = $1 AND project_id = $2")
+        .bind(build_id)
+        .bind(project.id)
+        .fetch_optional(&pool)
+        .await;
+
+    match status {
+        Ok(Some(BuildState::PENDING | BuildState::BUILDING)) => {}
+        Ok(Some(status)) => {
+            return error_response(StatusCode::CONFLICT, &format!("Build already finished as {status}"));
+        }
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Build not found"),
+        Err(err) => {
+            tracing::error!(?err, "Can't cancel build: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    }
+
+    let outcome = build_control.cancel(build_id).await;
+
+    // a running build is marked by its task once it stopped
+    if outcome != CancelOutcome::Signalled {
+        let updated = sqlx::query(
+            "UPDATE builds SET status = 'failed', log = log || $1 WHERE id = $2 AND status IN ('pending', 'building')",
+        )
+        .bind(CANCELLED_LOG)
+        .bind(build_id)
+        .execute(&pool)
+        .await;
+
+        if let Err(err) = updated {
+            tracing::error!(?err, "Can't cancel build: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+        build_control.publish(project.id, build_id, "failed");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, %build_id, ?outcome, "Build cancelled");
+
+    let message = match outcome {
+        CancelOutcome::Signalled => "Build is being cancelled",
+        CancelOutcome::Dequeued | CancelOutcome::NotFound => "Build cancelled",
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&CancelBuildResponse { id: build_id, message: message.to_string() }).unwrap()))
+        .unwrap()
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{git::git_command, projects::context::ProjectContext, startup::AppState};
+
+const FSCK_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_FINDINGS: usize = 500;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    /// unreachable objects, harmless and cleaned up by gc
+    Info,
+    Warning,
+    /// missing or broken objects, clones and builds can fail
+    Error,
+}
+
+#[derive(Serialize, Debug)]
+struct Finding {
+    severity: Severity,
+    /// e.g. dangling, missing, broken link
+    kind: String,
+    object_type: Option<String>,
+    object_id: Option<String>,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct FsckResponse {
+    /// no errors, dangling objects don't count
+    healthy: bool,
+    findings: Vec<Finding>,
+    /// more findings than returned
+    truncated: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+fn is_object_type(word: &str) -> bool {
+    matches!(word, "blob" | "tree" | "commit" | "tag")
+}
+
+/// One line of `git fsck` output, e.g. `dangling blob <id>` or `error: ...`
+fn parse_line(line: &str) -> Finding {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+
+    match words.as_slice() {
+        ["dangling" | "unreachable", object_type, object_id, ..] if is_object_type(object_type) => Finding {
+            severity: Severity::Info,
+            kind: words[0].to_string(),
+            object_type: Some(object_type.to_string()),
+            object_id: Some(object_id.to_string()),
+            message: line.to_string(),
+        },
+        ["missing", object_type, object_id, ..] if is_object_type(object_type) => Finding {
+            severity: Severity::Error,
+            kind: "missing".to_string(),
+            object_type: Some(object_type.to_string()),
+            object_id: Some(object_id.to_string()),
+            message: line.to_string(),
+        },
+        ["broken", "link", ..] | ["error:", ..] | ["fatal:", ..] => Finding {
+            severity: Severity::Error,
+            kind: match words[0] {
+                "broken" => "broken link".to_string(),
+                _ => "error".to_string(),
+            },
+            object_type: None,
+            object_id: None,
+            message: line.to_string(),
+        },
+        _ => Finding {
+            severity: Severity::Warning,
+            kind: words.first().map(|word| word.trim_end_matches(':')).unwrap_or_default().to_string(),
+            object_type: None,
+            object_id: None,
+            message: line.to_string(),
+        },
+    }
+}
+
+/// Runs `git fsck` on the project repository, for when clones of it start failing
+#[tracing::instrument(skip(project))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { git_binary, .. }): State<AppState>,
+) -> Response<Body> {
+    // only users of the owner, fsck is heavy on large repositories
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let ProjectContext { owner, project, user, repo_path, .. } = project;
+
+    if !repo_path.is_dir() {
+        tracing::warn!(repo_path = %repo_path.display(), "REPO_MISSING: Project exists but its repository is not on disk");
+        return error_response(StatusCode::NOT_FOUND, "Repository not found");
+    }
+
+    tracing::info!(owner, project, user_id = %user.id, "REPO_FSCK: started");
+
+    let fsck = git_command(&git_binary, &repo_path, ["fsck", "--no-progress", "--full"], None);
+    let output = match tokio::time::timeout(FSCK_TIMEOUT, fsck).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => {
+            tracing::error!(?err, "Can't check repository: Failed to run git fsck");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to run git fsck");
+        }
+        Err(_) => {
+            tracing::warn!(owner, project, "REPO_FSCK: timed out");
+            return error_response(StatusCode::GATEWAY_TIMEOUT, "Repository check took too long");
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut findings = stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("Checking "))
+        .map(parse_line);
+
+    let mut limited = findings.by_ref().take(MAX_FINDINGS).collect::<Vec<_>>();
+    let truncated = findings.next().is_some();
+
+    let has_errors = limited.iter().any(|finding| matches!(finding.severity, Severity::Error));
+    // a failing fsck that printed nothing useful still means something is wrong
+    if !output.status.success() && !has_errors {
+        limited.push(Finding {
+            severity: Severity::Error,
+            kind: "error".to_string(),
+            object_type: None,
+            object_id: None,
+            message: format!("git fsck exited with {}", output.status),
+        });
+    }
+
+    let healthy = output.status.success() && !has_errors;
+    tracing::info!(owner, project, healthy, findings = limited.len(), "REPO_FSCK: finished");
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&FsckResponse {
+            healthy,
+            findings: limited,
+            truncated,
+        }).unwrap(),
+    )
+}
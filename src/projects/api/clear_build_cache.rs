@@ -0,0 +1,71 @@
+use axum::extract::State;
+use axum::response::Response;
+use bollard::Docker;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{build_cache, git::canonical_repo_name, projects::context::ProjectContext, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Drops the dependency cache of a project, the next build installs everything from scratch
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    // a new generation changes the cache key, which is all docker builds need
+    let updated = sqlx::query(
+        r#"UPDATE projects
+           SET cache_generation = cache_generation + 1, dependency_cache_key = NULL, updated_at = now()
+           WHERE projects.id = $1
+        "#,
+    )
+    .bind(project.id)
+    .execute(&pool)
+    .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't clear build cache: Failed to query database");
+        return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    let container_name = format!("{}-{}", project.owner, canonical_repo_name(&project.project)).replace('.', "-");
+
+    // static builds keep theirs in a volume, it would otherwise only be replaced on the next build
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't clear build cache: Failed to connect to docker");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to connect to docker");
+        }
+    };
+
+    if let Err(err) = build_cache::clear(&docker, &container_name).await {
+        // in use by a running build, that build replaces it anyway since the key changed
+        tracing::warn!(?err, container_name, "Failed to remove dependency cache volume");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, "Build cache cleared");
+
+    json_response(StatusCode::OK, "Build cache cleared")
+}
@@ -3,20 +3,18 @@ use axum::{
     response::Response,
     Json,
 };
+use bytes::Bytes;
 use garde::{Unvalidated, Validate};
-use hyper::{Body, StatusCode};
+use hyper::{header::HeaderValue, Body, HeaderMap, StatusCode};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 use uuid::Uuid;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 use rand::{Rng, SeedableRng};
 
 use crate::{
-    auth::Auth,
+    auth::{Auth, User},
+    git::{hash_token, resolve_repo_path},
     startup::AppState,
 };
 
@@ -44,17 +42,225 @@ struct CreateProjectResponse {
     project_name: String,
     domain: String,
     git_username: String,
-    git_password: String,
+    /// only in the response that created the project, it isn't kept for replays
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_password: Option<String>,
 }
 
-#[tracing::instrument(skip(pool, base, domain))]
+/// Retries with the same `Idempotency-Key` within this window get the original response
+const IDEMPOTENCY_KEY_TTL_HOURS: i32 = 24;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+fn project_response(project: &CreateProjectResponse) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string(project).unwrap()))
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Creates the project at most once per `Idempotency-Key`, a repeated key gets the response
+/// of the first request instead of creating the project again. Only the id of the project is
+/// kept for that, a replay answers with the project but without its git password.
+#[tracing::instrument(skip(auth, state, headers))]
 pub async fn post(
     auth: Auth,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateProjectRequest>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return json_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let key = match headers.get("Idempotency-Key").map(|key| key.to_str()) {
+        None => {
+            return match create(user, State(state), Json(Unvalidated::new(req))).await {
+                Ok(project) => project_response(&project),
+                Err(response) => response,
+            };
+        }
+        Some(Ok(key)) if !key.trim().is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => key.trim().to_string(),
+        Some(_) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Idempotency-Key must be between 1 and {MAX_IDEMPOTENCY_KEY_LENGTH} characters"),
+            );
+        }
+    };
+
+    create_once(user, state, key, req).await
+}
+
+async fn create_once(user: User, state: AppState, key: String, req: CreateProjectRequest) -> Response<Body> {
+    let (user_id, username) = (user.id, user.username.clone());
+    let pool = state.pool.clone();
+    // the same key has to come with the same request to be replayed
+    let fingerprint = format!("{}/{}", req.owner, req.project);
+
+    if let Err(err) = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at < now()")
+        .execute(&pool)
+        .await
+    {
+        tracing::error!(?err, "Can't delete idempotency_keys: Failed to query database");
+    }
+
+    // claim the key, only the request that inserted it gets to create the project
+    let claimed = sqlx::query(
+        r#"INSERT INTO idempotency_keys (user_id, key, request, expires_at)
+           VALUES ($1, $2, $3, now() + make_interval(hours => $4))
+           ON CONFLICT (user_id, key) DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(&key)
+    .bind(&fingerprint)
+    .bind(IDEMPOTENCY_KEY_TTL_HOURS)
+    .execute(&pool)
+    .await;
+
+    match claimed {
+        Ok(result) if result.rows_affected() == 1 => {}
+        Ok(_) => {
+            let stored = sqlx::query_as::<_, (String, Option<i32>, Option<String>, Option<Uuid>, Option<String>, Option<String>)>(
+                r#"SELECT idempotency_keys.request, idempotency_keys.status_code, idempotency_keys.response,
+                          projects.id, project_owners.name, projects.name
+                   FROM idempotency_keys
+                   LEFT JOIN projects ON projects.id = idempotency_keys.project_id
+                   LEFT JOIN project_owners ON project_owners.id = projects.owner_id
+                   WHERE idempotency_keys.user_id = $1 AND idempotency_keys.key = $2
+                "#,
+            )
+            .bind(user_id)
+            .bind(&key)
+            .fetch_optional(&pool)
+            .await;
+
+            return match stored {
+                Ok(Some((request, ..))) if request != fingerprint => json_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Idempotency-Key was already used for a different request",
+                ),
+                Ok(Some((_, Some(_), _, Some(id), Some(owner), Some(project)))) => {
+                    let mut response = project_response(&CreateProjectResponse {
+                        id,
+                        domain: project_url(state.secure, &state.domain, &owner, &project),
+                        owner_name: owner,
+                        project_name: project,
+                        git_username: username,
+                        git_password: None,
+                    });
+                    response.headers_mut().insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+                    response
+                }
+                Ok(Some((_, Some(status_code), Some(response), ..))) => Response::builder()
+                    .status(StatusCode::from_u16(status_code as u16).unwrap_or(StatusCode::OK))
+                    .header(axum::http::header::CONTENT_TYPE, "application/json")
+                    .header("Idempotent-Replayed", "true")
+                    .body(Body::from(response))
+                    .unwrap(),
+                Ok(Some(_)) => json_response(
+                    StatusCode::CONFLICT,
+                    "A request with this Idempotency-Key is still being processed",
+                ),
+                // expired and deleted in between, the client can simply retry
+                Ok(None) => json_response(StatusCode::CONFLICT, "Idempotency-Key expired, retry the request"),
+                Err(err) => {
+                    tracing::error!(?err, "Can't get idempotency_keys: Failed to query database");
+                    json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database")
+                }
+            };
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't insert idempotency_keys: Failed to query database");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    }
+
+    let response = match create(user, State(state), Json(Unvalidated::new(req))).await {
+        // the project is looked up again on a replay, its git password is never stored
+        Ok(project) => {
+            let stored = sqlx::query(
+                "UPDATE idempotency_keys SET status_code = $1, project_id = $2 WHERE user_id = $3 AND key = $4",
+            )
+            .bind(StatusCode::OK.as_u16() as i32)
+            .bind(project.id)
+            .bind(user_id)
+            .bind(&key)
+            .execute(&pool)
+            .await;
+
+            if let Err(err) = stored {
+                tracing::error!(?err, "Can't update idempotency_keys: Failed to query database");
+            }
+
+            return project_response(&project);
+        }
+        Err(response) => response,
+    };
+    let (parts, body) = response.into_parts();
+
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!(?err, "Can't store idempotent response: Failed to read response body");
+            Bytes::new()
+        }
+    };
+
+    // server errors aren't kept so a retry gets to try again
+    let stored = match parts.status.is_server_error() {
+        true => sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2")
+            .bind(user_id)
+            .bind(&key)
+            .execute(&pool)
+            .await,
+        false => sqlx::query(
+            "UPDATE idempotency_keys SET status_code = $1, response = $2 WHERE user_id = $3 AND key = $4",
+        )
+        .bind(parts.status.as_u16() as i32)
+        .bind(String::from_utf8_lossy(&body).to_string())
+        .bind(user_id)
+        .bind(&key)
+        .execute(&pool)
+        .await,
+    };
+
+    if let Err(err) = stored {
+        tracing::error!(?err, "Can't update idempotency_keys: Failed to query database");
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn project_url(secure: bool, domain: &str, owner: &str, project: &str) -> String {
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    format!("{protocol}://{domain}/{owner}/{project}")
+}
+
+#[tracing::instrument(skip(pool, base, domain))]
+async fn create(
+    current_user: User,
     State(AppState {
         pool, base, domain, secure, ..
     }): State<AppState>,
     Json(req): Json<Unvalidated<CreateProjectRequest>>,
-) -> Response<Body> {    
+) -> Result<CreateProjectResponse, Response<Body>> {
     let CreateProjectRequest { owner, project } = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
         Err(err) => {
@@ -62,20 +268,14 @@ pub async fn post(
                 message: err.to_string()
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     };
 
-    // Get current user early since we'll need it for project limit check
-    let current_user = auth.current_user.unwrap();
-
-    let path = match project.ends_with(".git") {
-        true => format!("{base}/{owner}/{project}"),
-        false => format!("{base}/{owner}/{project}.git"),
-    };
+    let path = resolve_repo_path(&base, &owner, &project);
 
     // check if owner exist
     let owner_id = match sqlx::query!(
@@ -91,10 +291,10 @@ pub async fn post(
                 message: "Owner does not exist".to_string()
             }).unwrap();
             
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
         Err(err) => {
             tracing::error!(?err, "Can't get project_owners: Failed to query database");
@@ -103,10 +303,10 @@ pub async fn post(
                 message: format!("Failed to query database {}", err.to_string())
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     };
 
@@ -131,10 +331,10 @@ pub async fn post(
                     message: "Project limit reached. You can only have a maximum of 3 projects per user.".to_string(),
                 }).unwrap();
 
-                return Response::builder()
+                return Err(Response::builder()
                     .status(StatusCode::BAD_REQUEST)
                     .body(Body::from(json))
-                    .unwrap();
+                    .unwrap());
             }
         }
         Err(err) => {
@@ -143,10 +343,10 @@ pub async fn post(
                 message: format!("Failed to query database {}", err.to_string())
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     }
 
@@ -165,10 +365,10 @@ pub async fn post(
                 message: "Project already exists".to_string(),
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::CONFLICT)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
         Err(err) => {
             tracing::error!(?err, "Can't get projects: Failed to query database");
@@ -176,10 +376,10 @@ pub async fn post(
                 message: format!("Failed to query database {}", err.to_string())
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     }
 
@@ -193,11 +393,11 @@ pub async fn post(
                 message: format!("Failed to begin transaction {}", err.to_string())
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .header("Content-Type", "text/html")
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     };
 
@@ -228,10 +428,10 @@ pub async fn post(
                 message: "Failed to insert into database".to_string()
             }).unwrap();
 
-            return Response::builder()
+            return Err(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(json))
-                .unwrap();
+                .unwrap());
         }
     };
 
@@ -241,10 +441,10 @@ pub async fn post(
             message: format!("Failed to create project: {}", err.to_string())
         }).unwrap();
 
-        return Response::builder()
+        return Err(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body(Body::from(json))
-            .unwrap();
+            .unwrap());
     }
 
     // generate token
@@ -256,16 +456,28 @@ pub async fn post(
         })
         .collect::<String>();
 
-    // Store token as plain text for easier debugging
-    // Keep argon2 imports to avoid compile errors
+    // only the hash is stored, the token is shown once in the response
+    let token_hash = match hash_token(&token) {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!(?err, "Can't create project: Failed to hash token");
 
-    if let Err(err) = sqlx::query!(
-        "INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)",
-        Uuid::from(Ulid::new()),
-        project_id,
-        token,
-    )
-    .execute(&mut *tx)
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to create git credentials".to_string()
+            }).unwrap();
+
+            return Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap());
+        }
+    };
+
+    if let Err(err) = sqlx::query("INSERT INTO api_token (id, project_id, token) VALUES ($1, $2, $3)")
+        .bind(Uuid::from(Ulid::new()))
+        .bind(project_id)
+        .bind(&token_hash)
+        .execute(&mut *tx)
     .await
     {
         tracing::error!(
@@ -277,10 +489,10 @@ pub async fn post(
             message: format!("Failed to insert into database {}", err.to_string())
         }).unwrap();
 
-        return Response::builder()
+        return Err(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body(Body::from(json))
-            .unwrap();
+            .unwrap());
     };
 
     if let Err(err) = tx.commit().await {
@@ -291,32 +503,62 @@ pub async fn post(
         }).unwrap();
 
 
-        return Response::builder()
+        return Err(Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body(Body::from(json))
-            .unwrap();
+            .unwrap());
     }
 
-    let protocol = match secure {
-        true => "https",
-        false => "http",
-    };
+    Ok(CreateProjectResponse {
+        id: project_id,
+        domain: project_url(secure, &domain, &owner, &project),
+        owner_name: owner,
+        project_name: project,
+        git_username: current_user.username,
+        git_password: Some(token),
+    })
+}
 
-    let username = current_user.username;
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
 
-    let json = serde_json::to_string(
-        &CreateProjectResponse {
-            id: project_id,
-            owner_name: owner.clone(),
-            project_name: project.clone(),
-            domain: format!("{protocol}://{domain}/{owner}/{project}"),
-            git_username: username,
-            git_password: token,
-        }
-    ).unwrap();
+    use super::*;
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(json))
-        .unwrap()
+    fn request() -> CreateProjectRequest {
+        CreateProjectRequest { owner: "alice".to_string(), project: "site".to_string() }
+    }
+
+    async fn json(response: Response<Body>) -> serde_json::Value {
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn the_same_key_creates_one_project(pool: sqlx::PgPool) {
+        let base = std::env::temp_dir().join(format!("pws-create-test-{}", Uuid::new_v4()));
+        let state = test_support::app_state(pool.clone(), &base.to_string_lossy()).await;
+        let alice = test_support::user(&pool, "alice").await;
+        test_support::owner(&pool, "alice", &alice).await;
+
+        let first = create_once(alice.clone(), state.clone(), "key".to_string(), request()).await;
+        let replayed = create_once(alice, state, "key".to_string(), request()).await;
+        let _ = std::fs::remove_dir_all(&base);
+
+        assert_eq!((first.status(), replayed.status()), (StatusCode::OK, StatusCode::OK));
+        assert!(replayed.headers().contains_key("Idempotent-Replayed"));
+
+        let mut first = json(first).await;
+        let replayed = json(replayed).await;
+        // the password is only shown once, the rest of the response is the same
+        assert!(first.as_object_mut().unwrap().remove("git_password").is_some());
+        assert_eq!(first, replayed);
+
+        let projects = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM projects").fetch_one(&pool).await.unwrap();
+        assert_eq!(projects, 1);
+        let stored = sqlx::query_scalar::<_, Option<String>>("SELECT response FROM idempotency_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, None);
+    }
 }
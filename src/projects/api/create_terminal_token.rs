@@ -0,0 +1,86 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const TOKEN_LENGTH: usize = 32;
+/// in seconds, the token only has to survive until the websocket is opened
+pub const TERMINAL_TOKEN_TTL: i32 = 60;
+
+#[derive(Serialize, Debug)]
+struct TerminalTokenResponse {
+    token: String,
+    expires_in: i32,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    // a shell in the container can do anything the app can, viewers don't get one
+    if let Err(response) = project.require_editor() {
+        return response;
+    }
+
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let token = (0..TOKEN_LENGTH)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect::<String>();
+
+    if let Err(err) = sqlx::query(
+        r#"INSERT INTO terminal_token (token, project_id, user_id, expires_at)
+           VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+        "#,
+    )
+    .bind(&token)
+    .bind(project.id)
+    .bind(project.user.id)
+    .bind(TERMINAL_TOKEN_TTL)
+    .execute(&pool)
+    .await
+    {
+        tracing::error!(?err, "Can't insert terminal_token: Failed to insert into database");
+
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Failed to create token".to_string(),
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    }
+
+    // expired tokens are never usable, clean them up while we're here
+    if let Err(err) = sqlx::query("DELETE FROM terminal_token WHERE expires_at < now()")
+        .execute(&pool)
+        .await
+    {
+        tracing::warn!(?err, "Can't delete expired terminal_token: Failed to query database");
+    }
+
+    let json = serde_json::to_string(&TerminalTokenResponse {
+        token,
+        expires_in: TERMINAL_TOKEN_TTL,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
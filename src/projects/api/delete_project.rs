@@ -9,6 +9,7 @@ use hyper::{Body, StatusCode};
 use serde::Serialize;
 
 use crate::auth::Auth;
+use crate::git::{canonical_repo_name, resolve_repo_path};
 use crate::startup::AppState;
 
 #[derive(Serialize)]
@@ -50,10 +51,7 @@ pub async fn post(
             .unwrap()
     }
 
-    let path = match project.ends_with(".git") {
-        true => format!("{base}/{owner}/{project}"),
-        false => format!("{base}/{owner}/{project}.git"),
-    };
+    let path = resolve_repo_path(&base, &owner, &project);
 
     match auth.current_user {
         Some(user) => {
@@ -145,7 +143,7 @@ pub async fn post(
         },
     };
 
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
 
     let docker = match Docker::connect_with_local_defaults() {
         Err(err) => {
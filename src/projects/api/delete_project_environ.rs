@@ -5,7 +5,7 @@ use garde::{Unvalidated, Validate};
 use hyper::{Body, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::context::ProjectContext, startup::AppState};
 
 #[derive(Deserialize, Validate, Debug)]
 pub struct DeleteProjectEnvironRequest {
@@ -18,8 +18,9 @@ struct ErrorResponse {
     message: String
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(context, auth, pool))]
 pub async fn post(
+    context: ProjectContext,
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
@@ -27,6 +28,10 @@ pub async fn post(
 ) -> Response<Body> {
     let _user = auth.current_user.unwrap();
 
+    if let Err(response) = context.require_editor() {
+        return response;
+    }
+
     let DeleteProjectEnvironRequest { key } = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
         Err(err) => {
@@ -82,14 +87,14 @@ pub async fn post(
     };
 
 
-    match sqlx::query!(
+    match sqlx::query(
         r#"UPDATE projects
-            SET environs = environs - $1
+            SET environs = environs - $1, secret_environs = array_remove(secret_environs, $1)
             WHERE id = $2
         "#,
-        key,
-        project.id
     )
+    .bind(&key)
+    .bind(project.id)
     .execute(&pool)
     .await {
         Ok(data) => data,
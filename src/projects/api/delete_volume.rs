@@ -5,6 +5,7 @@ use bollard::container::{StopContainerOptions, StartContainerOptions};
 use hyper::{Body, StatusCode};
 use serde::Serialize;
 use crate::auth::Auth;
+use crate::git::canonical_repo_name;
 
 #[derive(Serialize)]
 struct DeleteVolumeSuccessResponse {
@@ -19,7 +20,7 @@ struct DeleteVolumeErrorResponse {
 
 #[tracing::instrument(skip(auth))]
 pub async fn post(auth: Auth, Path((owner, project)): Path<(String, String)>) -> Response<Body> {
-    let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
     let db_name = format!("{}-db", container_name);
     let volume_name = format!("{}-volume", container_name);
 
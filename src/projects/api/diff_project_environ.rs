@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{Query, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{projects::{context::ProjectContext, environ}, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct DiffQuery {
+    /// `<owner>/<project>` to compare with
+    against: String,
+}
+
+#[derive(Serialize, Debug)]
+struct EnvironEntry {
+    key: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ChangedEntry {
+    key: String,
+    value: String,
+    against_value: String,
+}
+
+#[derive(Serialize, Debug)]
+struct EnvironDiffResponse {
+    project: String,
+    against: String,
+    only_in_project: Vec<EnvironEntry>,
+    only_in_against: Vec<EnvironEntry>,
+    /// values flagged as secret or of secret looking keys are masked, they are listed when they
+    /// differ all the same
+    changed: Vec<ChangedEntry>,
+    unchanged: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Decrypted environs of a project together with the keys flagged as secret
+async fn project_environs(pool: &PgPool, project_id: Uuid) -> Result<(BTreeMap<String, String>, Vec<String>), sqlx::Error> {
+    let (environs, secret_keys) = sqlx::query_as::<_, (Value, Vec<String>)>(
+        "SELECT environs, secret_environs FROM projects WHERE id = $1",
+    )
+    .bind(project_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((environ::pairs(&environs).into_iter().collect(), secret_keys))
+}
+
+/// Which environment variables differ between two projects, e.g. staging and production
+#[tracing::instrument(skip(project, state))]
+pub async fn get(
+    project: ProjectContext,
+    State(state): State<AppState>,
+    Query(DiffQuery { against }): Query<DiffQuery>,
+) -> Response<Body> {
+    let Some((against_owner, against_project)) = against
+        .split_once('/')
+        .filter(|(owner, project)| !owner.is_empty() && !project.is_empty() && !project.contains('/'))
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "against must be <owner>/<project>");
+    };
+
+    // the same answer whether it's missing or not shared, so the diff can't be used to probe
+    // for projects
+    let against = match ProjectContext::resolve(&state, project.user.clone(), against_owner, against_project).await {
+        Ok(against) => against,
+        Err(response) if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::FORBIDDEN) => {
+            return error_response(StatusCode::NOT_FOUND, "Project does not exist or you don't have access");
+        }
+        Err(response) => return response,
+    };
+
+    let (source, source_secrets) = match project_environs(&state.pool, project.id).await {
+        Ok(environs) => environs,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project environs: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let (target, target_secrets) = match project_environs(&state.pool, against.id).await {
+        Ok(environs) => environs,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project environs: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    // flagged as secret in either project is enough to mask the value in both
+    let secret_keys = [source_secrets, target_secrets].concat();
+
+    let entry = |key: &String, value: &String| EnvironEntry {
+        key: key.clone(),
+        value: environ::display_value(key, value, &secret_keys),
+    };
+
+    let mut diff = EnvironDiffResponse {
+        project: format!("{}/{}", project.owner, project.project),
+        against: format!("{}/{}", against.owner, against.project),
+        only_in_project: Vec::new(),
+        only_in_against: Vec::new(),
+        changed: Vec::new(),
+        unchanged: Vec::new(),
+    };
+
+    for (key, value) in &source {
+        match target.get(key) {
+            None => diff.only_in_project.push(entry(key, value)),
+            Some(against_value) if against_value == value => diff.unchanged.push(key.clone()),
+            Some(against_value) => diff.changed.push(ChangedEntry {
+                key: key.clone(),
+                value: environ::display_value(key, value, &secret_keys),
+                against_value: environ::display_value(key, against_value, &secret_keys),
+            }),
+        }
+    }
+
+    diff.only_in_against = target
+        .iter()
+        .filter(|(key, _)| !source.contains_key(*key))
+        .map(|(key, value)| entry(key, value))
+        .collect();
+
+    json_response(StatusCode::OK, serde_json::to_string(&diff).unwrap())
+}
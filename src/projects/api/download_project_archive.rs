@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use hyper::{Body, StatusCode};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    git::{canonical_repo_name, git_spawn, head_is_unborn, open_bare_repo, OpenRepoError},
+    projects::{content_type::attachment, context::ProjectContext},
+    startup::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    /// Branch, tag, or commit hash (defaults to "HEAD")
+    #[serde(rename = "ref")]
+    r#ref: Option<String>,
+    /// `tar.gz` (default) or `zip`
+    format: Option<String>,
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::to_string(&serde_json::json!({
+        "message": message
+    }))
+    .unwrap();
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { git_binary, .. }): State<AppState>,
+    Query(ArchiveQuery { r#ref, format }): Query<ArchiveQuery>,
+) -> Response<Body> {
+    let (format, content_type, extension) = match format.as_deref() {
+        None | Some("tar.gz") | Some("tgz") => ("tar.gz", "application/gzip", "tar.gz"),
+        Some("zip") => ("zip", "application/zip", "zip"),
+        Some(_) => return json_error(StatusCode::BAD_REQUEST, "Unsupported format, use tar.gz or zip"),
+    };
+
+    // ---- Resolve ref to a commit before handing anything to git ----
+    let ref_input = r#ref.unwrap_or_else(|| "HEAD".to_string());
+    let commit_id = {
+        let repo = match open_bare_repo(&project.repo_path) {
+            Ok(r) => r,
+            Err(OpenRepoError::Missing) => return json_error(StatusCode::NOT_FOUND, "Repository not found"),
+            Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open repository"),
+        };
+
+        match repo.revparse_single(&ref_input).and_then(|obj| obj.peel_to_commit()) {
+            Ok(commit) => commit.id(),
+            // Unborn HEAD (new repo or deleted default branch) => empty repo, nothing to archive
+            Err(_) if head_is_unborn(&repo) => {
+                return json_error(StatusCode::BAD_REQUEST, "Repository is empty")
+            }
+            Err(_) => return json_error(StatusCode::NOT_FOUND, "Reference not found"),
+        }
+    };
+
+    let short_id = &commit_id.to_string()[..7];
+    let name = format!("{}-{}", canonical_repo_name(&project.project), short_id);
+
+    // ---- Stream git archive output straight into the response ----
+    let mut child = match git_spawn(
+        &git_binary,
+        &project.repo_path,
+        ["archive".to_string(), format!("--format={format}"), format!("--prefix={name}/"), commit_id.to_string()],
+    ) {
+        Ok(child) => child,
+        Err(err) => {
+            tracing::error!(?err, "Can't create archive: Failed to spawn git archive");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create archive");
+        }
+    };
+
+    let stdout = child.stdout.take().expect("failed to get stdout");
+    tokio::spawn(async move {
+        match child.wait_with_output().await {
+            Ok(output) if !output.status.success() => {
+                tracing::error!(
+                    status = ?output.status,
+                    stderr = %String::from_utf8_lossy(&output.stderr),
+                    "git archive failed"
+                );
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!(?err, "Failed to wait for git archive"),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", attachment(&format!("{name}.{extension}")))
+        .body(Body::wrap_stream(ReaderStream::new(stdout)))
+        .unwrap()
+}
@@ -0,0 +1,82 @@
+use axum::extract::{Query, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    projects::{context::ProjectContext, environ},
+    startup::AppState,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    /// replace the values of variables flagged as secret, for sharing the file
+    mask: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// The project's environment variables as a `.env` file download, sorted by key
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(ExportQuery { mask }): Query<ExportQuery>,
+) -> Response<Body> {
+    // viewers don't get to see the values
+    if let Err(response) = project.require_editor() {
+        return response;
+    }
+
+    let environs = sqlx::query_as::<_, (serde_json::Value, Vec<String>)>(
+        "SELECT environs, secret_environs FROM projects WHERE id = $1",
+    )
+    .bind(project.id)
+    .fetch_one(&pool)
+    .await;
+
+    let (environs, secret_keys) = match environs {
+        Ok(environs) => environs,
+        Err(err) => {
+            tracing::error!(?err, "Can't export environs: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let mask = mask.unwrap_or(false);
+    let mut pairs = environ::pairs(&environs);
+    pairs.sort();
+
+    let mut body = String::new();
+    for (key, value) in pairs {
+        let value = match mask {
+            true => environ::display_value(&key, &value, &secret_keys),
+            false => value,
+        };
+        body.push_str(&environ::dotenv_line(&key, &value));
+        body.push('\n');
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(axum::http::header::CONTENT_DISPOSITION, "attachment; filename=\".env\"")
+        .header(axum::http::header::CACHE_CONTROL, "no-store")
+        .body(Body::from(body))
+        .unwrap()
+}
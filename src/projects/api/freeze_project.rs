@@ -0,0 +1,102 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct FreezeResponse {
+    frozen: bool,
+    pinned_commit: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Pins the project to the commit of its latest successful build. Pushes are still accepted
+/// while frozen but nothing gets deployed until the project is unfrozen. Freezing a frozen
+/// project keeps the commit it was pinned to.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let deployed = sqlx::query_as::<_, (String,)>(
+        r#"SELECT builds.commit_sha
+           FROM builds
+           WHERE builds.project_id = $1
+             AND builds.status = 'successful'
+             AND builds.commit_sha IS NOT NULL
+           ORDER BY builds.finished_at DESC NULLS LAST
+           LIMIT 1
+        "#,
+    )
+    .bind(project.id)
+    .fetch_optional(&pool)
+    .await;
+
+    let commit = match deployed {
+        Ok(Some((commit,))) => commit,
+        Ok(None) => {
+            return error_response(StatusCode::CONFLICT, "Project has no successful build to freeze at");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't freeze project: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let pinned = sqlx::query_as::<_, (String,)>(
+        r#"UPDATE projects
+           SET pinned_commit = COALESCE(projects.pinned_commit, $1), updated_at = now()
+           WHERE projects.id = $2
+           RETURNING projects.pinned_commit
+        "#,
+    )
+    .bind(&commit)
+    .bind(project.id)
+    .fetch_one(&pool)
+    .await;
+
+    let pinned_commit = match pinned {
+        Ok((pinned_commit,)) => pinned_commit,
+        Err(err) => {
+            tracing::error!(?err, "Can't freeze project: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    tracing::info!(owner = %project.owner, project = %project.project, pinned_commit, "Project frozen");
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&FreezeResponse {
+            frozen: true,
+            pinned_commit,
+        }).unwrap(),
+    )
+}
@@ -1,11 +1,87 @@
 use std::fmt;
 
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
+use chrono::{DateTime, Utc};
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, git::{open_bare_repo, resolve_repo_path, OpenRepoError}, startup::AppState};
+
+/// How far back a branch is searched for a built commit
+const MAX_BRANCH_COMMITS: usize = 200;
+
+/// Left-hand text when no `label` is given
+const DEFAULT_LABEL: &str = "PWS Build Status";
+
+const MAX_LABEL_LENGTH: usize = 64;
+
+/// Short enough that a README shows a finished build soon after, long enough that image
+/// proxies don't hit us on every view
+const CACHE_CONTROL: &str = "public, max-age=30";
+
+#[derive(Deserialize, Debug)]
+pub struct BadgeQuery {
+    /// branch, tag or commit whose latest build is shown, the latest build of any when omitted
+    r#ref: Option<String>,
+    /// only builds triggered from this branch, e.g. `develop`
+    branch: Option<String>,
+    /// `flat`, `flat-square` or `plastic`, like shields.io
+    style: Option<String>,
+    /// left-hand text
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BadgeStyle {
+    Flat,
+    FlatSquare,
+    Plastic,
+}
+
+impl BadgeStyle {
+    fn parse(style: &str) -> Option<Self> {
+        match style {
+            "flat" => Some(BadgeStyle::Flat),
+            "flat-square" => Some(BadgeStyle::FlatSquare),
+            "plastic" => Some(BadgeStyle::Plastic),
+            _ => None,
+        }
+    }
+
+    fn base(self) -> badgen::Style {
+        match self {
+            BadgeStyle::Flat | BadgeStyle::FlatSquare => badgen::Style::flat(),
+            // the classic style has the gradient and rounded corners plastic badges have
+            BadgeStyle::Plastic => badgen::Style::classic(),
+        }
+    }
+
+    fn render(self, style: &badgen::Style, status: &str, label: &str) -> String {
+        let badge = badgen::badge(style, status, Some(label)).unwrap();
+        match self {
+            BadgeStyle::FlatSquare => square_corners(&badge),
+            _ => badge,
+        }
+    }
+}
+
+/// Sets every `rx` corner radius of the svg to 0, badgen has no square style of its own
+fn square_corners(svg: &str) -> String {
+    let mut out = String::with_capacity(svg.len());
+    let mut rest = svg;
+    while let Some(start) = rest.find(" rx=\"") {
+        let value_start = start + " rx=\"".len();
+        let Some(value_len) = rest[value_start..].find('"') else {
+            break;
+        };
+        out.push_str(&rest[..value_start]);
+        out.push('0');
+        rest = &rest[value_start + value_len..];
+    }
+    out.push_str(rest);
+    out
+}
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -35,9 +111,41 @@ struct ErrorResponse {
 #[tracing::instrument(skip(auth, pool))]
 pub async fn get(
     auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
+    State(AppState { pool, base, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
+    Query(BadgeQuery { r#ref, branch, style, label }): Query<BadgeQuery>,
 ) -> Response<Body> {
+    let badge_style = match style.as_deref() {
+        None => BadgeStyle::Flat,
+        Some(style) => match BadgeStyle::parse(style) {
+            Some(badge_style) => badge_style,
+            None => {
+                let json = serde_json::to_string(&ErrorResponse {
+                    message: "style must be flat, flat-square or plastic".to_string()
+                }).unwrap();
+
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json))
+                    .unwrap();
+            }
+        },
+    };
+
+    let label = label.unwrap_or_else(|| DEFAULT_LABEL.to_string());
+    if label.is_empty() || label.chars().count() > MAX_LABEL_LENGTH {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: format!("label must be 1 to {MAX_LABEL_LENGTH} characters")
+        }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
+            .unwrap();
+    }
+
     // check if project exist
     let project_record = match sqlx::query!(
         r#"SELECT projects.id
@@ -78,16 +186,37 @@ pub async fn get(
         }
     };
 
-    let build = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, updated_at, finished_at, log 
-        FROM builds WHERE project_id = $1
-        ORDER BY created_at DESC"#,
-        project_record.id
+    // builds only record the commit they built, so a ref is matched through its history
+    let commits = match &r#ref {
+        None => None,
+        Some(r#ref) => {
+            let repo_path = resolve_repo_path(&base, &owner, &project);
+
+            match branch_commits(&repo_path, r#ref) {
+                Ok(commits) => Some(commits),
+                Err(response) => return response,
+            }
+        }
+    };
+
+    let build = sqlx::query_as::<_, (BuildState, DateTime<Utc>)>(
+        r#"SELECT status, updated_at
+           FROM builds
+           WHERE project_id = $1
+             AND ($2::TEXT[] IS NULL OR commit_sha = ANY($2))
+             AND ($3::TEXT IS NULL OR branch = $3)
+           ORDER BY created_at DESC
+           LIMIT 1
+        "#,
     )
-    .fetch_one(&pool)
-    .await 
-    {
-        Ok(record) => record,
+    .bind(project_record.id)
+    .bind(commits)
+    .bind(branch)
+    .fetch_optional(&pool)
+    .await;
+
+    let build = match build {
+        Ok(build) => build,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
                 message: format!("Failed to query database: {}", err.to_string())
@@ -100,26 +229,79 @@ pub async fn get(
         }, 
     };
 
-    let mut style = badgen::Style::flat();
+    let mut style = badge_style.base();
+
+    let Some((status, updated_at)) = build else {
+        style.background = badgen::Color::Grey;
+        let badge = badge_style.render(&style, "no builds", &label);
+
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "image/svg+xml")
+            .header("Cache-Control", CACHE_CONTROL)
+            .body(Body::from(badge))
+            .unwrap();
+    };
     
-    style.background = match &build.status {
+    style.background = match &status {
         BuildState::PENDING => badgen::Color::Grey,
         BuildState::FAILED => badgen::Color::Red,
         BuildState::SUCCESSFUL => badgen::Color::Green,
         BuildState::BUILDING => badgen::Color::Yellow,
     };
 
-    let badge = badgen::badge(
-        &style, 
-        &build.status.to_string(),
-        Some("PWS Build Status"), 
-    ).unwrap();
+    let badge = badge_style.render(&style, &status.to_string(), &label);
 
     Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "image/svg+xml")
-        .header("Cache-Control", "no-cache")
-        .header("Last-Modified", build.updated_at.to_rfc2822())
+        .header("Cache-Control", CACHE_CONTROL)
+        .header("Last-Modified", updated_at.to_rfc2822())
         .body(Body::from(badge))
         .unwrap()
 }
+
+/// Latest commits reachable from `r#ref`, newest first
+fn branch_commits(repo_path: &std::path::Path, r#ref: &str) -> Result<Vec<String>, Response<Body>> {
+    let error = |status: StatusCode, message: &str| {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap();
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
+            .unwrap()
+    };
+
+    let repo = match open_bare_repo(repo_path) {
+        Ok(repo) => repo,
+        Err(OpenRepoError::Missing) => return Err(error(StatusCode::NOT_FOUND, "Repository not found")),
+        Err(_) => return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open repository")),
+    };
+
+    let tip = match repo.revparse_single(r#ref).and_then(|object| object.peel_to_commit()) {
+        Ok(commit) => commit.id(),
+        Err(_) => return Err(error(StatusCode::NOT_FOUND, "Reference not found")),
+    };
+
+    let mut revwalk = match repo.revwalk() {
+        Ok(revwalk) => revwalk,
+        Err(err) => {
+            tracing::error!(?err, "Can't generate badge: Failed to walk history");
+            return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to walk history"));
+        }
+    };
+
+    if let Err(err) = revwalk.push(tip) {
+        tracing::error!(?err, "Can't generate badge: Failed to walk history");
+        return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to walk history"));
+    }
+
+    Ok(revwalk
+        .filter_map(Result::ok)
+        .take(MAX_BRANCH_COMMITS)
+        .map(|oid| oid.to_string())
+        .collect())
+}
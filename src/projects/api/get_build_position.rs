@@ -0,0 +1,72 @@
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{projects::context::ProjectContext, queue::BuildPosition, startup::AppState};
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "state", rename_all = "lowercase")]
+enum BuildPositionResponse {
+    Queued {
+        /// builds that start before this one, 0 when it is next
+        position: usize,
+        /// build slots free right now, the build waits while there are none
+        available_slots: usize,
+    },
+    Building,
+    Done,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// How far back in the build queue a pending build is
+#[tracing::instrument(skip(project, pool, build_control))]
+pub async fn get(
+    project: ProjectContext,
+    Path((_, _, build_id)): Path<(String, String, Uuid)>,
+    State(AppState { pool, build_control, .. }): State<AppState>,
+) -> Response<Body> {
+    let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM builds WHERE id = $1 AND project_id = $2)")
+        .bind(build_id)
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await;
+
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return error_response(StatusCode::NOT_FOUND, "Build not found"),
+        Err(err) => {
+            tracing::error!(?err, "Can't get build position: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    }
+
+    let response = match build_control.position(build_id).await {
+        BuildPosition::Queued { position, available_slots } => BuildPositionResponse::Queued { position, available_slots },
+        BuildPosition::Building => BuildPositionResponse::Building,
+        BuildPosition::Done => BuildPositionResponse::Done,
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap()
+}
@@ -37,7 +37,7 @@ pub async fn get(
             .unwrap();
     };
 
-    // check if project exist and user has access (owner or shared)
+    // check if project exist and user has access (owner or shared with an editor)
     let row = sqlx::query(
         r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
            FROM projects
@@ -46,7 +46,7 @@ pub async fn get(
            LEFT JOIN project_shares ON projects.id = project_shares.project_id
            WHERE projects.name = $1
              AND project_owners.name = $2
-             AND (users_owners.user_id = $3 OR project_shares.user_id = $3)
+             AND (users_owners.user_id = $3 OR (project_shares.user_id = $3 AND project_shares.role = 'editor'))
         "#,
     )
     .bind(&project)
@@ -1,11 +1,12 @@
-use axum::extract::{State, Path};
+use axum::extract::State;
 use axum::response::Response;
+use bollard::Docker;
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{git::canonical_repo_name, projects::context::ProjectContext, startup::AppState};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -25,6 +26,17 @@ struct ProjectStatusResponse {
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
+    image_size_bytes: Option<i64>,
+    layer_count: Option<i32>,
+    running: bool,
+    /// whether the deployed app answers requests, a successful build can still be down
+    health_status: String,
+    last_checked_at: Option<DateTime<Utc>>,
+    /// pushes aren't deployed while frozen, the app stays on `pinned_commit`
+    frozen: bool,
+    pinned_commit: Option<String>,
+    /// stopped for being idle, the next request to the project starts it again
+    sleeping: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -32,23 +44,20 @@ struct ErrorResponse {
     error: String,
 }
 
-#[tracing::instrument(skip(_auth, pool))]
+#[tracing::instrument(skip(context, pool))]
 pub async fn get(
-    _auth: Auth,
+    context: ProjectContext,
     State(AppState { pool, .. }): State<AppState>,
-    Path((owner, project)): Path<(String, String)>,
 ) -> Response<Body> {
-    // Check if project exists
-    let project_record = match sqlx::query_as::<_, (Uuid,)>(
-        r#"SELECT projects.id
+    // anyone the project is shared with can see how it's doing
+    let ProjectContext { id, owner, project, .. } = context;
+
+    let project_record = match sqlx::query_as::<_, (String, Option<DateTime<Utc>>, Option<String>, bool)>(
+        r#"SELECT health_status, last_checked_at, pinned_commit, sleeping
            FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           WHERE projects.name = $1
-           AND project_owners.name = $2"#,
+           WHERE id = $1"#,
     )
-    .bind(&project)
-    .bind(&owner)
+    .bind(id)
     .fetch_optional(&pool)
     .await
     {
@@ -79,13 +88,13 @@ pub async fn get(
     };
 
     // Get latest build status
-    let build = match sqlx::query_as::<_, (Uuid, Uuid, BuildState, DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>)>(
-        r#"SELECT id, project_id, status, created_at, updated_at, finished_at
+    let build = match sqlx::query_as::<_, (Uuid, Uuid, BuildState, DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>, Option<i64>, Option<i32>)>(
+        r#"SELECT id, project_id, status, created_at, updated_at, finished_at, image_size_bytes, layer_count
         FROM builds WHERE project_id = $1
         ORDER BY created_at DESC
         LIMIT 1"#,
     )
-    .bind(project_record.0)
+    .bind(id)
     .fetch_one(&pool)
     .await 
     {
@@ -104,6 +113,27 @@ pub async fn get(
         }
     };
 
+    // the last build stays the source of truth for status, running only tells whether
+    // the container is currently up (it is down after a stop or a crash)
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+    let running = match Docker::connect_with_local_defaults() {
+        Ok(docker) => match docker.inspect_container(&container_name, None).await {
+            Ok(container) => container
+                .state
+                .and_then(|state| state.running)
+                .unwrap_or(false),
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => false,
+            Err(err) => {
+                tracing::warn!(?err, "Failed to inspect project container");
+                false
+            }
+        },
+        Err(err) => {
+            tracing::warn!(?err, "Failed to connect to docker");
+            false
+        }
+    };
+
     let response = ProjectStatusResponse {
         project: project.clone(),
         owner: owner.clone(),
@@ -112,6 +142,14 @@ pub async fn get(
         created_at: build.3,
         updated_at: build.4,
         finished_at: build.5,
+        image_size_bytes: build.6,
+        layer_count: build.7,
+        running,
+        health_status: project_record.0,
+        last_checked_at: project_record.1,
+        frozen: project_record.2.is_some(),
+        pinned_commit: project_record.2,
+        sleeping: project_record.3,
     };
 
     let json = serde_json::to_string(&response).unwrap();
@@ -0,0 +1,106 @@
+use axum::response::Response;
+use git2::{BranchType, Repository};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::projects::{context::ProjectContext, repo::open_project_repo};
+
+#[derive(Serialize, Debug)]
+struct Branch {
+    name: String,
+    target: String,
+    is_default: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Tag {
+    name: String,
+    /// the commit an annotated tag points to, not the tag object
+    target: String,
+}
+
+#[derive(Serialize, Debug)]
+struct RefsResponse {
+    /// branch HEAD points to, set even before anything was pushed to it
+    default_branch: Option<String>,
+    branches: Vec<Branch>,
+    tags: Vec<Tag>,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+/// Name of the branch HEAD is a symbolic ref to, read without resolving it so an unborn
+/// HEAD still has one
+fn default_branch(repo: &Repository) -> Option<String> {
+    let head = repo.find_reference("HEAD").ok()?;
+    let target = head.symbolic_target()?;
+    Some(target.strip_prefix("refs/heads/").unwrap_or(target).to_string())
+}
+
+fn list(repo: &Repository) -> Result<RefsResponse, git2::Error> {
+    let default_branch = default_branch(repo);
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(name) = branch.name()?.map(str::to_string) else {
+            continue;
+        };
+        let Some(target) = branch.get().target() else {
+            continue;
+        };
+
+        branches.push(Branch {
+            is_default: default_branch.as_deref() == Some(name.as_str()),
+            name,
+            target: target.to_string(),
+        });
+    }
+
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        let Ok(target) = repo
+            .revparse_single(&format!("refs/tags/{name}"))
+            .and_then(|object| object.peel_to_commit())
+        else {
+            continue;
+        };
+
+        tags.push(Tag {
+            name: name.to_string(),
+            target: target.id().to_string(),
+        });
+    }
+
+    Ok(RefsResponse { default_branch, branches, tags })
+}
+
+/// Branches and tags of the repository, what the tree view accepts as `ref`. An empty
+/// repository has none of either.
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+) -> Response<Body> {
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    match list(&repo) {
+        Ok(refs) => json_response(StatusCode::OK, serde_json::to_string(&refs).unwrap()),
+        Err(err) => {
+            tracing::error!(?err, "Can't list refs: Failed to read repository");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read references")
+        }
+    }
+}
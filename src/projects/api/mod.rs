@@ -1,8 +1,8 @@
-use axum::{middleware, Router, routing::{get, post}};
+use axum::{extract::{Path, State}, middleware, Router, routing::{get, post}};
 use axum_extra::routing::RouterExt;
 use hyper::Body;
 
-use crate::{auth::auth, startup::AppState, configuration::Settings};
+use crate::{auth::{auth, Auth}, startup::AppState, configuration::Settings};
 
 mod create_project;
 mod project_dashboard;
@@ -10,6 +10,10 @@ mod web_terminal;
 mod delete_project;
 mod delete_volume;
 mod view_build_log;
+mod cancel_build;
+mod retry_build;
+mod get_build_position;
+mod tail_build_log;
 mod view_container_log;
 mod view_project_environ;
 mod update_project_environ;
@@ -17,28 +21,113 @@ mod delete_project_environ;
 mod bulk_update_project_environ;
 mod generate_status_badge;
 mod get_project_status;
+mod stream_project_status;
 mod get_git_credentials;
 mod regenerate_git_password;
 mod view_project_tree;
+mod view_project_blob;
+mod view_project_raw;
+mod view_commit_log;
+mod view_diff;
+mod list_refs;
+mod view_build_branch;
+mod update_build_branch;
+mod view_project_full_tree;
 mod check_project_access;
+mod view_runtime_environ;
+mod stop_project;
+mod start_project;
+mod download_project_archive;
+mod create_terminal_token;
+mod update_build_settings;
+mod view_container_metrics;
+mod validate_dockerfile;
+mod view_project_activity;
+mod update_placeholder;
+mod clear_build_cache;
+mod diff_project_environ;
+mod check_repository;
+mod rename_branch;
+mod regenerate_trigger_secret;
+mod trigger_build;
+mod update_clone_strategy;
+mod update_submodules;
+mod freeze_project;
+mod unfreeze_project;
+mod view_project_usage;
+mod wake_project;
+mod update_idle_timeout;
+mod view_deploy_webhook;
+mod update_deploy_webhook;
+mod export_project_environ;
+mod remove_project_member;
+mod add_project_member;
+
+pub async fn router(_state: AppState, config: &Settings) -> Router<AppState, Body> {
+    let base_images = config.build.baseimages.clone();
 
-pub async fn router(_state: AppState, _config: &Settings) -> Router<AppState, Body> {
     Router::new()
         .route_with_tsr("/api/project/new", post(create_project::post))
         .route_with_tsr("/api/project/:owner/:project/access", get(check_project_access::get))
+        .route_with_tsr("/api/project/:owner/:project/members/add", post(add_project_member::post))
+        .route_with_tsr("/api/project/:owner/:project/members/remove", post(remove_project_member::post))
         .route_with_tsr("/api/project/:owner/:project/builds", get(project_dashboard::get))
+        .route_with_tsr("/api/project/:owner/:project/activity", get(view_project_activity::get))
         .route_with_tsr("/api/project/:owner/:project/logs", get(view_container_log::get))
+        .route_with_tsr("/api/project/:owner/:project/metrics", get(view_container_metrics::get))
+        .route_with_tsr("/api/project/:owner/:project/usage", get(view_project_usage::get))
         .route_with_tsr("/api/project/:owner/:project/env", get(view_project_environ::get).post(update_project_environ::post))
         .route_with_tsr("/api/project/:owner/:project/env/bulk", post(bulk_update_project_environ::post))
         .route_with_tsr("/api/project/:owner/:project/env/delete", post(delete_project_environ::post))
+        .route_with_tsr("/api/project/:owner/:project/env/diff", get(diff_project_environ::get))
+        .route_with_tsr("/api/project/:owner/:project/env/export", get(export_project_environ::get))
+        .route_with_tsr("/api/project/:owner/:project/runtime-env", get(view_runtime_environ::get))
+        .route_with_tsr("/api/project/:owner/:project/build-settings", post(update_build_settings::post))
+        .route_with_tsr("/api/project/:owner/:project/placeholder", post(update_placeholder::post))
+        .route_with_tsr("/api/project/:owner/:project/clone-strategy", post(update_clone_strategy::post))
+        .route_with_tsr("/api/project/:owner/:project/build-branch", get(view_build_branch::get).post(update_build_branch::post))
+        .route_with_tsr("/api/project/:owner/:project/deploy-webhook", get(view_deploy_webhook::get).post(update_deploy_webhook::post))
+        .route_with_tsr("/api/project/:owner/:project/submodules", post(update_submodules::post))
+        .route_with_tsr("/api/project/:owner/:project/freeze", post(freeze_project::post))
+        .route_with_tsr("/api/project/:owner/:project/unfreeze", post(unfreeze_project::post))
+        .route_with_tsr("/api/project/:owner/:project/wake", get(wake_project::get))
+        .route_with_tsr("/api/project/:owner/:project/idle-timeout", post(update_idle_timeout::post))
+        .route_with_tsr("/api/project/:owner/:project/clear-cache", post(clear_build_cache::post))
+        .route_with_tsr(
+            "/api/project/:owner/:project/validate-dockerfile",
+            post(move |auth: Auth, state: State<AppState>, path: Path<(String, String)>, body: String| {
+                validate_dockerfile::post(auth, state, path, body, base_images.clone())
+            }),
+        )
         .route_with_tsr("/api/project/:owner/:project/builds/:build_id", get(view_build_log::get))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/cancel", post(cancel_build::post))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/retry", post(retry_build::post))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/position", get(get_build_position::get))
+        .route_with_tsr("/api/project/:owner/:project/builds/:build_id/logs/ws", get(tail_build_log::ws))
+        .route_with_tsr("/api/project/:owner/:project/status/stream", get(stream_project_status::get))
         .route_with_tsr("/api/project/:owner/:project/delete", post(delete_project::post))
+        .route_with_tsr("/api/project/:owner/:project/stop", post(stop_project::post))
+        .route_with_tsr("/api/project/:owner/:project/start", post(start_project::post))
         .route_with_tsr("/api/project/:owner/:project/volume/delete", post(delete_volume::post))
-        .route_with_tsr("/api/project/:owner/:project/terminal/ws", get(web_terminal::ws))
+        .route_with_tsr("/api/project/:owner/:project/terminal/token", post(create_terminal_token::post))
         .route_with_tsr("/api/project/:owner/:project/git-credentials", get(get_git_credentials::get))
         .route_with_tsr("/api/project/:owner/:project/regenerate-git-password", post(regenerate_git_password::post))
+        .route_with_tsr("/api/project/:owner/:project/trigger/secret", post(regenerate_trigger_secret::post))
         .route_with_tsr("/api/project/:owner/:project/tree", get(view_project_tree::get))
+        .route_with_tsr("/api/project/:owner/:project/tree/full", get(view_project_full_tree::get))
+        .route_with_tsr("/api/project/:owner/:project/blob", get(view_project_blob::get))
+        .route_with_tsr("/api/project/:owner/:project/raw", get(view_project_raw::get))
+        .route_with_tsr("/api/project/:owner/:project/commits", get(view_commit_log::get))
+        .route_with_tsr("/api/project/:owner/:project/diff", get(view_diff::get))
+        .route_with_tsr("/api/project/:owner/:project/refs", get(list_refs::get))
+        .route_with_tsr("/api/project/:owner/:project/fsck", post(check_repository::post))
+        .route_with_tsr("/api/project/:owner/:project/branches/:name/rename", post(rename_branch::post))
+        .route_with_tsr("/api/project/:owner/:project/archive", get(download_project_archive::get))
         .route_layer(middleware::from_fn(auth))
         .route_with_tsr("/api/project/:owner/:project/badge/status", get(generate_status_badge::get))
         .route_with_tsr("/api/project/:owner/:project/status", get(get_project_status::get))
+        // signed with the project's trigger secret instead of a session
+        .route_with_tsr("/api/project/:owner/:project/trigger", post(trigger_build::post))
+        // authenticates on its own so a terminal token works without the session cookie
+        .route_with_tsr("/api/project/:owner/:project/terminal/ws", get(web_terminal::ws))
 }
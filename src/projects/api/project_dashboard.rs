@@ -1,13 +1,49 @@
 use std::fmt;
 
-use axum::extract::{State, Path};
+use axum::extract::{State, Query};
 use axum::response::Response;
 use chrono::{DateTime, Utc};
 use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+/// Builds returned when the client doesn't ask for a `limit`
+const DEFAULT_LIMIT: i64 = 20;
+/// Highest `limit` a client can ask for
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct BuildListQuery {
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page, older builds are returned
+    cursor: Option<String>,
+}
+
+/// Position in the history, the creation time plus the id to break ties between builds
+/// created in the same microsecond
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    created_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn parse(cursor: &str) -> Option<Self> {
+        let (micros, id) = cursor.split_once('_')?;
+        Some(Self {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}_{}", self.created_at.timestamp_micros(), self.id)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -40,40 +76,36 @@ struct Build {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
+    /// from creation to finishing, so it includes the time spent queued
+    duration_ms: Option<i64>,
+    image_size_bytes: Option<i64>,
+    layer_count: Option<i32>,
+    priority: String,
+    /// settings the build ran with, none until it starts
+    config_snapshot: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Debug)]
 struct ProjectBuildListResponse {
-    data: Vec<Build>
+    data: Vec<Build>,
+    /// pass as `cursor` for the next page, `null` on the last one
+    next_cursor: Option<String>,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+/// Build history a page at a time, newest first
+#[tracing::instrument(skip(project, pool))]
 pub async fn get(
-    auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
-    Path((owner, project)): Path<(String, String)>,
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(BuildListQuery { limit, cursor }): Query<BuildListQuery>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
-
-    // check if project exist
-    let project_record = match sqlx::query!(
-        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
-        Ok(Some(record)) => record,
-        Ok(None) => {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let cursor = match cursor.as_deref().map(Cursor::parse) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string(),
+                message: "cursor must be the next_cursor of an earlier page".to_string(),
             }).unwrap();
 
             return Response::builder()
@@ -81,26 +113,20 @@ pub async fn get(
                 .body(Body::from(json))
                 .unwrap();
         }
-        Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
-
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string()),
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(json))
-                .unwrap();
-        }
     };
 
-    let build_records = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at 
+    let mut build_records = match sqlx::query_as::<_, (Uuid, BuildState, DateTime<Utc>, Option<DateTime<Utc>>, Option<i64>, Option<i32>, String, Option<serde_json::Value>)>(
+        r#"SELECT id, status, created_at, finished_at, image_size_bytes, layer_count, priority, config_snapshot
         FROM builds WHERE project_id = $1
-        ORDER BY created_at DESC"#,
-        project_record.id
+          AND ($2::TIMESTAMPTZ IS NULL OR (created_at, id) < ($2, $3))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $4"#,
     )
+    .bind(project.id)
+    .bind(cursor.map(|cursor| cursor.created_at))
+    .bind(cursor.map(|cursor| cursor.id))
+    // one more than the page to know whether there's a next one
+    .bind(limit + 1)
     .fetch_all(&pool)
     .await 
     {
@@ -117,17 +143,31 @@ pub async fn get(
         }, 
     };
 
+    let has_more = build_records.len() as i64 > limit;
+    build_records.truncate(limit as usize);
+
+    let next_cursor = match has_more {
+        true => build_records.last().map(|record| Cursor { created_at: record.2, id: record.0 }.to_string()),
+        false => None,
+    };
+
     let builds = build_records.into_iter().map(|record|{ 
         Build {
-            id: record.id,
-            status: record.status,
-            created_at: record.created_at,
-            finished_at: record.finished_at,
+            id: record.0,
+            status: record.1,
+            created_at: record.2,
+            finished_at: record.3,
+            duration_ms: record.3.map(|finished_at| (finished_at - record.2).num_milliseconds()),
+            image_size_bytes: record.4,
+            layer_count: record.5,
+            priority: record.6,
+            config_snapshot: record.7,
         }
     }).collect::<Vec<_>>();
 
     let json = serde_json::to_string(&ProjectBuildListResponse {
-        data: builds
+        data: builds,
+        next_cursor,
     }).unwrap();
 
     Response::builder()
@@ -135,3 +175,36 @@ pub async fn get(
         .body(Body::from(json))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips_through_its_display() {
+        let cursor = Cursor {
+            created_at: DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: Uuid::parse_str("0b7a4a2e-41cf-4b8e-9a43-2f1c2f1e9d10").unwrap(),
+        };
+
+        let displayed = cursor.to_string();
+        let parsed = Cursor::parse(&displayed).unwrap();
+
+        assert_eq!(displayed, "1700000000123456_0b7a4a2e-41cf-4b8e-9a43-2f1c2f1e9d10");
+        assert_eq!(parsed.created_at, cursor.created_at);
+        assert_eq!(parsed.id, cursor.id);
+    }
+
+    #[test]
+    fn cursor_rejects_anything_but_a_next_cursor() {
+        for cursor in [
+            "",
+            "1700000000123456",
+            "_0b7a4a2e-41cf-4b8e-9a43-2f1c2f1e9d10",
+            "yesterday_0b7a4a2e-41cf-4b8e-9a43-2f1c2f1e9d10",
+            "1700000000123456_not-a-uuid",
+        ] {
+            assert!(Cursor::parse(cursor).is_none(), "{cursor:?}");
+        }
+    }
+}
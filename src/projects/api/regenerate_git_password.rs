@@ -3,13 +3,9 @@ use axum::response::Response;
 use hyper::{Body, StatusCode};
 use serde::Serialize;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 use rand::{Rng, SeedableRng};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, git::hash_token, startup::AppState};
 use sqlx::Row;
 use uuid::Uuid;
 
@@ -54,7 +50,7 @@ pub async fn post(
            JOIN project_owners ON projects.owner_id = project_owners.id
            LEFT JOIN users_owners ON project_owners.id = users_owners.owner_id
            LEFT JOIN project_shares ON projects.id = project_shares.project_id
-           WHERE (users_owners.user_id = $3 OR project_shares.user_id = $3)
+           WHERE (users_owners.user_id = $3 OR (project_shares.user_id = $3 AND project_shares.role = 'editor'))
              AND projects.name = $1
              AND project_owners.name = $2
         "#,
@@ -101,13 +97,28 @@ pub async fn post(
         })
         .collect::<String>();
 
-    // Store password as plain text for easier debugging
-    // Keep argon2 imports to avoid compile errors
+    // only the hash is stored, the password is shown once in the response
+    let password_hash = match hash_token(&new_password) {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::error!(?err, "Failed to hash password");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update password".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("content-type", "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
 
     match sqlx::query(
         "UPDATE api_token SET token = $1, updated_at = now() WHERE project_id = $2",
     )
-    .bind(&new_password)
+    .bind(&password_hash)
     .bind(project_id)
     .execute(&pool)
     .await
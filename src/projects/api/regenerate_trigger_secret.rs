@@ -0,0 +1,84 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use rand::{distributions::Alphanumeric, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+const SECRET_LENGTH: usize = 40;
+
+#[derive(Serialize, Debug)]
+struct TriggerSecretResponse {
+    secret: String,
+    trigger_url: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Sets a new secret for the inbound trigger webhook, which also turns the webhook on. The
+/// previous secret stops working right away.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, domain, secure, .. }): State<AppState>,
+) -> Response<Body> {
+    // only users of the owner can deploy on behalf of the project
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let secret = (0..SECRET_LENGTH)
+        .map(|_| rng.sample(Alphanumeric) as char)
+        .collect::<String>();
+
+    let updated = sqlx::query("UPDATE projects SET webhook_secret = $1, updated_at = now() WHERE id = $2")
+        .bind(&secret)
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't set trigger secret: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    let ProjectContext { owner, project, .. } = project;
+    tracing::info!(owner, project, "Trigger secret regenerated");
+
+    let protocol = match secure {
+        true => "https",
+        false => "http",
+    };
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&TriggerSecretResponse {
+            secret,
+            trigger_url: format!("{protocol}://{domain}/api/project/{owner}/{project}/trigger"),
+            message: "Secret regenerated. Please save this secret as it won't be shown again.".to_string(),
+        }).unwrap(),
+    )
+}
@@ -0,0 +1,114 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct RemoveMemberRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Revokes a share of the project. Users of the owner aren't members, they keep their access
+/// through the owner.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(RemoveMemberRequest { user_id }): Json<RemoveMemberRequest>,
+) -> Response<Body> {
+    // shared editors can't remove each other
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let removed = sqlx::query("DELETE FROM project_shares WHERE project_id = $1 AND user_id = $2")
+        .bind(project.id)
+        .bind(user_id)
+        .execute(&pool)
+        .await;
+
+    match removed {
+        Ok(result) if result.rows_affected() == 0 => {
+            error_response(StatusCode::NOT_FOUND, "User is not a member of this project")
+        }
+        Ok(_) => {
+            tracing::info!(owner = project.owner, project = project.project, %user_id, "Project member removed");
+
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap()
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't remove project member: Failed to query database");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn the_owner_removes_a_member(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        let project_id = test_support::project(&pool, owner_id, "site").await;
+        let bob = test_support::user(&pool, "bob").await;
+        test_support::share(&pool, project_id, &bob, "editor").await;
+
+        let project = ProjectContext::resolve(&state, alice, "alice", "site").await.unwrap();
+        let response = post(project, State(state), Json(RemoveMemberRequest { user_id: bob.id })).await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let shares = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM project_shares WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(shares, 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn members_cant_remove_the_owner(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        let project_id = test_support::project(&pool, owner_id, "site").await;
+        let bob = test_support::user(&pool, "bob").await;
+        test_support::share(&pool, project_id, &bob, "editor").await;
+
+        let project = ProjectContext::resolve(&state, bob, "alice", "site").await.unwrap();
+        let response = post(project, State(state.clone()), Json(RemoveMemberRequest { user_id: alice.id })).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // the owner keeps the project
+        let owner = ProjectContext::resolve(&state, alice, "alice", "site").await.unwrap();
+        assert_eq!(owner.role, crate::projects::context::ProjectRole::Owner);
+    }
+}
@@ -0,0 +1,123 @@
+use axum::extract::Path;
+use axum::response::Response;
+use axum::Json;
+use git2::{Branch, BranchType, ErrorCode};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{git::{open_bare_repo, OpenRepoError}, projects::context::ProjectContext};
+
+#[derive(Deserialize, Debug)]
+pub struct RenameBranchRequest {
+    pub new_name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct RenameBranchResponse {
+    name: String,
+    /// whether HEAD pointed at the branch and was moved along with it
+    default_branch: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Renames a branch in the bare repo, keeping HEAD on it when it was the default branch
+fn rename(repo_path: &std::path::Path, name: &str, new_name: &str) -> Result<bool, Response<Body>> {
+    let repo = match open_bare_repo(repo_path) {
+        Ok(repo) => repo,
+        Err(OpenRepoError::Missing) => return Err(error_response(StatusCode::NOT_FOUND, "Repository not found")),
+        Err(_) => return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open repository")),
+    };
+
+    let mut branch = match repo.find_branch(name, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(err) if err.code() == ErrorCode::NotFound => {
+            return Err(error_response(StatusCode::NOT_FOUND, "Branch not found"));
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't rename branch: Failed to find branch");
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to find branch"));
+        }
+    };
+
+    let old_ref = format!("refs/heads/{name}");
+    let is_default = repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(|target| target == old_ref))
+        .unwrap_or(false);
+
+    // without force an existing branch with the new name fails the rename
+    let renamed = match branch.rename(new_name, false) {
+        Ok(renamed) => renamed,
+        Err(err) if err.code() == ErrorCode::Exists => {
+            return Err(error_response(StatusCode::CONFLICT, "A branch with that name already exists"));
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't rename branch: Failed to rename reference");
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to rename branch"));
+        }
+    };
+
+    if is_default {
+        let new_ref = renamed.get().name().map(|name| name.to_string()).unwrap_or_else(|| format!("refs/heads/{new_name}"));
+        if let Err(err) = repo.set_head(&new_ref) {
+            tracing::error!(?err, "Can't rename branch: Failed to move HEAD");
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Branch renamed but HEAD could not be updated"));
+        }
+    }
+
+    Ok(is_default)
+}
+
+/// Renames a branch without a local clone, e.g. master to main
+#[tracing::instrument(skip(project))]
+pub async fn post(
+    project: ProjectContext,
+    Path((_, _, name)): Path<(String, String, String)>,
+    Json(RenameBranchRequest { new_name }): Json<RenameBranchRequest>,
+) -> Response<Body> {
+    // only users of the owner can change the repository
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    if !Branch::name_is_valid(&new_name).unwrap_or(false) {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid branch name");
+    }
+
+    let ProjectContext { owner, project, repo_path, .. } = project;
+
+    let default_branch = match rename(&repo_path, &name, &new_name) {
+        Ok(default_branch) => default_branch,
+        Err(response) => return response,
+    };
+
+    tracing::info!(owner, project, from = name, to = new_name, default_branch, "Branch renamed");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_string(&RenameBranchResponse {
+                name: new_name,
+                default_branch,
+            }).unwrap(),
+        ))
+        .unwrap()
+}
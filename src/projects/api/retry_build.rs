@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    git::{
+        build_branch, canonical_repo_name, checkout_build_source, clone_strategy, open_bare_repo, pinned_commit,
+        resolve_deploy_commit,
+    },
+    projects::context::ProjectContext,
+    queue::{BuildPriority, BuildQueueItem, EnqueueOutcome},
+    startup::AppState,
+};
+
+use super::view_build_log::BuildState;
+
+/// How long the request waits for the queue to acknowledge the build
+const ENQUEUE_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug)]
+struct RetryResponse {
+    /// the new build, the retried one is left as it was
+    build_id: Uuid,
+    retry_of: Uuid,
+    commit_sha: String,
+    position: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Builds the commit of an earlier build again, e.g. after it failed on a registry hiccup.
+/// The retry is a new build in the history. Only builds of the current deploy branch whose
+/// commit is still in its history are retried, so a retry never rolls the app onto another
+/// branch.
+#[tracing::instrument(skip(project, pool, build_channel, build_queue_load))]
+pub async fn post(
+    project: ProjectContext,
+    Path((_, _, build_id)): Path<(String, String, Uuid)>,
+    State(AppState { pool, build_channel, build_queue_load, .. }): State<AppState>,
+) -> Response<Body> {
+    if let Err(response) = project.require_editor() {
+        return response;
+    }
+
+    let build = sqlx::query_as::<_, (BuildState, Option<String>, Option<String>)>(
+        "SELECT status, commit_sha, branch FROM builds WHERE id = $1 AND project_id = $2",
+    )
+    .bind(build_id)
+    .bind(project.id)
+    .fetch_optional(&pool)
+    .await;
+
+    let (commit_sha, branch) = match build {
+        Ok(Some((BuildState::PENDING | BuildState::BUILDING, _, _))) => {
+            return error_response(StatusCode::CONFLICT, "Build hasn't finished yet");
+        }
+        Ok(Some((_, Some(commit_sha), branch))) => (commit_sha, branch),
+        Ok(Some((_, None, _))) => {
+            return error_response(StatusCode::BAD_REQUEST, "Build has no commit to build again");
+        }
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Build not found"),
+        Err(err) => {
+            tracing::error!(?err, "Can't retry build: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    if git2::Oid::from_str(&commit_sha).is_err() {
+        return error_response(StatusCode::BAD_REQUEST, "Build has no commit to build again");
+    }
+
+    // a frozen project stays on its pinned commit until it is unfrozen
+    if let Some(pinned) = pinned_commit(&pool, &project.owner, &project.project).await {
+        return error_response(
+            StatusCode::CONFLICT,
+            &format!("Project is frozen at {}, unfreeze it to deploy", &pinned[..pinned.len().min(7)]),
+        );
+    }
+
+    let build_branch = build_branch(&pool, &project.owner, &project.project).await;
+    let deploy = open_bare_repo(&project.repo_path)
+        .map_err(|err| format!("{err:?}"))
+        .and_then(|repo| resolve_deploy_commit(&repo, build_branch.as_deref(), Some(&commit_sha)).map_err(|err| err.to_string()));
+
+    // the commit may be gone since, e.g. after a force push and a gc, or the deploy branch changed
+    let (commit, deploy_branch) = match deploy {
+        Ok(deploy) => deploy,
+        Err(err) => {
+            tracing::warn!(err, commit_sha, "Can't retry build: Commit isn't on the deploy branch");
+            return error_response(StatusCode::CONFLICT, &format!("Commit {commit_sha} can't be deployed again: {err}"));
+        }
+    };
+
+    if branch.is_some() && branch != deploy_branch {
+        return error_response(
+            StatusCode::CONFLICT,
+            &format!(
+                "Build was of {}, the project deploys {} now",
+                branch.unwrap_or_default(),
+                deploy_branch.unwrap_or_else(|| "HEAD".to_string()),
+            ),
+        );
+    }
+
+    if build_queue_load.is_saturated() {
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &format!("The build queue is full ({} builds waiting), please try again later", build_queue_load.depth()),
+        );
+    }
+
+    let strategy = clone_strategy(&pool, &project.owner, &project.project).await;
+    let container_name = format!("{}-{}", project.owner, canonical_repo_name(&project.project)).replace('.', "-");
+
+    // a new working tree of its own, whatever is building right now keeps its files
+    let checkout = {
+        let repo_path = project.repo_path.clone();
+        tokio::task::spawn_blocking(move || checkout_build_source(&repo_path, commit, strategy))
+            .await
+            .map_err(|err| format!("{err:?}"))
+            .and_then(|checkout| checkout.map_err(|err| format!("{err:?}")))
+    };
+    let container_src = match checkout {
+        Ok(source) => source,
+        Err(err) => {
+            tracing::error!(err, %commit, "Can't retry build: Failed to check out commit");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to prepare build source");
+        }
+    };
+
+    let (reply, outcome) = tokio::sync::oneshot::channel();
+    if let Err(err) = build_channel
+        .send(BuildQueueItem {
+            container_name,
+            container_src,
+            owner: project.owner.clone(),
+            repo: project.project.clone(),
+            commit_sha: commit_sha.clone(),
+            branch: deploy_branch,
+            force: true,
+            reply: Some(reply),
+            priority: BuildPriority::Rebuild,
+        })
+        .await
+    {
+        tracing::error!(?err, "Failed to send build request to queue");
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Build queue is unavailable, please try again later");
+    }
+
+    let outcome = match tokio::time::timeout(ENQUEUE_REPLY_TIMEOUT, outcome).await {
+        Ok(Ok(outcome)) => outcome,
+        _ => {
+            return error_response(StatusCode::GATEWAY_TIMEOUT, "Build was requested but the queue didn't answer in time");
+        }
+    };
+
+    match outcome {
+        EnqueueOutcome::Queued { build_id: new_build_id, position }
+        | EnqueueOutcome::AlreadyQueued { build_id: new_build_id, position } => {
+            tracing::info!(owner = %project.owner, project = %project.project, %build_id, %new_build_id, "Build retried");
+            Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&RetryResponse {
+                    build_id: new_build_id,
+                    retry_of: build_id,
+                    commit_sha,
+                    position,
+                }).unwrap()))
+                .unwrap()
+        }
+        outcome @ EnqueueOutcome::AlreadyDeployed => error_response(StatusCode::CONFLICT, &outcome.to_string()),
+        outcome @ EnqueueOutcome::Saturated { .. } => error_response(StatusCode::SERVICE_UNAVAILABLE, &outcome.to_string()),
+        outcome @ EnqueueOutcome::Rejected(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &outcome.to_string()),
+    }
+}
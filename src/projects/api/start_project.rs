@@ -0,0 +1,102 @@
+use axum::extract::State;
+use axum::response::Response;
+use bollard::Docker;
+use bollard::container::StartContainerOptions;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{git::canonical_repo_name, projects::context::ProjectContext, sleep::clear_sleeping, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct StartProjectResponse {
+    message: String,
+    running: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Starts a previously stopped project container. Traefik picks the container labels up
+/// again once it is running, which registers the domain back on the proxy.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    if let Err(response) = project.require_editor() {
+        return response;
+    }
+
+    let ProjectContext { owner, project, .. } = project;
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't start project: Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to connect to docker".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+
+    // a sleeping project started by hand is awake again
+    if let Err(err) = clear_sleeping(&pool, &owner, &project).await {
+        tracing::warn!(?err, container_name, "Failed to clear sleeping state");
+    }
+
+    let (status, message) = match docker
+        .start_container(&container_name, None::<StartContainerOptions<String>>)
+        .await
+    {
+        Ok(_) => {
+            tracing::info!(container_name, "Project container started");
+            (StatusCode::OK, "Project started successfully")
+        }
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 304, .. }) => {
+            (StatusCode::CONFLICT, "Project is already running")
+        }
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+            (StatusCode::CONFLICT, "Project has no deployed container")
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't start project: Failed to start container");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to start container".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let json = match status {
+        StatusCode::OK => serde_json::to_string(&StartProjectResponse {
+            message: message.to_string(),
+            running: true,
+        }),
+        _ => serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }),
+    }.unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
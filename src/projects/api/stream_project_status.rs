@@ -0,0 +1,109 @@
+use std::convert::Infallible;
+
+use axum::extract::State;
+use axum::response::Response;
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+use super::get_project_status::BuildState;
+
+#[derive(Serialize, Debug)]
+struct StatusEvent {
+    build_id: Uuid,
+    status: BuildState,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Reads a `build_state` value as published by the build queue
+fn build_state(status: &str) -> BuildState {
+    match status {
+        "pending" => BuildState::PENDING,
+        "building" => BuildState::BUILDING,
+        "successful" => BuildState::SUCCESSFUL,
+        _ => BuildState::FAILED,
+    }
+}
+
+fn is_finished(status: &BuildState) -> bool {
+    matches!(status, BuildState::SUCCESSFUL | BuildState::FAILED)
+}
+
+fn event(build_id: Uuid, status: BuildState) -> Result<Bytes, Infallible> {
+    let data = serde_json::to_string(&StatusEvent { build_id, status }).unwrap();
+    Ok(Bytes::from(format!("event: status\ndata: {data}\n\n")))
+}
+
+/// Status of the latest build as server-sent events, one whenever it changes. Starts with the
+/// current status and ends once a build finished, a project whose latest build is already
+/// done gets that single event.
+#[tracing::instrument(skip(project, pool, build_control))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, build_control, .. }): State<AppState>,
+) -> Response<Body> {
+    // subscribed first so nothing published while the latest build is read gets lost
+    let receiver = build_control.subscribe();
+
+    let latest = sqlx::query_as::<_, (Uuid, BuildState)>(
+        "SELECT id, status FROM builds WHERE project_id = $1 ORDER BY created_at DESC LIMIT 1",
+    )
+    .bind(project.id)
+    .fetch_optional(&pool)
+    .await;
+
+    let latest = match latest {
+        Ok(latest) => latest,
+        Err(err) => {
+            tracing::error!(?err, "Can't stream project status: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let finished = latest.as_ref().map_or(false, |(_, status)| is_finished(status));
+    let current = stream::iter(latest.map(|(build_id, status)| event(build_id, status)));
+
+    let project_id = project.id;
+    let updates = stream::unfold((!finished).then_some(receiver), move |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(update) if update.project_id == project_id => {
+                    let status = build_state(update.status);
+                    let finished = is_finished(&status);
+                    return Some((event(update.build_id, status), (!finished).then_some(receiver)));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "text/event-stream")
+        .header(axum::http::header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(current.chain(updates)))
+        .unwrap()
+}
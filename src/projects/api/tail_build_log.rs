@@ -0,0 +1,107 @@
+use axum::extract::{
+    ws::{Message, WebSocket, WebSocketUpgrade},
+    Path, State,
+};
+use axum::response::{IntoResponse, Response};
+use hyper::StatusCode;
+use sqlx::PgPool;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::{live_log, projects::context::ProjectContext, queue::BuildStatusEvent, startup::AppState};
+
+use super::view_build_log::BuildState;
+
+/// Sends every line as its own message, false once the client is gone
+async fn send_lines(socket: &mut WebSocket, text: &str) -> bool {
+    for line in text.lines() {
+        if socket.send(Message::Text(line.to_string())).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+async fn tail(mut socket: WebSocket, pool: PgPool, build_id: Uuid, mut events: broadcast::Receiver<BuildStatusEvent>) {
+    loop {
+        if let Some((backlog, mut lines)) = live_log::subscribe(build_id) {
+            if !send_lines(&mut socket, &backlog).await {
+                return;
+            }
+
+            loop {
+                match lines.recv().await {
+                    Ok(line) => {
+                        if socket.send(Message::Text(line)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!(%build_id, skipped, "Build log watcher fell behind");
+                    }
+                    // the build is done
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            break;
+        }
+
+        let build = sqlx::query_as::<_, (BuildState, String)>("SELECT status, log FROM builds WHERE id = $1")
+            .bind(build_id)
+            .fetch_optional(&pool)
+            .await;
+
+        match build {
+            // still waiting in the queue, its log starts with the build
+            Ok(Some((BuildState::PENDING, _))) => loop {
+                match events.recv().await {
+                    Ok(event) if event.build_id == build_id => break,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                }
+            },
+            Ok(Some((_, log))) => {
+                send_lines(&mut socket, &log).await;
+                break;
+            }
+            Ok(None) => break,
+            Err(err) => {
+                tracing::error!(?err, "Can't tail build log: Failed to query database");
+                break;
+            }
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Output of a build as it happens, a line per message. A build that already finished gets
+/// its stored log, a waiting one is followed from the moment it starts. The socket closes
+/// once the build is done.
+#[tracing::instrument(skip(project, pool, build_control, ws))]
+pub async fn ws(
+    project: ProjectContext,
+    Path((_, _, build_id)): Path<(String, String, Uuid)>,
+    State(AppState { pool, build_control, .. }): State<AppState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // subscribed before looking at the build so its start can't be missed
+    let events = build_control.subscribe();
+
+    let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM builds WHERE id = $1 AND project_id = $2)")
+        .bind(build_id)
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await;
+
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Build not found").into_response(),
+        Err(err) => {
+            tracing::error!(?err, "Can't tail build log: Failed to query database");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    ws.on_upgrade(move |socket| tail(socket, pool, build_id, events)).into_response()
+}
@@ -0,0 +1,300 @@
+use std::{path::Path as StdPath, time::Duration};
+
+use axum::extract::{State, Path};
+use axum::response::Response;
+use bytes::Bytes;
+use data_encoding::HEXLOWER_PERMISSIVE;
+use hmac::{Hmac, Mac};
+use hyper::{Body, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    git::{
+        canonical_repo_name, checkout_build_source, open_bare_repo, pinned_commit, resolve_deploy_commit,
+        resolve_repo_path, BuildSource, CloneStrategy, DeployRefError, OpenRepoError,
+    },
+    queue::{BuildPriority, BuildQueueItem, EnqueueOutcome},
+    startup::AppState,
+};
+
+/// Headers the signature is read from, GitHub's name works so its webhooks can be pointed
+/// here directly
+const SIGNATURE_HEADERS: &[&str] = &["x-pws-signature", "x-hub-signature-256"];
+
+const ENQUEUE_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug, Default)]
+struct TriggerRequest {
+    /// the deploy branch, or a tag or commit on it, the deploy branch's tip when missing. A
+    /// GitHub push payload carries the pushed branch here, pushes to other branches are refused.
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct TriggerResponse {
+    build_id: Uuid,
+    commit_sha: String,
+    position: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Checks a `sha256=<hex>` signature of the raw request body
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature
+        .trim()
+        .strip_prefix("sha256=")
+        .and_then(|hex| HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok())
+    else {
+        return false;
+    };
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+
+    // compares in constant time
+    mac.verify_slice(&signature).is_ok()
+}
+
+enum SourceError {
+    MissingRepository,
+    Ref(DeployRefError),
+    Internal(String),
+}
+
+/// Resolves the ref against the deploy branch and checks it out for the build to run from
+fn prepare_source(
+    path: &StdPath,
+    build_branch: Option<&str>,
+    git_ref: Option<&str>,
+    strategy: CloneStrategy,
+) -> Result<(git2::Oid, Option<String>, BuildSource), SourceError> {
+    let repo = open_bare_repo(path).map_err(|err| match err {
+        OpenRepoError::Missing => SourceError::MissingRepository,
+        err => SourceError::Internal(format!("{err:?}")),
+    })?;
+
+    let (commit, branch) = resolve_deploy_commit(&repo, build_branch, git_ref).map_err(SourceError::Ref)?;
+
+    let source = checkout_build_source(path, commit, strategy).map_err(|err| SourceError::Internal(err.to_string()))?;
+
+    Ok((commit, branch, source))
+}
+
+/// Queues a build from an external system, e.g. a CI job or a GitHub webhook of a mirrored
+/// repository. Authenticated by an HMAC of the body with the project's trigger secret rather
+/// than a session.
+#[tracing::instrument(skip(pool, build_channel, build_queue_load, headers, body))]
+pub async fn post(
+    State(AppState { pool, base, build_channel, build_queue_load, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let (secret, strategy, build_branch) = match sqlx::query_as::<_, (Option<String>, String, Option<String>)>(
+        r#"SELECT projects.webhook_secret, projects.clone_strategy, projects.build_branch
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE projects.name = $1
+             AND project_owners.name = $2
+             AND projects.deleted_at IS NULL
+        "#,
+    )
+    .bind(&project)
+    .bind(&owner)
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some((secret, strategy, build_branch))) => (secret, strategy, build_branch),
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, "Project does not exist");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't trigger build: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let signature = SIGNATURE_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| value.to_str().ok());
+
+    // a project without a secret has the webhook turned off, answered the same as a bad
+    // signature so the two can't be told apart
+    let verified = match (&secret, signature) {
+        (Some(secret), Some(signature)) => verify_signature(secret, &body, signature),
+        _ => false,
+    };
+
+    if !verified {
+        tracing::warn!(owner, project, "TRIGGER_REJECTED: invalid signature");
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid signature");
+    }
+
+    let request = match body.is_empty() {
+        true => TriggerRequest::default(),
+        false => match serde_json::from_slice::<TriggerRequest>(&body) {
+            Ok(request) => request,
+            Err(err) => {
+                return error_response(StatusCode::BAD_REQUEST, &format!("Invalid request body: {err}"));
+            }
+        },
+    };
+
+    // a frozen project stays on its pinned commit until it is unfrozen, whoever asks
+    if let Some(pinned) = pinned_commit(&pool, &owner, &project).await {
+        tracing::info!(owner, project, pinned, "TRIGGER_IGNORED: project is frozen");
+        return error_response(
+            StatusCode::CONFLICT,
+            &format!("Project is frozen at {}, unfreeze it to deploy", &pinned[..pinned.len().min(7)]),
+        );
+    }
+
+    if build_queue_load.is_saturated() {
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &format!("The build queue is full ({} builds waiting), please try again later", build_queue_load.depth()),
+        );
+    }
+
+    let path = resolve_repo_path(&base, &owner, &project);
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+
+    let strategy = CloneStrategy::from_column(&strategy);
+    // without a ref the branch pushes deploy from is built, other branches never are
+    // checked out into a working tree of its own, off the request task
+    let prepared = tokio::task::spawn_blocking(move || {
+        prepare_source(&path, build_branch.as_deref(), request.git_ref.as_deref(), strategy)
+    })
+    .await
+    .unwrap_or_else(|err| Err(SourceError::Internal(format!("{err:?}"))));
+    let (commit, branch, container_src) = match prepared {
+        Ok(source) => source,
+        Err(SourceError::MissingRepository) => {
+            return error_response(StatusCode::NOT_FOUND, "Repository not found, push to the project first");
+        }
+        Err(SourceError::Ref(err @ DeployRefError::Unknown(_))) => {
+            return error_response(StatusCode::BAD_REQUEST, &err.to_string());
+        }
+        Err(SourceError::Ref(err @ DeployRefError::NotDeployBranch { .. })) => {
+            tracing::info!(owner, project, %err, "TRIGGER_IGNORED: not the deploy branch");
+            return error_response(StatusCode::CONFLICT, &err.to_string());
+        }
+        Err(SourceError::Internal(err)) => {
+            tracing::error!(err, "Can't trigger build: Failed to prepare build source");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to prepare build source");
+        }
+    };
+
+    let (reply, outcome) = tokio::sync::oneshot::channel();
+    if let Err(err) = build_channel
+        .send(BuildQueueItem {
+            container_name,
+            container_src,
+            owner: owner.clone(),
+            repo: project.clone(),
+            commit_sha: commit.to_string(),
+            branch,
+            force: true,
+            reply: Some(reply),
+            priority: BuildPriority::Rebuild,
+        })
+        .await
+    {
+        tracing::error!(?err, "Failed to send build request to queue");
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Build queue is unavailable, please try again later");
+    }
+
+    let outcome = match tokio::time::timeout(ENQUEUE_REPLY_TIMEOUT, outcome).await {
+        Ok(Ok(outcome)) => outcome,
+        _ => {
+            return error_response(StatusCode::GATEWAY_TIMEOUT, "Build was requested but the queue didn't answer in time");
+        }
+    };
+
+    match outcome {
+        EnqueueOutcome::Queued { build_id, position } | EnqueueOutcome::AlreadyQueued { build_id, position } => {
+            tracing::info!(owner, project, %build_id, commit_sha = %commit, "Build triggered by webhook");
+            json_response(
+                StatusCode::ACCEPTED,
+                serde_json::to_string(&TriggerResponse {
+                    build_id,
+                    commit_sha: commit.to_string(),
+                    position,
+                }).unwrap(),
+            )
+        }
+        outcome @ EnqueueOutcome::AlreadyDeployed => {
+            error_response(StatusCode::CONFLICT, &outcome.to_string())
+        }
+        outcome @ EnqueueOutcome::Saturated { .. } => {
+            error_response(StatusCode::SERVICE_UNAVAILABLE, &outcome.to_string())
+        }
+        outcome @ EnqueueOutcome::Rejected(_) => {
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &outcome.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", data_encoding::HEXLOWER.encode(&mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn signatures_of_the_body_are_accepted() {
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let signature = sign("secret", body);
+
+        assert!(verify_signature("secret", body, &signature));
+        // GitHub's hex is lowercase, uppercase works all the same
+        assert!(verify_signature("secret", body, &format!("sha256={}", signature["sha256=".len()..].to_uppercase())));
+    }
+
+    #[test]
+    fn tampered_signatures_are_rejected() {
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let signature = sign("secret", body);
+
+        assert!(!verify_signature("secret", br#"{"ref":"refs/heads/evil"}"#, &signature));
+        assert!(!verify_signature("other", body, &signature));
+        assert!(!verify_signature("secret", body, &signature.replacen("sha256=", "sha1=", 1)));
+        assert!(!verify_signature("secret", body, "sha256=not-hex"));
+
+        let mut flipped = signature.into_bytes();
+        let last = flipped.len() - 1;
+        flipped[last] = if flipped[last] == b'0' { b'1' } else { b'0' };
+        assert!(!verify_signature("secret", body, std::str::from_utf8(&flipped).unwrap()));
+    }
+}
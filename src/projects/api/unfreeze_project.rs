@@ -0,0 +1,152 @@
+use std::{path::Path as StdPath, time::Duration};
+
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    git::{build_branch, canonical_repo_name, checkout_build_source, clone_strategy, open_bare_repo, resolve_deploy_commit, BuildSource, CloneStrategy},
+    projects::context::ProjectContext,
+    queue::{BuildPriority, BuildQueueItem},
+    startup::AppState,
+};
+
+const ENQUEUE_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug, Default)]
+pub struct UnfreezeRequest {
+    /// build the deployed branch right away instead of waiting for the next push
+    #[serde(default)]
+    pub build: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct UnfreezeResponse {
+    frozen: bool,
+    /// what the queue said about the build of HEAD, when one was asked for
+    build: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Checks out the deployed branch of the bare repo for the build to run from, HEAD unless the
+/// project has a build branch
+fn prepare_head(
+    path: &StdPath,
+    build_branch: Option<&str>,
+    strategy: CloneStrategy,
+) -> Result<(git2::Oid, Option<String>, BuildSource), String> {
+    let repo = open_bare_repo(path).map_err(|err| format!("{err:?}"))?;
+    let (commit, branch) =
+        resolve_deploy_commit(&repo, build_branch, None).map_err(|err| format!("{err}, nothing to build"))?;
+
+    let source = checkout_build_source(path, commit, strategy).map_err(|err| err.to_string())?;
+
+    Ok((commit, branch, source))
+}
+
+/// Lets pushes deploy again. Pushes made while frozen only get deployed by the next push, or
+/// right away with `{"build": true}`.
+#[tracing::instrument(skip(project, pool, build_channel))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, build_channel, .. }): State<AppState>,
+    body: Option<Json<UnfreezeRequest>>,
+) -> Response<Body> {
+    // only users of the owner can change what is deployed
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+
+    let updated = sqlx::query("UPDATE projects SET pinned_commit = NULL, updated_at = now() WHERE id = $1")
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't unfreeze project: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    let ProjectContext { owner, project, repo_path, .. } = project;
+    tracing::info!(owner, project, "Project unfrozen");
+
+    if !req.build {
+        return json_response(
+            StatusCode::OK,
+            serde_json::to_string(&UnfreezeResponse { frozen: false, build: None }).unwrap(),
+        );
+    }
+
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+
+    let strategy = clone_strategy(&pool, &owner, &project).await;
+    let branch = build_branch(&pool, &owner, &project).await;
+    let prepared = tokio::task::spawn_blocking(move || prepare_head(&repo_path, branch.as_deref(), strategy))
+        .await
+        .unwrap_or_else(|err| Err(format!("Failed to check out HEAD: {err}")));
+    let (commit, branch, container_src) = match prepared {
+        Ok(source) => source,
+        Err(err) => {
+            tracing::warn!(err, "Project unfrozen but HEAD can't be built");
+            return json_response(
+                StatusCode::OK,
+                serde_json::to_string(&UnfreezeResponse { frozen: false, build: Some(err) }).unwrap(),
+            );
+        }
+    };
+
+    let (reply, outcome) = tokio::sync::oneshot::channel();
+    let sent = build_channel
+        .send(BuildQueueItem {
+            container_name,
+            container_src,
+            owner,
+            repo: project,
+            commit_sha: commit.to_string(),
+            branch,
+            force: false,
+            reply: Some(reply),
+            priority: BuildPriority::Push,
+        })
+        .await;
+
+    let message = match sent {
+        Ok(_) => match tokio::time::timeout(ENQUEUE_REPLY_TIMEOUT, outcome).await {
+            Ok(Ok(outcome)) => outcome.to_string(),
+            _ => "Build requested, check the dashboard for its status".to_string(),
+        },
+        Err(err) => {
+            tracing::error!(?err, "Failed to send build request to queue");
+            "Build queue is unavailable, please try again later".to_string()
+        }
+    };
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&UnfreezeResponse { frozen: false, build: Some(message) }).unwrap(),
+    )
+}
@@ -0,0 +1,99 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use git2::{BranchType, ErrorCode};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{git::{open_bare_repo, OpenRepoError}, projects::context::ProjectContext, startup::AppState};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateBuildBranchRequest {
+    /// `null` to deploy the default branch again
+    pub build_branch: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Checks the branch exists in the bare repo, so a typo doesn't quietly stop every deploy
+fn check_branch(repo_path: &std::path::Path, branch: &str) -> Result<(), Response<Body>> {
+    let repo = match open_bare_repo(repo_path) {
+        Ok(repo) => repo,
+        Err(OpenRepoError::Missing) => {
+            return Err(error_response(StatusCode::BAD_REQUEST, "Push to the project before choosing a build branch"));
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't update build branch: Failed to open repository");
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open repository"));
+        }
+    };
+
+    match repo.find_branch(branch, BranchType::Local) {
+        Ok(_) => Ok(()),
+        Err(err) if err.code() == ErrorCode::NotFound || err.code() == ErrorCode::InvalidSpec => {
+            Err(error_response(StatusCode::BAD_REQUEST, &format!("Branch {branch} doesn't exist")))
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't update build branch: Failed to find branch");
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to find branch"))
+        }
+    }
+}
+
+/// Chooses the branch pushes deploy from, takes effect on the next push
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateBuildBranchRequest>,
+) -> Response<Body> {
+    // only users of the owner can change what is deployed
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let branch = req
+        .build_branch
+        .map(|branch| branch.trim().trim_start_matches("refs/heads/").to_string())
+        .filter(|branch| !branch.is_empty());
+
+    if let Some(branch) = &branch {
+        if let Err(response) = check_branch(&project.repo_path, branch) {
+            return response;
+        }
+    }
+
+    let updated = sqlx::query("UPDATE projects SET build_branch = $1, updated_at = now() WHERE id = $2")
+        .bind(&branch)
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update build branch: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, ?branch, "Build branch updated");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&UpdateBuildBranchRequest { build_branch: branch }).unwrap()))
+        .unwrap()
+}
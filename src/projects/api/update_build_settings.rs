@@ -0,0 +1,115 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{projects::context::ProjectContext, startup::AppState, static_site};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildType {
+    Docker,
+    Static,
+}
+
+impl BuildType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Static => "static",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateBuildSettingsRequest {
+    pub build_type: BuildType,
+    /// e.g. `npm ci && npm run build`, required for static projects
+    pub build_command: Option<String>,
+    /// e.g. `dist`, required for static projects
+    pub output_dir: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BuildSettingsResponse {
+    build_type: BuildType,
+    build_command: Option<String>,
+    output_dir: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Switches a project between docker builds and static builds, takes effect on the next build
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateBuildSettingsRequest>,
+) -> Response<Body> {
+    // only users of the owner can change how the project is built
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let UpdateBuildSettingsRequest { build_type, build_command, output_dir } = req;
+    let build_command = build_command.map(|command| command.trim().to_string()).filter(|command| !command.is_empty());
+    let output_dir = output_dir.map(|dir| dir.trim().trim_end_matches('/').to_string()).filter(|dir| !dir.is_empty());
+
+    if build_type == BuildType::Static {
+        let validated = match (&build_command, &output_dir) {
+            (Some(command), Some(dir)) => static_site::validate_build_command(command)
+                .and_then(|_| static_site::validate_output_dir(dir)),
+            _ => Err("Static projects need both a build command and an output directory".to_string()),
+        };
+
+        if let Err(message) = validated {
+            return error_response(StatusCode::BAD_REQUEST, message);
+        }
+    }
+
+    let updated = sqlx::query(
+        r#"UPDATE projects
+           SET build_type = $1, build_command = $2, output_dir = $3, updated_at = now()
+           WHERE projects.id = $4
+        "#,
+    )
+    .bind(build_type.as_str())
+    .bind(&build_command)
+    .bind(&output_dir)
+    .bind(project.id)
+    .execute(&pool)
+    .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update build settings: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database".to_string());
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, build_type = build_type.as_str(), "Build settings updated");
+
+    let json = serde_json::to_string(&BuildSettingsResponse {
+        build_type,
+        build_command,
+        output_dir,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,62 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{git::CloneStrategy, projects::context::ProjectContext, startup::AppState};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateCloneStrategyRequest {
+    pub strategy: CloneStrategy,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Switches how the build context is made from the repository, takes effect on the next build.
+/// `export` leaves out `.git`, which makes it smaller and faster to create.
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateCloneStrategyRequest>,
+) -> Response<Body> {
+    // only users of the owner can change how the project is built
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let updated = sqlx::query("UPDATE projects SET clone_strategy = $1, updated_at = now() WHERE id = $2")
+        .bind(req.strategy.as_str())
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update clone strategy: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, strategy = req.strategy.as_str(), "Clone strategy updated");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&req).unwrap()))
+        .unwrap()
+}
@@ -0,0 +1,91 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateDeployWebhookRequest {
+    /// `null` to stop sending the webhook, the secret is removed with it
+    pub url: Option<String>,
+    /// signs the payload into `X-PWS-Signature` when set
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeployWebhookResponse {
+    url: Option<String>,
+    has_secret: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Sets the url finished builds are posted to, replacing the previous url and secret
+#[tracing::instrument(skip(project, pool, req))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateDeployWebhookRequest>,
+) -> Response<Body> {
+    // only users of the owner can change where deploys are reported
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let url = req.url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty());
+    if let Some(url) = &url {
+        match Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "https" || parsed.scheme() == "http" => {}
+            _ => return error_response(StatusCode::BAD_REQUEST, "Webhook url must be an http:// or https:// url"),
+        }
+    }
+    let secret = url.as_ref().and(req.secret).filter(|secret| !secret.is_empty());
+
+    let updated = sqlx::query(
+        r#"UPDATE projects
+           SET deploy_webhook_url = $1, deploy_webhook_secret = $2, updated_at = now()
+           WHERE id = $3
+        "#,
+    )
+    .bind(&url)
+    .bind(&secret)
+    .bind(project.id)
+    .execute(&pool)
+    .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update deploy webhook: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, enabled = url.is_some(), "Deploy webhook updated");
+
+    let json = serde_json::to_string(&DeployWebhookResponse {
+        has_secret: secret.is_some(),
+        url,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,73 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+/// Shorter timeouts would put projects to sleep between two checks of a normal visit
+const MIN_IDLE_TIMEOUT: i32 = 300;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateIdleTimeoutRequest {
+    /// in seconds, `null` for the server default and 0 to never sleep
+    pub idle_timeout: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Sets how long the project can go without traffic before its container is put to sleep
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdateIdleTimeoutRequest>,
+) -> Response<Body> {
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    if let Some(seconds) = req.idle_timeout {
+        if seconds != 0 && !(MIN_IDLE_TIMEOUT..).contains(&seconds) {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Idle timeout must be 0 or at least {MIN_IDLE_TIMEOUT} seconds"),
+            );
+        }
+    }
+
+    let updated = sqlx::query("UPDATE projects SET idle_timeout = $1, updated_at = now() WHERE id = $2")
+        .bind(req.idle_timeout)
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update idle timeout: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, idle_timeout = ?req.idle_timeout, "Idle timeout updated");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&req).unwrap()))
+        .unwrap()
+}
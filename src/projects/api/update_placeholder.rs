@@ -0,0 +1,61 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdatePlaceholderRequest {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Turns the "not deployed yet" page on the project subdomain on or off
+#[tracing::instrument(skip(project, pool))]
+pub async fn post(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Json(req): Json<UpdatePlaceholderRequest>,
+) -> Response<Body> {
+    // only users of the owner can change it
+    if let Err(response) = project.require_owner() {
+        return response;
+    }
+
+    let updated = sqlx::query("UPDATE projects SET placeholder_enabled = $1, updated_at = now() WHERE id = $2")
+        .bind(req.enabled)
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+    if let Err(err) = updated {
+        tracing::error!(?err, "Can't update placeholder: Failed to query database");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+    }
+
+    tracing::info!(owner = %project.owner, project = %project.project, enabled = req.enabled, "Placeholder page updated");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&req).unwrap()))
+        .unwrap()
+}
@@ -5,7 +5,7 @@ use garde::{Unvalidated, Validate};
 use hyper::{Body, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::{context::ProjectContext, environ, environ_cipher}, startup::AppState};
 
 #[derive(Deserialize, Validate, Debug)]
 pub struct UpdateProjectEnvironRequest {
@@ -13,6 +13,9 @@ pub struct UpdateProjectEnvironRequest {
     pub key: String,
     #[garde(length(min=1))]
     pub value: String,
+    /// hide the value outside of the env page and from build logs, unchanged when omitted
+    #[garde(skip)]
+    pub is_secret: Option<bool>,
 }
 
 #[derive(Serialize, Debug)]
@@ -20,8 +23,9 @@ struct ErrorResponse {
     message: String
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(context, auth, pool))]
 pub async fn post(
+    context: ProjectContext,
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
@@ -29,7 +33,11 @@ pub async fn post(
 ) -> Response<Body> {
     let _user = auth.current_user.unwrap();
 
-    let UpdateProjectEnvironRequest { key, value } = match req.validate(&()) {
+    if let Err(response) = context.require_editor() {
+        return response;
+    }
+
+    let UpdateProjectEnvironRequest { key, value, is_secret } = match req.validate(&()) {
         Ok(valid) => valid.into_inner(),
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
@@ -43,6 +51,15 @@ pub async fn post(
         }
     };
 
+    if let Err(message) = environ::validate(&key, &value) {
+        let json = serde_json::to_string(&ErrorResponse { message }).unwrap();
+
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(json))
+            .unwrap();
+    }
+
     // check if project exist
     let project = match sqlx::query!(
         r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env
@@ -89,8 +106,8 @@ pub async fn post(
             SET environs = jsonb_set(projects.environs, $1, $2, true)
             WHERE id = $3
         "#,
-        &[key],
-        serde_json::Value::String(value),
+        &[key.clone()],
+        serde_json::Value::String(environ_cipher::encrypt(&value)),
         project.id
     )
     .execute(&pool)
@@ -113,6 +130,36 @@ pub async fn post(
         }    
     };
 
+    if let Some(is_secret) = is_secret {
+        let flagged = sqlx::query(
+            r#"UPDATE projects
+                SET secret_environs = CASE WHEN $2
+                    THEN array_append(array_remove(secret_environs, $1), $1)
+                    ELSE array_remove(secret_environs, $1)
+                END
+                WHERE id = $3
+            "#,
+        )
+        .bind(&key)
+        .bind(is_secret)
+        .bind(project.id)
+        .execute(&pool)
+        .await;
+
+        if let Err(err) = flagged {
+            tracing::error!(?err, "Can't flag project environ as secret: Failed to update database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to update database".to_string()
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(json))
+                .unwrap();
+        }
+    }
+
     Response::builder()
         .status(StatusCode::NO_CONTENT)
         .body(Body::empty())
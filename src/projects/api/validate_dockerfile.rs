@@ -0,0 +1,88 @@
+use axum::extract::{State, Path};
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{auth::Auth, dockerfile::{self, BaseImageAllowlist}, startup::AppState};
+
+/// in bytes, a Dockerfile is never anywhere near this
+const MAX_DOCKERFILE_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Checks a Dockerfile sent as the request body against what a build of this project would
+/// accept, without building anything
+#[tracing::instrument(skip(auth, pool, body, base_images))]
+pub async fn post(
+    auth: Auth,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((owner, project)): Path<(String, String)>,
+    body: String,
+    base_images: Vec<String>,
+) -> Response<Body> {
+    let Some(user) = auth.current_user else {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    // check if project exist and user has access (owner or shared)
+    match sqlx::query(
+        r#"SELECT 1 FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           LEFT JOIN users_owners ON project_owners.id = users_owners.owner_id
+           LEFT JOIN project_shares ON projects.id = project_shares.project_id
+           WHERE projects.name = $1
+             AND project_owners.name = $2
+             AND (users_owners.user_id = $3 OR project_shares.user_id = $3)
+        "#,
+    )
+    .bind(&project)
+    .bind(&owner)
+    .bind(user.id)
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, "Project does not exist or you don't have access");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get project: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    }
+
+    if body.len() > MAX_DOCKERFILE_SIZE {
+        return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Dockerfile is too large");
+    }
+
+    let allowlist = match BaseImageAllowlist::new(&base_images) {
+        Ok(allowlist) => allowlist,
+        Err(err) => {
+            tracing::error!(?err, "Can't validate dockerfile: Invalid base image allowlist");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Invalid base image allowlist");
+        }
+    };
+
+    let json = serde_json::to_string(&dockerfile::analyze(&body, &allowlist)).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,66 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{git::open_bare_repo, projects::context::ProjectContext, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct BuildBranchResponse {
+    /// `null` when pushes to the default branch deploy
+    build_branch: Option<String>,
+    /// what HEAD points to, deployed when there is no build branch
+    default_branch: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Which branch pushes deploy from
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let build_branch = sqlx::query_scalar::<_, Option<String>>("SELECT build_branch FROM projects WHERE id = $1")
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await;
+
+    let build_branch = match build_branch {
+        Ok(build_branch) => build_branch,
+        Err(err) => {
+            tracing::error!(?err, "Can't get build branch: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    // nothing pushed yet still has a HEAD, only a missing repository has no default branch
+    let default_branch = open_bare_repo(&project.repo_path).ok().and_then(|repo| {
+        let head = repo.find_reference("HEAD").ok()?;
+        let target = head.symbolic_target()?;
+        Some(target.trim_start_matches("refs/heads/").to_string())
+    });
+
+    let json = serde_json::to_string(&BuildBranchResponse { build_branch, default_branch }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -7,7 +7,7 @@ use hyper::{Body, StatusCode};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{projects::context::ProjectContext, startup::AppState};
 
 #[derive(Serialize, Deserialize, Debug, sqlx::Type)]
 #[sqlx(type_name = "build_state", rename_all = "lowercase")] 
@@ -35,7 +35,9 @@ struct BuildDetailResponse {
     status: BuildState,
     created_at: DateTime<Utc>,
     finished_at: Option<DateTime<Utc>>,
-    logs: String
+    logs: String,
+    /// settings the build ran with, none until it starts
+    config_snapshot: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Debug)]
@@ -43,64 +45,33 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(project, pool))]
 pub async fn get(
-    auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
-    Path((owner, project, build_id)): Path<(String, String, Uuid)>,
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Path((_, _, build_id)): Path<(String, String, Uuid)>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
-
-    // check if project exist
-    let _project_record = match sqlx::query!(
-        r#"SELECT projects.id, projects.name AS project, project_owners.name AS owner
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
+    let (id, status, created_at, finished_at, log, config_snapshot) = match sqlx::query_as::<_, (Uuid, BuildState, DateTime<Utc>, Option<DateTime<Utc>>, String, Option<serde_json::Value>)>(
+        r#"SELECT id, status, created_at, finished_at, log, config_snapshot
+        FROM builds WHERE id = $1 AND project_id = $2"#,
     )
+    .bind(build_id)
+    .bind(project.id)
     .fetch_optional(&pool)
-    .await
+    .await 
     {
         Ok(Some(record)) => record,
+        // a build of another project is as unknown here as one that doesn't exist
         Ok(None) => {
             let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
-        Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
-
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
+                message: "Build not found".to_string()
             }).unwrap();
 
             return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(StatusCode::NOT_FOUND)
                 .body(Body::from(json))
                 .unwrap();
         }
-    };
-
-    let build = match sqlx::query!(
-        r#"SELECT id, project_id, status AS "status: BuildState", created_at, finished_at, log 
-        FROM builds WHERE id = $1
-        ORDER BY created_at DESC"#,
-        build_id
-    )
-    .fetch_one(&pool)
-    .await 
-    {
-        Ok(record) => record,
         Err(err) => {
             let json = serde_json::to_string(&ErrorResponse {
                 message: format!("Failed to query database: {}", err.to_string())
@@ -114,11 +85,12 @@ pub async fn get(
     };
 
     let json = serde_json::to_string(&BuildDetailResponse {
-        id: build.id,
-        status: build.status,
-        created_at: build.created_at,
-        finished_at: build.finished_at,
-        logs: build.log,
+        id,
+        status,
+        created_at,
+        finished_at,
+        logs: log,
+        config_snapshot,
     }).unwrap();
 
     Response::builder()
@@ -0,0 +1,192 @@
+use std::path::Path as StdPath;
+
+use axum::{
+    extract::Query,
+    response::Response,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use git2::{Commit, Oid, Signature, Sort};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{git::head_is_unborn, projects::{context::ProjectContext, repo::open_project_repo}};
+
+/// Commits returned when the client doesn't ask for a `limit`
+const DEFAULT_LIMIT: usize = 30;
+/// Highest `limit` a client can ask for
+const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct CommitLogQuery {
+    /// Branch, tag, or commit hash (defaults to "HEAD")
+    #[serde(rename = "ref")]
+    r#ref: Option<String>,
+    /// Only commits that changed this file or directory
+    path: Option<String>,
+    limit: Option<usize>,
+    /// `next_cursor` of the previous page, commits after it are returned
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Person {
+    name: String,
+    email: String,
+}
+
+impl From<Signature<'_>> for Person {
+    fn from(signature: Signature) -> Self {
+        Self {
+            name: String::from_utf8_lossy(signature.name_bytes()).to_string(),
+            email: String::from_utf8_lossy(signature.email_bytes()).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CommitEntry {
+    id: String,
+    author: Person,
+    committer: Person,
+    /// first line of the message
+    summary: String,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct CommitLogResponse {
+    #[serde(rename = "ref")]
+    r#ref: String,
+    is_empty_repo: bool,
+    commits: Vec<CommitEntry>,
+    /// pass as `cursor` for the next page, `null` on the last one
+    next_cursor: Option<String>,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+/// Whether `commit` changed `path`, like `git log -- <path>` a merge only counts when it
+/// differs from every parent
+fn touches(commit: &Commit, path: &StdPath) -> bool {
+    let id_at = |commit: &Commit| commit.tree().ok().and_then(|tree| tree.get_path(path).ok()).map(|entry| entry.id());
+
+    let current = id_at(commit);
+    match commit.parent_count() {
+        0 => current.is_some(),
+        _ => commit.parents().all(|parent| id_at(&parent) != current),
+    }
+}
+
+/// History of a ref a page at a time, newest first, for the commits view
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+    Query(CommitLogQuery { r#ref, path, limit, cursor }): Query<CommitLogQuery>,
+) -> Response<Body> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let path = path.map(|path| path.trim_matches('/').to_string()).filter(|path| !path.is_empty());
+    let cursor = match cursor.as_deref().map(Oid::from_str) {
+        None => None,
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(_)) => return error_response(StatusCode::BAD_REQUEST, "cursor must be a commit id"),
+    };
+
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    let ref_input = r#ref.unwrap_or_else(|| "HEAD".to_string());
+    let start = match repo.revparse_single(&ref_input) {
+        Ok(object) => match object.peel_to_commit() {
+            Ok(commit) => commit.id(),
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Reference is not a commit"),
+        },
+        // unborn HEAD, nothing pushed yet or the default branch was deleted
+        Err(_) if head_is_unborn(&repo) => {
+            return json_response(
+                StatusCode::OK,
+                serde_json::to_string(&CommitLogResponse {
+                    r#ref: ref_input,
+                    is_empty_repo: true,
+                    commits: vec![],
+                    next_cursor: None,
+                }).unwrap(),
+            );
+        }
+        Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid reference"),
+    };
+
+    let mut revwalk = match repo.revwalk() {
+        Ok(revwalk) => revwalk,
+        Err(err) => {
+            tracing::error!(?err, "Can't get commits: Failed to walk history");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to walk history");
+        }
+    };
+    if let Err(err) = revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).and_then(|_| revwalk.push(start)) {
+        tracing::error!(?err, "Can't get commits: Failed to walk history");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to walk history");
+    }
+
+    // pages continue the same walk, so merged branches aren't lost between them
+    let mut skipping = cursor.is_some();
+    let mut commits = Vec::new();
+    let mut has_more = false;
+
+    for oid in revwalk {
+        let Ok(oid) = oid else {
+            continue;
+        };
+
+        if skipping {
+            skipping = Some(oid) != cursor;
+            continue;
+        }
+
+        let Ok(commit) = repo.find_commit(oid) else {
+            continue;
+        };
+        if path.as_ref().map_or(false, |path| !touches(&commit, StdPath::new(path))) {
+            continue;
+        }
+
+        if commits.len() == limit {
+            has_more = true;
+            break;
+        }
+
+        commits.push(CommitEntry {
+            id: oid.to_string(),
+            author: commit.author().into(),
+            committer: commit.committer().into(),
+            summary: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).to_string(),
+            timestamp: Utc.timestamp_opt(commit.time().seconds(), 0).single().unwrap_or_default(),
+        });
+    }
+
+    let next_cursor = match has_more {
+        true => commits.last().map(|commit| commit.id.clone()),
+        false => None,
+    };
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&CommitLogResponse {
+            r#ref: ref_input,
+            is_empty_repo: false,
+            commits,
+            next_cursor,
+        }).unwrap(),
+    )
+}
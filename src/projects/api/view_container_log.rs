@@ -1,13 +1,13 @@
-use axum::extract::{State, Path};
+use axum::extract::{State, Query};
 use axum::response::Response;
 use bollard::container::{LogsOptions, LogOutput};
 use bollard::Docker;
 use futures::StreamExt;
 use hyper::{Body, StatusCode};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{projects::context::ProjectContext, startup::AppState};
 
 #[derive(Serialize, Debug)]
 struct LogResponse {
@@ -20,52 +20,55 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+/// Lines sent when the client doesn't ask for a `tail`
+const DEFAULT_TAIL: &str = "100";
+
+#[derive(Deserialize, Debug)]
+pub struct LogQuery {
+    /// keep the response open and send new lines as the container writes them
+    follow: Option<bool>,
+    /// lines to start from, a number or `all`
+    tail: Option<String>,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+#[tracing::instrument(skip(project, pool))]
 pub async fn get(
-    auth: Auth,
-    State(AppState { pool, domain, secure, .. }): State<AppState>,
-    Path((owner, project)): Path<(String, String)>,
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(LogQuery { follow, tail }): Query<LogQuery>,
 ) -> Response<Body> {
-    let _user = auth.current_user.unwrap();
-
-    // check if project exist
-    let project = match sqlx::query!(
-        r#"SELECT projects.id, domains.name AS container_name
-           FROM projects
-           JOIN project_owners ON projects.owner_id = project_owners.id
-           JOIN users_owners ON project_owners.id = users_owners.owner_id
-           JOIN domains ON domains.project_id = projects.id
-           AND projects.name = $1
-           AND project_owners.name = $2
-        "#,
-        project,
-        owner,
-    )
-    .fetch_optional(&pool)
-    .await
-    {
-        Ok(Some(record)) => record,
-        Ok(None) => {
-            let json = serde_json::to_string(&ErrorResponse {
-                message: "Project does not exist".to_string()
-            }).unwrap();
+    let tail = match tail.as_deref().map(str::trim) {
+        None | Some("") => DEFAULT_TAIL.to_string(),
+        Some("all") => "all".to_string(),
+        Some(tail) => match tail.parse::<u32>() {
+            Ok(tail) => tail.to_string(),
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "tail must be a number of lines or all"),
+        },
+    };
 
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(json))
-                .unwrap();
-        }
+    // the container is named after the project's domain
+    let container_name = match sqlx::query_scalar::<_, String>("SELECT name FROM domains WHERE project_id = $1")
+        .bind(project.id)
+        .fetch_optional(&pool)
+        .await
+    {
+        Ok(Some(container_name)) => container_name,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Project has not been deployed yet"),
         Err(err) => {
-            tracing::error!(?err, "Can't get projects: Failed to query database");
-
-            let json = serde_json::to_string(&ErrorResponse {
-                message: format!("Failed to query database: {}", err.to_string())
-            }).unwrap();
-
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(json))
-                .unwrap();
+            tracing::error!(?err, "Can't get project domain: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
         }
     };
 
@@ -88,8 +91,40 @@ pub async fn get(
         }
     };
 
-    let log_stream = &mut docker.logs(&project.container_name, Some(LogsOptions {
-        tail: "100",
+    // like `docker logs -f`, the seed lines from `tail` come first. Dropping the body when the
+    // client goes away drops the docker stream with it.
+    if follow.unwrap_or(false) {
+        let stream = docker
+            .logs(&container_name, Some(LogsOptions {
+                follow: true,
+                tail,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }))
+            .filter_map(|log_result| async move {
+                match log_result {
+                    Ok(LogOutput::StdOut { message } | LogOutput::StdErr { message }) => Some(Ok(message)),
+                    Ok(_) => None,
+                    Err(err) => {
+                        tracing::warn!(?err, "Container log stream ended");
+                        Some(Err(std::io::Error::new(std::io::ErrorKind::Other, err)))
+                    }
+                }
+            });
+
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header(axum::http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(axum::http::header::CACHE_CONTROL, "no-cache")
+            // proxies would otherwise hold the lines back until their buffer fills
+            .header("X-Accel-Buffering", "no")
+            .body(Body::wrap_stream(stream))
+            .unwrap();
+    }
+
+    let log_stream = &mut docker.logs(&container_name, Some(LogsOptions {
+        tail,
         stdout: true,
         stderr: true,
         ..Default::default()
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::response::Response;
+use bollard::Docker;
+use bollard::container::{MemoryStatsStats, Stats, StatsOptions};
+use futures_util::StreamExt;
+use hyper::{Body, StatusCode};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::{git::canonical_repo_name, projects::context::ProjectContext};
+
+/// Samples are reused for this long so a polling dashboard doesn't hit the docker daemon on
+/// every request
+const METRICS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref METRICS_CACHE: Mutex<HashMap<String, (Instant, ContainerMetrics)>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct ContainerMetrics {
+    /// of a single core, can go above 100 when the container uses more than one
+    cpu_percent: f64,
+    memory_usage_bytes: u64,
+    memory_limit_bytes: u64,
+    network_rx_bytes: u64,
+    network_tx_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+impl From<Stats> for ContainerMetrics {
+    fn from(stats: Stats) -> Self {
+        let cpu_delta = stats.cpu_stats.cpu_usage.total_usage as f64
+            - stats.precpu_stats.cpu_usage.total_usage as f64;
+        let system_delta = stats.cpu_stats.system_cpu_usage.unwrap_or(0) as f64
+            - stats.precpu_stats.system_cpu_usage.unwrap_or(0) as f64;
+        let online_cpus = stats
+            .cpu_stats
+            .online_cpus
+            .or_else(|| stats.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|usage| usage.len() as u64))
+            .unwrap_or(1) as f64;
+
+        let cpu_percent = match cpu_delta > 0.0 && system_delta > 0.0 {
+            true => cpu_delta / system_delta * online_cpus * 100.0,
+            false => 0.0,
+        };
+
+        // page cache is reclaimable, docker stats leaves it out of the usage as well
+        let cache = match stats.memory_stats.stats {
+            Some(MemoryStatsStats::V1(stats)) => stats.cache,
+            Some(MemoryStatsStats::V2(stats)) => stats.inactive_file,
+            None => 0,
+        };
+
+        let (network_rx_bytes, network_tx_bytes) = stats
+            .networks
+            .unwrap_or_default()
+            .values()
+            .fold((0, 0), |(rx, tx), network| (rx + network.rx_bytes, tx + network.tx_bytes));
+
+        Self {
+            cpu_percent: (cpu_percent * 100.0).round() / 100.0,
+            memory_usage_bytes: stats.memory_stats.usage.unwrap_or(0).saturating_sub(cache),
+            memory_limit_bytes: stats.memory_stats.limit.unwrap_or(0),
+            network_rx_bytes,
+            network_tx_bytes,
+        }
+    }
+}
+
+/// Point in time resource usage of the project container
+#[tracing::instrument(skip(project))]
+pub async fn get(project: ProjectContext) -> Response<Body> {
+    let ProjectContext { owner, project, .. } = project;
+
+    let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+
+    if let Some((sampled_at, metrics)) = METRICS_CACHE.lock().unwrap().get(&container_name) {
+        if sampled_at.elapsed() < METRICS_CACHE_TTL {
+            return json_response(StatusCode::OK, serde_json::to_string(metrics).unwrap());
+        }
+    }
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't get metrics: Failed to connect to docker");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to connect to docker");
+        }
+    };
+
+    // a stopped container reports zeroes, which would look like an idle app
+    let running = match docker.inspect_container(&container_name, None).await {
+        Ok(container) => container.state.and_then(|state| state.running).unwrap_or(false),
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => false,
+        Err(err) => {
+            tracing::error!(?err, "Can't get metrics: Failed to inspect container");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to inspect container");
+        }
+    };
+
+    if !running {
+        return error_response(StatusCode::CONFLICT, "Project has no running container");
+    }
+
+    // without one_shot docker waits for a second sample so the cpu usage can be computed
+    let stats = docker
+        .stats(
+            &container_name,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: false,
+            }),
+        )
+        .next()
+        .await;
+
+    let metrics = match stats {
+        Some(Ok(stats)) => ContainerMetrics::from(stats),
+        Some(Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. })) | None => {
+            return error_response(StatusCode::CONFLICT, "Project has no running container");
+        }
+        Some(Err(err)) => {
+            tracing::error!(?err, "Can't get metrics: Failed to get container stats");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get container stats");
+        }
+    };
+
+    let mut cache = METRICS_CACHE.lock().unwrap();
+    cache.retain(|_, (sampled_at, _)| sampled_at.elapsed() < METRICS_CACHE_TTL);
+    cache.insert(container_name, (Instant::now(), metrics.clone()));
+    drop(cache);
+
+    json_response(StatusCode::OK, serde_json::to_string(&metrics).unwrap())
+}
@@ -0,0 +1,61 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{projects::context::ProjectContext, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct DeployWebhookResponse {
+    /// `null` while no webhook is set
+    url: Option<String>,
+    /// the secret itself is never shown again
+    has_secret: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Where finished builds are reported to
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let webhook = sqlx::query_as::<_, (Option<String>, bool)>(
+        "SELECT deploy_webhook_url, deploy_webhook_secret IS NOT NULL FROM projects WHERE id = $1",
+    )
+    .bind(project.id)
+    .fetch_one(&pool)
+    .await;
+
+    let (url, has_secret) = match webhook {
+        Ok(webhook) => webhook,
+        Err(err) => {
+            tracing::error!(?err, "Can't get deploy webhook: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let json = serde_json::to_string(&DeployWebhookResponse { url, has_secret }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
@@ -0,0 +1,143 @@
+use axum::{
+    extract::Query,
+    response::Response,
+};
+use git2::{Delta, DiffFindOptions, Patch, Repository, Tree};
+use hyper::{Body, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::projects::{context::ProjectContext, repo::open_project_repo};
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Branch, tag, or commit hash the changes are compared against
+    base: Option<String>,
+    /// Branch, tag, or commit hash with the changes
+    head: Option<String>,
+    /// Include the unified diff of every file
+    patch: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChangedFile {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    status: &'static str,
+    binary: bool,
+    additions: usize,
+    deletions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patch: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DiffResponse {
+    base: String,
+    head: String,
+    files: Vec<ChangedFile>,
+    additions: usize,
+    deletions: usize,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+fn resolve_tree<'a>(repo: &'a Repository, input: &str) -> Option<Tree<'a>> {
+    repo.revparse_single(input).ok()?.peel_to_tree().ok()
+}
+
+fn status_name(status: Delta) -> &'static str {
+    match status {
+        Delta::Added => "added",
+        Delta::Deleted => "deleted",
+        Delta::Modified => "modified",
+        Delta::Renamed => "renamed",
+        Delta::Copied => "copied",
+        Delta::Typechange => "typechange",
+        _ => "other",
+    }
+}
+
+/// Files changed between two refs, for the compare screen. Renames are detected like
+/// `git diff -M` does.
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+    Query(DiffQuery { base: base_ref, head: head_ref, patch }): Query<DiffQuery>,
+) -> Response<Body> {
+    let (Some(base_ref), Some(head_ref)) = (base_ref, head_ref) else {
+        return error_response(StatusCode::BAD_REQUEST, "base and head are required");
+    };
+    let with_patch = patch.unwrap_or(false);
+
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    let Some(base_tree) = resolve_tree(&repo, &base_ref) else {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid base reference");
+    };
+    let Some(head_tree) = resolve_tree(&repo, &head_ref) else {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid head reference");
+    };
+
+    let mut diff = match repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None) {
+        Ok(diff) => diff,
+        Err(err) => {
+            tracing::error!(?err, "Can't get diff: Failed to compare trees");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to compute diff");
+        }
+    };
+    if let Err(err) = diff.find_similar(Some(DiffFindOptions::new().renames(true))) {
+        tracing::warn!(?err, "Can't detect renames in diff");
+    }
+
+    let mut files = Vec::with_capacity(diff.deltas().len());
+    for (index, delta) in diff.deltas().enumerate() {
+        let path = |file: git2::DiffFile| file.path().map(|path| path.to_string_lossy().to_string());
+
+        let mut file = ChangedFile {
+            old_path: path(delta.old_file()),
+            new_path: path(delta.new_file()),
+            status: status_name(delta.status()),
+            binary: delta.flags().is_binary(),
+            additions: 0,
+            deletions: 0,
+            patch: None,
+        };
+
+        // binary files have no lines to count
+        if let Ok(Some(mut patch)) = Patch::from_diff(&diff, index) {
+            if let Ok((_, additions, deletions)) = patch.line_stats() {
+                file.additions = additions;
+                file.deletions = deletions;
+            }
+            if with_patch {
+                file.patch = patch.to_buf().ok().map(|buf| String::from_utf8_lossy(&buf).to_string());
+            }
+        }
+
+        files.push(file);
+    }
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&DiffResponse {
+            additions: files.iter().map(|file| file.additions).sum(),
+            deletions: files.iter().map(|file| file.deletions).sum(),
+            base: base_ref,
+            head: head_ref,
+            files,
+        }).unwrap(),
+    )
+}
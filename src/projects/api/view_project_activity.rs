@@ -0,0 +1,122 @@
+use axum::extract::State;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{pagination::{Paginated, PaginationParams}, projects::context::ProjectContext, startup::AppState};
+
+/// Every activity of the project bound to `$1`, synthesized from builds and project shares
+const ACTIVITY_QUERY: &str = r#"
+    SELECT builds.priority AS type,
+           NULL::TEXT AS actor,
+           builds.created_at AS timestamp,
+           CASE WHEN builds.commit_sha IS NULL THEN 'Build queued'
+                ELSE 'Build queued for commit ' || LEFT(builds.commit_sha, 7)
+           END AS summary
+    FROM builds
+    WHERE builds.project_id = $1
+    UNION ALL
+    SELECT CASE WHEN builds.status = 'successful' THEN 'deploy' ELSE 'build_failed' END,
+           NULL::TEXT,
+           builds.finished_at,
+           CASE WHEN builds.status = 'successful' THEN 'Deployed' ELSE 'Build failed' END
+           || COALESCE(' commit ' || LEFT(builds.commit_sha, 7), '')
+    FROM builds
+    WHERE builds.project_id = $1
+      AND builds.finished_at IS NOT NULL
+      AND builds.status IN ('successful', 'failed')
+    UNION ALL
+    SELECT 'member_added',
+           NULL::TEXT,
+           project_shares.created_at,
+           users.username || ' was given access to the project'
+    FROM project_shares
+    JOIN users ON project_shares.user_id = users.id
+    WHERE project_shares.project_id = $1
+"#;
+
+#[derive(Serialize, Debug)]
+struct ActivityEntry {
+    /// push, rebuild, preview, deploy, build_failed or member_added
+    r#type: String,
+    /// who did it, not known for entries synthesized from builds
+    actor: Option<String>,
+    timestamp: DateTime<Utc>,
+    summary: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// What happened recently on a project, newest first. There is no audit log yet so the feed
+/// is put together from builds and project shares, env changes will show up once one exists.
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+    pagination: PaginationParams,
+) -> Response<Body> {
+    let entries = sqlx::query_as::<_, (String, Option<String>, DateTime<Utc>, String)>(&format!(
+        "SELECT type, actor, timestamp, summary FROM ({ACTIVITY_QUERY}) AS activity
+         ORDER BY timestamp DESC
+         LIMIT $2 OFFSET $3"
+    ))
+    .bind(project.id)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .fetch_all(&pool)
+    .await;
+
+    let entries = match entries {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|(r#type, actor, timestamp, summary)| ActivityEntry {
+                r#type,
+                actor,
+                timestamp,
+                summary,
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            tracing::error!(?err, "Can't get project activity: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let total = match sqlx::query_as::<_, (i64,)>(&format!("SELECT COUNT(*) FROM ({ACTIVITY_QUERY}) AS activity"))
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await
+    {
+        Ok((total,)) => total,
+        Err(err) => {
+            tracing::error!(?err, "Can't count project activity: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&Paginated::new(entries, &pagination, total)).unwrap(),
+    )
+}
@@ -0,0 +1,97 @@
+use std::path::Path as StdPath;
+
+use axum::{
+    extract::Query,
+    response::Response,
+};
+use git2::{ErrorCode, ObjectType};
+use hyper::{Body, StatusCode};
+
+use crate::projects::{content_type::content_type, context::ProjectContext, repo::open_project_repo};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BlobQuery {
+    /// Branch, tag, or commit hash (defaults to "HEAD")
+    #[serde(rename = "ref")]
+    r#ref: Option<String>,
+    /// File path within the repo
+    path: Option<String>,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+/// Contents of a file in the repository, the counterpart of the tree listing for when the
+/// user opens a file. Served inline, the `Content-Type` is guessed from the path and content.
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+    Query(BlobQuery { r#ref, path }): Query<BlobQuery>,
+) -> Response<Body> {
+    let path = path.unwrap_or_default();
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "path is required");
+    }
+
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    let ref_input = r#ref.unwrap_or_else(|| "HEAD".to_string());
+    let tree = match repo.revparse_single(&ref_input) {
+        Ok(object) => match object.peel_to_tree() {
+            Ok(tree) => tree,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Reference is not a tree/commit"),
+        },
+        // an empty repository has no files either
+        Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid reference"),
+    };
+
+    let entry = match tree.get_path(StdPath::new(path)) {
+        Ok(entry) => entry,
+        Err(err) if err.code() == ErrorCode::NotFound => {
+            return error_response(StatusCode::NOT_FOUND, "Path not found");
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get blob: Failed to resolve path");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve path");
+        }
+    };
+
+    if entry.kind() != Some(ObjectType::Blob) {
+        // tell the client what the path is so it can go to the tree endpoint instead
+        let body = serde_json::to_string(&serde_json::json!({
+            "message": "Path is not a file",
+            "object_type": entry.kind().map(|kind| kind.str()).unwrap_or("unknown"),
+            "object_id": entry.id().to_string(),
+        }))
+        .unwrap();
+        return json_response(StatusCode::BAD_REQUEST, body);
+    }
+
+    let blob = match repo.find_blob(entry.id()) {
+        Ok(blob) => blob,
+        Err(err) => {
+            tracing::error!(?err, "Can't get blob: Failed to read object");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file");
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type(path, blob.content()))
+        .header("X-Content-Type-Options", "nosniff")
+        .body(Body::from(blob.content().to_vec()))
+        .unwrap()
+}
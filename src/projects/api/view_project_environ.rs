@@ -1,16 +1,24 @@
-use axum::extract::{State, Path};
+use axum::extract::{State, Path, Query};
 use axum::response::Response;
 use hyper::{Body, StatusCode};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::{auth::Auth, startup::AppState};
+use crate::{auth::Auth, projects::{context::ProjectContext, environ, environ_cipher}, startup::AppState};
+
+#[derive(Deserialize, Debug)]
+pub struct EnvironQuery {
+    /// show the values of secrets instead of masking them
+    reveal: Option<bool>,
+}
 
 #[derive(Serialize, Debug)]
 struct EnvironResponse {
     id: Uuid,
     env: Value,
+    /// keys flagged as secret, their values are masked unless revealed
+    secrets: Vec<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -18,26 +26,35 @@ struct ErrorResponse {
     message: String,
 }
 
-#[tracing::instrument(skip(auth, pool))]
+#[tracing::instrument(skip(context, auth, pool))]
 pub async fn get(
+    context: ProjectContext,
     auth: Auth,
     State(AppState { pool, domain, secure, .. }): State<AppState>,
     Path((owner, project)): Path<(String, String)>,
+    Query(EnvironQuery { reveal }): Query<EnvironQuery>,
 ) -> Response<Body> {
     let _user = auth.current_user.unwrap();
 
+    let reveal = reveal.unwrap_or(false);
+    if reveal {
+        if let Err(response) = context.require_editor() {
+            return response;
+        }
+    }
+
     // check if project exist
-    let project = match sqlx::query!(
-        r#"SELECT projects.id AS id, projects.name AS project, projects.environs AS env
+    let (id, env, secrets) = match sqlx::query_as::<_, (Uuid, Value, Vec<String>)>(
+        r#"SELECT projects.id AS id, projects.environs AS env, projects.secret_environs AS secrets
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            JOIN users_owners ON project_owners.id = users_owners.owner_id
            AND projects.name = $1
            AND project_owners.name = $2
         "#,
-        project,
-        owner,
     )
+    .bind(&project)
+    .bind(&owner)
     .fetch_optional(&pool)
     .await
     {
@@ -66,9 +83,21 @@ pub async fn get(
         }
     };
 
+    let mut env = environ_cipher::decrypt_environs(&env);
+    if !reveal {
+        if let Some(env) = env.as_object_mut() {
+            for (key, value) in env.iter_mut() {
+                if environ::is_masked(key, &secrets) {
+                    *value = Value::String(environ::MASKED_VALUE.to_string());
+                }
+            }
+        }
+    }
+
     let json = serde_json::to_string(&EnvironResponse {
-        id: project.id,
-        env: project.env,
+        id,
+        env,
+        secrets,
     }).unwrap();
 
     Response::builder()
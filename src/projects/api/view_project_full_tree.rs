@@ -0,0 +1,204 @@
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use git2::{ObjectType, Odb, Repository, Tree};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    git::head_is_unborn,
+    projects::{context::ProjectContext, repo::open_project_repo, tree_filter::TreeFilter},
+    startup::AppState,
+};
+
+/// Entries returned when the client doesn't ask for a `limit`
+const DEFAULT_MAX_ENTRIES: usize = 5_000;
+/// Highest `limit` a client can ask for
+const MAX_ENTRIES: usize = 50_000;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum FullTreeEntry {
+    Dir { path: String },
+    File { path: String, size: u64 },
+    Symlink { path: String },
+    Submodule { path: String },
+}
+
+#[derive(Serialize, Debug)]
+struct FullTreeResponse {
+    #[serde(rename = "ref")]
+    r#ref: String,
+    is_empty_repo: bool,
+    entries: Vec<FullTreeEntry>,
+    /// the listing stopped at the entry limit, narrow it down with `ignore`
+    truncated: bool,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+struct Walk<'a> {
+    repo: &'a Repository,
+    /// sizes come from object headers, no need to inflate every blob
+    odb: Option<Odb<'a>>,
+    filter: Option<&'a TreeFilter>,
+    limit: usize,
+    entries: Vec<FullTreeEntry>,
+    truncated: bool,
+}
+
+impl Walk<'_> {
+    fn push(&mut self, entry: FullTreeEntry) -> bool {
+        if self.entries.len() >= self.limit {
+            self.truncated = true;
+            return false;
+        }
+
+        self.entries.push(entry);
+        true
+    }
+
+    /// Lists everything under `tree`. Ignored directories aren't descended into, like
+    /// gitignore.
+    fn walk(&mut self, tree: &Tree, dir: &str) {
+        for entry in tree.iter() {
+            if self.truncated {
+                break;
+            }
+
+            let name = String::from_utf8_lossy(entry.name_bytes()).to_string();
+            let path = match dir.is_empty() {
+                true => name,
+                false => format!("{dir}/{name}"),
+            };
+
+            if self.filter.map_or(false, |filter| filter.is_ignored(&path)) {
+                continue;
+            }
+
+            if entry.kind() == Some(ObjectType::Tree) {
+                let Ok(subtree) = self.repo.find_tree(entry.id()) else {
+                    continue;
+                };
+
+                // the directory comes before its contents
+                if !self.push(FullTreeEntry::Dir { path: path.clone() }) {
+                    break;
+                }
+                self.walk(&subtree, &path);
+                continue;
+            }
+
+            let entry = match entry.kind() {
+                Some(ObjectType::Commit) => FullTreeEntry::Submodule { path },
+                // 0o120000 is a symlink in git trees
+                Some(ObjectType::Blob) if entry.filemode() == 0o120000 => FullTreeEntry::Symlink { path },
+                Some(ObjectType::Blob) => {
+                    let size = self
+                        .odb
+                        .as_ref()
+                        .and_then(|odb| odb.read_header(entry.id()).ok())
+                        .map(|(len, _)| len as u64)
+                        .unwrap_or(0);
+                    FullTreeEntry::File { path, size }
+                }
+                _ => continue,
+            };
+
+            if !self.push(entry) {
+                break;
+            }
+        }
+    }
+}
+
+/// Every entry of the repository in one response, for clients that would otherwise walk the
+/// tree one directory at a time. Filtering happens here so large repositories don't send what
+/// the client would throw away.
+///
+/// Query: `ref`, `ignore` (`default` or comma separated globs matched against the full path,
+/// the same as the tree listing) and `limit`.
+#[tracing::instrument(skip(project, tree_ignore))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { tree_ignore, .. }): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response<Body> {
+    let mut r#ref = None;
+    let mut ignore = None;
+    let mut limit = DEFAULT_MAX_ENTRIES;
+
+    for (key, value) in params {
+        match key.trim_end_matches("[]") {
+            "ref" => r#ref = Some(value),
+            "ignore" => ignore = Some(value),
+            "limit" => match value.parse::<usize>() {
+                Ok(value) if value > 0 => limit = value.min(MAX_ENTRIES),
+                _ => return error_response(StatusCode::BAD_REQUEST, "limit must be a positive number"),
+            },
+            _ => {}
+        }
+    }
+
+    let filter = match TreeFilter::from_query(ignore.as_deref(), &tree_ignore) {
+        Ok(filter) => filter,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, &format!("Invalid ignore glob: {err}")),
+    };
+
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    let ref_input = r#ref.unwrap_or_else(|| "HEAD".to_string());
+    let tree = match repo.revparse_single(&ref_input) {
+        Ok(object) => match object.peel_to_tree() {
+            Ok(tree) => tree,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Reference is not a tree/commit"),
+        },
+        // unborn HEAD, nothing pushed yet or the default branch was deleted
+        Err(_) if head_is_unborn(&repo) => {
+            return json_response(
+                StatusCode::OK,
+                serde_json::to_string(&FullTreeResponse {
+                    r#ref: ref_input,
+                    is_empty_repo: true,
+                    entries: vec![],
+                    truncated: false,
+                }).unwrap(),
+            );
+        }
+        Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid reference"),
+    };
+
+    let mut walk = Walk {
+        repo: &repo,
+        odb: repo.odb().ok(),
+        filter: filter.as_ref(),
+        limit,
+        entries: Vec::new(),
+        truncated: false,
+    };
+    walk.walk(&tree, "");
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&FullTreeResponse {
+            r#ref: ref_input,
+            is_empty_repo: false,
+            entries: walk.entries,
+            truncated: walk.truncated,
+        }).unwrap(),
+    )
+}
@@ -0,0 +1,184 @@
+use std::{
+    io::Read,
+    path::{Path as StdPath, PathBuf},
+};
+
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use bytes::Bytes;
+use git2::{ErrorCode, ObjectType, Oid};
+use hyper::{body::Sender, Body, StatusCode};
+use tokio::runtime::Handle;
+
+use crate::{
+    git::open_bare_repo,
+    projects::{
+        content_type::{attachment, OCTET_STREAM},
+        context::ProjectContext,
+        repo::open_project_repo,
+    },
+    startup::AppState,
+};
+
+/// Bytes read from the object database per chunk of a streamed download
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RawQuery {
+    /// Branch, tag, or commit hash (defaults to "HEAD")
+    #[serde(rename = "ref")]
+    r#ref: Option<String>,
+    /// File path within the repo
+    path: Option<String>,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::to_string(&serde_json::json!({ "message": message })).unwrap())
+}
+
+/// Sends the blob to `sender` a chunk at a time. Runs on a blocking thread since neither the
+/// repository nor its readers can be held across an await.
+fn stream_blob(repo_path: PathBuf, oid: Oid, sender: &mut Sender, handle: &Handle) -> Result<(), String> {
+    let repo = open_bare_repo(&repo_path).map_err(|err| format!("{err:?}"))?;
+    let odb = repo.odb().map_err(|err| err.to_string())?;
+
+    let (mut reader, _, _) = match odb.reader(oid) {
+        Ok(reader) => reader,
+        // packed objects can't be read as a stream, they have to be inflated at once
+        Err(_) => {
+            let object = odb.read(oid).map_err(|err| err.to_string())?;
+            for chunk in object.data().chunks(CHUNK_SIZE) {
+                handle
+                    .block_on(sender.send_data(Bytes::copy_from_slice(chunk)))
+                    .map_err(|err| err.to_string())?;
+            }
+            return Ok(());
+        }
+    };
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buf).map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        // fails once the client went away
+        handle
+            .block_on(sender.send_data(Bytes::copy_from_slice(&buf[..read])))
+            .map_err(|err| err.to_string())?;
+    }
+}
+
+/// A file in the repository as a download, for the "Download" button of the file browser.
+/// Files larger than `git.rawstreamthreshold` are streamed from the object database so they
+/// never sit in memory as a whole.
+#[tracing::instrument(skip(project))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { raw_stream_threshold, .. }): State<AppState>,
+    Query(RawQuery { r#ref, path }): Query<RawQuery>,
+) -> Response<Body> {
+    let path = path.unwrap_or_default();
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "path is required");
+    }
+
+    let repo_path = project.repo_path.clone();
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+
+    // the streaming thread opens the repository again, only the object id leaves here
+    let (oid, size, content) = {
+        let ref_input = r#ref.unwrap_or_else(|| "HEAD".to_string());
+        let tree = match repo.revparse_single(&ref_input) {
+            Ok(object) => match object.peel_to_tree() {
+                Ok(tree) => tree,
+                Err(_) => return error_response(StatusCode::BAD_REQUEST, "Reference is not a tree/commit"),
+            },
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid reference"),
+        };
+
+        let entry = match tree.get_path(StdPath::new(path)) {
+            Ok(entry) => entry,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                return error_response(StatusCode::NOT_FOUND, "Path not found");
+            }
+            Err(err) => {
+                tracing::error!(?err, "Can't get raw file: Failed to resolve path");
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve path");
+            }
+        };
+
+        if entry.kind() != Some(ObjectType::Blob) {
+            let body = serde_json::to_string(&serde_json::json!({
+                "message": "Path is not a file",
+                "object_type": entry.kind().map(|kind| kind.str()).unwrap_or("unknown"),
+                "object_id": entry.id().to_string(),
+            }))
+            .unwrap();
+            return json_response(StatusCode::BAD_REQUEST, body);
+        }
+
+        // the header has the size without inflating the object
+        let size = match repo.odb().and_then(|odb| odb.read_header(entry.id())) {
+            Ok((size, _)) => size,
+            Err(err) => {
+                tracing::error!(?err, "Can't get raw file: Failed to read object header");
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file");
+            }
+        };
+
+        let content = match size > raw_stream_threshold {
+            true => None,
+            false => match repo.find_blob(entry.id()) {
+                Ok(blob) => Some(blob.content().to_vec()),
+                Err(err) => {
+                    tracing::error!(?err, "Can't get raw file: Failed to read object");
+                    return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file");
+                }
+            },
+        };
+
+        (entry.id(), size, content)
+    };
+
+    let body = match content {
+        Some(content) => Body::from(content),
+        None => {
+            let (mut sender, body) = Body::channel();
+            let handle = Handle::current();
+
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = stream_blob(repo_path, oid, &mut sender, &handle) {
+                    tracing::warn!(err, %oid, "Raw file download stopped");
+                    sender.abort();
+                }
+            });
+
+            body
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", OCTET_STREAM)
+        .header("Content-Disposition", attachment(path))
+        .header("Content-Length", size)
+        .header("X-Content-Type-Options", "nosniff")
+        .body(body)
+        .unwrap()
+}
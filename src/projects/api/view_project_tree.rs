@@ -1,22 +1,69 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Query, State},
     response::Response,
 };
 use hyper::{Body, StatusCode};
 use serde::Serialize;
-use git2::{ObjectType, Repository};
+use git2::{ObjectType, Repository, Tree, TreeEntry as GitTreeEntry, TreeWalkMode, TreeWalkResult};
+use std::collections::HashMap;
 use std::path::Path as StdPath;
 
-use crate::startup::AppState;
+use crate::{git::{gitmodule_urls, head_is_unborn}, projects::{last_commit::{last_commits, LastCommit}, context::ProjectContext, repo::open_project_repo, tree_filter::{dir_size, TreeFilter}}, startup::AppState};
+
+/// Entries in a page when the client doesn't ask for a `limit`
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Symlink targets longer than this are cut off, a real one never comes close
+const MAX_SYMLINK_TARGET: usize = 4096;
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 pub enum TreeEntry {
-    Dir { name: String },
-    File { name: String, size: u64 },
-    Symlink { name: String },
-    Submodule { name: String },
-    Other { name: String },
+    Dir {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        mode: String,
+        id: String,
+        #[serde(flatten)]
+        last_commit: Option<LastCommit>,
+    },
+    File {
+        name: String,
+        size: u64,
+        mode: String,
+        id: String,
+        #[serde(flatten)]
+        last_commit: Option<LastCommit>,
+    },
+    Symlink {
+        name: String,
+        mode: String,
+        id: String,
+        /// where the link points, cut off past MAX_SYMLINK_TARGET bytes
+        target: String,
+        #[serde(flatten)]
+        last_commit: Option<LastCommit>,
+    },
+    Submodule {
+        name: String,
+        mode: String,
+        id: String,
+        /// commit of the submodule the repository pins
+        commit: String,
+        /// from `.gitmodules`, left out when it isn't there
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        #[serde(flatten)]
+        last_commit: Option<LastCommit>,
+    },
+    Other {
+        name: String,
+        mode: String,
+        id: String,
+        #[serde(flatten)]
+        last_commit: Option<LastCommit>,
+    },
 }
 
 #[derive(Serialize, Debug)]
@@ -26,6 +73,12 @@ pub struct TreeResponse {
     path: String,
     is_empty_repo: bool,
     entries: Vec<TreeEntry>,
+    /// entries in the listing across all pages
+    total: usize,
+    /// there are entries past this page, ask for them with a higher offset
+    has_more: bool,
+    /// a recursive listing stopped at the entry limit
+    truncated: bool,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -35,81 +88,112 @@ pub struct TreeQuery {
     r#ref: Option<String>,
     /// Directory path within the repo (defaults to root)
     path: Option<String>,
+    /// `default` for the configured ignore globs, or a comma separated glob list
+    ignore: Option<String>,
+    /// Include the total size of each directory
+    sizes: Option<bool>,
+    /// Entries to skip, for directories too large to list at once
+    offset: Option<usize>,
+    /// Entries to return, DEFAULT_PAGE_SIZE by default and at most the configured maximum
+    limit: Option<usize>,
+    /// List everything below `path`, with names relative to it
+    recursive: Option<bool>,
+    /// Include the last commit that changed each entry
+    with_commit: Option<bool>,
 }
 
-#[tracing::instrument(skip(pool, base))]
+/// What every entry of a listing is described with
+struct Describe<'a> {
+    repo: &'a Repository,
+    sizes: bool,
+    filter: Option<&'a TreeFilter>,
+    /// submodule urls by path, from `.gitmodules` at the root of the listed ref
+    submodule_urls: HashMap<String, String>,
+}
+
+impl Describe<'_> {
+    /// `entry_path` is where the entry sits in the repository and `name` what the listing
+    /// calls it
+    fn entry(&self, entry: &GitTreeEntry, name: String, entry_path: &str, last_commit: Option<LastCommit>) -> TreeEntry {
+        // octal like git prints it, e.g. 100755 for an executable file
+        let mode = format!("{:06o}", entry.filemode());
+        let id = entry.id().to_string();
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let size = match self.sizes {
+                    true => self
+                        .repo
+                        .find_tree(entry.id())
+                        .ok()
+                        .map(|subtree| dir_size(self.repo, &subtree, entry_path, self.filter)),
+                    false => None,
+                };
+                TreeEntry::Dir { name, size, mode, id, last_commit }
+            }
+            Some(ObjectType::Commit) => TreeEntry::Submodule {
+                name,
+                mode,
+                // a submodule entry is the commit it pins
+                commit: id.clone(),
+                url: self.submodule_urls.get(entry_path).cloned(),
+                id,
+                last_commit,
+            },
+            // 0o120000 is a symlink in git trees
+            Some(ObjectType::Blob) if entry.filemode() == 0o120000 => {
+                // git stores the destination of a symlink as its blob
+                let target = self
+                    .repo
+                    .find_blob(entry.id())
+                    .map(|blob| {
+                        let content = blob.content();
+                        String::from_utf8_lossy(&content[..content.len().min(MAX_SYMLINK_TARGET)]).to_string()
+                    })
+                    .unwrap_or_default();
+                TreeEntry::Symlink { name, mode, id, target, last_commit }
+            }
+            Some(ObjectType::Blob) => {
+                let size = self.repo.find_blob(entry.id()).map(|b| b.size() as u64).unwrap_or(0);
+                TreeEntry::File { name, size, mode, id, last_commit }
+            }
+            _ => TreeEntry::Other { name, mode, id, last_commit },
+        }
+    }
+}
+
+/// Submodule urls from the `.gitmodules` at the root of `tree`, none when it has no such file
+fn submodule_urls(repo: &Repository, tree: &Tree) -> HashMap<String, String> {
+    tree.get_name(".gitmodules")
+        .and_then(|entry| repo.find_blob(entry.id()).ok())
+        .map(|blob| gitmodule_urls(&String::from_utf8_lossy(blob.content())))
+        .unwrap_or_default()
+}
+
+#[tracing::instrument(skip(project, tree_ignore))]
 pub async fn get(
-    Path((owner, project)): Path<(String, String)>,
-    State(AppState { pool, base, .. }): State<AppState>,
-    Query(TreeQuery { r#ref, path }): Query<TreeQuery>,
+    project: ProjectContext,
+    State(AppState { tree_ignore, tree_max_entries, tree_max_recursive, .. }): State<AppState>,
+    Query(TreeQuery { r#ref, path, ignore, sizes, offset, limit, recursive, with_commit }): Query<TreeQuery>,
 ) -> Response<Body> {
-    // ---- Project existence (runtime SQLx; no macros -> no DATABASE_URL at build) ----
-    
-    
-    let exists = sqlx::query_scalar::<_, bool>(
-        r#"
-        SELECT EXISTS (
-          SELECT 1
-          FROM projects
-          JOIN project_owners ON projects.owner_id = project_owners.id
-          WHERE project_owners.name = $1
-            AND projects.name = $2
-            AND projects.deleted_at IS NULL
-        )
-        "#
-    )
-    .bind(&owner)
-    .bind(&project)
-    .fetch_one(&pool)
-    .await;
-
-    let exists = match exists {
-        Ok(v) => v,
+    let filter = match TreeFilter::from_query(ignore.as_deref(), &tree_ignore) {
+        Ok(filter) => filter,
         Err(err) => {
             let body = serde_json::to_string(&serde_json::json!({
-                "message": format!("Database error: {}", err)
+                "message": format!("Invalid ignore glob: {}", err)
             })).unwrap();
             return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(StatusCode::BAD_REQUEST)
                 .header("Content-Type", "application/json")
                 .body(Body::from(body))
                 .unwrap();
         }
     };
 
-    if !exists {
-        let body = serde_json::to_string(&serde_json::json!({
-            "message": "Project not found"
-        })).unwrap();
-        return Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header("Content-Type", "application/json")
-            .body(Body::from(body))
-            .unwrap();
-    }
-
-
-
     // ---- Open bare repository ----
-    let repo_path = if project.ends_with(".git") {
-        format!("{base}/{owner}/{project}")
-    } else {
-        format!("{base}/{owner}/{project}.git")
-    };
-
-    let repo = match Repository::open_bare(&repo_path) {
-        Ok(r) => r,
-        Err(err) => {
-            let body = serde_json::to_string(&serde_json::json!({
-                "message": format!("Failed to open repository: {}", err)
-            }))
-            .unwrap();
-            return Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header("Content-Type", "application/json")
-                .body(Body::from(body))
-                .unwrap();
-        }
+    let repo = match open_project_repo(&project) {
+        Ok(repo) => repo,
+        Err(response) => return response,
     };
 
     // ---- Resolve ref (default HEAD); handle unborn HEAD (empty repo) ----
@@ -121,12 +205,23 @@ pub async fn get(
             } else if let Ok(tree) = obj.peel_to_tree() {
                 (false, Some(Some(tree)))
             } else {
-                (false, None)
+                // tell the client what the ref is so it can go to the blob endpoint instead
+                let body = serde_json::to_string(&serde_json::json!({
+                    "message": "Reference is not a tree/commit",
+                    "object_type": obj.kind().map(|kind| kind.str()).unwrap_or("unknown"),
+                    "object_id": obj.id().to_string(),
+                }))
+                .unwrap();
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
             }
         }
         Err(_) => {
-            // Unborn HEAD => empty repo
-            if repo.head().ok().and_then(|h| h.target()).is_none() {
+            // Unborn HEAD (new repo or deleted default branch) => empty repo
+            if head_is_unborn(&repo) {
                 (true, None)
             } else {
                 let body = serde_json::to_string(&serde_json::json!({
@@ -148,6 +243,9 @@ pub async fn get(
             path: path.clone().unwrap_or_default(),
             is_empty_repo: true,
             entries: vec![],
+            total: 0,
+            has_more: false,
+            truncated: false,
         })
         .unwrap();
         return Response::builder()
@@ -172,6 +270,9 @@ pub async fn get(
         }
     };
 
+    // read before descending, `.gitmodules` sits at the root
+    let submodule_urls = submodule_urls(&repo, &tree);
+
     // ---- Traverse into subdirectory if path provided ----
     let path_str = path.unwrap_or_default();
     if !path_str.is_empty() {
@@ -195,7 +296,9 @@ pub async fn get(
                     Some(t) => tree = t.clone(),
                     None => {
                         let body = serde_json::to_string(&serde_json::json!({
-                            "message": "Path is not a directory"
+                            "message": "Path is not a directory",
+                            "object_type": obj.kind().map(|kind| kind.str()).unwrap_or("unknown"),
+                            "object_id": obj.id().to_string(),
                         }))
                         .unwrap();
                         return Response::builder()
@@ -221,57 +324,115 @@ pub async fn get(
     }
 
 
-    // ---- Collect and sort entries: dirs, files, symlinks, submodules, others ----
+    let recursive = recursive.unwrap_or(false);
+    if recursive && with_commit.unwrap_or(false) {
+        let body = serde_json::to_string(&serde_json::json!({
+            "message": "with_commit only works on a single directory, not a recursive listing"
+        }))
+        .unwrap();
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+    }
+    let dir_path = path_str.trim_matches('/').to_string();
+
+    // ---- A page at a time, directories in monorepos can have tens of thousands of entries ----
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(tree_max_entries);
+
+    let describe = Describe {
+        repo: &repo,
+        sizes: sizes.unwrap_or(false),
+        filter: filter.as_ref(),
+        submodule_urls,
+    };
     let mut entries: Vec<TreeEntry> = Vec::new();
+    let mut total = 0;
+    let mut truncated = false;
 
-    for entry in tree.iter() {
-        let name = entry
-            .name()
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| String::from_utf8_lossy(entry.name_bytes()).to_string());
+    if recursive {
+        // ---- Walk everything below the directory, parents come before their contents ----
+        // the walk aborts at the cap, which makes it return an error that isn't one
+        let _ = tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            let name = format!("{dir}{}", String::from_utf8_lossy(entry.name_bytes()));
+            let entry_path = match dir_path.as_str() {
+                "" => name.clone(),
+                dir => format!("{dir}/{name}"),
+            };
+            if filter.as_ref().map_or(false, |filter| filter.is_ignored(&entry_path)) {
+                return TreeWalkResult::Skip;
+            }
 
-        match entry.kind() {
-            Some(ObjectType::Tree) => entries.push(TreeEntry::Dir { name }),
-            Some(ObjectType::Commit) => entries.push(TreeEntry::Submodule { name }),
-            Some(ObjectType::Blob) => {
-                // 0o120000 is a symlink in git trees
-                if entry.filemode() == 0o120000 {
-                    entries.push(TreeEntry::Symlink { name });
-                } else {
-                    let size = repo.find_blob(entry.id()).map(|b| b.size() as u64).unwrap_or(0);
-                    entries.push(TreeEntry::File { name, size });
-                }
+            if entries.len() >= tree_max_recursive {
+                truncated = true;
+                return TreeWalkResult::Abort;
             }
-            _ => entries.push(TreeEntry::Other { name }),
-        }
-    }
 
-    // Sort by (rank, lowercase_name). Using owned key avoids lifetimes.
-    entries.sort_by_key(|e| {
-        use TreeEntry::*;
-        let rank: u8 = match e {
-            Dir { .. } => 0,
-            File { .. } => 1,
-            Symlink { .. } => 2,
-            Submodule { .. } => 3,
-            Other { .. } => 4,
-        };
-        let name = match e {
-            Dir { name }
-            | File { name, .. }
-            | Symlink { name }
-            | Submodule { name }
-            | Other { name } => name.to_lowercase(),
+            entries.push(describe.entry(entry, name, &entry_path, None));
+            TreeWalkResult::Ok
+        });
+
+        total = entries.len();
+        entries = entries.into_iter().skip(offset).take(limit).collect();
+    } else {
+        // ---- Find what last changed each entry, only when asked since it walks history ----
+        let mut changed_by = match with_commit.unwrap_or(false) {
+            true => repo
+                .revparse_single(&ref_input)
+                .and_then(|obj| obj.peel_to_commit())
+                .ok()
+                .map(|commit| last_commits(&repo, &commit, &dir_path)),
+            false => None,
         };
-        (rank, name)
-    });
+
+        // ---- Collect and sort entries: dirs, files, symlinks, submodules, others ----
+        // sorted before describing them, only the entries of the page get looked up
+        let mut listed = Vec::new();
+        for entry in tree.iter() {
+            let name = entry
+                .name()
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| String::from_utf8_lossy(entry.name_bytes()).to_string());
+
+            let entry_path = match dir_path.as_str() {
+                "" => name.clone(),
+                dir => format!("{dir}/{name}"),
+            };
+            if filter.as_ref().map_or(false, |filter| filter.is_ignored(&entry_path)) {
+                continue;
+            }
+
+            let rank: u8 = match entry.kind() {
+                Some(ObjectType::Tree) => 0,
+                Some(ObjectType::Blob) if entry.filemode() != 0o120000 => 1,
+                Some(ObjectType::Blob) => 2,
+                Some(ObjectType::Commit) => 3,
+                _ => 4,
+            };
+            listed.push((rank, name.to_lowercase(), name, entry_path, entry));
+        }
+
+        // names that only differ in case fall back to the exact name, pages never overlap
+        listed.sort_by(|a, b| (a.0, &a.1, &a.2).cmp(&(b.0, &b.1, &b.2)));
+        total = listed.len();
+
+        for (_, _, name, entry_path, entry) in listed.into_iter().skip(offset).take(limit) {
+            let last_commit = changed_by.as_mut().and_then(|changed_by| changed_by.remove(&name));
+            entries.push(describe.entry(&entry, name, &entry_path, last_commit));
+        }
+    }
 
     // ---- Respond ----
     let json = serde_json::to_string(&TreeResponse {
         r#ref: ref_input,
         path: path_str,
         is_empty_repo: false,
+        has_more: offset + entries.len() < total,
         entries,
+        total,
+        truncated,
     })
     .unwrap();
 
@@ -0,0 +1,88 @@
+use axum::extract::State;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{
+    git::canonical_repo_name,
+    projects::context::ProjectContext,
+    startup::AppState,
+    usage::{docker_usage, refresh_repo_size, DockerUsage},
+};
+
+#[derive(Serialize, Debug)]
+struct UsageResponse {
+    repo_bytes: u64,
+    repo_measured_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    docker: DockerUsage,
+    total_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+/// Disk used by the project: its repository, the image it runs and its volumes. The
+/// repository size is the one measured after the last push.
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let measured = sqlx::query_as::<_, (Option<i64>, Option<DateTime<Utc>>)>(
+        "SELECT repo_size_bytes, repo_size_updated_at FROM projects WHERE id = $1",
+    )
+    .bind(project.id)
+    .fetch_one(&pool)
+    .await;
+
+    let (repo_bytes, repo_measured_at) = match measured {
+        Ok((Some(size), measured_at)) => (size as u64, measured_at),
+        // not pushed to since sizes are tracked
+        Ok((None, _)) => {
+            let size = refresh_repo_size(&pool, &project.owner, &project.project, project.repo_path.clone()).await;
+            (size.unwrap_or(0), size.map(|_| Utc::now()))
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't get usage: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    let container_name = format!("{}-{}", project.owner, canonical_repo_name(&project.project)).replace('.', "-");
+    let docker = match docker_usage(&[container_name.clone()]).await {
+        Ok(mut usage) => usage.remove(&container_name).unwrap_or_default(),
+        Err(err) => {
+            tracing::error!(?err, "Can't get usage: Failed to get docker disk usage");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get docker disk usage");
+        }
+    };
+
+    let json = serde_json::to_string(&UsageResponse {
+        repo_bytes,
+        repo_measured_at,
+        docker,
+        total_bytes: repo_bytes + docker.total_bytes(),
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
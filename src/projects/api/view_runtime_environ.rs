@@ -0,0 +1,146 @@
+use axum::extract::State;
+use axum::response::Response;
+use bollard::Docker;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    git::canonical_repo_name,
+    projects::{context::ProjectContext, environ},
+    startup::AppState,
+};
+
+#[derive(Serialize, Debug)]
+struct RuntimeEnvVar {
+    key: String,
+    value: String,
+    masked: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct RuntimeEnvironResponse {
+    id: Uuid,
+    container_name: String,
+    env: Vec<RuntimeEnvVar>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Environment of the running container, masked the same way as the project's env page
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let secret_keys = match sqlx::query_as::<_, (Vec<String>,)>("SELECT secret_environs FROM projects WHERE id = $1")
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await
+    {
+        Ok((secret_keys,)) => secret_keys,
+        Err(err) => {
+            tracing::error!(?err, "Can't get project: Failed to query database");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Internal server error".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't inspect container: Failed to connect to docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to connect to docker".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let container_name = format!("{}-{}", project.owner, canonical_repo_name(&project.project)).replace('.', "-");
+
+    let not_running = || {
+        let json = serde_json::to_string(&ErrorResponse {
+            message: "Project has no running container".to_string(),
+        }).unwrap();
+
+        Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap()
+    };
+
+    let container = match docker.inspect_container(&container_name, None).await {
+        Ok(container) => container,
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+            return not_running();
+        }
+        Err(err) => {
+            tracing::error!(?err, "Can't inspect container: Failed to query docker");
+
+            let json = serde_json::to_string(&ErrorResponse {
+                message: "Failed to inspect container".to_string(),
+            }).unwrap();
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .unwrap();
+        }
+    };
+
+    let running = container
+        .state
+        .and_then(|state| state.running)
+        .unwrap_or(false);
+
+    if !running {
+        return not_running();
+    }
+
+    let env = container
+        .config
+        .and_then(|config| config.env)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|var| {
+            let (key, value) = var.split_once('=').unwrap_or((var.as_str(), ""));
+            RuntimeEnvVar {
+                key: key.to_string(),
+                value: environ::display_value(key, value, &secret_keys),
+                masked: environ::is_masked(key, &secret_keys),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&RuntimeEnvironResponse {
+        id: project.id,
+        container_name,
+        env,
+    }).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
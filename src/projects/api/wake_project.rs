@@ -0,0 +1,77 @@
+use axum::extract::State;
+use axum::response::Response;
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+
+use crate::{projects::context::ProjectContext, sleep, startup::AppState};
+
+#[derive(Serialize, Debug)]
+struct WakeResponse {
+    sleeping: bool,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn json_response(status: StatusCode, json: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::to_string(&ErrorResponse {
+            message: message.to_string(),
+        }).unwrap(),
+    )
+}
+
+/// Starts a project that was put to sleep for being idle, without waiting for a visitor to
+/// do it. Projects that aren't asleep are left alone.
+#[tracing::instrument(skip(project, pool))]
+pub async fn get(
+    project: ProjectContext,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Response<Body> {
+    let sleeping = match sqlx::query_scalar::<_, bool>("SELECT sleeping FROM projects WHERE id = $1")
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await
+    {
+        Ok(sleeping) => sleeping,
+        Err(err) => {
+            tracing::error!(?err, "Can't wake project: Failed to query database");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database");
+        }
+    };
+
+    if !sleeping {
+        return json_response(
+            StatusCode::OK,
+            serde_json::to_string(&WakeResponse {
+                sleeping: false,
+                message: "Project is not sleeping".to_string(),
+            }).unwrap(),
+        );
+    }
+
+    if let Err(err) = sleep::wake(&pool, &project.owner, &project.project).await {
+        tracing::error!(?err, "Can't wake project: Failed to start container");
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start container");
+    }
+
+    json_response(
+        StatusCode::OK,
+        serde_json::to_string(&WakeResponse {
+            sleeping: false,
+            message: "Project woken up".to_string(),
+        }).unwrap(),
+    )
+}
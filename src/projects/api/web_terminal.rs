@@ -1,10 +1,17 @@
 use std::{net::SocketAddr, time::Duration, borrow::Cow};
 
-use axum::{extract::{WebSocketUpgrade, Path, ConnectInfo, ws::{Message, CloseFrame}}, TypedHeader, headers, response::IntoResponse};
-use bollard::{Docker, exec::{CreateExecOptions, StartExecResults}};
+use axum::{extract::{WebSocketUpgrade, Path, Query, State, ConnectInfo, ws::{Message, CloseFrame}}, TypedHeader, headers, response::{IntoResponse, Response}};
+use bollard::{Docker, exec::{CreateExecOptions, ResizeExecOptions, StartExecResults}};
 use futures_util::{StreamExt, SinkExt};
-use tokio::io::AsyncWriteExt;
+use hyper::{HeaderMap, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{auth::Auth, git::canonical_repo_name, startup::AppState};
+
+/// Subprotocol prefix carrying a terminal token, e.g. `pws.token.<token>`
+const TOKEN_PROTOCOL_PREFIX: &str = "pws.token.";
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -12,24 +19,189 @@ pub struct WsRequest {
     pub message: String,
 }
 
-#[tracing::instrument]
+/// Messages from the client that control the terminal instead of typing into it, sent as
+/// text frames like `{"type": "resize", "cols": 120, "rows": 40}`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ControlMessage {
+    /// the client's terminal changed size, full screen programs like `vim` redraw to fit
+    Resize { cols: u16, rows: u16 },
+}
+
+/// What a frame from the client asks for
+enum ClientInput {
+    Control(ControlMessage),
+    /// typed into the shell as is
+    Data(Vec<u8>),
+}
+
+impl ClientInput {
+    /// Text frames are a control message, a `{"message": ...}` line which gets a newline, or
+    /// otherwise raw keystrokes. Binary frames are always raw keystrokes.
+    fn parse(msg: Message) -> Option<Self> {
+        match msg {
+            Message::Text(text) => {
+                if let Ok(control) = serde_json::from_str::<ControlMessage>(&text) {
+                    return Some(ClientInput::Control(control));
+                }
+                match serde_json::from_str::<WsRequest>(&text) {
+                    Ok(WsRequest { message }) => Some(ClientInput::Data(format!("{message}\n").into_bytes())),
+                    Err(_) => Some(ClientInput::Data(text.into_bytes())),
+                }
+            }
+            Message::Binary(data) => Some(ClientInput::Data(data)),
+            _ => None,
+        }
+    }
+}
+
+/// Hands a frame from the client to the shell, `false` once the shell can't take input anymore
+async fn forward_input<W: AsyncWrite + Unpin>(docker: &Docker, exec_id: &str, input: &mut W, msg: Message) -> bool {
+    match ClientInput::parse(msg) {
+        Some(ClientInput::Control(ControlMessage::Resize { cols, rows })) => {
+            if cols == 0 || rows == 0 {
+                tracing::debug!(cols, rows, "Ignoring empty terminal size");
+                return true;
+            }
+            let size = ResizeExecOptions { width: cols, height: rows };
+            if let Err(err) = docker.resize_exec(exec_id, size).await {
+                tracing::warn!(?err, cols, rows, "Can't resize terminal");
+            }
+            true
+        }
+        Some(ClientInput::Data(data)) => match input.write_all(&data).await {
+            Ok(_) => true,
+            Err(err) => {
+                tracing::error!(?err, "Can't write to terminal");
+                false
+            }
+        },
+        None => true,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    token: Option<String>,
+}
+
+/// Token from `?token=` or from a `pws.token.<token>` entry of `Sec-WebSocket-Protocol`,
+/// along with the protocol entry so it can be echoed back on upgrade
+fn terminal_token(query: Option<String>, headers: &HeaderMap) -> Option<(String, Option<String>)> {
+    if let Some(token) = query.filter(|token| !token.is_empty()) {
+        return Some((token, None));
+    }
+
+    headers
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .map(str::trim)
+                .find(|protocol| protocol.starts_with(TOKEN_PROTOCOL_PREFIX))
+        })
+        .map(|protocol| {
+            (
+                protocol.trim_start_matches(TOKEN_PROTOCOL_PREFIX).to_string(),
+                Some(protocol.to_string()),
+            )
+        })
+}
+
+/// Opens a shell in the project's container. Output is sent as text frames, input is taken as
+/// described by [`ClientInput::parse`], along with [`ControlMessage`]s to resize the terminal.
+#[tracing::instrument(skip(auth, pool, ws, headers))]
 pub async fn ws(
+    auth: Auth,
     Path((owner, project)): Path<(String, String)>,
-    // State(AppState { pool, base, .. }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(WsQuery { token }): Query<WsQuery>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
         String::from("Unknown browser")
     };
 
+    // a terminal token is consumed on use, otherwise fall back to the session cookie
+    let (user_id, ws) = match terminal_token(token, &headers) {
+        Some((token, protocol)) => {
+            let user_id = sqlx::query_as::<_, (Uuid,)>(
+                r#"DELETE FROM terminal_token
+                   USING projects, project_owners
+                   WHERE terminal_token.token = $1
+                     AND terminal_token.expires_at > now()
+                     AND terminal_token.project_id = projects.id
+                     AND projects.owner_id = project_owners.id
+                     AND projects.name = $2
+                     AND project_owners.name = $3
+                   RETURNING terminal_token.user_id
+                "#,
+            )
+            .bind(&token)
+            .bind(&project)
+            .bind(&owner)
+            .fetch_optional(&pool)
+            .await;
+
+            let ws = match protocol {
+                Some(protocol) => ws.protocols([protocol]),
+                None => ws,
+            };
+
+            match user_id {
+                Ok(Some((user_id,))) => (user_id, ws),
+                Ok(None) => {
+                    tracing::info!(user_agent, "Rejected websocket connection: invalid terminal token");
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                Err(err) => {
+                    tracing::error!(?err, "Can't delete terminal_token: Failed to query database");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        None => match auth.current_user {
+            Some(user) => {
+                // check if project exist and user has access (owner or shared with an editor)
+                let has_access = sqlx::query(
+                    r#"SELECT 1 FROM projects
+                       JOIN project_owners ON projects.owner_id = project_owners.id
+                       LEFT JOIN users_owners ON project_owners.id = users_owners.owner_id
+                       LEFT JOIN project_shares ON projects.id = project_shares.project_id
+                       WHERE projects.name = $1
+                         AND project_owners.name = $2
+                         AND (users_owners.user_id = $3 OR (project_shares.user_id = $3 AND project_shares.role = 'editor'))
+                    "#,
+                )
+                .bind(&project)
+                .bind(&owner)
+                .bind(user.id)
+                .fetch_optional(&pool)
+                .await;
+
+                match has_access {
+                    Ok(Some(_)) => (user.id, ws),
+                    Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+                    Err(err) => {
+                        tracing::error!(?err, "Can't get project: Failed to query database");
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                }
+            }
+            None => return StatusCode::UNAUTHORIZED.into_response(),
+        },
+    };
+
     // let who = SocketAddr::from(([127, 0, 0, 1], 0));
     let who = addr;
 
-    tracing::info!(user_agent, "New websocket connection");
+    tracing::info!(user_agent, %user_id, "New websocket connection");
 
     ws.on_upgrade(move |mut socket| {
         async move {
@@ -68,7 +240,7 @@ pub async fn ws(
                 }
             };
 
-            let container_name = format!("{owner}-{}", project.trim_end_matches(".git")).replace('.', "-");
+            let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
             let exec = match docker
                 .create_exec(
                     &container_name,
@@ -159,6 +331,7 @@ pub async fn ws(
             });
 
             // This second task will receive messages from client
+            let exec_id = exec.id;
             let mut recv_task = tokio::spawn({
                 async move {
                     let mut cnt = 0;
@@ -166,27 +339,10 @@ pub async fn ws(
                         cnt += 1;
                         // print message and break if instructed to do so
                         match msg {
-                            Message::Text(t) => {
-                                match serde_json::from_str::<WsRequest>(&t) {
-                                    Err(err) => {
-                                        tracing::debug!(?err, "Can't parse message");
-                                    },
-                                    Ok(msg) => {
-                                        let mut msg = msg.message;
-                                        msg.push_str("\n");
-                                        match input.write_all(msg.as_bytes()).await {
-                                            Err(err) => {
-                                                tracing::error!(?err, "Can't write to terminal");
-                                                break;
-                                            },
-                                            Ok(_) => {
-                                                // if let Err(err) = tx.send(WsMessage::Message(msg.message)).await {
-                                                //     tracing::error!(?err, "Can't send message");
-                                                // }
-                                            }
-                                        }
-                                    }
-                                };
+                            Message::Text(_) | Message::Binary(_) => {
+                                if !forward_input(&docker, &exec_id, &mut input, msg).await {
+                                    break;
+                                }
                             }
                             Message::Close(c) => {
                                 if let Some(cf) = c {
@@ -226,4 +382,71 @@ pub async fn ws(
             tracing::info!(?who, "Websocket context destroyed");
         }
     })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::{
+        container::{Config, CreateContainerOptions, RemoveContainerOptions, StartContainerOptions},
+        image::CreateImageOptions,
+        service::HostConfig,
+    };
+    use futures_util::TryStreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs a docker daemon"]
+    async fn resizing_changes_the_columns_of_the_shell() {
+        let docker = Docker::connect_with_local_defaults().unwrap();
+        let name = format!("pws-terminal-test-{}", Uuid::new_v4());
+        docker
+            .create_image(Some(CreateImageOptions { from_image: "bash:5", ..Default::default() }), None, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        // gone on its own even when the test fails halfway
+        let config = Config {
+            image: Some("bash:5"),
+            cmd: Some(vec!["sleep", "60"]),
+            host_config: Some(HostConfig { auto_remove: Some(true), ..Default::default() }),
+            ..Default::default()
+        };
+        docker
+            .create_container(Some(CreateContainerOptions { name: name.as_str(), platform: None }), config)
+            .await
+            .unwrap();
+        docker.start_container(&name, None::<StartContainerOptions<&str>>).await.unwrap();
+
+        let exec = CreateExecOptions {
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            attach_stdin: Some(true),
+            tty: Some(true),
+            cmd: Some(vec!["bash"]),
+            ..Default::default()
+        };
+        let exec = docker.create_exec(&name, exec).await.unwrap();
+        let Ok(StartExecResults::Attached { mut output, mut input }) = docker.start_exec(&exec.id, None).await else {
+            panic!("Can't attach to the shell");
+        };
+
+        let resize = Message::Text(r#"{"type": "resize", "cols": 123, "rows": 45}"#.to_string());
+        assert!(forward_input(&docker, &exec.id, &mut input, resize).await);
+        // bash checks the size again after an external command, whether or not it saw the SIGWINCH
+        let echo = Message::Text(r#"{"message": "/bin/true; echo columns=$COLUMNS"}"#.to_string());
+        assert!(forward_input(&docker, &exec.id, &mut input, echo).await);
+
+        let mut printed = String::new();
+        while !printed.contains("columns=123") {
+            match tokio::time::timeout(Duration::from_secs(10), output.next()).await {
+                Ok(Some(Ok(chunk))) => printed.push_str(&String::from_utf8_lossy(&chunk.into_bytes())),
+                other => panic!("The shell printed {printed:?} and then {other:?}"),
+            }
+        }
+
+        let remove = RemoveContainerOptions { force: true, ..Default::default() };
+        docker.remove_container(&name, Some(remove)).await.unwrap();
+    }
 }
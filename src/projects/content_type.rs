@@ -0,0 +1,69 @@
+use hyper::header::HeaderValue;
+
+/// Same window git looks at to decide a file is binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+pub const PLAIN_TEXT: &str = "text/plain; charset=utf-8";
+
+/// Types a browser would run or render as a page if served inline from our origin, they are
+/// shown as text instead
+const ACTIVE_CONTENT: &[&str] = &["text/html", "application/xhtml+xml", "image/svg+xml", "application/xml", "text/xml"];
+
+/// A NUL byte near the start, the same heuristic git uses
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+fn is_textual(mime: &mime_guess::Mime) -> bool {
+    mime.type_() == mime_guess::mime::TEXT
+        || matches!(mime.subtype().as_str(), "javascript" | "json" | "xml" | "toml" | "yaml")
+        || mime.suffix().map_or(false, |suffix| suffix == "json" || suffix == "xml")
+}
+
+/// `Content-Type` for a file shown inline, guessed from the extension and falling back to
+/// the content for unknown ones. Text gets an utf-8 charset and content that doesn't match
+/// a textual extension is sent as binary.
+pub fn content_type(path: &str, content: &[u8]) -> String {
+    let binary = is_binary(content);
+
+    match mime_guess::from_path(path).first() {
+        Some(mime) if ACTIVE_CONTENT.contains(&mime.essence_str()) => match binary {
+            true => OCTET_STREAM.to_string(),
+            false => PLAIN_TEXT.to_string(),
+        },
+        Some(mime) if is_textual(&mime) => match binary || std::str::from_utf8(content).is_err() {
+            true => OCTET_STREAM.to_string(),
+            false => format!("{}; charset=utf-8", mime.essence_str()),
+        },
+        Some(mime) => mime.essence_str().to_string(),
+        None => match binary {
+            true => OCTET_STREAM.to_string(),
+            false => PLAIN_TEXT.to_string(),
+        },
+    }
+}
+
+/// `Content-Disposition` to download a file as the last segment of its path
+pub fn attachment(path: &str) -> HeaderValue {
+    let filename = path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("download");
+
+    // the quoted form only takes printable ascii, filename* carries the real name
+    let fallback = filename
+        .chars()
+        .map(|c| match c.is_ascii_graphic() || c == ' ' {
+            true if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let encoded = filename
+        .bytes()
+        .map(|byte| match byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            true => (byte as char).to_string(),
+            false => format!("%{byte:02X}"),
+        })
+        .collect::<String>();
+
+    HeaderValue::from_str(&format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}"))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+    response::Response,
+};
+use hyper::{Body, StatusCode};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Auth, User},
+    git::resolve_repo_path,
+    startup::AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectRole {
+    /// belongs to the project's owner, can change anything
+    Owner,
+    /// the project was shared with them to work on, e.g. deploy and change its environment
+    Editor,
+    /// the project was shared with them to look at, its code, status and logs
+    Viewer,
+}
+
+/// Roles a project can be shared with, `project_shares.role`
+pub const SHARE_ROLES: &[&str] = &["viewer", "editor"];
+
+impl ProjectRole {
+    /// Role of a `project_shares.role`, unknown ones get the least access
+    pub fn from_share(role: &str) -> Self {
+        match role {
+            "editor" => ProjectRole::Editor,
+            _ => ProjectRole::Viewer,
+        }
+    }
+}
+
+/// The project of an `/:owner/:project/...` route together with the signed in user's access
+/// to it. Rejects with 401 without a session, 404 when the project doesn't exist and 403
+/// when the user has no access.
+#[derive(Debug, Clone)]
+pub struct ProjectContext {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub owner: String,
+    pub project: String,
+    pub user: User,
+    pub role: ProjectRole,
+    pub repo_path: PathBuf,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let json = serde_json::to_string(&ErrorResponse {
+        message: message.to_string(),
+    }).unwrap();
+
+    Response::builder()
+        .status(status)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+impl ProjectContext {
+    /// For handlers that change the project, shared users can only look at it
+    pub fn require_owner(&self) -> Result<(), Response<Body>> {
+        match self.role {
+            ProjectRole::Owner => Ok(()),
+            _ => Err(error_response(StatusCode::FORBIDDEN, "Only the project owner can do this")),
+        }
+    }
+
+    /// For handlers that change how the project runs, viewers can only look at it
+    pub fn require_editor(&self) -> Result<(), Response<Body>> {
+        match self.role {
+            ProjectRole::Owner | ProjectRole::Editor => Ok(()),
+            ProjectRole::Viewer => Err(error_response(StatusCode::FORBIDDEN, "Viewers can't change this project")),
+        }
+    }
+
+    /// The user's access to a project that isn't the one in the path, e.g. the other side of a
+    /// comparison. Rejects the same way the extractor does.
+    pub async fn resolve(state: &AppState, user: User, owner: &str, project: &str) -> Result<Self, Response<Body>> {
+        let record = sqlx::query_as::<_, (Uuid, Uuid, bool, Option<String>)>(
+            r#"SELECT projects.id, project_owners.id,
+                 EXISTS (
+                   SELECT 1 FROM users_owners
+                   WHERE users_owners.owner_id = project_owners.id AND users_owners.user_id = $3
+                 ),
+                 (
+                   SELECT project_shares.role FROM project_shares
+                   WHERE project_shares.project_id = projects.id AND project_shares.user_id = $3
+                 )
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.name = $1
+                 AND project_owners.name = $2
+                 AND projects.deleted_at IS NULL
+            "#,
+        )
+        .bind(project)
+        .bind(owner)
+        .bind(user.id)
+        .fetch_optional(&state.pool)
+        .await;
+
+        let (id, owner_id, is_owner, share_role) = match record {
+            Ok(Some(record)) => record,
+            Ok(None) => return Err(error_response(StatusCode::NOT_FOUND, "Project not found")),
+            Err(err) => {
+                tracing::error!(?err, "Can't resolve project: Failed to query database");
+                return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query database"));
+            }
+        };
+
+        let role = match (is_owner, share_role) {
+            (true, _) => ProjectRole::Owner,
+            (false, Some(share_role)) => ProjectRole::from_share(&share_role),
+            (false, None) => {
+                return Err(error_response(StatusCode::FORBIDDEN, "You don't have access to this project"));
+            }
+        };
+
+        Ok(Self {
+            id,
+            owner_id,
+            owner: owner.to_string(),
+            project: project.to_string(),
+            user,
+            role,
+            repo_path: resolve_repo_path(&state.base, owner, project),
+        })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ProjectContext {
+    type Rejection = Response<Body>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = Auth::from_request_parts(parts, state)
+            .await
+            .map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+        let Some(user) = auth.current_user else {
+            return Err(error_response(StatusCode::UNAUTHORIZED, "Unauthorized"));
+        };
+
+        // by name so routes with more params than owner and project work as well
+        let Ok(Path(params)) = Path::<HashMap<String, String>>::from_request_parts(parts, state).await else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "Invalid project path"));
+        };
+        let (Some(owner), Some(project)) = (params.get("owner"), params.get("project")) else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "Invalid project path"));
+        };
+
+        Self::resolve(state, user, owner, project).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn unknown_projects_are_not_found(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let user = test_support::user(&pool, "alice").await;
+        test_support::owner(&pool, "alice", &user).await;
+
+        let response = ProjectContext::resolve(&state, user, "alice", "missing").await.unwrap_err();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn projects_of_other_owners_are_forbidden(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        test_support::project(&pool, owner_id, "site").await;
+        let mallory = test_support::user(&pool, "mallory").await;
+
+        let response = ProjectContext::resolve(&state, mallory, "alice", "site").await.unwrap_err();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn owners_and_shared_users_get_their_role(pool: sqlx::PgPool) {
+        let state = test_support::app_state(pool.clone(), "/nonexistent").await;
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        let project_id = test_support::project(&pool, owner_id, "site").await;
+        let bob = test_support::user(&pool, "bob").await;
+        test_support::share(&pool, project_id, &bob, "viewer").await;
+
+        let owner = ProjectContext::resolve(&state, alice, "alice", "site").await.unwrap();
+        assert_eq!((owner.id, owner.owner_id, owner.role), (project_id, owner_id, ProjectRole::Owner));
+
+        let viewer = ProjectContext::resolve(&state, bob, "alice", "site").await.unwrap();
+        assert_eq!((viewer.id, viewer.role), (project_id, ProjectRole::Viewer));
+        // enough to read the status and the logs, not to change anything
+        assert!(viewer.require_editor().is_err());
+    }
+}
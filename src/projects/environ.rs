@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use super::environ_cipher;
+
+pub const MAX_ENVIRON_KEY_LENGTH: usize = 256;
+
+/// Keys end up as `KEY=value` entries, a key with `=` or whitespace could spoof another variable
+pub fn validate_key(key: &str) -> Result<(), String> {
+    let mut chars = key.chars();
+
+    let valid = match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+
+    if !valid || key.len() > MAX_ENVIRON_KEY_LENGTH {
+        return Err(format!(
+            "Invalid environment variable name {key:?}: use letters, digits and underscores, not starting with a digit"
+        ));
+    }
+
+    Ok(())
+}
+
+/// NUL can't be encoded in the container environment and a newline would start a new
+/// instruction in generated Dockerfiles, tabs are the only control character allowed
+pub fn validate_value(key: &str, value: &str) -> Result<(), String> {
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!(
+            "Invalid value for environment variable {key}: newlines and control characters are not allowed"
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    validate_key(key)?;
+    validate_value(key, value)
+}
+
+/// Decrypted key value pairs of a project's `environs`, entries stored before validation
+/// existed are left out instead of being passed to docker
+pub fn pairs(environs: &Value) -> Vec<(String, String)> {
+    let Some(map) = environs.as_object() else {
+        return Vec::new();
+    };
+
+    map.iter()
+        .filter_map(|(key, value)| {
+            let value = match environ_cipher::decrypt(value.as_str().unwrap_or_default()) {
+                Ok(value) => value,
+                Err(err) => {
+                    tracing::warn!(key, "Skipping environment variable: {}", err);
+                    return None;
+                }
+            };
+
+            match validate(key, &value) {
+                Ok(()) => Some((key.clone(), value)),
+                Err(err) => {
+                    tracing::warn!("Skipping environment variable: {}", err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Whether two stored environs hold the same variables. Each value is encrypted with its own
+/// nonce, so the stored JSON differs even when nothing changed.
+pub fn same_environs(a: &Value, b: &Value) -> bool {
+    let decrypted = |environs| pairs(environs).into_iter().collect::<BTreeMap<_, _>>();
+    decrypted(a) == decrypted(b)
+}
+
+/// Parts of a key that mark its value as a credential
+const SECRET_KEY_PARTS: &[&str] = &["SECRET", "PASSWORD", "PASSWD", "TOKEN", "PRIVATE", "CREDENTIAL", "API_KEY", "DATABASE_URL"];
+
+/// What masked values are shown as, in every listing, diff, export and log. Sending it back
+/// as the value of a masked variable keeps the stored value.
+pub const MASKED_VALUE: &str = "********";
+
+/// Secret values shorter than this aren't redacted from logs, masking every `1` or `on` would
+/// make the log unreadable without hiding anything worth hiding
+const MIN_REDACTED_LENGTH: usize = 4;
+
+/// Whether a value should be masked when shown outside of the env page, by its name
+pub fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+}
+
+/// Whether the value of `key` is masked wherever it is shown: flagged as secret in
+/// `secret_keys`, or named like a credential in case nobody flagged it
+pub fn is_masked(key: &str, secret_keys: &[String]) -> bool {
+    secret_keys.iter().any(|secret| secret == key) || is_secret_key(key)
+}
+
+/// The value, or [`MASKED_VALUE`] when it is masked
+pub fn display_value(key: &str, value: &str, secret_keys: &[String]) -> String {
+    match is_masked(key, secret_keys) {
+        true => MASKED_VALUE.to_string(),
+        false => value.to_string(),
+    }
+}
+
+/// Characters a `.env` value can have without being quoted
+fn is_bare_dotenv_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-./:@,+%".contains(c)
+}
+
+/// `KEY=value` line of a `.env` file. Values with anything but plain characters are double
+/// quoted, with `\`, `"` and `$` escaped so loaders that interpolate read the value back as is.
+pub fn dotenv_line(key: &str, value: &str) -> String {
+    if !value.is_empty() && value.chars().all(is_bare_dotenv_char) {
+        return format!("{key}={value}");
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    format!("{key}=\"{quoted}\"")
+}
+
+/// Values of the masked variables, the ones to keep out of logs
+pub fn secret_values(environs: &Value, secret_keys: &[String]) -> Vec<String> {
+    let Some(map) = environs.as_object() else {
+        return Vec::new();
+    };
+
+    let mut values = map
+        .iter()
+        .filter(|(key, _)| is_masked(key, secret_keys))
+        .filter_map(|(_, value)| environ_cipher::decrypt(value.as_str()?).ok())
+        .filter(|value| value.len() >= MIN_REDACTED_LENGTH)
+        .collect::<Vec<_>>();
+    // longest first, so a secret containing another is replaced whole
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    values
+}
+
+/// `text` with every occurrence of the secret values replaced by [`MASKED_VALUE`]
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .fold(text.to_string(), |text, secret| text.replace(secret.as_str(), MASKED_VALUE))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn is_secret_key_matches_credential_names_in_any_case() {
+        assert!(is_secret_key("DB_PASSWORD"));
+        assert!(is_secret_key("github_token"));
+        assert!(is_secret_key("STRIPE_API_KEY"));
+        assert!(is_secret_key("DATABASE_URL"));
+        assert!(!is_secret_key("PORT"));
+        assert!(!is_secret_key("NODE_ENV"));
+    }
+
+    #[test]
+    fn same_environs_ignores_order_and_invalid_entries() {
+        let built = json!({ "A": "1", "B": "2", "BAD KEY": "x" });
+        let current = json!({ "B": "2", "A": "1" });
+
+        assert!(same_environs(&built, &current));
+        assert!(!same_environs(&built, &json!({ "A": "1", "B": "3" })));
+        assert!(!same_environs(&built, &json!({ "A": "1" })));
+    }
+
+    #[test]
+    fn validate_key_accepts_shell_names() {
+        for key in ["PORT", "_PRIVATE", "database_url", "A1_B2"] {
+            assert_eq!(validate_key(key), Ok(()), "{key}");
+        }
+    }
+
+    #[test]
+    fn validate_key_rejects_names_that_could_spoof_another_variable() {
+        let too_long = "A".repeat(MAX_ENVIRON_KEY_LENGTH + 1);
+        for key in ["", "1PORT", "A-B", "A B", "A=B", "PORT\n", too_long.as_str()] {
+            assert!(validate_key(key).is_err(), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn validate_value_allows_tabs_only() {
+        assert_eq!(validate_value("KEY", "a\tb c"), Ok(()));
+        assert!(validate_value("KEY", "a\nb").is_err());
+        assert!(validate_value("KEY", "a\0b").is_err());
+    }
+
+    #[test]
+    fn dotenv_line_quotes_values_loaders_would_interpolate() {
+        assert_eq!(dotenv_line("PORT", "8080"), "PORT=8080");
+        assert_eq!(dotenv_line("URL", "https://example.com/a"), "URL=https://example.com/a");
+        assert_eq!(dotenv_line("EMPTY", ""), "EMPTY=\"\"");
+        assert_eq!(dotenv_line("GREETING", "hi there"), "GREETING=\"hi there\"");
+        assert_eq!(dotenv_line("PRICE", "$5 \"off\""), "PRICE=\"\\$5 \\\"off\\\"\"");
+        assert_eq!(dotenv_line("COLUMNS", "a\tb"), "COLUMNS=\"a\\tb\"");
+    }
+
+    #[test]
+    fn flagged_keys_are_masked_whatever_their_name() {
+        let secret_keys = vec!["SENTRY_DSN".to_string()];
+
+        assert!(is_masked("SENTRY_DSN", &secret_keys));
+        assert!(is_masked("DB_PASSWORD", &secret_keys));
+        assert!(!is_masked("PORT", &secret_keys));
+
+        assert_eq!(display_value("SENTRY_DSN", "https://key@sentry.io/1", &secret_keys), MASKED_VALUE);
+        assert_eq!(display_value("PORT", "8080", &secret_keys), "8080");
+    }
+
+    #[test]
+    fn secret_values_skip_short_and_unmasked_values() {
+        let environs = json!({
+            "API_TOKEN": "abcdef",
+            "SENTRY_DSN": "https://key@sentry.io/1",
+            "DEBUG_PASSWORD": "1",
+            "PORT": "8080",
+        });
+
+        let values = secret_values(&environs, &["SENTRY_DSN".to_string()]);
+
+        assert_eq!(values, vec!["https://key@sentry.io/1".to_string(), "abcdef".to_string()]);
+    }
+
+    #[test]
+    fn redact_replaces_the_longest_secret_first() {
+        let secrets = vec!["hunter2-admin".to_string(), "hunter2".to_string()];
+
+        assert_eq!(
+            redact("login hunter2-admin then hunter2", &secrets),
+            format!("login {MASKED_VALUE} then {MASKED_VALUE}"),
+        );
+    }
+}
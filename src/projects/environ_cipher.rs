@@ -0,0 +1,305 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use data_encoding::BASE64;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::EnvironSettings;
+
+/// Marks a stored value as encrypted, followed by `v<key version>:` and the base64 of the
+/// nonce and the ciphertext
+const PREFIX: &str = "enc:v";
+const NONCE_LENGTH: usize = 12;
+
+/// Keys environment variable values are encrypted with, by version. Values are always
+/// encrypted with the current one, older ones are only kept to read what was stored before
+/// a rotation.
+pub struct EnvironCipher {
+    version: u32,
+    keys: HashMap<u32, ChaCha20Poly1305>,
+}
+
+static CIPHER: OnceLock<EnvironCipher> = OnceLock::new();
+
+fn parse_key(key: &str) -> Result<ChaCha20Poly1305, String> {
+    let key = BASE64
+        .decode(key.trim().as_bytes())
+        .map_err(|err| format!("key is not valid base64: {err}"))?;
+    ChaCha20Poly1305::new_from_slice(&key).map_err(|_| "key must be 32 bytes".to_string())
+}
+
+impl EnvironCipher {
+    /// `None` when no key is configured, values are then stored as they are
+    pub fn from_settings(settings: &EnvironSettings) -> Result<Option<Self>, String> {
+        if settings.key.is_empty() {
+            return Ok(None);
+        }
+
+        let mut keys = HashMap::new();
+        for previous in &settings.previouskeys {
+            let (version, key) = previous
+                .split_once(':')
+                .ok_or_else(|| "previous keys must be written as <version>:<key>".to_string())?;
+            let version = version
+                .parse::<u32>()
+                .map_err(|_| format!("invalid previous key version {version:?}"))?;
+            keys.insert(version, parse_key(key)?);
+        }
+        keys.insert(settings.keyversion, parse_key(&settings.key)?);
+
+        Ok(Some(Self {
+            version: settings.keyversion,
+            keys,
+        }))
+    }
+
+    fn current_prefix(&self) -> String {
+        format!("{PREFIX}{}:", self.version)
+    }
+
+    fn encrypt(&self, value: &str) -> String {
+        let cipher = &self.keys[&self.version];
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // only fails for inputs far beyond what a variable can hold
+        let ciphertext = cipher.encrypt(&nonce, value.as_bytes()).unwrap();
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        format!("{}{}", self.current_prefix(), BASE64.encode(&sealed))
+    }
+
+    fn decrypt(&self, version: u32, sealed: &str) -> Result<String, String> {
+        let cipher = self
+            .keys
+            .get(&version)
+            .ok_or_else(|| format!("no key with version {version} is configured"))?;
+        let sealed = BASE64
+            .decode(sealed.as_bytes())
+            .map_err(|_| "ciphertext is not valid base64".to_string())?;
+        if sealed.len() < NONCE_LENGTH {
+            return Err("ciphertext is too short".to_string());
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let value = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| format!("can't decrypt with the key of version {version}"))?;
+        String::from_utf8(value).map_err(|_| "decrypted value is not UTF-8".to_string())
+    }
+}
+
+/// Sets the key once at startup, before anything reads or writes environment variables
+pub fn init(settings: &EnvironSettings) -> Result<(), String> {
+    if let Some(cipher) = EnvironCipher::from_settings(settings)? {
+        let _ = CIPHER.set(cipher);
+    }
+    Ok(())
+}
+
+/// How a value is stored, encrypted with the current key when one is configured
+pub fn encrypt(value: &str) -> String {
+    match CIPHER.get() {
+        Some(cipher) => cipher.encrypt(value),
+        None => value.to_string(),
+    }
+}
+
+/// The value of a stored one, values stored before encryption was turned on are returned as
+/// they are
+pub fn decrypt(stored: &str) -> Result<String, String> {
+    let Some(rest) = stored.strip_prefix(PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let (version, sealed) = rest
+        .split_once(':')
+        .and_then(|(version, sealed)| Some((version.parse::<u32>().ok()?, sealed)))
+        .ok_or_else(|| "malformed encrypted value".to_string())?;
+
+    match CIPHER.get() {
+        Some(cipher) => cipher.decrypt(version, sealed),
+        None => Err("value is encrypted but no key is configured".to_string()),
+    }
+}
+
+/// A copy of `environs` with every value decrypted, ones that can't be are left out
+pub fn decrypt_environs(environs: &Value) -> Value {
+    let Some(map) = environs.as_object() else {
+        return environs.clone();
+    };
+
+    map.iter()
+        .filter_map(|(key, value)| match decrypt(value.as_str().unwrap_or_default()) {
+            Ok(value) => Some((key.clone(), Value::String(value))),
+            Err(err) => {
+                tracing::warn!(key, "Skipping environment variable: {}", err);
+                None
+            }
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// `environs` with every value stored with the current key, `None` when it already was
+fn reencrypt_environs(cipher: &EnvironCipher, environs: &Value) -> Option<Value> {
+    let map = environs.as_object()?;
+    let prefix = cipher.current_prefix();
+    if map.values().all(|value| value.as_str().map_or(true, |value| value.starts_with(&prefix))) {
+        return None;
+    }
+
+    let mut encrypted = serde_json::Map::new();
+    for (key, value) in map {
+        let value = value.as_str().unwrap_or_default();
+        let value = match value.starts_with(&prefix) {
+            true => value.to_string(),
+            false => match decrypt(value) {
+                Ok(value) => cipher.encrypt(&value),
+                Err(err) => {
+                    // kept as is so a missing old key doesn't lose the value
+                    tracing::warn!(key, "Can't re-encrypt environment variable: {}", err);
+                    value.to_string()
+                }
+            },
+        };
+        encrypted.insert(key.clone(), Value::String(value));
+    }
+    Some(encrypted.into())
+}
+
+/// Encrypts the values stored in plain text or with an older key, in projects and the build
+/// history. Runs at every startup, after the first one with a new key there's nothing to do.
+pub async fn encrypt_existing(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let Some(cipher) = CIPHER.get() else {
+        return Ok(0);
+    };
+    let pattern = format!("{}%", cipher.current_prefix());
+
+    let mut encrypted = 0;
+    for table in ["projects", "builds"] {
+        let rows = sqlx::query_as::<_, (Uuid, Value)>(&format!(
+            r#"SELECT id, environs FROM {table}
+               WHERE jsonb_typeof(environs) = 'object'
+                 AND EXISTS (SELECT 1 FROM jsonb_each_text(environs) AS entry WHERE entry.value NOT LIKE $1)
+            "#,
+        ))
+        .bind(&pattern)
+        .fetch_all(pool)
+        .await?;
+
+        for (id, environs) in rows {
+            let Some(reencrypted) = reencrypt_environs(cipher, &environs) else {
+                continue;
+            };
+
+            // skipped when the row changed since it was read, it's picked up on the next start
+            let updated = sqlx::query(&format!("UPDATE {table} SET environs = $1 WHERE id = $2 AND environs = $3"))
+                .bind(&reencrypted)
+                .bind(id)
+                .bind(&environs)
+                .execute(pool)
+                .await?;
+            encrypted += updated.rows_affected() as usize;
+        }
+    }
+
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> String {
+        BASE64.encode(&[byte; 32])
+    }
+
+    fn cipher(key: String, keyversion: u32, previouskeys: Vec<String>) -> EnvironCipher {
+        EnvironCipher::from_settings(&EnvironSettings { key, keyversion, previouskeys })
+            .unwrap()
+            .unwrap()
+    }
+
+    /// What [`decrypt`] does with the global cipher, against the given one
+    fn open(cipher: &EnvironCipher, stored: &str) -> Result<String, String> {
+        let (version, sealed) = stored.strip_prefix(PREFIX).unwrap().split_once(':').unwrap();
+        cipher.decrypt(version.parse().unwrap(), sealed)
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher = cipher(key(1), 1, Vec::new());
+
+        let stored = cipher.encrypt("postgres://user:pass@db/app");
+
+        assert!(stored.starts_with("enc:v1:"));
+        assert!(!stored.contains("pass@db"));
+        assert_eq!(open(&cipher, &stored).unwrap(), "postgres://user:pass@db/app");
+    }
+
+    #[test]
+    fn every_encryption_gets_its_own_nonce() {
+        let cipher = cipher(key(1), 1, Vec::new());
+
+        assert_ne!(cipher.encrypt("same"), cipher.encrypt("same"));
+    }
+
+    #[test]
+    fn values_of_a_previous_key_stay_readable_after_rotation() {
+        let old = cipher(key(1), 1, Vec::new());
+        let stored = old.encrypt("value");
+
+        let rotated = cipher(key(2), 2, vec![format!("1:{}", key(1))]);
+
+        assert_eq!(open(&rotated, &stored).unwrap(), "value");
+        assert!(rotated.encrypt("value").starts_with("enc:v2:"));
+    }
+
+    #[test]
+    fn values_of_an_unknown_key_version_are_refused() {
+        let old = cipher(key(1), 1, Vec::new());
+        let stored = old.encrypt("value");
+
+        let rotated = cipher(key(2), 2, Vec::new());
+
+        assert!(open(&rotated, &stored).is_err());
+    }
+
+    #[test]
+    fn tampered_values_are_refused() {
+        let cipher = cipher(key(1), 1, Vec::new());
+        let mut sealed = BASE64.decode(cipher.encrypt("value")["enc:v1:".len()..].as_bytes()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        assert!(cipher.decrypt(1, &BASE64.encode(&sealed)).is_err());
+        assert!(cipher.decrypt(1, "not base64!").is_err());
+        assert!(cipher.decrypt(1, &BASE64.encode(b"short")).is_err());
+    }
+
+    #[test]
+    fn settings_without_a_key_store_values_as_they_are() {
+        let settings = EnvironSettings { key: String::new(), keyversion: 1, previouskeys: Vec::new() };
+
+        assert!(EnvironCipher::from_settings(&settings).unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        let settings = |key: String, previouskeys: Vec<String>| EnvironSettings { key, keyversion: 2, previouskeys };
+
+        assert!(EnvironCipher::from_settings(&settings("not base64!".to_string(), Vec::new())).is_err());
+        assert!(EnvironCipher::from_settings(&settings(BASE64.encode(&[1; 16]), Vec::new())).is_err());
+        assert!(EnvironCipher::from_settings(&settings(key(2), vec![key(1)])).is_err());
+        assert!(EnvironCipher::from_settings(&settings(key(2), vec![format!("one:{}", key(1))])).is_err());
+    }
+
+    #[test]
+    fn plain_text_values_are_returned_as_they_are() {
+        assert_eq!(decrypt("8080").unwrap(), "8080");
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, TimeZone, Utc};
+use git2::{Commit, Oid, Repository};
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+/// Cached directories are dropped all at once past this many entries
+const MAX_CACHED_DIRS: usize = 1_000;
+/// Commits looked at before giving up on entries that haven't changed in a long time
+const MAX_HISTORY: usize = 10_000;
+
+lazy_static! {
+    // keyed by commit and directory, the history behind a commit never changes
+    static ref LAST_COMMIT_CACHE: Mutex<HashMap<(Oid, String), HashMap<String, LastCommit>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The last commit that changed an entry, shown next to it in the file browser
+#[derive(Serialize, Debug, Clone)]
+pub struct LastCommit {
+    pub last_commit_id: String,
+    /// first line of the message
+    pub last_commit_message: String,
+    pub last_commit_time: DateTime<Utc>,
+}
+
+impl LastCommit {
+    fn from_commit(commit: &Commit) -> Self {
+        Self {
+            last_commit_id: commit.id().to_string(),
+            last_commit_message: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).to_string(),
+            last_commit_time: Utc.timestamp_opt(commit.time().seconds(), 0).single().unwrap_or_default(),
+        }
+    }
+}
+
+/// Ids of the entries of `dir` as of `commit`, empty when it didn't exist yet
+fn dir_entries(repo: &Repository, commit: &Commit, dir: &str) -> HashMap<String, Oid> {
+    let Ok(root) = commit.tree() else {
+        return HashMap::new();
+    };
+
+    let tree = match dir.is_empty() {
+        true => Some(root),
+        false => root
+            .get_path(std::path::Path::new(dir))
+            .ok()
+            .and_then(|entry| repo.find_tree(entry.id()).ok()),
+    };
+
+    tree.map(|tree| {
+        tree.iter()
+            .map(|entry| (String::from_utf8_lossy(entry.name_bytes()).to_string(), entry.id()))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The last commit that changed each entry of `dir`, by entry name. Follows first parents
+/// like `git log --first-parent`, so changes merged from a branch show up as the merge.
+pub fn last_commits(repo: &Repository, head: &Commit, dir: &str) -> HashMap<String, LastCommit> {
+    let key = (head.id(), dir.to_string());
+    if let Some(cached) = LAST_COMMIT_CACHE.lock().unwrap().get(&key) {
+        return cached.clone();
+    }
+
+    let mut remaining = dir_entries(repo, head, dir);
+    let mut found = HashMap::new();
+    let mut commit = head.clone();
+
+    for _ in 0..MAX_HISTORY {
+        if remaining.is_empty() {
+            break;
+        }
+
+        let parent = commit.parent(0).ok();
+        let before = parent
+            .as_ref()
+            .map(|parent| dir_entries(repo, parent, dir))
+            .unwrap_or_default();
+
+        // an entry changed here when the parent had another version of it, or none
+        remaining.retain(|name, id| match before.get(name) == Some(id) {
+            true => true,
+            false => {
+                found.insert(name.clone(), LastCommit::from_commit(&commit));
+                false
+            }
+        });
+
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+
+    let mut cache = LAST_COMMIT_CACHE.lock().unwrap();
+    if cache.len() >= MAX_CACHED_DIRS {
+        cache.clear();
+    }
+    cache.insert(key, found.clone());
+
+    found
+}
@@ -1 +1,8 @@
 pub mod api;
+pub mod content_type;
+pub mod context;
+pub mod environ;
+pub mod environ_cipher;
+pub mod last_commit;
+pub mod repo;
+pub mod tree_filter;
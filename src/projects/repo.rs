@@ -0,0 +1,35 @@
+use git2::Repository;
+use hyper::{Body, Response, StatusCode};
+
+use crate::{
+    git::{open_bare_repo, OpenRepoError},
+    projects::context::ProjectContext,
+};
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&serde_json::json!({ "message": message })).unwrap()))
+        .unwrap()
+}
+
+/// Opens the bare repository of a project for the repository browsing endpoints, the error
+/// is the response to send when its repository is missing. Taking the [`ProjectContext`]
+/// means the signed in user has at least viewer access to the project.
+///
+/// The repository isn't Send, it has to be dropped before the handler awaits again.
+pub fn open_project_repo(project: &ProjectContext) -> Result<Repository, Response<Body>> {
+    match open_bare_repo(&project.repo_path) {
+        Ok(repo) => Ok(repo),
+        Err(OpenRepoError::Missing) => Err(error_response(StatusCode::NOT_FOUND, "Repository not found")),
+        Err(OpenRepoError::NotARepository(_)) => Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Project directory is not a git repository",
+        )),
+        Err(OpenRepoError::Other(err)) => Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to open repository: {err}"),
+        )),
+    }
+}
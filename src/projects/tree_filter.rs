@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use git2::{ObjectType, Repository, Tree};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use lazy_static::lazy_static;
+
+/// Cached directory sizes are dropped all at once past this many entries
+const MAX_CACHED_SIZES: usize = 10_000;
+
+lazy_static! {
+    // trees are immutable, so a size computed for a tree id (and filter) never goes stale
+    static ref DIR_SIZE_CACHE: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+}
+
+/// Paths left out of tree listings and size calculations
+pub struct TreeFilter {
+    set: GlobSet,
+    signature: String,
+}
+
+impl TreeFilter {
+    /// Builds the filter from the `ignore` query param: `default` uses the configured globs,
+    /// anything else is a comma separated glob list. No param means no filter.
+    pub fn from_query(ignore: Option<&str>, defaults: &[String]) -> Result<Option<Self>, globset::Error> {
+        let globs = match ignore.map(str::trim) {
+            None | Some("") => return Ok(None),
+            Some("default") => defaults.to_vec(),
+            Some(globs) => globs
+                .split(',')
+                .map(|glob| glob.trim().to_string())
+                .filter(|glob| !glob.is_empty())
+                .collect(),
+        };
+
+        let mut builder = GlobSetBuilder::new();
+        for glob in &globs {
+            builder.add(Glob::new(glob)?);
+        }
+
+        Ok(Some(Self {
+            set: builder.build()?,
+            signature: globs.join(","),
+        }))
+    }
+
+    /// `path` is relative to the repository root, without a leading slash
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.set.is_match(path)
+    }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    match dir.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", dir.trim_end_matches('/'), name),
+    }
+}
+
+/// Total size of the blobs under `tree`, skipping ignored paths. `path` is where the tree sits
+/// in the repository, it only matters when a filter is given.
+pub fn dir_size(repo: &Repository, tree: &Tree, path: &str, filter: Option<&TreeFilter>) -> u64 {
+    // without a filter the size only depends on the tree id, with one it also depends on
+    // where the tree is since globs match full paths
+    let key = match filter {
+        None => tree.id().to_string(),
+        Some(filter) => format!("{}:{}:{}", tree.id(), path, filter.signature),
+    };
+
+    if let Some(size) = DIR_SIZE_CACHE.lock().unwrap().get(&key) {
+        return *size;
+    }
+
+    let odb = match repo.odb() {
+        Ok(odb) => odb,
+        Err(err) => {
+            tracing::error!(?err, "Can't compute tree size: Failed to open object database");
+            return 0;
+        }
+    };
+
+    let mut size = 0;
+    for entry in tree.iter() {
+        let name = String::from_utf8_lossy(entry.name_bytes()).to_string();
+        let entry_path = join_path(path, &name);
+
+        if filter.map_or(false, |filter| filter.is_ignored(&entry_path)) {
+            continue;
+        }
+
+        match entry.kind() {
+            Some(ObjectType::Blob) => {
+                // header only, no need to inflate the blob to know its size
+                size += odb.read_header(entry.id()).map(|(len, _)| len as u64).unwrap_or(0);
+            }
+            Some(ObjectType::Tree) => {
+                if let Ok(subtree) = repo.find_tree(entry.id()) {
+                    size += dir_size(repo, &subtree, &entry_path, filter);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut cache = DIR_SIZE_CACHE.lock().unwrap();
+    if cache.len() >= MAX_CACHED_SIZES {
+        cache.clear();
+    }
+    cache.insert(key, size);
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bare repo in a temporary directory, removed on drop
+    struct TestRepo {
+        path: std::path::PathBuf,
+        repo: Repository,
+    }
+
+    impl TestRepo {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("pws-tree-filter-test-{}", uuid::Uuid::new_v4()));
+            let repo = Repository::init_bare(&path).unwrap();
+            Self { path, repo }
+        }
+
+        /// Tree of `files`, each a path and its size in bytes
+        fn tree(&self, files: &[(&str, usize)]) -> Tree {
+            let mut index = git2::Index::new().unwrap();
+            for (path, size) in files {
+                let blob = self.repo.blob(&vec![b'x'; *size]).unwrap();
+                let entry = git2::IndexEntry {
+                    ctime: git2::IndexTime::new(0, 0),
+                    mtime: git2::IndexTime::new(0, 0),
+                    dev: 0,
+                    ino: 0,
+                    mode: 0o100644,
+                    uid: 0,
+                    gid: 0,
+                    file_size: *size as u32,
+                    id: blob,
+                    flags: 0,
+                    flags_extended: 0,
+                    path: path.as_bytes().to_vec(),
+                };
+                index.add(&entry).unwrap();
+            }
+            let id = index.write_tree_to(&self.repo).unwrap();
+            self.repo.find_tree(id).unwrap()
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn no_ignore_param_means_no_filter() {
+        assert!(TreeFilter::from_query(None, &[]).unwrap().is_none());
+        assert!(TreeFilter::from_query(Some(" "), &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn excluded_paths_are_ignored() {
+        let filter = TreeFilter::from_query(Some("node_modules, *.log"), &[]).unwrap().unwrap();
+
+        assert!(filter.is_ignored("node_modules"));
+        assert!(filter.is_ignored("debug.log"));
+        assert!(!filter.is_ignored("src/main.rs"));
+
+        let defaults = vec!["dist".to_string()];
+        let filter = TreeFilter::from_query(Some("default"), &defaults).unwrap().unwrap();
+        assert!(filter.is_ignored("dist"));
+        assert!(!filter.is_ignored("node_modules"));
+    }
+
+    #[test]
+    fn invalid_globs_are_rejected() {
+        assert!(TreeFilter::from_query(Some("src/[a"), &[]).is_err());
+    }
+
+    #[test]
+    fn excluded_paths_are_omitted_from_directory_sizes() {
+        let test = TestRepo::new();
+        let tree = test.tree(&[("index.js", 10), ("node_modules/left-pad/index.js", 1000), ("src/app.js", 20)]);
+        let filter = TreeFilter::from_query(Some("node_modules"), &[]).unwrap().unwrap();
+
+        assert_eq!(dir_size(&test.repo, &tree, "", None), 1030);
+        assert_eq!(dir_size(&test.repo, &tree, "", Some(&filter)), 30);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    cmp::Ordering as CmpOrdering,
+    collections::{BinaryHeap, HashMap, HashSet},
     hash::Hash,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -11,42 +12,160 @@ use std::{
 use anyhow::Result;
 use sqlx::PgPool;
 use thiserror::Error;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 use tokio::time::{timeout, sleep};
+use tokio_util::sync::CancellationToken;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{docker::{build_docker, DockerContainer}, configuration::Settings};
+use crate::{
+    build_cache::{self, DependencyCache},
+    build_config::ConfigSnapshot,
+    configuration::Settings,
+    deploy_webhook::{self, DeployEvent},
+    docker::{build_docker, DockerContainer},
+    git,
+    live_log,
+    metrics::BuildMetrics,
+    projects::environ,
+    static_site::{build_static, unpublish, StaticSite},
+};
 
 type ConcurrentMutex<T> = Arc<Mutex<T>>;
 
+/// Status change of a build, for clients watching a project live. Finished builds are
+/// published by the poll task once the build task is done, whatever ended it.
+#[derive(Clone, Debug)]
+pub struct BuildStatusEvent {
+    pub project_id: Uuid,
+    pub build_id: Uuid,
+    /// value of the `build_state` column the build was set to
+    pub status: &'static str,
+}
+
+/// Builds status events are kept for when a watcher falls behind
+const STATUS_EVENT_CAPACITY: usize = 256;
+
+fn publish(events: &broadcast::Sender<BuildStatusEvent>, project_id: Uuid, build_id: Uuid, status: &'static str) {
+    // nobody watching is fine
+    let _ = events.send(BuildStatusEvent { project_id, build_id, status });
+}
+
+/// Log line of a build that was cancelled
+pub const CANCELLED_LOG: &str = "Build cancelled";
+/// Log of a build that was waiting or running when the server shut down
+const SHUTDOWN_LOG: &str = "Server shutting down, push again or retry the build to deploy this commit";
+/// Log of a build turned away because too many builds were waiting
+const QUEUE_FULL_LOG: &str = "Build queue is full, push again later to deploy this commit";
+
 #[derive(Error, Debug)]
 #[error("{message:?}")]
 pub struct BuildError {
     message: String,
     inner_error: Option<Box<dyn std::error::Error + Send + Sync>>,
 }
+/// Project row as far as building it is concerned
+struct ProjectBuild {
+    id: Uuid,
+    /// `docker` or `static`
+    build_type: String,
+    build_command: Option<String>,
+    output_dir: Option<String>,
+    /// bumped by clearing the dependency cache
+    cache_generation: i32,
+    /// dependency cache key of the last successful build
+    dependency_cache_key: Option<String>,
+    submodules_enabled: bool,
+}
+
 #[derive(Debug)]
 pub struct BuildQueueItem {
     pub container_name: String,
-    pub container_src: String,
+    pub container_src: git::BuildSource,
     pub owner: String,
     pub repo: String,
+    pub commit_sha: String,
+    /// branch the commit was taken from, `None` when a tag or a commit was built
+    pub branch: Option<String>,
+    /// build even if the commit and environs match the current deployment
+    pub force: bool,
+    /// notified with what happened to the request, e.g. to tell the pushing client
+    pub reply: Option<oneshot::Sender<EnqueueOutcome>>,
+    pub priority: BuildPriority,
+}
+
+/// Where a build request came from, higher variants are dispatched first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BuildPriority {
+    Preview,
+    Push,
+    Rebuild,
+}
+
+impl BuildPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Preview => "preview",
+            Self::Push => "push",
+            Self::Rebuild => "rebuild",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EnqueueOutcome {
+    Queued { build_id: Uuid, position: usize },
+    /// merged into the build of the project that is still waiting, which now builds this push
+    AlreadyQueued { build_id: Uuid, position: usize },
+    AlreadyDeployed,
+    /// too many builds are waiting already
+    Saturated { depth: usize },
+    Rejected(String),
+}
+
+impl std::fmt::Display for EnqueueOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Queued { build_id, position } => {
+                write!(f, "Build queued (id {build_id}, position {position})")
+            }
+            Self::AlreadyQueued { build_id, position } => write!(
+                f,
+                "A build for this project is already queued (id {build_id}, position {position}), it will build this push instead"
+            ),
+            Self::AlreadyDeployed => write!(f, "This commit is already deployed with the same environment, skipping build"),
+            Self::Saturated { depth } => write!(
+                f,
+                "Build rejected: the build queue is full ({depth} builds waiting), push again later to deploy this commit"
+            ),
+            Self::Rejected(reason) => write!(f, "Build rejected: {reason}"),
+        }
+    }
+}
+
+fn report(reply: Option<oneshot::Sender<EnqueueOutcome>>, outcome: EnqueueOutcome) {
+    if let Some(reply) = reply {
+        // the requester may have stopped waiting, that's fine
+        let _ = reply.send(outcome);
+    }
 }
 
 #[derive(Debug)]
 pub struct BuildItem {
     pub build_id: Uuid,
+    pub project_id: Uuid,
     pub container_name: String,
-    pub container_src: String,
+    pub container_src: git::BuildSource,
     pub owner: String,
     pub repo: String,
     pub created_at: SystemTime,
 }
 
-unsafe impl Send for BuildItem {}
-unsafe impl Sync for BuildItem {}
+// moved into the build task and shared through the queue lock
+static_assertions::assert_impl_all!(BuildItem: Send, Sync);
 
 impl Hash for BuildItem {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -62,11 +181,236 @@ impl PartialEq for BuildItem {
 
 impl Eq for BuildItem {}
 
+/// Entry of the waiting queue, ordered by priority then by enqueue order so builds with the
+/// same priority stay first in first out
+#[derive(Debug)]
+pub struct QueuedBuild {
+    pub priority: BuildPriority,
+    pub sequence: u64,
+    pub item: BuildItem,
+}
+
+impl Ord for QueuedBuild {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedBuild {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedBuild {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for QueuedBuild {}
+
+/// How many builds are waiting, readable without taking the queue lock so pushes can be
+/// turned away before their pack is received
+#[derive(Clone, Debug)]
+pub struct QueueLoad {
+    depth: Arc<AtomicUsize>,
+    /// 0 means no limit
+    max: usize,
+}
+
+impl QueueLoad {
+    pub fn new(max: usize) -> Self {
+        Self {
+            depth: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.max > 0 && self.depth() >= self.max
+    }
+
+    fn set_depth(&self, depth: usize) {
+        self.depth.store(depth, Ordering::SeqCst);
+    }
+}
+
+/// A build that is running, for cancelling it and counting builds per owner
+struct RunningBuild {
+    owner: String,
+    cancel: CancellationToken,
+}
+
+/// Builds that are running, by build id
+type RunningBuilds = Arc<std::sync::Mutex<HashMap<Uuid, RunningBuild>>>;
+
+/// Takes the next build to run off the queue. Builds of owners that already have
+/// `max_per_owner` builds running are passed over, so one owner pushing many projects can't
+/// take every slot while others wait.
+fn next_eligible(
+    waiting_queue: &mut BinaryHeap<QueuedBuild>,
+    running: &HashMap<Uuid, RunningBuild>,
+    max_per_owner: usize,
+) -> Option<QueuedBuild> {
+    if max_per_owner == 0 {
+        return waiting_queue.pop();
+    }
+
+    let mut per_owner: HashMap<&str, usize> = HashMap::new();
+    for build in running.values() {
+        *per_owner.entry(build.owner.as_str()).or_default() += 1;
+    }
+
+    let mut passed_over = Vec::new();
+    let mut next = None;
+    while let Some(queued) = waiting_queue.pop() {
+        if per_owner.get(queued.item.owner.as_str()).copied().unwrap_or(0) < max_per_owner {
+            next = Some(queued);
+            break;
+        }
+        passed_over.push(queued);
+    }
+
+    // they keep their priority and sequence, so their order doesn't change
+    waiting_queue.extend(passed_over);
+    next
+}
+
+/// Hands a newer push of `container_name` to its build that is still waiting, which then
+/// builds the new source instead of the older one. Returns the build and its priority now, or
+/// `None` when the project has no build waiting.
+fn take_over_waiting_build(
+    waiting_queue: &mut BinaryHeap<QueuedBuild>,
+    container_name: &str,
+    container_src: git::BuildSource,
+    priority: BuildPriority,
+) -> Option<(Uuid, BuildPriority)> {
+    let mut queued = std::mem::take(waiting_queue).into_vec();
+    let existing = queued.iter_mut().find(|queued| queued.item.container_name == container_name).map(|existing| {
+        // the older checkout is dropped here, which removes it
+        existing.item.container_src = container_src;
+        existing.priority = existing.priority.max(priority);
+        (existing.item.build_id, existing.priority)
+    });
+    *waiting_queue = BinaryHeap::from(queued);
+    existing
+}
+
+/// What cancelling a build did
+#[derive(Debug, PartialEq)]
+pub enum CancelOutcome {
+    /// it was still waiting and won't run
+    Dequeued,
+    /// it is running and stops shortly, the build task marks it as failed
+    Signalled,
+    /// neither waiting nor running here, e.g. left over from before a restart
+    NotFound,
+}
+
+/// Where a build is, as far as the queue knows
+#[derive(Debug, PartialEq)]
+pub enum BuildPosition {
+    /// builds dispatched before this one, 0 when it is next
+    Queued { position: usize, available_slots: usize },
+    Building,
+    /// not in the queue anymore, finished or cancelled
+    Done,
+}
+
+/// Handle for looking into and cancelling builds from outside the queue tasks
+#[derive(Clone)]
+pub struct BuildControl {
+    build_count: Arc<AtomicUsize>,
+    load: QueueLoad,
+    waiting_queue: ConcurrentMutex<BinaryHeap<QueuedBuild>>,
+    waiting_set: ConcurrentMutex<HashSet<String>>,
+    running: RunningBuilds,
+    events: broadcast::Sender<BuildStatusEvent>,
+    metrics: Arc<BuildMetrics>,
+}
+
+impl BuildControl {
+    /// Build counters and the current queue state for `/metrics`
+    pub fn render_metrics(&self) -> String {
+        self.metrics.render(self.load.depth(), self.build_count.load(Ordering::SeqCst))
+    }
+
+    /// Status changes of every build from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<BuildStatusEvent> {
+        self.events.subscribe()
+    }
+
+    /// Tells the status watchers about a change made outside the queue tasks
+    pub fn publish(&self, project_id: Uuid, build_id: Uuid, status: &'static str) {
+        publish(&self.events, project_id, build_id, status);
+    }
+
+    pub async fn position(&self, build_id: Uuid) -> BuildPosition {
+        let waiting_queue = self.waiting_queue.lock().await;
+
+        if let Some(queued) = waiting_queue.iter().find(|queued| queued.item.build_id == build_id) {
+            return BuildPosition::Queued {
+                position: waiting_queue.iter().filter(|other| *other > queued).count(),
+                available_slots: self.build_count.load(Ordering::SeqCst),
+            };
+        }
+
+        match self.running.lock().unwrap().contains_key(&build_id) {
+            true => BuildPosition::Building,
+            false => BuildPosition::Done,
+        }
+    }
+
+    /// Takes a waiting build out of the queue, or tells a running one to stop
+    pub async fn cancel(&self, build_id: Uuid) -> CancelOutcome {
+        // the poll task registers a build as running before releasing the queue lock, so a
+        // build is always found in one of the two
+        let mut waiting_queue = self.waiting_queue.lock().await;
+
+        let mut container_name = None;
+        waiting_queue.retain(|queued| match queued.item.build_id == build_id {
+            true => {
+                container_name = Some(queued.item.container_name.clone());
+                false
+            }
+            false => true,
+        });
+
+        if let Some(container_name) = container_name {
+            self.waiting_set.lock().await.remove(&container_name);
+            self.load.set_depth(waiting_queue.len());
+            tracing::info!("BUILD_CANCELLED: build_id={}, container={}, state=queued", build_id, container_name);
+            BuildMetrics::inc(&self.metrics.cancelled);
+            return CancelOutcome::Dequeued;
+        }
+
+        match self.running.lock().unwrap().get(&build_id) {
+            Some(build) => {
+                build.cancel.cancel();
+                CancelOutcome::Signalled
+            }
+            None => CancelOutcome::NotFound,
+        }
+    }
+}
+
 pub struct BuildQueue {
     pub build_count: Arc<AtomicUsize>,
-    pub waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
+    pub load: QueueLoad,
+    pub waiting_queue: ConcurrentMutex<BinaryHeap<QueuedBuild>>,
     pub waiting_set: ConcurrentMutex<HashSet<String>>,
+    running: RunningBuilds,
     pub receive_channel: Receiver<BuildQueueItem>,
+    /// status changes of every build, subscribed to by the status stream
+    pub events: broadcast::Sender<BuildStatusEvent>,
+    pub metrics: Arc<BuildMetrics>,
     pub pg_pool: PgPool,
     pub config: Settings,
 }
@@ -74,19 +418,36 @@ pub struct BuildQueue {
 impl BuildQueue {
     pub fn new(build_count: usize, pg_pool: PgPool, config: Settings) -> (Self, Sender<BuildQueueItem>) {
         let (tx, rx) = mpsc::channel(32);
+        let (events, _) = broadcast::channel(STATUS_EVENT_CAPACITY);
 
         (
             Self {
                 build_count: Arc::new(AtomicUsize::new(build_count)),
-                waiting_queue: Arc::new(Mutex::new(VecDeque::new())),
+                load: QueueLoad::new(config.build.maxqueue),
+                waiting_queue: Arc::new(Mutex::new(BinaryHeap::new())),
                 waiting_set: Arc::new(Mutex::new(HashSet::new())),
+                running: Arc::new(std::sync::Mutex::new(HashMap::new())),
                 receive_channel: rx,
+                events,
+                metrics: Arc::new(BuildMetrics::default()),
                 pg_pool,
                 config,
             },
             tx,
         )
     }
+
+    pub fn control(&self) -> BuildControl {
+        BuildControl {
+            build_count: Arc::clone(&self.build_count),
+            load: self.load.clone(),
+            waiting_queue: Arc::clone(&self.waiting_queue),
+            waiting_set: Arc::clone(&self.waiting_set),
+            running: Arc::clone(&self.running),
+            events: self.events.clone(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
 }
 
 pub async fn trigger_build(
@@ -96,27 +457,38 @@ pub async fn trigger_build(
         repo,
         container_src,
         container_name,
+        project_id: _,
         created_at: _,
     }: BuildItem,
     pool: PgPool,
     config: &Settings,
+    events: &broadcast::Sender<BuildStatusEvent>,
 ) -> Result<String, BuildError> {
     // TODO: need to emmit error somewhere
-    let project = match sqlx::query!(
-        r#"SELECT projects.id
+    let project = match sqlx::query_as::<_, (Uuid, String, Option<String>, Option<String>, i32, Option<String>, bool)>(
+        r#"SELECT projects.id, projects.build_type, projects.build_command, projects.output_dir,
+                  projects.cache_generation, projects.dependency_cache_key, projects.submodules_enabled
            FROM projects
            JOIN project_owners ON projects.owner_id = project_owners.id
            WHERE project_owners.name = $1
            AND projects.name = $2
         "#,
-        owner,
-        repo
     )
+    .bind(&owner)
+    .bind(&repo)
     .fetch_optional(&pool)
     .await
     {
         Ok(project) => match project {
-            Some(project) => Ok(project),
+            Some((id, build_type, build_command, output_dir, cache_generation, dependency_cache_key, submodules_enabled)) => Ok(ProjectBuild {
+                id,
+                build_type,
+                build_command,
+                output_dir,
+                cache_generation,
+                dependency_cache_key,
+                submodules_enabled,
+            }),
             None => Err(BuildError {
                 message: format!("Project not found with owner {owner} and repo {repo}"),
                 inner_error: None,
@@ -128,17 +500,17 @@ pub async fn trigger_build(
         }),
     }?;
 
-    let build_id = match sqlx::query!(
-        r#"SELECT builds.id
+    let (build_id, environs) = match sqlx::query_as::<_, (Uuid, Option<serde_json::Value>)>(
+        r#"SELECT builds.id, builds.environs
            FROM builds
            WHERE builds.id = $1
         "#,
-        build_id,
     )
+    .bind(build_id)
     .fetch_optional(&pool)
     .await
     {
-        Ok(Some(build)) => Ok(build.id),
+        Ok(Some(build)) => Ok(build),
         Ok(None) => Err(BuildError {
             message: format!("Failed to find build with id: {build_id}"),
             inner_error: None,
@@ -149,29 +521,126 @@ pub async fn trigger_build(
         }),
     }?;
 
-    if let Err(err) = sqlx::query!(
-        "UPDATE builds set status = 'building' where id = $1",
-        build_id
-    )
-    .execute(&pool)
-    .await
+    // what the build is about to run with, before anything below can change it
+    let snapshot = ConfigSnapshot::capture(
+        &project.build_type,
+        project.build_command.as_deref(),
+        project.output_dir.as_deref(),
+        &container_src,
+        &environs.unwrap_or_default(),
+        config,
+    );
+
+    if let Err(err) = sqlx::query("UPDATE builds SET status = 'building', config_snapshot = $2 WHERE id = $1")
+        .bind(build_id)
+        .bind(serde_json::to_value(&snapshot).unwrap_or_default())
+        .execute(&pool)
+        .await
     {
         return Err(BuildError {
             message: "Failed to update build status: Failed to query database".to_string(),
             inner_error: Some(Box::new(err)),
         });
     }
+    publish(events, project.id, build_id, "building");
+
+    let submodule_log = match prepare_submodules(&project, &container_src, config).await {
+        Ok(log) => log,
+        Err(message) => {
+            tracing::warn!(container_name, "Failed to fetch submodules: {}", message);
+
+            if let Err(err) = sqlx::query("UPDATE builds SET status = 'failed', log = $1 WHERE id = $2")
+                .bind(&message)
+                .bind(build_id)
+                .execute(&pool)
+                .await
+            {
+                return Err(BuildError {
+                    message: format!("Failed to update build status: Failed to query database: {repo}"),
+                    inner_error: Some(Box::new(err)),
+                });
+            }
+
+            return Err(BuildError {
+                message: format!("Failed to fetch submodules of repository: {repo}"),
+                inner_error: None,
+            });
+        }
+    };
+
+    let cache = prepare_dependency_cache(&project, &container_name, &container_src).await;
+
+    // they come first in the stored log as well
+    live_log::push_lines(&submodule_log);
+    live_log::push_lines(&cache.as_ref().map(DependencyCache::log_line).unwrap_or_default());
 
     // TODO: Differentiate types of errors returned by build_docker (ex: ImageBuildError, NetworkCreateError, ContainerAttachError)
+    let build = match project.build_type.as_str() {
+        "static" => build_static(
+            &container_name,
+            &container_src,
+            project.build_command.as_deref().unwrap_or_default(),
+            project.output_dir.as_deref().unwrap_or_default(),
+            cache.as_ref(),
+            config,
+        )
+        .await
+        .map(|StaticSite { build_log }| DockerContainer {
+            // served by the app itself, there's no container to point at
+            ip: String::new(),
+            port: 0,
+            build_log,
+            image_size_bytes: None,
+            layer_count: None,
+        }),
+        _ => build_docker(&owner, &repo, &container_name, &container_src, cache.as_ref(), pool.clone(), config)
+            .await
+            .map(|container| {
+                // the project may have been a static site before
+                if let Err(err) = unpublish(&config.build.staticroot, &container_name) {
+                    tracing::warn!(container_name, "Failed to remove static site: {}", err);
+                }
+                container
+            }),
+    };
+
+    // submodules and the cache result go on top of the log, whether the build got that far or not
+    let cache_log = format!(
+        "{submodule_log}{}",
+        cache.as_ref().map(DependencyCache::log_line).unwrap_or_default()
+    );
+    let build = build
+        .map(|result| DockerContainer {
+            build_log: format!("{cache_log}{}", result.build_log),
+            ..result
+        })
+        .map_err(|err| anyhow::anyhow!("{cache_log}{err}"));
+
     let DockerContainer {
         ip, port, ..
-    } = match build_docker(&owner, &repo, &container_name, &container_src, pool.clone(), config).await {
+    } = match build {
         Ok(result) => {
-            if let Err(err) = sqlx::query!(
-                "UPDATE builds SET status = 'successful', log = $1 WHERE id = $2",
-                result.build_log,
-                build_id
+            if let Some(cache) = &cache {
+                if let Err(err) = sqlx::query("UPDATE projects SET dependency_cache_key = $1 WHERE id = $2")
+                    .bind(&cache.key)
+                    .bind(project.id)
+                    .execute(&pool)
+                    .await
+                {
+                    tracing::warn!(?err, container_name, "Failed to store dependency cache key");
+                }
+            }
+
+            if let Err(err) = sqlx::query(
+                r#"UPDATE builds
+                   SET status = 'successful', log = $1, image_size_bytes = $2, layer_count = $3
+                   WHERE id = $4
+                "#,
             )
+            .bind(&result.build_log)
+            .bind(result.image_size_bytes)
+            .bind(result.layer_count)
+            .bind(build_id)
             .execute(&pool)
             .await
             {
@@ -207,6 +676,11 @@ pub async fn trigger_build(
         }
     }?;
 
+    // the new container is running, a project that was asleep is awake again
+    if let Err(err) = crate::sleep::clear_sleeping(&pool, &owner, &repo).await {
+        tracing::warn!(?err, container_name, "Failed to clear sleeping state");
+    }
+
     // TODO: check why why need this
     let subdomain = match sqlx::query!(
         r#"SELECT domains.name
@@ -250,13 +724,69 @@ pub async fn trigger_build(
     Ok(subdomain)
 }
 
+/// Fetched when the build starts rather than on push so a failure ends up in the build log
+async fn prepare_submodules(project: &ProjectBuild, container_src: &str, config: &Settings) -> Result<String, String> {
+    if !project.submodules_enabled {
+        return Ok(String::new());
+    }
+
+    if config.build.submoduledepth == 0 {
+        return Ok("Submodules are turned off on this server, building without them\n".to_string());
+    }
+
+    // an export has no repository to fetch them into
+    if !std::path::Path::new(container_src).join(".git").exists() {
+        return Ok("Submodules are not fetched with the export clone strategy, building without them\n".to_string());
+    }
+
+    let container_src = container_src.to_string();
+    let max_depth = config.build.submoduledepth;
+    tokio::task::spawn_blocking(move || git::update_submodules(&container_src, max_depth))
+        .await
+        .map_err(|err| format!("Failed to fetch submodules: {err}"))?
+}
+
+/// A build without its dependency cache is only slower, so nothing here fails the build
+async fn prepare_dependency_cache(
+    project: &ProjectBuild,
+    container_name: &str,
+    container_src: &str,
+) -> Option<DependencyCache> {
+    let key = match build_cache::cache_key(container_src, project.cache_generation) {
+        Ok(Some(key)) => key,
+        Ok(None) => return None,
+        Err(err) => {
+            tracing::warn!(container_name, "Failed to compute dependency cache key: {}", err);
+            return None;
+        }
+    };
+
+    match build_cache::prepare(
+        container_name,
+        &key,
+        project.dependency_cache_key.as_deref(),
+        project.build_type == "static",
+    )
+    .await
+    {
+        Ok(cache) => {
+            tracing::info!(container_name, key = cache.key, hit = cache.hit, "Dependency cache prepared");
+            Some(cache)
+        }
+        Err(err) => {
+            tracing::warn!(container_name, "Failed to prepare dependency cache: {}", err);
+            None
+        }
+    }
+}
+
 pub async fn process_task_poll(
-    waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
-    waiting_set: ConcurrentMutex<HashSet<String>>,
-    build_count: Arc<AtomicUsize>,
+    control: BuildControl,
     pool: PgPool,
     config: Settings,
+    shutdown: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let BuildControl { build_count, load, waiting_queue, waiting_set, running, events, metrics } = control;
     let mut last_metrics_log = SystemTime::now();
     
     loop {
@@ -269,19 +799,24 @@ pub async fn process_task_poll(
         // Log metrics every 30 seconds
         if last_metrics_log.elapsed().unwrap_or(Duration::ZERO) > Duration::from_secs(30) {
             tracing::info!(
-                "BUILD_QUEUE_METRICS: available_slots={}, queue_length={}, waiting_set_size={}", 
-                current_build_count, queue_len, waiting_set.len()
+                "BUILD_QUEUE_METRICS: available_slots={}, queue_length={}, max_queue_length={}, waiting_set_size={}", 
+                current_build_count, queue_len, load.max, waiting_set.len()
             );
             last_metrics_log = SystemTime::now();
         }
 
         if current_build_count > 0 && queue_len > 0 {
-            let build_item = match waiting_queue.pop_front() {
-                Some(build_item) => build_item,
+            let next = next_eligible(&mut waiting_queue, &running.lock().unwrap(), config.build.maxperowner);
+            let build_item = match next {
+                Some(QueuedBuild { item, .. }) => item,
+                // every waiting build belongs to an owner at the limit
                 None => {
                     drop(waiting_queue);
                     drop(waiting_set);
-                    continue;
+                    tokio::select! {
+                        _ = sleep(Duration::from_millis(5)) => continue,
+                        _ = shutdown.cancelled() => break,
+                    }
                 },
             };
             
@@ -295,15 +830,31 @@ pub async fn process_task_poll(
             );
             
             waiting_set.remove(&build_item.container_name);
+            load.set_depth(waiting_queue.len());
+
+            // registered while the queue is still locked so a cancel never misses it
+            let cancel = CancellationToken::new();
+            running.lock().unwrap().insert(
+                build_item.build_id,
+                RunningBuild {
+                    owner: build_item.owner.clone(),
+                    cancel: cancel.clone(),
+                },
+            );
             drop(waiting_queue);
             drop(waiting_set);
 
             {
                 let build_count = Arc::clone(&build_count);
+                let running = Arc::clone(&running);
                 let pool = pool.clone();
                 let config = config.clone();
                 let build_id = build_item.build_id;
                 let container_name = build_item.container_name.clone();
+                let (owner, repo) = (build_item.owner.clone(), build_item.repo.clone());
+                let project_id = build_item.project_id;
+                let events = events.clone();
+                let metrics = Arc::clone(&metrics);
 
                 build_count.fetch_sub(1, Ordering::SeqCst);
                 tokio::spawn(async move {
@@ -311,28 +862,41 @@ pub async fn process_task_poll(
                     
                     // Add timeout wrapper around trigger_build
                     let build_timeout = Duration::from_secs(config.build.timeout as u64 / 1000); // Convert from ms
-                    let build_result = timeout(build_timeout, trigger_build(build_item, pool.clone(), &config)).await;
+                    // cancelling drops the build future, like the timeout does
+                    let build_result = tokio::select! {
+                        result = timeout(build_timeout, live_log::scope(build_id, trigger_build(build_item, pool.clone(), &config, &events))) => Some(result),
+                        _ = cancel.cancelled() => None,
+                    };
+                    running.lock().unwrap().remove(&build_id);
                     
-                    match build_result {
-                        Ok(Ok(subdomain)) => {
+                    let subdomain = match build_result {
+                        Some(Ok(Ok(subdomain))) => {
                             let build_duration = build_start.elapsed().unwrap_or(Duration::ZERO);
                             tracing::info!(
                                 "BUILD_SUCCESS: build_id={}, container={}, subdomain={}, duration={}ms", 
                                 build_id, container_name, subdomain, build_duration.as_millis()
                             );
+                            BuildMetrics::inc(&metrics.succeeded);
+                            metrics.observe_duration(build_duration);
+                            Some(subdomain)
                         },
-                        Ok(Err(BuildError { message, inner_error })) => {
+                        Some(Ok(Err(BuildError { message, inner_error }))) => {
                             let build_duration = build_start.elapsed().unwrap_or(Duration::ZERO);
                             tracing::error!(
                                 "BUILD_ERROR: build_id={}, container={}, duration={}ms, error={}, inner_error={:?}", 
                                 build_id, container_name, build_duration.as_millis(), message, inner_error
                             );
+                            BuildMetrics::inc(&metrics.failed);
+                            metrics.observe_duration(build_duration);
+                            None
                         },
-                        Err(_timeout_error) => {
+                        Some(Err(_timeout_error)) => {
                             tracing::error!(
                                 "BUILD_TIMEOUT: build_id={}, container={}, timeout_seconds={}", 
                                 build_id, container_name, build_timeout.as_secs()
                             );
+                            BuildMetrics::inc(&metrics.timed_out);
+                            metrics.observe_duration(build_timeout);
                             
                             // Mark build as failed due to timeout
                             let timeout_msg = format!("Build timeout after {} seconds", build_timeout.as_secs());
@@ -346,8 +910,40 @@ pub async fn process_task_poll(
                             {
                                 tracing::error!("Failed to update timeout build status: {:?}", err);
                             }
+                            None
                         }
-                    }
+                        None => {
+                            tracing::info!("BUILD_CANCELLED: build_id={}, container={}, state=building", build_id, container_name);
+                            BuildMetrics::inc(&metrics.cancelled);
+
+                            if let Err(err) = sqlx::query("UPDATE builds SET status = 'failed', log = log || $1 WHERE id = $2")
+                                .bind(CANCELLED_LOG)
+                                .bind(build_id)
+                                .execute(&pool)
+                                .await
+                            {
+                                tracing::error!("Failed to update cancelled build status: {:?}", err);
+                            }
+                            None
+                        }
+                    };
+
+                    // the build status is final by now, the webhook is sent in the background
+                    publish(&events, project_id, build_id, match subdomain {
+                        Some(_) => "successful",
+                        None => "failed",
+                    });
+                    deploy_webhook::notify(pool.clone(), DeployEvent {
+                        owner,
+                        repo,
+                        build_id,
+                        status: match subdomain {
+                            Some(_) => "successful",
+                            None => "failed",
+                        },
+                        subdomain,
+                        duration_ms: build_start.elapsed().unwrap_or(Duration::ZERO).as_millis() as u64,
+                    });
 
                     let final_count = build_count.fetch_add(1, Ordering::SeqCst) + 1;
                     tracing::debug!("BUILD_SLOT_RELEASED: build_id={}, available_slots={}", build_id, final_count);
@@ -357,23 +953,91 @@ pub async fn process_task_poll(
             drop(waiting_queue);
             drop(waiting_set);
         }
-        sleep(Duration::from_millis(5)).await;
+
+        tokio::select! {
+            _ = sleep(Duration::from_millis(5)) => {}
+            _ = shutdown.cancelled() => break,
+        }
     }
+
+    drain(&waiting_queue, &running, &pool, Duration::from_secs(config.build.shutdowngrace)).await;
     Ok(())
 }
 
+/// Gives running builds `grace` to finish once the server shuts down. Builds still running
+/// after it and the ones that never left the queue are marked failed, they'd otherwise stay
+/// pending or building forever.
+async fn drain(
+    waiting_queue: &ConcurrentMutex<BinaryHeap<QueuedBuild>>,
+    running: &RunningBuilds,
+    pool: &PgPool,
+    grace: Duration,
+) {
+    let deadline = tokio::time::Instant::now() + grace;
+    tracing::info!(
+        "BUILD_QUEUE_SHUTDOWN: waiting up to {}s for {} running builds",
+        grace.as_secs(), running.lock().unwrap().len()
+    );
+    loop {
+        let remaining = running.lock().unwrap().len();
+        if remaining == 0 || tokio::time::Instant::now() >= deadline {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    let mut unfinished = running.lock().unwrap().keys().copied().collect::<Vec<_>>();
+    unfinished.extend(waiting_queue.lock().await.iter().map(|queued| queued.item.build_id));
+    if unfinished.is_empty() {
+        return;
+    }
+
+    match sqlx::query(
+        "UPDATE builds SET status = 'failed', log = log || $1 WHERE id = ANY($2) AND status IN ('pending', 'building')",
+    )
+    .bind(SHUTDOWN_LOG)
+    .bind(&unfinished)
+    .execute(pool)
+    .await
+    {
+        Ok(result) => tracing::warn!("BUILD_QUEUE_SHUTDOWN: marked {} unfinished builds as failed", result.rows_affected()),
+        Err(err) => tracing::error!(%err, "Can't mark unfinished builds: Failed to query database"),
+    }
+}
+
 pub async fn process_task_enqueue(
-    waiting_queue: ConcurrentMutex<VecDeque<BuildItem>>,
-    waiting_set: ConcurrentMutex<HashSet<String>>,
+    control: BuildControl,
     pool: PgPool,
     mut receive_channel: Receiver<BuildQueueItem>,
+    shutdown: CancellationToken,
 ) {
-    while let Some(message) = receive_channel.recv().await {
+    let BuildControl { waiting_queue, waiting_set, load, events, metrics, .. } = control;
+    let mut sequence: u64 = 0;
+
+    loop {
+        let message = tokio::select! {
+            message = receive_channel.recv() => message,
+            _ = shutdown.cancelled() => {
+                // requests still on their way are turned away by the closed channel
+                receive_channel.close();
+                tracing::info!("BUILD_QUEUE_SHUTDOWN: no longer accepting builds");
+                None
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+
         let BuildQueueItem {
             container_name,
             container_src,
             owner,
             repo,
+            commit_sha,
+            branch,
+            force,
+            reply,
+            priority,
         } = message;
         let mut waiting_queue = waiting_queue.lock().await;
         let mut waiting_set = waiting_set.lock().await;
@@ -395,39 +1059,159 @@ pub async fn process_task_enqueue(
                 Some(project) => project,
                 None => {
                     tracing::error!("Project not found with owner {} and repo {}", owner, repo);
+                    report(reply, EnqueueOutcome::Rejected("project not found".to_string()));
                     continue;
                 }
             },
             Err(err) => {
                 tracing::error!(%err, "Can't query project: Failed to query database");
+                report(reply, EnqueueOutcome::Rejected("internal server error".to_string()));
                 continue;
             }
         };
 
+        // the waiting build takes this push over instead of building the older commit
         if waiting_set.contains(&container_name) {
+            let existing = take_over_waiting_build(&mut waiting_queue, &container_name, container_src, priority);
+
+            let Some((build_id, priority)) = existing else {
+                tracing::error!("Project {} is in the waiting set but not in the queue", container_name);
+                report(reply, EnqueueOutcome::Rejected("internal server error".to_string()));
+                continue;
+            };
+
+            if let Err(err) = sqlx::query(
+                r#"UPDATE builds
+                   SET commit_sha = $1, environs = projects.environs, priority = $2, branch = $4, updated_at = now()
+                   FROM projects
+                   WHERE builds.id = $3
+                     AND projects.id = builds.project_id
+                "#,
+            )
+            .bind(&commit_sha)
+            .bind(priority.as_str())
+            .bind(build_id)
+            .bind(&branch)
+            .execute(&pool)
+            .await
+            {
+                tracing::error!(%err, "Can't update queued build: Failed to query database");
+            }
+
+            let position = waiting_queue
+                .iter()
+                .find(|queued| queued.item.build_id == build_id)
+                .map_or(1, |build| waiting_queue.iter().filter(|other| *other > build).count() + 1);
+
+            tracing::info!(
+                "BUILD_COALESCED: build_id={}, container={}, owner={}, repo={}, commit={}, queue_position={}",
+                build_id, container_name, owner, repo, commit_sha, position
+            );
+            report(reply, EnqueueOutcome::AlreadyQueued { build_id, position });
+            continue;
+        }
+
+        // the latest build already deployed this exact commit with the same environs,
+        // building it again would produce the same container
+        if !force {
+            // values are encrypted with a random nonce, the stored environs only compare equal
+            // once decrypted
+            let already_deployed = match sqlx::query_as::<_, (bool, Option<serde_json::Value>, serde_json::Value)>(
+                r#"SELECT COALESCE(builds.status = 'successful' AND builds.commit_sha = $2, false),
+                          builds.environs,
+                          projects.environs
+                   FROM builds
+                   JOIN projects ON builds.project_id = projects.id
+                   WHERE builds.project_id = $1
+                   ORDER BY builds.created_at DESC
+                   LIMIT 1
+                "#,
+            )
+            .bind(project.id)
+            .bind(&commit_sha)
+            .fetch_optional(&pool)
+            .await
+            {
+                Ok(Some((true, Some(built), current))) => environ::same_environs(&built, &current),
+                Ok(_) => false,
+                Err(err) => {
+                    tracing::error!(%err, "Can't query latest build: Failed to query database");
+                    false
+                }
+            };
+
+            if already_deployed {
+                tracing::info!(
+                    "BUILD_SKIPPED: container={}, owner={}, repo={}, commit={}, reason=already deployed with same environs",
+                    container_name, owner, repo, commit_sha
+                );
+                report(reply, EnqueueOutcome::AlreadyDeployed);
+                continue;
+            }
+        }
+
+        // shed load instead of growing a backlog nobody waits for, already queued projects
+        // above don't add to it
+        if load.max > 0 && waiting_queue.len() >= load.max {
+            tracing::warn!(
+                "BUILD_REJECTED: container={}, owner={}, repo={}, reason=queue saturated, queue_length={}",
+                container_name, owner, repo, waiting_queue.len()
+            );
+
+            // kept in the build history so the rejected push doesn't just vanish
+            let build_id = Uuid::from(Ulid::new());
+            match sqlx::query(
+                r#"INSERT INTO builds (id, project_id, commit_sha, environs, priority, status, log, branch)
+                   SELECT $1, projects.id, $3, projects.environs, $4, 'failed', $5, $6
+                   FROM projects
+                   WHERE projects.id = $2
+                "#,
+            )
+            .bind(build_id)
+            .bind(project.id)
+            .bind(&commit_sha)
+            .bind(priority.as_str())
+            .bind(QUEUE_FULL_LOG)
+            .bind(&branch)
+            .execute(&pool)
+            .await
+            {
+                Ok(_) => publish(&events, project.id, build_id, "failed"),
+                Err(err) => tracing::error!(%err, "Can't record rejected build: Failed to query database"),
+            }
+
+            BuildMetrics::inc(&metrics.rejected);
+            report(reply, EnqueueOutcome::Saturated { depth: waiting_queue.len() });
             continue;
         }
 
         let build_id = Uuid::from(Ulid::new());
-        match sqlx::query!(
-            r#"INSERT INTO builds (id, project_id)
-               VALUES ($1, $2)
+        match sqlx::query(
+            r#"INSERT INTO builds (id, project_id, commit_sha, environs, priority, branch)
+               SELECT $1, projects.id, $3, projects.environs, $4, $5
+               FROM projects
+               WHERE projects.id = $2
             "#,
-            build_id,
-            project.id,
         )
-        .fetch_optional(&pool)
+        .bind(build_id)
+        .bind(project.id)
+        .bind(&commit_sha)
+        .bind(priority.as_str())
+        .bind(&branch)
+        .execute(&pool)
         .await
         {
             Ok(build_details) => build_details,
             Err(err) => {
                 tracing::error!(%err, "Can't create build: Failed to query database");
+                report(reply, EnqueueOutcome::Rejected("internal server error".to_string()));
                 continue;
             }
         };
 
         let build_item = BuildItem {
             build_id,
+            project_id: project.id,
             container_name: container_name.clone(),
             container_src,
             owner: owner.clone(),
@@ -435,41 +1219,321 @@ pub async fn process_task_enqueue(
             created_at: SystemTime::now(),
         };
         
+        sequence += 1;
+        let queued_build = QueuedBuild {
+            priority,
+            sequence,
+            item: build_item,
+        };
+        // builds that will be dispatched before this one, plus itself
+        let position = waiting_queue.iter().filter(|queued| **queued > queued_build).count() + 1;
+
         tracing::info!(
-            "BUILD_ENQUEUED: build_id={}, container={}, owner={}, repo={}, queue_position={}", 
-            build_id, container_name, owner, repo, waiting_queue.len()
+            "BUILD_ENQUEUED: build_id={}, container={}, owner={}, repo={}, priority={}, queue_position={}", 
+            build_id, container_name, owner, repo, priority.as_str(), position
         );
 
-        waiting_set.insert(build_item.container_name.clone());
-        waiting_queue.push_back(build_item);
+        waiting_set.insert(container_name.clone());
+        waiting_queue.push(queued_build);
+        load.set_depth(waiting_queue.len());
+        publish(&events, project.id, build_id, "pending");
+        BuildMetrics::inc(&metrics.enqueued);
+        report(reply, EnqueueOutcome::Queued { build_id, position });
+    }
+}
+
+/// Deletes finished builds past the retention period. The newest `build.keep` builds of every
+/// project and the latest successful build, which the running deployment comes from, are kept.
+pub async fn process_task_prune(pool: PgPool, config: Settings) {
+    let interval = Duration::from_secs(config.build.pruneinterval.max(1) * 60);
+
+    loop {
+        let result = sqlx::query(
+            r#"DELETE FROM builds
+               WHERE id IN (
+                   SELECT id FROM (
+                       SELECT id, status, created_at,
+                              ROW_NUMBER() OVER (PARTITION BY project_id ORDER BY created_at DESC) AS recency
+                       FROM builds
+                   ) ranked
+                   WHERE recency > $2
+                     AND created_at < now() - make_interval(days => $1)
+                     AND status IN ('successful', 'failed')
+               )
+               AND id NOT IN (
+                   SELECT DISTINCT ON (project_id) id
+                   FROM builds
+                   WHERE status = 'successful'
+                   ORDER BY project_id, created_at DESC
+               )
+            "#,
+        )
+        .bind(config.build.retention)
+        .bind(config.build.keep)
+        .execute(&pool)
+        .await;
+
+        match result {
+            Ok(result) => tracing::info!(
+                "BUILD_PRUNED: rows={}, retention_days={}, keep={}",
+                result.rows_affected(), config.build.retention, config.build.keep
+            ),
+            Err(err) => tracing::error!(%err, "Can't prune builds: Failed to query database"),
+        }
+
+        sleep(interval).await;
     }
 }
 
-pub async fn build_queue_handler(build_queue: BuildQueue) {
+/// Runs the queue until `shutdown` is cancelled, then returns once running builds finished
+/// or their grace period ran out
+pub async fn build_queue_handler(build_queue: BuildQueue, shutdown: CancellationToken) {
     {
-        let waiting_queue = Arc::clone(&build_queue.waiting_queue);
-        let waiting_set = Arc::clone(&build_queue.waiting_set);
         let pool = build_queue.pg_pool.clone();
         let config = build_queue.config.clone();
-        let build_count = Arc::clone(&build_queue.build_count);
 
         tokio::spawn(async move {
-            let _ = process_task_poll(waiting_queue, waiting_set, build_count, pool, config).await;
+            process_task_prune(pool, config).await;
         });
     }
+    let control = build_queue.control();
+    let pool = build_queue.pg_pool.clone();
+    let config = build_queue.config.clone();
+
     {
-        let waiting_queue = Arc::clone(&build_queue.waiting_queue);
-        let waiting_set = Arc::clone(&build_queue.waiting_set);
+        let control = build_queue.control();
         let pool = build_queue.pg_pool.clone();
+        let shutdown = shutdown.clone();
 
         tokio::spawn(async move {
-            process_task_enqueue(
-                waiting_queue,
-                waiting_set,
-                pool,
-                build_queue.receive_channel,
-            )
-            .await;
+            process_task_enqueue(control, pool, build_queue.receive_channel, shutdown).await;
         });
     }
+
+    let _ = process_task_poll(control, pool, config, shutdown).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    use super::*;
+
+    /// never exists, so dropping it doesn't touch the disk
+    fn source(name: &str) -> git::BuildSource {
+        git::BuildSource::new(std::env::temp_dir().join(format!("pws-missing-{}", Uuid::new_v4())).join(name))
+    }
+
+    fn queued(owner: &str, repo: &str, priority: BuildPriority, sequence: u64) -> QueuedBuild {
+        QueuedBuild {
+            priority,
+            sequence,
+            item: BuildItem {
+                build_id: Uuid::new_v4(),
+                project_id: Uuid::new_v4(),
+                container_name: format!("{owner}-{repo}"),
+                container_src: source(&format!("{owner}-{repo}")),
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                created_at: SystemTime::now(),
+            },
+        }
+    }
+
+    fn pop_order(mut queue: BinaryHeap<QueuedBuild>) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop()).map(|build| build.item.repo).collect()
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let queue = BinaryHeap::from(vec![
+            queued("a", "preview", BuildPriority::Preview, 0),
+            queued("a", "push", BuildPriority::Push, 1),
+            queued("a", "rebuild", BuildPriority::Rebuild, 2),
+        ]);
+
+        assert_eq!(pop_order(queue), ["rebuild", "push", "preview"]);
+    }
+
+    #[test]
+    fn same_priority_is_first_in_first_out() {
+        let queue = BinaryHeap::from(vec![
+            queued("a", "second", BuildPriority::Push, 2),
+            queued("a", "first", BuildPriority::Push, 1),
+            queued("a", "third", BuildPriority::Push, 3),
+        ]);
+
+        assert_eq!(pop_order(queue), ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn queue_load_is_saturated_at_its_max() {
+        let load = QueueLoad::new(2);
+        load.set_depth(1);
+        assert!(!load.is_saturated());
+        load.set_depth(2);
+        assert!(load.is_saturated());
+
+        let unlimited = QueueLoad::new(0);
+        unlimited.set_depth(1000);
+        assert!(!unlimited.is_saturated());
+    }
+
+    fn running(owners: &[&str]) -> HashMap<Uuid, RunningBuild> {
+        owners
+            .iter()
+            .map(|owner| {
+                (Uuid::new_v4(), RunningBuild { owner: owner.to_string(), cancel: CancellationToken::new() })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn next_eligible_without_a_limit_takes_the_head() {
+        let mut queue = BinaryHeap::from(vec![queued("a", "one", BuildPriority::Push, 1)]);
+
+        let next = next_eligible(&mut queue, &running(&["a", "a"]), 0);
+
+        assert_eq!(next.map(|build| build.item.repo).as_deref(), Some("one"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn next_eligible_passes_over_owners_at_their_limit() {
+        let mut queue = BinaryHeap::from(vec![
+            queued("busy", "first", BuildPriority::Push, 1),
+            queued("busy", "second", BuildPriority::Push, 2),
+            queued("idle", "third", BuildPriority::Push, 3),
+        ]);
+
+        let next = next_eligible(&mut queue, &running(&["busy"]), 1);
+
+        assert_eq!(next.map(|build| build.item.repo).as_deref(), Some("third"));
+        // the passed over builds keep their place
+        assert_eq!(pop_order(queue), ["first", "second"]);
+    }
+
+    #[test]
+    fn next_eligible_leaves_the_queue_alone_when_every_owner_is_at_the_limit() {
+        let mut queue = BinaryHeap::from(vec![
+            queued("busy", "first", BuildPriority::Rebuild, 1),
+            queued("busy", "second", BuildPriority::Push, 2),
+        ]);
+
+        assert!(next_eligible(&mut queue, &running(&["busy", "busy"]), 2).is_none());
+        assert_eq!(pop_order(queue), ["first", "second"]);
+    }
+
+    #[test]
+    fn pushing_again_while_waiting_builds_the_latest_source() {
+        let mut queue = BinaryHeap::from(vec![
+            queued("a", "site", BuildPriority::Push, 1),
+            queued("b", "other", BuildPriority::Push, 2),
+        ]);
+
+        let first = take_over_waiting_build(&mut queue, "a-site", source("first"), BuildPriority::Push);
+        let second = take_over_waiting_build(&mut queue, "a-site", source("second"), BuildPriority::Rebuild);
+
+        let build_id = first.map(|(build_id, _)| build_id);
+        assert!(build_id.is_some());
+        assert_eq!(second, build_id.map(|build_id| (build_id, BuildPriority::Rebuild)));
+        // still one build for the project, now building the second push first
+        assert_eq!(queue.len(), 2);
+        let next = queue.pop().unwrap();
+        assert_eq!(Some(next.item.build_id), build_id);
+        assert!(next.item.container_src.ends_with("second"));
+    }
+
+    #[test]
+    fn nothing_is_taken_over_without_a_waiting_build() {
+        let mut queue = BinaryHeap::from(vec![queued("a", "site", BuildPriority::Push, 1)]);
+
+        assert!(take_over_waiting_build(&mut queue, "a-other", source("new"), BuildPriority::Push).is_none());
+        assert_eq!(pop_order(queue), ["site"]);
+    }
+
+    /// Sends a push of `commit` to `alice/{repo}` to the queue, what receive-pack does
+    async fn enqueue(build_channel: &Sender<BuildQueueItem>, repo: &str, commit: &str, force: bool) -> EnqueueOutcome {
+        let (reply, outcome) = oneshot::channel();
+        build_channel
+            .send(BuildQueueItem {
+                container_name: format!("alice-{repo}"),
+                container_src: source(repo),
+                owner: "alice".to_string(),
+                repo: repo.to_string(),
+                commit_sha: commit.to_string(),
+                branch: Some("main".to_string()),
+                force,
+                reply: Some(reply),
+                priority: BuildPriority::Push,
+            })
+            .await
+            .unwrap();
+
+        outcome.await.unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn the_same_commit_is_built_once(pool: sqlx::PgPool) {
+        let (state, build_queue) =
+            test_support::app_state_with(pool.clone(), "/nonexistent", test_support::settings()).await;
+        let control = test_support::accept_builds(build_queue);
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        let project_id = test_support::project(&pool, owner_id, "site").await;
+        let commit = "1".repeat(40);
+
+        let EnqueueOutcome::Queued { build_id, .. } = enqueue(&state.build_channel, "site", &commit, false).await else {
+            panic!("the first push isn't queued");
+        };
+        // as if the build ran and deployed the commit
+        assert_eq!(control.cancel(build_id).await, CancelOutcome::Dequeued);
+        sqlx::query("UPDATE builds SET status = 'successful' WHERE id = $1")
+            .bind(build_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let outcome = enqueue(&state.build_channel, "site", &commit, false).await;
+        assert!(matches!(outcome, EnqueueOutcome::AlreadyDeployed), "{outcome:?}");
+        assert_eq!(test_support::builds(&pool, project_id).await, 1);
+
+        // unless it's asked for
+        let outcome = enqueue(&state.build_channel, "site", &commit, true).await;
+        assert!(matches!(outcome, EnqueueOutcome::Queued { .. }), "{outcome:?}");
+        assert_eq!(test_support::builds(&pool, project_id).await, 2);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_full_queue_rejects_new_builds(pool: sqlx::PgPool) {
+        let mut settings = test_support::settings();
+        settings.build.maxqueue = 2;
+        let (state, build_queue) = test_support::app_state_with(pool.clone(), "/nonexistent", settings).await;
+        test_support::accept_builds(build_queue);
+        let alice = test_support::user(&pool, "alice").await;
+        let owner_id = test_support::owner(&pool, "alice", &alice).await;
+        for repo in ["one", "two", "three"] {
+            test_support::project(&pool, owner_id, repo).await;
+        }
+        let commit = "1".repeat(40);
+
+        // nothing runs builds, every one stays in the queue
+        assert!(matches!(enqueue(&state.build_channel, "one", &commit, false).await, EnqueueOutcome::Queued { .. }));
+        assert!(matches!(enqueue(&state.build_channel, "two", &commit, false).await, EnqueueOutcome::Queued { .. }));
+        assert!(state.build_queue_load.is_saturated());
+
+        let outcome = enqueue(&state.build_channel, "three", &commit, false).await;
+        assert!(matches!(outcome, EnqueueOutcome::Saturated { depth: 2 }), "{outcome:?}");
+        let (status, log) = sqlx::query_as::<_, (String, String)>(
+            r#"SELECT builds.status::TEXT, builds.log
+               FROM builds
+               JOIN projects ON builds.project_id = projects.id
+               WHERE projects.name = 'three'
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((status.as_str(), log.as_str()), ("failed", QUEUE_FULL_LOG));
+    }
 }
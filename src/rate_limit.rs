@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{ConnectInfo, State};
+use axum::middleware::Next;
+use bytes::Bytes;
+use http_body::combinators::UnsyncBoxBody;
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::auth::Auth;
+use crate::configuration::{RateLimitSettings, RouteLimitSettings};
+
+/// Buckets that are full again are dropped once this many keys are tracked
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Read,
+    Heavy,
+}
+
+impl RouteClass {
+    /// Classifies project routes by their last path segments, anything else is exempt
+    fn from_path(path: &str) -> Option<Self> {
+        let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+
+        // /api/project/:owner/:project/<endpoint>/...
+        match segments.get(5).copied() {
+            Some("archive") | Some("diff") => Some(Self::Heavy),
+            Some("tree") | Some("blob") | Some("raw") | Some("commits") | Some("refs") => Some(Self::Read),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket per (user or ip, route class)
+#[derive(Clone)]
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Arc<Mutex<HashMap<(String, RouteClass), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn limits(&self, class: RouteClass) -> &RouteLimitSettings {
+        match class {
+            RouteClass::Read => &self.settings.read,
+            RouteClass::Heavy => &self.settings.heavy,
+        }
+    }
+
+    /// Takes a token, on failure returns how many seconds until one is available
+    fn acquire(&self, key: String, class: RouteClass) -> Result<(), u64> {
+        let limits = self.limits(class);
+        let burst = limits.burst.max(1) as f64;
+        let per_second = limits.perminute.max(1) as f64 / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_TRACKED_BUCKETS {
+            buckets.retain(|(_, class), bucket| {
+                let limits = self.limits(*class);
+                let refilled = bucket.tokens
+                    + now.duration_since(bucket.updated_at).as_secs_f64() * limits.perminute.max(1) as f64 / 60.0;
+                refilled < limits.burst.max(1) as f64
+            });
+        }
+
+        let bucket = buckets.entry((key, class)).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * per_second).min(burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+/// Client ip, the proxy appends the address it saw as the last X-Forwarded-For entry
+fn client_ip<B>(request: &Request<B>) -> Option<String> {
+    request
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+}
+
+pub async fn rate_limit<B>(
+    State(limiter): State<RateLimiter>,
+    auth: Auth,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, Response<Body>> {
+    if !limiter.settings.enabled {
+        return Ok(next.run(request).await);
+    }
+
+    let Some(class) = RouteClass::from_path(request.uri().path()) else {
+        return Ok(next.run(request).await);
+    };
+
+    let key = match (&auth.current_user, client_ip(&request)) {
+        (Some(user), _) => format!("user:{}", user.id),
+        (None, Some(ip)) => format!("ip:{ip}"),
+        (None, None) => "anonymous".to_string(),
+    };
+
+    if let Err(retry_after) = limiter.acquire(key.clone(), class) {
+        tracing::warn!(key, ?class, retry_after, "Rate limit exceeded");
+
+        return Err(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .header(axum::http::header::RETRY_AFTER, retry_after.max(1).to_string())
+            .body(Body::from(r#"{"message": "Too many requests, please slow down"}"#))
+            .unwrap());
+    }
+
+    Ok(next.run(request).await)
+}
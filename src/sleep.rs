@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, middleware::Next, response::Response};
+use bollard::{
+    container::{StartContainerOptions, StatsOptions, StopContainerOptions},
+    Docker,
+};
+use futures_util::StreamExt;
+use hyper::{header::HOST, Body, Request, StatusCode};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{configuration::Settings, git::canonical_repo_name, startup::AppState, static_site::project_subdomain};
+
+/// Seconds the page shown while a project wakes up waits before reloading
+const WAKE_REFRESH_SECONDS: u32 = 5;
+
+/// Received bytes of a container when last checked, and when they last grew by enough to
+/// count as traffic
+struct Traffic {
+    rx_bytes: u64,
+    last_active: Instant,
+}
+
+/// Whether a project without traffic since `last_active` should be put to sleep, a timeout
+/// of 0 keeps it awake
+pub fn is_idle(last_active: Instant, now: Instant, timeout: Duration) -> bool {
+    !timeout.is_zero() && now.saturating_duration_since(last_active) >= timeout
+}
+
+/// Bytes received by a running container over all its networks, `None` when it isn't running
+async fn received_bytes(docker: &Docker, container_name: &str) -> Option<u64> {
+    let stats = docker
+        .stats(
+            container_name,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: true,
+            }),
+        )
+        .next()
+        .await?
+        .ok()?;
+
+    // a stopped container has no networks in its stats
+    stats
+        .networks
+        .map(|networks| networks.values().map(|network| network.rx_bytes).sum())
+}
+
+/// Stops the containers of projects that went without traffic for their idle timeout and
+/// marks them sleeping, [`wake_on_request`] starts them again
+pub async fn run_sleeper(pool: PgPool, config: Settings) {
+    if !config.sleep.enabled {
+        tracing::info!("Idle sleeping is disabled");
+        return;
+    }
+
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(err) => {
+            tracing::error!(?err, "Can't start idle sleeper: Failed to connect to docker");
+            return;
+        }
+    };
+
+    // kept in memory, after a restart every project gets a full timeout before it sleeps
+    let mut traffic = HashMap::<String, Traffic>::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.sleep.interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        // static sites are served by the app, there's no container to stop
+        let projects = match sqlx::query_as::<_, (Uuid, String, String, Option<i32>)>(
+            r#"SELECT projects.id, project_owners.name, projects.name, projects.idle_timeout
+               FROM projects
+               JOIN project_owners ON projects.owner_id = project_owners.id
+               WHERE projects.deleted_at IS NULL
+                 AND NOT projects.sleeping
+                 AND projects.build_type = 'docker'
+                 AND EXISTS (
+                   SELECT 1 FROM builds
+                   WHERE builds.project_id = projects.id AND builds.status = 'successful'
+                 )
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        {
+            Ok(projects) => projects,
+            Err(err) => {
+                tracing::error!(?err, "Can't check idle projects: Failed to query database");
+                continue;
+            }
+        };
+
+        let now = Instant::now();
+        let mut running = HashSet::new();
+
+        for (id, owner, project, idle_timeout) in projects {
+            let timeout = match idle_timeout {
+                Some(seconds) => Duration::from_secs(seconds.max(0) as u64),
+                None => Duration::from_secs(config.sleep.idletimeout),
+            };
+            if timeout.is_zero() {
+                continue;
+            }
+
+            let container_name = format!("{owner}-{}", canonical_repo_name(&project)).replace('.', "-");
+            let Some(rx_bytes) = received_bytes(&docker, &container_name).await else {
+                continue;
+            };
+            running.insert(container_name.clone());
+
+            let entry = traffic.entry(container_name.clone()).or_insert(Traffic { rx_bytes, last_active: now });
+            // the counter starts over when the container is recreated by a deploy
+            if rx_bytes < entry.rx_bytes || rx_bytes - entry.rx_bytes >= config.sleep.minbytes {
+                entry.last_active = now;
+            }
+            entry.rx_bytes = rx_bytes;
+
+            if !is_idle(entry.last_active, now, timeout) {
+                continue;
+            }
+
+            if let Err(err) = put_to_sleep(&pool, &docker, id, &container_name).await {
+                tracing::error!(?err, container_name, "Can't put project to sleep");
+                continue;
+            }
+
+            running.remove(&container_name);
+            tracing::info!(container_name, idle_seconds = timeout.as_secs(), "Project put to sleep");
+        }
+
+        traffic.retain(|container_name, _| running.contains(container_name));
+    }
+}
+
+async fn put_to_sleep(pool: &PgPool, docker: &Docker, id: Uuid, container_name: &str) -> anyhow::Result<()> {
+    docker
+        .stop_container(container_name, Some(StopContainerOptions { t: 10 }))
+        .await?;
+
+    sqlx::query("UPDATE projects SET sleeping = true, slept_at = now() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Starts the container of a sleeping project again
+pub async fn wake(pool: &PgPool, owner: &str, project: &str) -> anyhow::Result<()> {
+    let container_name = format!("{owner}-{}", canonical_repo_name(project)).replace('.', "-");
+
+    let docker = Docker::connect_with_local_defaults()?;
+    match docker
+        .start_container(&container_name, None::<StartContainerOptions<String>>)
+        .await
+    {
+        // already running, e.g. two requests woke it at once
+        Ok(_) | Err(bollard::errors::Error::DockerResponseServerError { status_code: 304, .. }) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    clear_sleeping(pool, owner, project).await?;
+    tracing::info!(container_name, "Project woken up");
+
+    Ok(())
+}
+
+/// Clears the sleeping state, for when the container was started, stopped or replaced some
+/// other way so requests no longer wake it
+pub async fn clear_sleeping(pool: &PgPool, owner: &str, project: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE projects
+           SET sleeping = false, slept_at = NULL
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+             AND project_owners.name = $1
+             AND projects.name = $2
+             AND projects.sleeping
+        "#,
+    )
+    .bind(owner)
+    .bind(project)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+fn render_waking(project: &str) -> String {
+    let project = project
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta http-equiv="refresh" content="{WAKE_REFRESH_SECONDS}">
+<title>{project} is waking up</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 36rem; margin: 15vh auto; padding: 0 1rem; color: #1f2937; }}
+h1 {{ font-size: 1.5rem; }}
+</style>
+</head>
+<body>
+<h1>{project} is waking up</h1>
+<p>It was put to sleep after a while without visitors. This page reloads once it's up.</p>
+</body>
+</html>
+"#
+    )
+}
+
+/// Requests to the subdomain of a sleeping project reach the app since the proxy has no
+/// running container to send them to. The container is started and the visitor gets a page
+/// that reloads until the project answers by itself.
+pub async fn wake_on_request(
+    State(AppState { pool, domain, .. }): State<AppState>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let subdomain = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| project_subdomain(host, &domain))
+        .map(|subdomain| subdomain.to_string());
+
+    let Some(subdomain) = subdomain else {
+        return next.run(request).await;
+    };
+
+    // subdomains are container names, see docker::build_docker
+    let project = sqlx::query_as::<_, (String, String)>(
+        r#"SELECT project_owners.name, projects.name
+           FROM projects
+           JOIN project_owners ON projects.owner_id = project_owners.id
+           WHERE replace(project_owners.name || '-' || regexp_replace(projects.name, '\.git$', ''), '.', '-') = $1
+             AND projects.deleted_at IS NULL
+             AND projects.sleeping
+           LIMIT 1
+        "#,
+    )
+    .bind(&subdomain)
+    .fetch_optional(&pool)
+    .await;
+
+    let (owner, project) = match project {
+        Ok(Some(project)) => project,
+        Ok(None) => return next.run(request).await,
+        Err(err) => {
+            tracing::error!(?err, subdomain, "Can't check for sleeping project: Failed to query database");
+            return next.run(request).await;
+        }
+    };
+
+    if let Err(err) = wake(&pool, &owner, &project).await {
+        tracing::error!(?err, subdomain, "Can't wake project up");
+    }
+
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(axum::http::header::CACHE_CONTROL, "no-store")
+        .header(axum::http::header::RETRY_AFTER, WAKE_REFRESH_SECONDS.to_string())
+        .body(axum::body::boxed(Body::from(render_waking(&canonical_repo_name(&project)))))
+        .unwrap()
+}
@@ -13,6 +13,7 @@ use hyper::{Body, Method, Request, Response, StatusCode, Uri};
 
 use sqlx::PgPool;
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
 use uuid::Uuid;
@@ -21,22 +22,41 @@ use std::net::{SocketAddr, TcpListener};
 
 use crate::auth::User;
 use crate::configuration::Settings;
-use crate::queue::BuildQueueItem;
-use crate::{auth, dashboard, git, owner, projects, telemetry};
+use crate::queue::{BuildControl, BuildQueueItem, QueueLoad};
+use crate::rate_limit::{rate_limit, RateLimiter};
+use crate::placeholder::serve_placeholder;
+use crate::sleep::wake_on_request;
+use crate::static_site::{serve_static_site, StaticSites};
+use crate::{admin, auth, dashboard, git, owner, projects, telemetry};
 
 #[derive(Clone)]
 pub struct AppState {
     pub base: String,
     pub git_auth: bool,
+    /// git passwords stored before hashing still authenticate
+    pub git_plaintext_tokens: bool,
     pub sso: bool,
     pub domain: String,
     pub client: hyper::client::Client<hyper::client::HttpConnector, hyper::Body>,
     pub pool: PgPool,
     pub build_channel: Sender<BuildQueueItem>,
     pub secure: bool,
+    /// globs used when a tree listing asks for the default ignore set
+    pub tree_ignore: Vec<String>,
+    /// most entries in one page of a tree listing
+    pub tree_max_entries: usize,
+    /// most entries a recursive tree listing returns
+    pub tree_max_recursive: usize,
+    /// size in bytes above which raw file downloads are streamed from the object database
+    pub raw_stream_threshold: usize,
+    /// git executable the smart http endpoints and archives run
+    pub git_binary: String,
+    pub build_queue_load: QueueLoad,
+    /// looks into the build queue and cancels waiting and running builds
+    pub build_control: BuildControl,
 }
 
-pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Result<(), String> {
+pub async fn run(listener: TcpListener, state: AppState, config: Settings, shutdown: CancellationToken) -> Result<(), String> {
     let http_trace = telemetry::http_trace_layer();
     let pool = state.pool.clone();
 
@@ -56,8 +76,14 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
     let git_router = git::router(state.clone(), &config);
     let auth_router = auth::api::router(state.clone(), &config).await;
     let dashboard_router: Router<AppState> = dashboard::api::router(state.clone(), &config).await;
-    let project_router = projects::api::router(state.clone(), &config).await;
+    let project_router = projects::api::router(state.clone(), &config)
+        .await
+        .layer(middleware::from_fn_with_state(
+            RateLimiter::new(config.ratelimit.clone()),
+            rate_limit,
+        ));
     let owners_router = owner::api::router(state.clone(), &config).await;
+    let admin_router = admin::api::router(state.clone(), &config).await;
 
     let app = Router::new()
         .route("/", routing::any(|| async { Redirect::permanent("/web") }))
@@ -66,6 +92,7 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         .merge(dashboard_router)
         .merge(project_router)
         .merge(owners_router)
+        .merge(admin_router)
         .layer(http_trace)
         // TODO: rethink if we need this here. since it makes all routes under this query the
         // session even if they don't need it
@@ -75,6 +102,7 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         )
         .layer(SessionLayer::new(session_store))
         .route("/health", get(health_check))  // Health check without auth layers
+        .route("/metrics", get(build_metrics))
         .route("/web", routing::get(|| async { Redirect::permanent("/web/") }))
         .nest_service("/assets", ServeDir::new("assets"))
         // TODO: find a way to have this on the "/" path instead of "/web"  
@@ -85,6 +113,12 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
         // .fallback(fallback)  // Disabled: Traefik handles routing directly
         .with_state(state.clone())
         // .route_layer(middleware::from_fn_with_state(state, fallback_middleware))  // Disabled with fallback
+        // project subdomains that were never deployed get a page saying so
+        .layer(middleware::from_fn_with_state(state.clone(), serve_placeholder))
+        // subdomains of sleeping projects start their container again
+        .layer(middleware::from_fn_with_state(state.clone(), wake_on_request))
+        // project subdomains with a published static site never reach the routes above
+        .layer(middleware::from_fn_with_state(StaticSites::new(&config), serve_static_site))
         .layer(cors);
 
     let addr = listener
@@ -96,10 +130,42 @@ pub async fn run(listener: TcpListener, state: AppState, config: Settings) -> Re
     axum::Server::from_tcp(listener)
         .map_err(|err| format!("Failed to make server from tcp: {}", err))?
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
         .await
         .map_err(|err| format!("failed to start server: {}", err))
 }
 
+/// Cancels `shutdown` on SIGTERM, what container runtimes stop with, or ctrl-c
+pub async fn shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(?err, "Failed to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(?err, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received ctrl-c, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+    shutdown.cancel();
+}
+
 pub async fn health_check() -> Response<Body> {
     Response::builder()
         .status(StatusCode::OK)
@@ -108,6 +174,15 @@ pub async fn health_check() -> Response<Body> {
         .unwrap()
 }
 
+/// Build queue counters in the Prometheus text format, for scrapers
+pub async fn build_metrics(State(AppState { build_control, .. }): State<AppState>) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(Body::from(build_control.render_metrics()))
+        .unwrap()
+}
+
 pub async fn fallback(
     State(AppState {
         pool,
@@ -0,0 +1,330 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    extract::State,
+    middleware::Next,
+    response::Response,
+};
+use bollard::{container::RemoveContainerOptions, Docker};
+use hyper::{header::HOST, Body, Request};
+use tokio::process::Command;
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+use ulid::Ulid;
+
+use crate::{
+    build_cache::{DependencyCache, CACHE_DIR, CACHE_ENV},
+    configuration::Settings,
+};
+
+pub const MAX_BUILD_COMMAND_LENGTH: usize = 1000;
+
+pub struct StaticSite {
+    pub build_log: String,
+}
+
+/// The command is handed to `sh -c` inside a throwaway container, this only keeps out values
+/// that can't be a single command line
+pub fn validate_build_command(command: &str) -> Result<(), String> {
+    if command.trim().is_empty() {
+        return Err("Build command can't be empty".to_string());
+    }
+
+    if command.len() > MAX_BUILD_COMMAND_LENGTH {
+        return Err(format!(
+            "Build command can't be longer than {MAX_BUILD_COMMAND_LENGTH} characters"
+        ));
+    }
+
+    if command.contains(['\n', '\r', '\0']) {
+        return Err("Build command must be a single line".to_string());
+    }
+
+    Ok(())
+}
+
+/// Output directory has to stay inside the repository, e.g. `dist` or `build/web`
+pub fn validate_output_dir(output_dir: &str) -> Result<(), String> {
+    let path = Path::new(output_dir);
+
+    if output_dir.trim().is_empty() {
+        return Err("Output directory can't be empty".to_string());
+    }
+
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err("Output directory must be a relative path inside the repository".to_string());
+    }
+
+    Ok(())
+}
+
+/// Kills the build container when the build future is dropped, e.g. by the build timeout,
+/// since killing the docker cli alone leaves the container running
+struct BuildContainerGuard(String);
+
+impl Drop for BuildContainerGuard {
+    fn drop(&mut self) {
+        let _ = std::process::Command::new("docker")
+            .args(["rm", "-f", &self.0])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+    }
+}
+
+/// Runs the build command of a static project in a container with the clone mounted, then
+/// publishes the output directory to where [`serve_static_site`] looks for it
+#[tracing::instrument(skip(config))]
+pub async fn build_static(
+    container_name: &str,
+    container_src: &str,
+    command: &str,
+    output_dir: &str,
+    cache: Option<&DependencyCache>,
+    config: &Settings,
+) -> Result<StaticSite> {
+    validate_build_command(command).map_err(|err| anyhow::anyhow!(err))?;
+    validate_output_dir(output_dir).map_err(|err| anyhow::anyhow!(err))?;
+
+    let source = fs::canonicalize(container_src).map_err(|err| {
+        tracing::error!("Failed to resolve build source {}: {}", container_src, err);
+        err
+    })?;
+
+    let build_container = format!("{container_name}-static-build");
+    let _guard = BuildContainerGuard(build_container.clone());
+
+    tracing::info!(container_name, command, "STATIC BUILD START");
+
+    // package managers keep their downloads in the cache volume between builds
+    let cache_args = cache
+        .map(|cache| {
+            let mut args = vec!["-v".to_string(), format!("{}:{CACHE_DIR}", cache.id)];
+            for (key, value) in CACHE_ENV {
+                args.push("-e".to_string());
+                args.push(format!("{key}={value}"));
+            }
+            args
+        })
+        .unwrap_or_default();
+
+    let child = Command::new("docker")
+        .args([
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            build_container.clone(),
+            format!("--memory={}", config.container_memory_bytes().unwrap_or(256 * 1024 * 1024)),
+            format!("--cpu-period={}", config.container_cpu_period()),
+            format!("--cpu-quota={}", config.container_cpu_quota()),
+            "--pids-limit=256".to_string(),
+            "--cap-drop=ALL".to_string(),
+            "--security-opt=no-new-privileges".to_string(),
+            "-v".to_string(),
+            format!("{}:/app", source.display()),
+            "-w".to_string(),
+            "/app".to_string(),
+        ])
+        .args(cache_args)
+        .args([
+            config.build.staticimage.clone(),
+            "sh".to_string(),
+            "-c".to_string(),
+            command.to_string(),
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| {
+            tracing::error!("Failed to spawn static build: {}", err);
+            err
+        })?;
+
+    let command_timeout = Duration::from_secs(config.build.commandtimeout);
+    let output = match tokio::time::timeout(command_timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|err| {
+            tracing::error!("Failed to wait for static build: {}", err);
+            err
+        })?,
+        Err(_) => {
+            return Err(anyhow::anyhow!(
+                "Build command `{command}` timed out after {} seconds",
+                command_timeout.as_secs()
+            ));
+        }
+    };
+
+    let build_log = format!(
+        "$ {command}\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("{build_log}\nBuild command exited with {}", output.status));
+    }
+
+    let output_path = source.join(output_dir);
+    // the build may have replaced the output directory with a link pointing elsewhere
+    match fs::canonicalize(&output_path) {
+        Ok(resolved) if resolved.starts_with(&source) && resolved.is_dir() => {}
+        _ => {
+            return Err(anyhow::anyhow!(
+                "{build_log}\nOutput directory `{output_dir}` was not found inside the repository after the build"
+            ));
+        }
+    }
+
+    let root = PathBuf::from(&config.build.staticroot);
+    let site_name = container_name.to_string();
+    tokio::task::spawn_blocking(move || publish(&output_path, &root, &site_name))
+        .await?
+        .map_err(|err| {
+            tracing::error!("Failed to publish static site: {}", err);
+            err
+        })?;
+
+    // the project may have been deployed as a container before, its traefik route would
+    // otherwise keep taking the subdomain
+    let docker = Docker::connect_with_local_defaults()?;
+    match docker
+        .remove_container(
+            container_name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+    {
+        Ok(_) => tracing::info!(container_name, "Removed container replaced by static site"),
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {}
+        Err(err) => tracing::warn!(container_name, "Failed to remove container: {}", err),
+    }
+
+    Ok(StaticSite { build_log })
+}
+
+/// Swaps the served directory in one rename so requests never see a half copied site
+fn publish(output: &Path, root: &Path, site_name: &str) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+
+    let id = Ulid::new();
+    let staging = root.join(format!(".{site_name}.{id}"));
+    let retired = root.join(format!(".{site_name}.{id}.old"));
+    let target = root.join(site_name);
+
+    copy_dir(output, &staging)?;
+
+    if target.exists() {
+        fs::rename(&target, &retired)?;
+    }
+    fs::rename(&staging, &target)?;
+
+    if retired.exists() {
+        fs::remove_dir_all(&retired)?;
+    }
+
+    Ok(())
+}
+
+/// Symlinks are skipped, they could point outside of the build output
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a published site, e.g. when a project goes back to docker builds
+pub fn unpublish(root: &str, container_name: &str) -> io::Result<()> {
+    match fs::remove_dir_all(Path::new(root).join(container_name)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone)]
+pub struct StaticSites {
+    root: PathBuf,
+    domain: String,
+}
+
+impl StaticSites {
+    pub fn new(config: &Settings) -> Self {
+        Self {
+            root: PathBuf::from(&config.build.staticroot),
+            domain: config.domain(),
+        }
+    }
+
+    /// Published directory for `<subdomain>.<domain>`, if there's one
+    fn site_dir(&self, host: &str) -> Option<PathBuf> {
+        let dir = self.root.join(project_subdomain(host, &self.domain)?);
+        dir.is_dir().then_some(dir)
+    }
+}
+
+/// The `<subdomain>` of a `<subdomain>.<domain>` host, only a single label is a project
+pub fn project_subdomain<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    let host = host.split(':').next().unwrap_or(host);
+    let domain = domain.split(':').next().unwrap_or(domain);
+
+    let subdomain = host.strip_suffix(domain)?.strip_suffix('.')?;
+    if subdomain.is_empty() || subdomain.starts_with('.') || subdomain.contains(['.', '/', '\\']) {
+        return None;
+    }
+
+    Some(subdomain)
+}
+
+/// Serves projects with a static build type on their subdomain, anything else goes through
+pub async fn serve_static_site(
+    State(sites): State<StaticSites>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let site_dir = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| sites.site_dir(host));
+
+    let Some(site_dir) = site_dir else {
+        return next.run(request).await;
+    };
+
+    // unknown paths get index.html so client side routing keeps working
+    let index = site_dir.join("index.html");
+    match ServeDir::new(site_dir)
+        .append_index_html_on_directories(true)
+        .fallback(ServeFile::new(index))
+        .oneshot(request)
+        .await
+    {
+        Ok(response) => response.map(axum::body::boxed),
+        Err(err) => match err {},
+    }
+}
@@ -0,0 +1,171 @@
+//! Fixtures for tests that need the database, used with `#[sqlx::test(migrations = false)]`
+//! which hands every test a database of its own
+
+use std::{collections::HashSet, path::PathBuf};
+
+use hyper::client::Client;
+use sqlx::{Executor, PgPool};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    auth::User,
+    configuration::{self, Settings},
+    git,
+    queue::{process_task_enqueue, BuildControl, BuildQueue},
+    startup::AppState,
+};
+
+/// The defaults, without a configuration file or the environment
+pub fn settings() -> Settings {
+    configuration::defaults()
+        .and_then(|builder| builder.set_override("build.max", 1))
+        .and_then(|builder| builder.build())
+        .and_then(|config| config.try_deserialize::<Settings>())
+        .unwrap()
+}
+
+/// Creates the tables, the test database starts out empty
+pub async fn load_schema(pool: &PgPool) {
+    pool.execute(include_str!("../schema.sql")).await.unwrap();
+}
+
+/// State of a server with repositories under `base`, without a queue to send builds to
+pub async fn app_state(pool: PgPool, base: &str) -> AppState {
+    app_state_with(pool, base, settings()).await.0
+}
+
+/// [`app_state`] with other settings, and the queue its builds are sent to
+pub async fn app_state_with(pool: PgPool, base: &str, settings: Settings) -> (AppState, BuildQueue) {
+    load_schema(&pool).await;
+
+    let (build_queue, build_channel) = BuildQueue::new(settings.build.max, pool.clone(), settings.clone());
+
+    let state = AppState {
+        base: base.to_string(),
+        git_auth: true,
+        git_plaintext_tokens: false,
+        sso: false,
+        domain: settings.domain(),
+        client: Client::new(),
+        pool,
+        build_channel,
+        secure: false,
+        tree_ignore: settings.git.treeignore.clone(),
+        tree_max_entries: settings.git.treemaxentries,
+        tree_max_recursive: settings.git.treemaxrecursive,
+        raw_stream_threshold: settings.git.rawstreamthreshold,
+        git_binary: settings.git.binary.clone(),
+        build_queue_load: build_queue.load.clone(),
+        build_control: build_queue.control(),
+    };
+
+    (state, build_queue)
+}
+
+/// Queues the builds sent to `build_queue` like the server does, nothing ever runs them
+pub fn accept_builds(build_queue: BuildQueue) -> BuildControl {
+    let control = build_queue.control();
+    tokio::spawn(process_task_enqueue(
+        build_queue.control(),
+        build_queue.pg_pool,
+        build_queue.receive_channel,
+        CancellationToken::new(),
+    ));
+
+    control
+}
+
+/// Builds of the project, queued or not
+pub async fn builds(pool: &PgPool, project_id: Uuid) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM builds WHERE project_id = $1")
+        .bind(project_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+pub async fn user(pool: &PgPool, username: &str) -> User {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO users (id, username, password, name) VALUES ($1, $2, '', $2)")
+        .bind(id)
+        .bind(username)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    User {
+        id,
+        username: username.to_string(),
+        password: String::new(),
+        name: username.to_string(),
+        permissions: HashSet::new(),
+    }
+}
+
+/// An owner `user` belongs to
+pub async fn owner(pool: &PgPool, name: &str, user: &User) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO project_owners (id, name) VALUES ($1, $2)")
+        .bind(id)
+        .bind(name)
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO users_owners (user_id, owner_id) VALUES ($1, $2)")
+        .bind(user.id)
+        .bind(id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    id
+}
+
+pub async fn project(pool: &PgPool, owner_id: Uuid, name: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO projects (id, owner_id, name) VALUES ($1, $2, $3)")
+        .bind(id)
+        .bind(owner_id)
+        .bind(name)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    id
+}
+
+/// Shares the project with `user`, `role` is `viewer` or `editor`
+pub async fn share(pool: &PgPool, project_id: Uuid, user: &User, role: &str) {
+    sqlx::query("INSERT INTO project_shares (project_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(project_id)
+        .bind(user.id)
+        .bind(role)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// The empty repository of a project under `base`, with `main` as its default branch
+pub fn repository(base: &str, owner: &str, name: &str) -> PathBuf {
+    let path = git::resolve_repo_path(base, owner, name);
+    let repo = git2::Repository::init_bare(&path).unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+
+    path
+}
+
+/// A git token for a project or, given an owner, for every project of the owner
+pub async fn token(pool: &PgPool, project_id: Option<Uuid>, owner_id: Option<Uuid>) -> String {
+    let token = Uuid::new_v4().simple().to_string();
+    sqlx::query("INSERT INTO api_token (id, project_id, owner_id, token) VALUES ($1, $2, $3, $4)")
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(owner_id)
+        .bind(git::hash_token(&token).unwrap())
+        .execute(pool)
+        .await
+        .unwrap();
+
+    token
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bollard::Docker;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::build_cache;
+
+/// Bytes taken by the files under `path`. Symlinks count as themselves, they aren't followed.
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        size += match metadata.is_dir() {
+            true => dir_size(&entry.path())?,
+            false => metadata.len(),
+        };
+    }
+
+    Ok(size)
+}
+
+/// Walks the bare repository again and stores its size on the project, done after every push
+/// so reading the usage doesn't have to walk it
+pub async fn refresh_repo_size(pool: &PgPool, owner: &str, project: &str, repo_path: PathBuf) -> Option<u64> {
+    let size = match tokio::task::spawn_blocking(move || dir_size(&repo_path)).await {
+        Ok(Ok(size)) => size,
+        Ok(Err(err)) => {
+            tracing::warn!(?err, owner, project, "Can't measure repository: Failed to walk directory");
+            return None;
+        }
+        Err(err) => {
+            tracing::error!(?err, owner, project, "Can't measure repository: Task failed");
+            return None;
+        }
+    };
+
+    if let Err(err) = sqlx::query(
+        r#"UPDATE projects
+           SET repo_size_bytes = $1, repo_size_updated_at = now()
+           FROM project_owners
+           WHERE projects.owner_id = project_owners.id
+             AND project_owners.name = $2
+             AND projects.name = $3
+        "#,
+    )
+    .bind(size as i64)
+    .bind(owner)
+    .bind(project)
+    .execute(pool)
+    .await
+    {
+        tracing::error!(?err, owner, project, "Can't store repository size: Failed to query database");
+    }
+
+    Some(size)
+}
+
+/// What the containers and volumes of a project take in docker
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct DockerUsage {
+    /// image the app container runs, shared layers are counted in full
+    pub image_bytes: u64,
+    /// the database volume
+    pub volume_bytes: u64,
+    /// the dependency cache volume of static builds
+    pub cache_bytes: u64,
+}
+
+impl DockerUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.image_bytes + self.volume_bytes + self.cache_bytes
+    }
+}
+
+/// Usage of every container in `container_names` from a single `docker system df`, which is
+/// slow enough that it shouldn't run once per project
+pub async fn docker_usage(container_names: &[String]) -> Result<HashMap<String, DockerUsage>, bollard::errors::Error> {
+    let docker = Docker::connect_with_local_defaults()?;
+    let df = docker.df().await?;
+
+    let image_sizes = df
+        .images
+        .unwrap_or_default()
+        .into_iter()
+        .map(|image| (image.id, image.size.max(0) as u64))
+        .collect::<HashMap<_, _>>();
+
+    let container_images = df
+        .containers
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|container| {
+            let image_id = container.image_id?;
+            let names = container.names.unwrap_or_default();
+            Some(names.into_iter().map(move |name| (name.trim_start_matches('/').to_string(), image_id.clone())))
+        })
+        .flatten()
+        .collect::<HashMap<_, _>>();
+
+    // -1 when docker didn't measure the volume
+    let volume_sizes = df
+        .volumes
+        .unwrap_or_default()
+        .into_iter()
+        .map(|volume| (volume.name, volume.usage_data.map_or(0, |usage| usage.size.max(0) as u64)))
+        .collect::<HashMap<_, _>>();
+
+    Ok(container_names
+        .iter()
+        .map(|name| {
+            let usage = DockerUsage {
+                image_bytes: container_images
+                    .get(name)
+                    .and_then(|image_id| image_sizes.get(image_id))
+                    .copied()
+                    .unwrap_or(0),
+                volume_bytes: volume_sizes.get(&format!("{name}-volume")).copied().unwrap_or(0),
+                cache_bytes: volume_sizes.get(&build_cache::volume_name(name)).copied().unwrap_or(0),
+            };
+            (name.clone(), usage)
+        })
+        .collect())
+}
@@ -39,12 +39,13 @@ const apiFetcher = (input: URL | RequestInfo, options?: RequestInit) =>
     return res.json();
   });
 
-type TreeEntry =
+type TreeEntry = { mode: string; id: string } & (
   | { kind: "dir"; name: string }
   | { kind: "file"; name: string; size: number }
   | { kind: "symlink"; name: string }
   | { kind: "submodule"; name: string }
-  | { kind: "other"; name: string };
+  | { kind: "other"; name: string }
+);
 
 type TreeResponse = {
   ref: string;
@@ -113,7 +113,7 @@ async function handleAddMember() {
   try {
     setIsAddingMember(true)
     
-    const response = await fetch(`${import.meta.env.VITE_API_URL}/owner/${owner}/${project}/invite`, {
+    const response = await fetch(`${import.meta.env.VITE_API_URL}/project/${owner}/${project}/members/add`, {
       method: "POST",
       credentials: "include",
       headers: {
@@ -147,8 +147,11 @@ async function handleAddMember() {
   
 async function handleRemoveMember(userId: string) {
   try {
-    const response = await apiFetcher(`${import.meta.env.VITE_API_URL}/owner/${owner}/${project}/remove/${userId}`, {
-      method: "POST"
+    const response = await apiFetcher(`${import.meta.env.VITE_API_URL}/project/${owner}/${project}/members/remove`, {
+      method: "POST",
+      body: JSON.stringify({
+        user_id: userId
+      })
     })
     
     if (response.ok) {